@@ -0,0 +1,40 @@
+use divan::{black_box, Bencher};
+use django_template_ast::{parse_resilient, Edit, IncrementalParser, Span};
+
+const BASE_TEMPLATE: &str = include_str!("templates/base.html");
+
+fn main() {
+    divan::main();
+}
+
+/// A template of a couple of thousand lines, and where a keystroke lands in its middle.
+fn large_template() -> (String, usize) {
+    let source = BASE_TEMPLATE.repeat(2000 / BASE_TEMPLATE.lines().count() + 1);
+    let middle = source.len() / 2;
+    let offset = source[middle..]
+        .find("{{")
+        .map_or(middle, |index| middle + index + 3);
+    (source, offset)
+}
+
+/// Parse the whole template again, as on every keystroke before incremental parsing.
+#[divan::bench]
+fn reparse(bencher: Bencher) {
+    let (mut source, offset) = large_template();
+    source.insert(offset, 'x');
+
+    bencher.bench(|| parse_resilient(black_box(&source)));
+}
+
+/// Type a character into the template and delete it again. This should take a small
+/// fraction of `reparse`, whatever the size of the template.
+#[divan::bench]
+fn keystroke(bencher: Bencher) {
+    let (source, offset) = large_template();
+    let mut parser = IncrementalParser::new(&source);
+
+    bencher.bench_local(|| {
+        parser.edit(black_box(&Edit::new(Span::new(offset, offset), "x")));
+        parser.edit(black_box(&Edit::new(Span::new(offset, offset + 1), "")));
+    });
+}
@@ -0,0 +1,20 @@
+use divan::counter::BytesCount;
+use divan::{black_box, Bencher};
+use django_template_ast::Lexer;
+
+const BASE_TEMPLATE: &str = include_str!("templates/base.html");
+
+fn main() {
+    divan::main();
+}
+
+/// Tokenize the base template repeated `copies` times. Throughput should stay flat
+/// as the input grows; a drop means lexing has gone super-linear again.
+#[divan::bench(args = [1, 10, 100])]
+fn tokenize(bencher: Bencher, copies: usize) {
+    let source = BASE_TEMPLATE.repeat(copies);
+
+    bencher
+        .counter(BytesCount::of_str(&source))
+        .bench(|| Lexer::new(black_box(&source)).tokenize().unwrap());
+}
@@ -0,0 +1,50 @@
+from typing import Literal, Optional
+
+class TemplateSyntaxError(Exception):
+    diagnostics: list[Diagnostic]
+
+class Token:
+    kind: str
+    lexeme: str
+    # Code point indices into the source, so `source[start:end] == lexeme`.
+    start: int
+    end: int
+    line: int
+
+class Node:
+    kind: Literal[
+        "text",
+        "variable",
+        "tag",
+        "block",
+        "branch",
+        "comment",
+        "element",
+        "embedded",
+        "html_comment",
+        "error",
+        "missing",
+    ]
+    name: Optional[str]
+    bits: list[str]
+    filters: list[tuple[str, Optional[str]]]
+    attributes: list[tuple[str, Optional[str]]]
+    children: list[Node]
+    branches: list[Node]
+
+class Diagnostic:
+    code: str
+    severity: Literal["error", "warning"]
+    message: str
+    # Code point indices into the source of the primary label's span.
+    start: int
+    end: int
+    line: int
+    column: int
+    notes: list[str]
+    help: Optional[str]
+    def render(self, path: str = "<template>", color: bool = False) -> str: ...
+
+def tokenize(source: str, lossless: bool = False) -> list[Token]: ...
+def parse(source: str) -> list[Node]: ...
+def check(source: str) -> list[Diagnostic]: ...
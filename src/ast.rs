@@ -0,0 +1,309 @@
+use crate::builtins::Builtin;
+use crate::html::{Attribute, Element, Embedded};
+use crate::span::Span;
+use std::fmt;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Ast {
+    pub nodes: Vec<Node>,
+}
+
+impl Ast {
+    pub fn new(nodes: Vec<Node>) -> Self {
+        Ast { nodes }
+    }
+}
+
+impl fmt::Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for node in &self.nodes {
+            write!(f, "{}", node)?;
+        }
+        Ok(())
+    }
+}
+
+/// A template node. Printing a tree parsed from a lossless token stream reproduces
+/// the original source exactly; otherwise whitespace is normalized to single spaces.
+///
+/// Every node knows where it is in the source, see [`Node::span`].
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Node {
+    Text {
+        text: String,
+        span: Span,
+    },
+    Variable(Variable),
+    Tag(Tag),
+    Comment {
+        text: String,
+        span: Span,
+    },
+    Block(Block),
+    Builtin(Builtin),
+    Element(Element),
+    Embedded(Embedded),
+    /// `<!-- -->`, with the nodes inside it, which may include Django nodes.
+    HtmlComment {
+        nodes: Vec<Node>,
+        span: Span,
+    },
+    /// Source that could not be parsed, kept so the tree still prints it back.
+    Error(Vec<Node>),
+    /// A delimiter or end tag that should have been present, e.g. `endif`. Its span is
+    /// empty, where the missing source would go.
+    Missing {
+        expected: String,
+        span: Span,
+    },
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Text { text, .. } => write!(f, "{}", text),
+            Node::Variable(variable) => write!(f, "{}", variable),
+            Node::Tag(tag) => write!(f, "{}", tag),
+            Node::Comment { text, .. } => write!(f, "{{#{}#}}", text),
+            Node::Block(block) => write!(f, "{}", block),
+            Node::Builtin(builtin) => write!(f, "{}", builtin),
+            Node::Element(element) => write!(f, "{}", element),
+            Node::Embedded(embedded) => write!(f, "{}", embedded),
+            Node::HtmlComment { nodes, .. } => {
+                write!(f, "<!--")?;
+                for part in nodes {
+                    write!(f, "{}", part)?;
+                }
+                write!(f, "-->")
+            }
+            Node::Error(nodes) => {
+                for node in nodes {
+                    write!(f, "{}", node)?;
+                }
+                Ok(())
+            }
+            Node::Missing { .. } => Ok(()),
+        }
+    }
+}
+
+impl Node {
+    /// The source the node was parsed from. Blocks, builtins, embedded code and errors
+    /// span their parts; nodes built by hand have empty spans.
+    pub fn span(&self) -> Span {
+        match self {
+            Node::Text { span, .. }
+            | Node::Comment { span, .. }
+            | Node::HtmlComment { span, .. }
+            | Node::Missing { span, .. } => *span,
+            Node::Variable(variable) => variable.span,
+            Node::Tag(tag) => tag.span,
+            Node::Block(block) => block.tag.span.join(block.end.span),
+            Node::Builtin(builtin) => {
+                let start = builtin
+                    .sections()
+                    .first()
+                    .map_or(Span::default(), |(tag, _)| tag.span);
+                builtin.end().map_or(start, |end| start.join(end.span))
+            }
+            Node::Element(element) => element.span,
+            Node::Embedded(Embedded { nodes, .. }) | Node::Error(nodes) => match nodes.as_slice() {
+                [first, .., last] => first.span().join(last.span()),
+                [node] => node.span(),
+                [] => Span::default(),
+            },
+        }
+    }
+
+    /// Move the node `offset` bytes and `lines` lines later in the source, or earlier
+    /// when negative, after an edit before it.
+    pub(crate) fn shift(&mut self, offset: isize, lines: isize) {
+        let shift_tag = |tag: &mut Tag| {
+            tag.span = tag.span.shift(offset);
+            tag.line = tag.line.saturating_add_signed(lines);
+        };
+        let shift_all =
+            |nodes: &mut [Node]| nodes.iter_mut().for_each(|node| node.shift(offset, lines));
+        match self {
+            Node::Text { span, .. } | Node::Comment { span, .. } | Node::Missing { span, .. } => {
+                *span = span.shift(offset);
+            }
+            Node::Variable(variable) => variable.span = variable.span.shift(offset),
+            Node::Tag(tag) => shift_tag(tag),
+            Node::Block(block) => {
+                shift_tag(&mut block.tag);
+                shift_all(&mut block.nodes);
+                for branch in &mut block.branches {
+                    shift_tag(&mut branch.tag);
+                    shift_all(&mut branch.nodes);
+                }
+                shift_tag(&mut block.end);
+            }
+            Node::Builtin(builtin) => {
+                let (sections, end) = builtin.sections_mut();
+                for (tag, nodes) in sections {
+                    shift_tag(tag);
+                    shift_all(nodes);
+                }
+                if let Some(end) = end {
+                    shift_tag(end);
+                }
+            }
+            Node::Element(element) => {
+                element.span = element.span.shift(offset);
+                for attribute in &mut element.attributes {
+                    match attribute {
+                        Attribute::Html(attribute) => {
+                            if let Some(value) = &mut attribute.value {
+                                shift_all(&mut value.parts);
+                            }
+                        }
+                        Attribute::Template { node, .. } => node.shift(offset, lines),
+                    }
+                }
+                shift_all(&mut element.children);
+            }
+            Node::HtmlComment { nodes, span } => {
+                *span = span.shift(offset);
+                shift_all(nodes);
+            }
+            Node::Embedded(Embedded { nodes, .. }) | Node::Error(nodes) => shift_all(nodes),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Variable {
+    pub var: String,
+    pub filters: Vec<Filter>,
+    /// The variable and its filters exactly as written between the braces.
+    pub expression: String,
+    /// Whitespace after `{{` and before `}}`.
+    pub trivia: Vec<String>,
+    pub span: Span,
+}
+
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{{{{{}{}{}}}}}",
+            trivia_at(&self.trivia, 0),
+            self.expression,
+            trivia_at(&self.trivia, 1)
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Filter {
+    pub name: String,
+    pub arg: Option<String>,
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.arg {
+            Some(arg) => write!(f, "{}:{}", self.name, arg),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// A value followed by any number of filters, e.g. `user.name|default:"nobody"`. This
+/// is how Django reads the arguments of most builtin tags.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct FilterExpression {
+    pub var: String,
+    pub filters: Vec<Filter>,
+}
+
+impl fmt::Display for FilterExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.var)?;
+        for filter in &self.filters {
+            write!(f, "|{}", filter)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Tag {
+    pub name: String,
+    pub bits: Vec<String>,
+    pub line: usize,
+    /// Whitespace before the name, before each bit, and before `%}`.
+    pub trivia: Vec<String>,
+    pub span: Span,
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{%{}{}", trivia_at(&self.trivia, 0), self.name)?;
+        for (index, bit) in self.bits.iter().enumerate() {
+            write!(f, "{}{}", trivia_at(&self.trivia, index + 1), bit)?;
+        }
+        write!(f, "{}%}}", trivia_at(&self.trivia, self.bits.len() + 1))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Block {
+    pub tag: Tag,
+    pub nodes: Vec<Node>,
+    pub branches: Vec<Branch>,
+    pub end: Tag,
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.tag)?;
+        for node in &self.nodes {
+            write!(f, "{}", node)?;
+        }
+        for branch in &self.branches {
+            write!(f, "{}", branch)?;
+        }
+        write!(f, "{}", self.end)
+    }
+}
+
+/// An intermediate section of a block, e.g. the `{% else %}` of an `{% if %}`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Branch {
+    pub tag: Tag,
+    pub nodes: Vec<Node>,
+}
+
+impl fmt::Display for Branch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.tag)?;
+        for node in &self.nodes {
+            write!(f, "{}", node)?;
+        }
+        Ok(())
+    }
+}
+
+/// Nodes built by hand rather than parsed may have no trivia, in which case the parts
+/// are separated by a single space.
+fn trivia_at(trivia: &[String], index: usize) -> &str {
+    trivia.get(index).map_or(" ", String::as_str)
+}
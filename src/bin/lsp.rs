@@ -0,0 +1,9 @@
+use lsp_server::Connection;
+use std::error::Error;
+
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (connection, io_threads) = Connection::stdio();
+    django_template_ast::lsp::run(connection)?;
+    io_threads.join()?;
+    Ok(())
+}
@@ -0,0 +1,1250 @@
+use crate::ast::{Block, Branch, Filter, FilterExpression, Node, Tag};
+use crate::condition::{parse_condition, Condition};
+use crate::error::ParserError;
+use std::fmt;
+
+/// A tag from Django's builtin library, with its arguments parsed into typed fields.
+/// Every node keeps the tags it was parsed from so that it prints back the way it
+/// was written.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Builtin {
+    Autoescape(Autoescape),
+    Block(NamedBlock),
+    CsrfToken(CsrfToken),
+    Cycle(Cycle),
+    Debug(Debug),
+    Extends(Extends),
+    Filter(FilterBlock),
+    Firstof(Firstof),
+    For(For),
+    If(If),
+    Ifchanged(Ifchanged),
+    Include(Include),
+    Load(Load),
+    Lorem(Lorem),
+    Now(Now),
+    Partial(Partial),
+    Partialdef(Partialdef),
+    Regroup(Regroup),
+    Resetcycle(Resetcycle),
+    Spaceless(Spaceless),
+    Templatetag(Templatetag),
+    Url(Url),
+    Widthratio(Widthratio),
+    With(With),
+}
+
+impl Builtin {
+    /// Each tag that starts a section of the builtin, with the nodes that follow it:
+    /// e.g. the `{% if %}`, `{% elif %}` and `{% else %}` branches of an if. A tag
+    /// without a body has a single section with no nodes.
+    pub fn sections(&self) -> Vec<(&Tag, &[Node])> {
+        match self {
+            Builtin::Autoescape(node) => vec![(&node.tag, &node.body)],
+            Builtin::Block(node) => vec![(&node.tag, &node.body)],
+            Builtin::CsrfToken(node) => vec![(&node.tag, &[])],
+            Builtin::Cycle(node) => vec![(&node.tag, &[])],
+            Builtin::Debug(node) => vec![(&node.tag, &[])],
+            Builtin::Extends(node) => vec![(&node.tag, &[])],
+            Builtin::Filter(node) => vec![(&node.tag, &node.body)],
+            Builtin::Firstof(node) => vec![(&node.tag, &[])],
+            Builtin::For(node) => with_branch(&node.tag, &node.body, &node.empty),
+            Builtin::If(node) => node
+                .branches
+                .iter()
+                .map(|branch| (&branch.tag, branch.nodes.as_slice()))
+                .collect(),
+            Builtin::Ifchanged(node) => with_branch(&node.tag, &node.body, &node.else_branch),
+            Builtin::Include(node) => vec![(&node.tag, &[])],
+            Builtin::Load(node) => vec![(&node.tag, &[])],
+            Builtin::Lorem(node) => vec![(&node.tag, &[])],
+            Builtin::Now(node) => vec![(&node.tag, &[])],
+            Builtin::Partial(node) => vec![(&node.tag, &[])],
+            Builtin::Partialdef(node) => vec![(&node.tag, &node.body)],
+            Builtin::Regroup(node) => vec![(&node.tag, &[])],
+            Builtin::Resetcycle(node) => vec![(&node.tag, &[])],
+            Builtin::Spaceless(node) => vec![(&node.tag, &node.body)],
+            Builtin::Templatetag(node) => vec![(&node.tag, &[])],
+            Builtin::Url(node) => vec![(&node.tag, &[])],
+            Builtin::Widthratio(node) => vec![(&node.tag, &[])],
+            Builtin::With(node) => vec![(&node.tag, &node.body)],
+        }
+    }
+
+    /// The tag closing the block, or `None` if the builtin is not a block.
+    pub fn end(&self) -> Option<&Tag> {
+        match self {
+            Builtin::Autoescape(node) => Some(&node.end),
+            Builtin::Block(node) => Some(&node.end),
+            Builtin::Filter(node) => Some(&node.end),
+            Builtin::For(node) => Some(&node.end),
+            Builtin::If(node) => Some(&node.end),
+            Builtin::Ifchanged(node) => Some(&node.end),
+            Builtin::Partialdef(node) => Some(&node.end),
+            Builtin::Spaceless(node) => Some(&node.end),
+            Builtin::With(node) => Some(&node.end),
+            _ => None,
+        }
+    }
+
+    /// Mutable access to the sections of the builtin and its end tag.
+    pub(crate) fn sections_mut(&mut self) -> (Vec<(&mut Tag, &mut [Node])>, Option<&mut Tag>) {
+        match self {
+            Builtin::Autoescape(node) => {
+                (vec![(&mut node.tag, &mut node.body)], Some(&mut node.end))
+            }
+            Builtin::Block(node) => (vec![(&mut node.tag, &mut node.body)], Some(&mut node.end)),
+            Builtin::CsrfToken(node) => (vec![(&mut node.tag, &mut [])], None),
+            Builtin::Cycle(node) => (vec![(&mut node.tag, &mut [])], None),
+            Builtin::Debug(node) => (vec![(&mut node.tag, &mut [])], None),
+            Builtin::Extends(node) => (vec![(&mut node.tag, &mut [])], None),
+            Builtin::Filter(node) => (vec![(&mut node.tag, &mut node.body)], Some(&mut node.end)),
+            Builtin::Firstof(node) => (vec![(&mut node.tag, &mut [])], None),
+            Builtin::For(node) => (
+                with_branch_mut(&mut node.tag, &mut node.body, &mut node.empty),
+                Some(&mut node.end),
+            ),
+            Builtin::If(node) => (
+                node.branches
+                    .iter_mut()
+                    .map(|branch| (&mut branch.tag, branch.nodes.as_mut_slice()))
+                    .collect(),
+                Some(&mut node.end),
+            ),
+            Builtin::Ifchanged(node) => (
+                with_branch_mut(&mut node.tag, &mut node.body, &mut node.else_branch),
+                Some(&mut node.end),
+            ),
+            Builtin::Include(node) => (vec![(&mut node.tag, &mut [])], None),
+            Builtin::Load(node) => (vec![(&mut node.tag, &mut [])], None),
+            Builtin::Lorem(node) => (vec![(&mut node.tag, &mut [])], None),
+            Builtin::Now(node) => (vec![(&mut node.tag, &mut [])], None),
+            Builtin::Partial(node) => (vec![(&mut node.tag, &mut [])], None),
+            Builtin::Partialdef(node) => {
+                (vec![(&mut node.tag, &mut node.body)], Some(&mut node.end))
+            }
+            Builtin::Regroup(node) => (vec![(&mut node.tag, &mut [])], None),
+            Builtin::Resetcycle(node) => (vec![(&mut node.tag, &mut [])], None),
+            Builtin::Spaceless(node) => {
+                (vec![(&mut node.tag, &mut node.body)], Some(&mut node.end))
+            }
+            Builtin::Templatetag(node) => (vec![(&mut node.tag, &mut [])], None),
+            Builtin::Url(node) => (vec![(&mut node.tag, &mut [])], None),
+            Builtin::Widthratio(node) => (vec![(&mut node.tag, &mut [])], None),
+            Builtin::With(node) => (vec![(&mut node.tag, &mut node.body)], Some(&mut node.end)),
+        }
+    }
+}
+
+impl fmt::Display for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (tag, nodes) in self.sections() {
+            write!(f, "{}", tag)?;
+            for node in nodes {
+                write!(f, "{}", node)?;
+            }
+        }
+        match self.end() {
+            Some(end) => write!(f, "{}", end),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `{% autoescape on %}...{% endautoescape %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Autoescape {
+    pub enabled: bool,
+    pub body: Vec<Node>,
+    pub tag: Tag,
+    pub end: Tag,
+}
+
+/// `{% block name %}...{% endblock %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct NamedBlock {
+    pub name: String,
+    pub body: Vec<Node>,
+    pub tag: Tag,
+    pub end: Tag,
+}
+
+/// `{% csrf_token %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct CsrfToken {
+    pub tag: Tag,
+}
+
+/// `{% cycle 'odd' 'even' as rowcolors silent %}`, or `{% cycle rowcolors %}` to
+/// advance a named cycle, in which case `values` is empty.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Cycle {
+    pub values: Vec<FilterExpression>,
+    pub name: Option<String>,
+    pub silent: bool,
+    pub tag: Tag,
+}
+
+/// `{% debug %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Debug {
+    pub tag: Tag,
+}
+
+/// `{% extends "base.html" %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Extends {
+    pub parent: FilterExpression,
+    pub tag: Tag,
+}
+
+/// `{% filter force_escape|lower %}...{% endfilter %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct FilterBlock {
+    pub filters: Vec<Filter>,
+    pub body: Vec<Node>,
+    pub tag: Tag,
+    pub end: Tag,
+}
+
+/// `{% firstof a b "fallback" as value %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Firstof {
+    pub values: Vec<FilterExpression>,
+    pub asvar: Option<String>,
+    pub tag: Tag,
+}
+
+/// `{% for key, value in items reversed %}...{% empty %}...{% endfor %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct For {
+    pub targets: Vec<String>,
+    pub iterable: FilterExpression,
+    pub reversed: bool,
+    pub body: Vec<Node>,
+    pub empty: Option<Branch>,
+    pub tag: Tag,
+    pub end: Tag,
+}
+
+/// `{% if %}`, any `{% elif %}`s and an optional `{% else %}`, in source order.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct If {
+    pub branches: Vec<IfBranch>,
+    pub end: Tag,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct IfBranch {
+    /// `None` for the `{% else %}` branch.
+    pub condition: Option<Condition>,
+    pub nodes: Vec<Node>,
+    pub tag: Tag,
+}
+
+/// `{% ifchanged a b %}...{% else %}...{% endifchanged %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Ifchanged {
+    pub values: Vec<FilterExpression>,
+    pub body: Vec<Node>,
+    pub else_branch: Option<Branch>,
+    pub tag: Tag,
+    pub end: Tag,
+}
+
+/// `{% include "name.html" with key=value only %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Include {
+    pub template: FilterExpression,
+    pub extra_context: Vec<(String, FilterExpression)>,
+    pub only: bool,
+    pub tag: Tag,
+}
+
+/// `{% load library other %}` or `{% load name other from library %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Load {
+    pub libraries: Vec<String>,
+    /// The tags and filters picked out of a single library with `from`.
+    pub names: Vec<String>,
+    pub tag: Tag,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum LoremMethod {
+    Words,
+    Paragraphs,
+    Blocks,
+}
+
+/// `{% lorem 3 p random %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Lorem {
+    pub count: FilterExpression,
+    pub method: LoremMethod,
+    pub random: bool,
+    pub tag: Tag,
+}
+
+/// `{% now "Y-m-d" as today %}`, with `format` unquoted.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Now {
+    pub format: String,
+    pub asvar: Option<String>,
+    pub tag: Tag,
+}
+
+/// `{% partial name %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Partial {
+    pub name: String,
+    pub tag: Tag,
+}
+
+/// `{% partialdef name inline %}...{% endpartialdef %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Partialdef {
+    pub name: String,
+    pub inline: bool,
+    pub body: Vec<Node>,
+    pub tag: Tag,
+    pub end: Tag,
+}
+
+/// `{% regroup people by gender as groups %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Regroup {
+    pub target: FilterExpression,
+    pub grouper: String,
+    pub asvar: String,
+    pub tag: Tag,
+}
+
+/// `{% resetcycle name %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Resetcycle {
+    pub name: Option<String>,
+    pub tag: Tag,
+}
+
+/// `{% spaceless %}...{% endspaceless %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Spaceless {
+    pub body: Vec<Node>,
+    pub tag: Tag,
+    pub end: Tag,
+}
+
+/// `{% templatetag openblock %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Templatetag {
+    pub name: String,
+    pub tag: Tag,
+}
+
+const TEMPLATETAGS: &[(&str, &str)] = &[
+    ("openblock", "{%"),
+    ("closeblock", "%}"),
+    ("openvariable", "{{"),
+    ("closevariable", "}}"),
+    ("openbrace", "{"),
+    ("closebrace", "}"),
+    ("opencomment", "{#"),
+    ("closecomment", "#}"),
+];
+
+impl Templatetag {
+    /// The syntax the tag stands for, e.g. `{%` for `openblock`.
+    pub fn output(&self) -> &'static str {
+        TEMPLATETAGS
+            .iter()
+            .find(|(name, _)| *name == self.name)
+            .map_or("", |(_, output)| output)
+    }
+}
+
+/// `{% url "app:view" arg key=value as link %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Url {
+    pub view: FilterExpression,
+    pub args: Vec<FilterExpression>,
+    pub kwargs: Vec<(String, FilterExpression)>,
+    pub asvar: Option<String>,
+    pub tag: Tag,
+}
+
+/// `{% widthratio value max_value max_width as ratio %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Widthratio {
+    pub value: FilterExpression,
+    pub max_value: FilterExpression,
+    pub max_width: FilterExpression,
+    pub asvar: Option<String>,
+    pub tag: Tag,
+}
+
+/// `{% with name=value other=value %}...{% endwith %}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct With {
+    pub assignments: Vec<(String, FilterExpression)>,
+    pub body: Vec<Node>,
+    pub tag: Tag,
+    pub end: Tag,
+}
+
+/// Turn a generic block into its builtin node, leaving other blocks untouched.
+pub(crate) fn block_node(block: Block) -> Result<Node, ParserError> {
+    let builtin = match block.tag.name.as_str() {
+        "autoescape" => Builtin::Autoescape(autoescape(block)?),
+        "block" => Builtin::Block(named_block(block)?),
+        "filter" => Builtin::Filter(filter_block(block)?),
+        "for" => Builtin::For(for_block(block)?),
+        "if" => Builtin::If(if_block(block)?),
+        "ifchanged" => Builtin::Ifchanged(ifchanged(block)?),
+        "partialdef" => Builtin::Partialdef(partialdef(block)?),
+        "spaceless" => Builtin::Spaceless(Spaceless {
+            body: block.nodes,
+            tag: block.tag,
+            end: block.end,
+        }),
+        "with" => Builtin::With(with_block(block)?),
+        _ => return Ok(Node::Block(block)),
+    };
+    Ok(Node::Builtin(builtin))
+}
+
+/// Turn a generic tag into its builtin node, leaving other tags untouched.
+pub(crate) fn tag_node(tag: Tag) -> Result<Node, ParserError> {
+    let builtin = match tag.name.as_str() {
+        "csrf_token" => Builtin::CsrfToken(CsrfToken { tag }),
+        "cycle" => Builtin::Cycle(cycle(tag)?),
+        "debug" => Builtin::Debug(Debug { tag }),
+        "extends" => Builtin::Extends(extends(tag)?),
+        "firstof" => Builtin::Firstof(firstof(tag)?),
+        "include" => Builtin::Include(include(tag)?),
+        "load" => Builtin::Load(load(tag)?),
+        "lorem" => Builtin::Lorem(lorem(tag)?),
+        "now" => Builtin::Now(now(tag)?),
+        "partial" => Builtin::Partial(partial(tag)?),
+        "regroup" => Builtin::Regroup(regroup(tag)?),
+        "resetcycle" => Builtin::Resetcycle(resetcycle(tag)?),
+        "templatetag" => Builtin::Templatetag(templatetag(tag)?),
+        "url" => Builtin::Url(url(tag)?),
+        "widthratio" => Builtin::Widthratio(widthratio(tag)?),
+        _ => return Ok(Node::Tag(tag)),
+    };
+    Ok(Node::Builtin(builtin))
+}
+
+fn autoescape(block: Block) -> Result<Autoescape, ParserError> {
+    let enabled = match block.tag.bits.as_slice() {
+        [arg] if arg == "on" => true,
+        [arg] if arg == "off" => false,
+        _ => return Err(invalid(&block.tag, "argument must be 'on' or 'off'")),
+    };
+    Ok(Autoescape {
+        enabled,
+        body: block.nodes,
+        tag: block.tag,
+        end: block.end,
+    })
+}
+
+fn named_block(block: Block) -> Result<NamedBlock, ParserError> {
+    let name = single_name(&block)?;
+    Ok(NamedBlock {
+        name,
+        body: block.nodes,
+        tag: block.tag,
+        end: block.end,
+    })
+}
+
+fn filter_block(block: Block) -> Result<FilterBlock, ParserError> {
+    let [bit] = block.tag.bits.as_slice() else {
+        return Err(invalid(&block.tag, "takes exactly one argument"));
+    };
+    let filters = split_unquoted(bit, '|')
+        .into_iter()
+        .map(|part| filter(&block.tag, part))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(filter) = filters
+        .iter()
+        .find(|filter| filter.name == "escape" || filter.name == "safe")
+    {
+        return Err(invalid(
+            &block.tag,
+            &format!(
+                "'{}' is not permitted, use the 'autoescape' tag instead",
+                filter.name
+            ),
+        ));
+    }
+    Ok(FilterBlock {
+        filters,
+        body: block.nodes,
+        tag: block.tag,
+        end: block.end,
+    })
+}
+
+fn for_block(mut block: Block) -> Result<For, ParserError> {
+    let tag = &block.tag;
+    let bits = &tag.bits;
+    if bits.len() < 3 {
+        return Err(invalid(tag, "should have at least four words"));
+    }
+    let reversed = bits.last().is_some_and(|bit| bit == "reversed");
+    let in_index = if reversed {
+        bits.len() - 3
+    } else {
+        bits.len() - 2
+    };
+    if bits[in_index] != "in" {
+        return Err(invalid(tag, "should use the format 'for x in y'"));
+    }
+    let targets: Vec<String> = bits[..in_index]
+        .join(" ")
+        .split(',')
+        .map(|target| target.trim().to_string())
+        .collect();
+    if let Some(target) = targets
+        .iter()
+        .find(|target| target.is_empty() || target.contains(char::is_whitespace))
+    {
+        return Err(invalid(tag, &format!("invalid loop variable '{}'", target)));
+    }
+    let iterable = filter_expression(tag, &bits[in_index + 1])?;
+
+    let empty = match block.branches.len() {
+        0 => None,
+        1 => block.branches.pop(),
+        _ => return Err(invalid(tag, "only one 'empty' is allowed")),
+    };
+    Ok(For {
+        targets,
+        iterable,
+        reversed,
+        body: block.nodes,
+        empty,
+        tag: block.tag,
+        end: block.end,
+    })
+}
+
+fn if_block(block: Block) -> Result<If, ParserError> {
+    let first = IfBranch {
+        condition: None,
+        nodes: block.nodes,
+        tag: block.tag,
+    };
+    let mut branches = vec![first];
+    branches.extend(block.branches.into_iter().map(|branch| IfBranch {
+        condition: None,
+        nodes: branch.nodes,
+        tag: branch.tag,
+    }));
+
+    let last = branches.len() - 1;
+    for (index, branch) in branches.iter_mut().enumerate() {
+        let tag = &branch.tag;
+        match (tag.name.as_str(), tag.bits.is_empty()) {
+            ("if" | "elif", true) => return Err(invalid(tag, "requires a condition")),
+            ("if" | "elif", false) => branch.condition = Some(parse_condition(tag)?),
+            ("else", false) => return Err(invalid(tag, "takes no arguments")),
+            ("else", true) if index != last => {
+                return Err(invalid(tag, "must be the last branch"));
+            }
+            _ => {}
+        }
+    }
+    Ok(If {
+        branches,
+        end: block.end,
+    })
+}
+
+fn ifchanged(mut block: Block) -> Result<Ifchanged, ParserError> {
+    let values = filter_expressions(&block.tag, &block.tag.bits)?;
+    let else_branch = match block.branches.len() {
+        0 => None,
+        1 => block.branches.pop(),
+        _ => return Err(invalid(&block.tag, "only one 'else' is allowed")),
+    };
+    Ok(Ifchanged {
+        values,
+        body: block.nodes,
+        else_branch,
+        tag: block.tag,
+        end: block.end,
+    })
+}
+
+fn partialdef(block: Block) -> Result<Partialdef, ParserError> {
+    let (name, inline) = match block.tag.bits.as_slice() {
+        [name] => (name.clone(), false),
+        [name, inline] if inline == "inline" => (name.clone(), true),
+        _ => {
+            return Err(invalid(
+                &block.tag,
+                "expects a name and an optional 'inline'",
+            ))
+        }
+    };
+    check_end_name(&block, &name)?;
+    Ok(Partialdef {
+        name,
+        inline,
+        body: block.nodes,
+        tag: block.tag,
+        end: block.end,
+    })
+}
+
+fn with_block(block: Block) -> Result<With, ParserError> {
+    let tag = &block.tag;
+    let assignments = match tag.bits.as_slice() {
+        // The legacy `{% with value as name %}` form
+        [value, as_, name] if as_ == "as" => {
+            vec![(name.clone(), filter_expression(tag, value)?)]
+        }
+        [] => return Err(invalid(tag, "expected at least one variable assignment")),
+        bits => kwargs(tag, bits)?,
+    };
+    Ok(With {
+        assignments,
+        body: block.nodes,
+        tag: block.tag,
+        end: block.end,
+    })
+}
+
+fn cycle(tag: Tag) -> Result<Cycle, ParserError> {
+    let bits = tag.bits.as_slice();
+    let (values, name, silent) = match bits {
+        [] => return Err(invalid(&tag, "requires at least two arguments")),
+        [name] => (&[][..], Some(name.clone()), false),
+        [values @ .., as_, name, silent] if as_ == "as" && silent == "silent" => {
+            (values, Some(name.clone()), true)
+        }
+        [values @ .., as_, name] if as_ == "as" => (values, Some(name.clone()), false),
+        values => (values, None, false),
+    };
+    let values = filter_expressions(&tag, values)?;
+    Ok(Cycle {
+        values,
+        name,
+        silent,
+        tag,
+    })
+}
+
+fn extends(tag: Tag) -> Result<Extends, ParserError> {
+    let [parent] = tag.bits.as_slice() else {
+        return Err(invalid(&tag, "takes exactly one argument"));
+    };
+    let parent = filter_expression(&tag, parent)?;
+    Ok(Extends { parent, tag })
+}
+
+fn firstof(tag: Tag) -> Result<Firstof, ParserError> {
+    let (values, asvar) = split_asvar(&tag.bits);
+    if values.is_empty() {
+        return Err(invalid(&tag, "expects at least one argument"));
+    }
+    let values = filter_expressions(&tag, values)?;
+    Ok(Firstof { values, asvar, tag })
+}
+
+fn include(tag: Tag) -> Result<Include, ParserError> {
+    let Some((template, options)) = tag.bits.split_first() else {
+        return Err(invalid(&tag, "takes at least one argument"));
+    };
+    let template = filter_expression(&tag, template)?;
+
+    let mut extra_context = None;
+    let mut only = false;
+    let mut options = options.iter().peekable();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "only" if !only => only = true,
+            "with" if extra_context.is_none() => {
+                let mut bits = Vec::new();
+                while let Some(bit) = options.next_if(|bit| kwarg(bit).is_some()) {
+                    bits.push(bit.clone());
+                }
+                if bits.is_empty() {
+                    return Err(invalid(
+                        &tag,
+                        "'with' expects at least one variable assignment",
+                    ));
+                }
+                extra_context = Some(kwargs(&tag, &bits)?);
+            }
+            "only" | "with" => {
+                return Err(invalid(
+                    &tag,
+                    &format!("'{}' was specified more than once", option),
+                ))
+            }
+            _ => return Err(invalid(&tag, &format!("unknown option '{}'", option))),
+        }
+    }
+    Ok(Include {
+        template,
+        extra_context: extra_context.unwrap_or_default(),
+        only,
+        tag,
+    })
+}
+
+fn load(tag: Tag) -> Result<Load, ParserError> {
+    let (libraries, names) = match tag.bits.as_slice() {
+        [] => return Err(invalid(&tag, "expects at least one library")),
+        [names @ .., from, library] if from == "from" && !names.is_empty() => {
+            (vec![library.clone()], names.to_vec())
+        }
+        libraries => (libraries.to_vec(), Vec::new()),
+    };
+    Ok(Load {
+        libraries,
+        names,
+        tag,
+    })
+}
+
+fn lorem(tag: Tag) -> Result<Lorem, ParserError> {
+    let mut bits = tag.bits.as_slice();
+    let mut random = false;
+    if let [rest @ .., last] = bits {
+        if last == "random" {
+            random = true;
+            bits = rest;
+        }
+    }
+    let mut method = LoremMethod::Blocks;
+    if let [rest @ .., last] = bits {
+        let parsed = match last.as_str() {
+            "w" => Some(LoremMethod::Words),
+            "p" => Some(LoremMethod::Paragraphs),
+            "b" => Some(LoremMethod::Blocks),
+            _ => None,
+        };
+        if let Some(parsed) = parsed {
+            method = parsed;
+            bits = rest;
+        }
+    }
+    let count = match bits {
+        [] => "1",
+        [count] => count.as_str(),
+        _ => {
+            return Err(invalid(
+                &tag,
+                "expects at most a count, a method and 'random'",
+            ))
+        }
+    };
+    let count = filter_expression(&tag, count)?;
+    Ok(Lorem {
+        count,
+        method,
+        random,
+        tag,
+    })
+}
+
+fn now(tag: Tag) -> Result<Now, ParserError> {
+    let (format, asvar) = match split_asvar(&tag.bits) {
+        ([format], asvar) => (format, asvar),
+        _ => return Err(invalid(&tag, "expects a format string")),
+    };
+    let format = unquote(format).ok_or_else(|| invalid(&tag, "format must be a string"))?;
+    Ok(Now {
+        format: format.to_string(),
+        asvar,
+        tag,
+    })
+}
+
+fn partial(tag: Tag) -> Result<Partial, ParserError> {
+    let [name] = tag.bits.as_slice() else {
+        return Err(invalid(&tag, "takes exactly one argument"));
+    };
+    Ok(Partial {
+        name: name.clone(),
+        tag,
+    })
+}
+
+fn regroup(tag: Tag) -> Result<Regroup, ParserError> {
+    let [target, by, grouper, as_, asvar] = tag.bits.as_slice() else {
+        return Err(invalid(&tag, "should use the format 'regroup x by y as z'"));
+    };
+    if by != "by" || as_ != "as" {
+        return Err(invalid(&tag, "should use the format 'regroup x by y as z'"));
+    }
+    Ok(Regroup {
+        target: filter_expression(&tag, target)?,
+        grouper: grouper.clone(),
+        asvar: asvar.clone(),
+        tag,
+    })
+}
+
+fn resetcycle(tag: Tag) -> Result<Resetcycle, ParserError> {
+    let name = match tag.bits.as_slice() {
+        [] => None,
+        [name] => Some(name.clone()),
+        _ => return Err(invalid(&tag, "accepts at most one argument")),
+    };
+    Ok(Resetcycle { name, tag })
+}
+
+fn templatetag(tag: Tag) -> Result<Templatetag, ParserError> {
+    let name = match tag.bits.as_slice() {
+        [name] if TEMPLATETAGS.iter().any(|(known, _)| known == name) => name.clone(),
+        _ => return Err(invalid(&tag, "invalid templatetag argument")),
+    };
+    Ok(Templatetag { name, tag })
+}
+
+fn url(tag: Tag) -> Result<Url, ParserError> {
+    let (bits, asvar) = split_asvar(&tag.bits);
+    let Some((view, rest)) = bits.split_first() else {
+        return Err(invalid(
+            &tag,
+            "takes at least one argument, the name of a url",
+        ));
+    };
+    let view = filter_expression(&tag, view)?;
+    let mut args = Vec::new();
+    let mut kwargs = Vec::new();
+    for bit in rest {
+        match kwarg(bit) {
+            Some((name, value)) => kwargs.push((name.to_string(), filter_expression(&tag, value)?)),
+            None => args.push(filter_expression(&tag, bit)?),
+        }
+    }
+    Ok(Url {
+        view,
+        args,
+        kwargs,
+        asvar,
+        tag,
+    })
+}
+
+fn widthratio(tag: Tag) -> Result<Widthratio, ParserError> {
+    let (bits, asvar) = split_asvar(&tag.bits);
+    let [value, max_value, max_width] = bits else {
+        return Err(invalid(&tag, "takes at least three arguments"));
+    };
+    Ok(Widthratio {
+        value: filter_expression(&tag, value)?,
+        max_value: filter_expression(&tag, max_value)?,
+        max_width: filter_expression(&tag, max_width)?,
+        asvar,
+        tag,
+    })
+}
+
+pub(crate) fn invalid(tag: &Tag, reason: &str) -> ParserError {
+    ParserError::InvalidArguments {
+        name: tag.name.clone(),
+        reason: reason.to_string(),
+        line: tag.line,
+    }
+}
+
+/// The name of a `{% block %}`-like tag, which the end tag may repeat.
+fn single_name(block: &Block) -> Result<String, ParserError> {
+    let [name] = block.tag.bits.as_slice() else {
+        return Err(invalid(&block.tag, "takes exactly one argument"));
+    };
+    check_end_name(block, name)?;
+    Ok(name.clone())
+}
+
+fn check_end_name(block: &Block, name: &str) -> Result<(), ParserError> {
+    match block.end.bits.as_slice() {
+        [] => Ok(()),
+        [end_name] if end_name == name => Ok(()),
+        _ => Err(invalid(
+            &block.end,
+            &format!("expected '{}' to close '{}'", block.end.name, name),
+        )),
+    }
+}
+
+/// Split a trailing `as name` off of a tag's bits.
+fn split_asvar(bits: &[String]) -> (&[String], Option<String>) {
+    match bits {
+        [rest @ .., as_, name] if as_ == "as" => (rest, Some(name.clone())),
+        _ => (bits, None),
+    }
+}
+
+fn filter_expressions(tag: &Tag, bits: &[String]) -> Result<Vec<FilterExpression>, ParserError> {
+    bits.iter().map(|bit| filter_expression(tag, bit)).collect()
+}
+
+pub(crate) fn filter_expression(tag: &Tag, bit: &str) -> Result<FilterExpression, ParserError> {
+    let mut parts = split_unquoted(bit, '|').into_iter();
+    let var = match parts.next() {
+        Some(var) if !var.is_empty() => var.to_string(),
+        _ => return Err(invalid(tag, &format!("could not parse '{}'", bit))),
+    };
+    let filters = parts
+        .map(|part| filter(tag, part))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(FilterExpression { var, filters })
+}
+
+fn filter(tag: &Tag, part: &str) -> Result<Filter, ParserError> {
+    let name = split_unquoted(part, ':')[0];
+    if name.is_empty() {
+        return Err(invalid(tag, &format!("missing filter name in '{}'", part)));
+    }
+    Ok(Filter {
+        name: name.to_string(),
+        arg: part.get(name.len() + 1..).map(String::from),
+    })
+}
+
+fn kwargs(tag: &Tag, bits: &[String]) -> Result<Vec<(String, FilterExpression)>, ParserError> {
+    bits.iter()
+        .map(|bit| match kwarg(bit) {
+            Some((name, value)) => Ok((name.to_string(), filter_expression(tag, value)?)),
+            None => Err(invalid(
+                tag,
+                &format!("expected 'name=value', got '{}'", bit),
+            )),
+        })
+        .collect()
+}
+
+/// Split a `name=value` bit, as used by `with`, `include` and `url`.
+pub(crate) fn kwarg(bit: &str) -> Option<(&str, &str)> {
+    let (name, value) = bit.split_once('=')?;
+    let is_name = !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    (is_name && !value.is_empty()).then_some((name, value))
+}
+
+/// The contents of a quoted string literal.
+pub(crate) fn unquote(bit: &str) -> Option<&str> {
+    let quote = bit.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    bit.strip_prefix(quote)?.strip_suffix(quote)
+}
+
+/// Split `source` on `separator`, ignoring separators that appear inside quotes.
+pub(crate) fn split_unquoted(source: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (index, c) in source.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, c) if c == separator => {
+                parts.push(&source[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&source[start..]);
+    parts
+}
+
+fn with_branch<'a>(
+    tag: &'a Tag,
+    body: &'a [Node],
+    branch: &'a Option<Branch>,
+) -> Vec<(&'a Tag, &'a [Node])> {
+    let mut sections = vec![(tag, body)];
+    sections.extend(
+        branch
+            .iter()
+            .map(|branch| (&branch.tag, branch.nodes.as_slice())),
+    );
+    sections
+}
+
+fn with_branch_mut<'a>(
+    tag: &'a mut Tag,
+    body: &'a mut [Node],
+    branch: &'a mut Option<Branch>,
+) -> Vec<(&'a mut Tag, &'a mut [Node])> {
+    let mut sections = vec![(tag, body)];
+    sections.extend(
+        branch
+            .iter_mut()
+            .map(|branch| (&mut branch.tag, branch.nodes.as_mut_slice())),
+    );
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::span::Span;
+
+    fn parse_node(source: &str) -> Result<Node, ParserError> {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.tokenize()?;
+        let mut parser = Parser::new(tokens);
+        let mut ast = parser.parse()?;
+        Ok(ast.nodes.remove(0))
+    }
+
+    fn parse_builtin(source: &str) -> Builtin {
+        match parse_node(source).unwrap() {
+            Node::Builtin(builtin) => builtin,
+            node => panic!("Expected a builtin for {}, got {:?}", source, node),
+        }
+    }
+
+    fn expression(var: &str, filters: &[(&str, Option<&str>)]) -> FilterExpression {
+        FilterExpression {
+            var: var.to_string(),
+            filters: filters
+                .iter()
+                .map(|(name, arg)| Filter {
+                    name: name.to_string(),
+                    arg: arg.map(String::from),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_filter_expression() {
+        let tag = Tag {
+            name: "test".to_string(),
+            bits: vec![],
+            line: 1,
+            trivia: vec![],
+            span: Span::default(),
+        };
+        let test_cases = vec![
+            ("name", expression("name", &[])),
+            (
+                "a.b|default:'x|y'|lower",
+                expression("a.b", &[("default", Some("'x|y'")), ("lower", None)]),
+            ),
+            (
+                r#""a:b"|date:"H:i""#,
+                expression(r#""a:b""#, &[("date", Some(r#""H:i""#))]),
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let parsed = filter_expression(&tag, input).unwrap();
+            assert_eq!(parsed, expected);
+            assert_eq!(parsed.to_string(), input);
+        }
+        assert!(filter_expression(&tag, "|lower").is_err());
+        assert!(filter_expression(&tag, "a|").is_err());
+    }
+
+    #[test]
+    fn test_for() {
+        let Builtin::For(node) =
+            parse_builtin("{% for key, value in items|dictsort:'a' reversed %}{% endfor %}")
+        else {
+            panic!("Expected a for loop");
+        };
+        assert_eq!(node.targets, vec!["key", "value"]);
+        assert_eq!(
+            node.iterable,
+            expression("items", &[("dictsort", Some("'a'"))])
+        );
+        assert!(node.reversed);
+        assert_eq!(node.empty, None);
+    }
+
+    #[test]
+    fn test_tag_arguments() {
+        let Builtin::Cycle(node) = parse_builtin(r#"{% cycle 'odd' "even" as rows silent %}"#)
+        else {
+            panic!("Expected a cycle");
+        };
+        assert_eq!(
+            node.values,
+            vec![expression("'odd'", &[]), expression(r#""even""#, &[])]
+        );
+        assert_eq!(node.name, Some("rows".to_string()));
+        assert!(node.silent);
+
+        let Builtin::Include(node) =
+            parse_builtin(r#"{% include "card.html" with title=page.title|upper only %}"#)
+        else {
+            panic!("Expected an include");
+        };
+        assert_eq!(node.template, expression(r#""card.html""#, &[]));
+        assert_eq!(
+            node.extra_context,
+            vec![(
+                "title".to_string(),
+                expression("page.title", &[("upper", None)])
+            )]
+        );
+        assert!(node.only);
+
+        let Builtin::Url(node) = parse_builtin(r#"{% url "post" post.pk page=2 as link %}"#) else {
+            panic!("Expected a url");
+        };
+        assert_eq!(node.view, expression(r#""post""#, &[]));
+        assert_eq!(node.args, vec![expression("post.pk", &[])]);
+        assert_eq!(
+            node.kwargs,
+            vec![("page".to_string(), expression("2", &[]))]
+        );
+        assert_eq!(node.asvar, Some("link".to_string()));
+
+        let Builtin::Load(node) = parse_builtin("{% load intcomma naturaltime from humanize %}")
+        else {
+            panic!("Expected a load");
+        };
+        assert_eq!(node.libraries, vec!["humanize"]);
+        assert_eq!(node.names, vec!["intcomma", "naturaltime"]);
+
+        let Builtin::Lorem(node) = parse_builtin("{% lorem 2 w random %}") else {
+            panic!("Expected a lorem");
+        };
+        assert_eq!(node.count, expression("2", &[]));
+        assert_eq!(node.method, LoremMethod::Words);
+        assert!(node.random);
+
+        let Builtin::Now(node) = parse_builtin(r#"{% now "Y" as year %}"#) else {
+            panic!("Expected a now");
+        };
+        assert_eq!(node.format, "Y");
+        assert_eq!(node.asvar, Some("year".to_string()));
+
+        let Builtin::With(node) = parse_builtin("{% with total=items|length %}{% endwith %}")
+        else {
+            panic!("Expected a with");
+        };
+        assert_eq!(
+            node.assignments,
+            vec![(
+                "total".to_string(),
+                expression("items", &[("length", None)])
+            )]
+        );
+
+        let Builtin::Block(node) = parse_builtin("{% block content %}{% endblock content %}")
+        else {
+            panic!("Expected a block");
+        };
+        assert_eq!(node.name, "content");
+    }
+
+    #[test]
+    fn test_not_builtin() {
+        assert!(matches!(
+            parse_node("{% static 'app.css' %}").unwrap(),
+            Node::Tag(_)
+        ));
+        assert!(matches!(
+            parse_node("{% comment %}{% endcomment %}").unwrap(),
+            Node::Block(_)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        let test_cases = vec![
+            "{% for x %}{% endfor %}",
+            "{% for x of y %}{% endfor %}",
+            "{% for x in y %}{% empty %}{% empty %}{% endfor %}",
+            "{% if %}{% endif %}",
+            "{% if a %}{% else %}{% elif b %}{% endif %}",
+            "{% block a %}{% endblock b %}",
+            "{% autoescape maybe %}{% endautoescape %}",
+            "{% filter escape %}{% endfilter %}",
+            "{% with a %}{% endwith %}",
+            "{% cycle %}",
+            "{% extends %}",
+            "{% include 'a.html' with %}",
+            "{% include 'a.html' only only %}",
+            "{% now Y %}",
+            "{% regroup a by b %}",
+            "{% templatetag openbracket %}",
+            "{% url %}",
+            "{% widthratio a b %}",
+        ];
+
+        for input in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let result = parse_node(input);
+            assert!(
+                matches!(result, Err(ParserError::InvalidArguments { .. })),
+                "Expected invalid arguments for input: {}, got {:?}",
+                input,
+                result
+            );
+        }
+    }
+}
@@ -0,0 +1,689 @@
+use crate::diagnostic::{Diagnostic, Renderer};
+use crate::lexer::Lexer;
+use crate::schema::SCHEMA_VERSION;
+use crate::span::SourceMap;
+use crate::token::TokenType;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+const USAGE: &str = "\
+Usage: django-template-ast <COMMAND> [OPTIONS] [PATH]...
+
+Commands:
+  tokens  Print the tokens of each template
+  ast     Print the syntax tree of each template
+  check   Report problems in each template
+  fmt     Normalize the whitespace inside tags and variables
+
+Options:
+  --format <text|json>         Output format for tokens, ast and check [default: text]
+  --color <auto|always|never>  Highlight diagnostics [default: auto]
+  --check                      With fmt, list templates that would change instead of
+                               rewriting them
+  -h, --help                   Print this message
+
+Directories are searched for templates. With no paths, or `-`, the template is read
+from stdin.
+
+Exit status is 0 on success, 1 if a template has errors or, with `fmt --check`, would
+be reformatted, and 2 if the command could not run.";
+
+/// File extensions searched for in directories.
+const TEMPLATE_EXTENSIONS: &[&str] = &["html", "htm", "txt", "xml", "djhtml"];
+
+#[derive(Error, Debug)]
+enum CliError {
+    #[error("{0}")]
+    Usage(String),
+    #[error("{path}: {source}")]
+    Io { path: String, source: io::Error },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Command {
+    Tokens,
+    Ast,
+    Check,
+    Fmt,
+    Help,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    command: Command,
+    format: Format,
+    color: Color,
+    check: bool,
+    paths: Vec<String>,
+}
+
+/// A template to work on. `file` is `None` for stdin.
+struct Input {
+    name: String,
+    file: Option<PathBuf>,
+    source: String,
+}
+
+/// Run the command line tool with `args`, not including the program name, and return
+/// the exit status.
+pub fn run(args: impl IntoIterator<Item = String>) -> u8 {
+    let result = parse_args(args).and_then(|options| {
+        if options.command == Command::Help {
+            println!("{}", USAGE);
+            return Ok(true);
+        }
+        let inputs = read_inputs(&options.paths, &mut io::stdin())?;
+        // Diagnostics go to stdout for `check`, where they are the output, and to
+        // stderr otherwise.
+        let terminal = match options.command {
+            Command::Check => io::stdout().is_terminal(),
+            _ => io::stderr().is_terminal(),
+        };
+        let color = match options.color {
+            Color::Always => true,
+            Color::Never => false,
+            Color::Auto => terminal && std::env::var_os("NO_COLOR").is_none(),
+        };
+        execute(
+            &options,
+            &inputs,
+            color,
+            &mut io::stdout(),
+            &mut io::stderr(),
+        )
+    });
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(CliError::Usage(message)) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            2
+        }
+        Err(error) => {
+            eprintln!("error: {}", error);
+            2
+        }
+    }
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, CliError> {
+    let mut args = args.into_iter();
+    let command = match args.next().as_deref() {
+        Some("tokens") => Command::Tokens,
+        Some("ast") => Command::Ast,
+        Some("check") => Command::Check,
+        Some("fmt") => Command::Fmt,
+        Some("-h" | "--help" | "help") => Command::Help,
+        Some(command) => {
+            return Err(CliError::Usage(format!("unknown command '{}'", command)));
+        }
+        None => return Err(CliError::Usage("missing command".to_string())),
+    };
+    let mut options = Options {
+        command,
+        format: Format::Text,
+        color: Color::Auto,
+        check: false,
+        paths: Vec::new(),
+    };
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value)),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |name: &str| {
+            inline_value
+                .map(str::to_string)
+                .or_else(|| args.next())
+                .ok_or_else(|| CliError::Usage(format!("missing value for '{}'", name)))
+        };
+        match flag.as_str() {
+            "--format" => {
+                options.format = match value("--format")?.as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    format => {
+                        return Err(CliError::Usage(format!("unknown format '{}'", format)));
+                    }
+                }
+            }
+            "--color" => {
+                options.color = match value("--color")?.as_str() {
+                    "auto" => Color::Auto,
+                    "always" => Color::Always,
+                    "never" => Color::Never,
+                    color => {
+                        return Err(CliError::Usage(format!("unknown color '{}'", color)));
+                    }
+                }
+            }
+            "--check" if command == Command::Fmt => options.check = true,
+            "-h" | "--help" => options.command = Command::Help,
+            "-" => options.paths.push(arg),
+            flag if flag.starts_with('-') => {
+                return Err(CliError::Usage(format!("unknown option '{}'", flag)));
+            }
+            _ => options.paths.push(arg),
+        }
+    }
+    Ok(options)
+}
+
+fn read_inputs(paths: &[String], stdin: &mut dyn Read) -> Result<Vec<Input>, CliError> {
+    if paths.is_empty() {
+        return read_inputs(&["-".to_string()], stdin);
+    }
+    let mut inputs = Vec::new();
+    for path in paths {
+        if path == "-" {
+            let mut source = String::new();
+            stdin
+                .read_to_string(&mut source)
+                .map_err(|source| CliError::Io {
+                    path: "<stdin>".to_string(),
+                    source,
+                })?;
+            inputs.push(Input {
+                name: "<stdin>".to_string(),
+                file: None,
+                source,
+            });
+            continue;
+        }
+        let mut files = Vec::new();
+        find_templates(Path::new(path), true, &mut files)?;
+        for file in files {
+            let source = fs::read_to_string(&file).map_err(|source| io_error(&file, source))?;
+            inputs.push(Input {
+                name: file.display().to_string(),
+                file: Some(file),
+                source,
+            });
+        }
+    }
+    Ok(inputs)
+}
+
+/// Collect `path` if it is a file, or the templates under it if it is a directory.
+/// Files named explicitly are kept whatever their extension.
+fn find_templates(path: &Path, explicit: bool, files: &mut Vec<PathBuf>) -> Result<(), CliError> {
+    if !path.is_dir() {
+        let is_template = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| TEMPLATE_EXTENSIONS.contains(&extension));
+        if explicit || is_template {
+            files.push(path.to_path_buf());
+        }
+        return Ok(());
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect()
+        })
+        .map_err(|source| io_error(path, source))?;
+    entries.sort();
+    for entry in entries {
+        let hidden = entry
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if !hidden {
+            find_templates(&entry, false, files)?;
+        }
+    }
+    Ok(())
+}
+
+fn io_error(path: &Path, source: io::Error) -> CliError {
+    CliError::Io {
+        path: path.display().to_string(),
+        source,
+    }
+}
+
+/// Run the command on every input. Returns whether all of them succeeded.
+fn execute(
+    options: &Options,
+    inputs: &[Input],
+    color: bool,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> Result<bool, CliError> {
+    let renderer = if color {
+        Renderer::styled()
+    } else {
+        Renderer::plain()
+    };
+    let write_error = |source| CliError::Io {
+        path: "<stdout>".to_string(),
+        source,
+    };
+    let mut success = true;
+    let (mut errors, mut warnings) = (0, 0);
+    for input in inputs {
+        let header = inputs.len() > 1 && options.format == Format::Text;
+        let diagnostics = match options.command {
+            Command::Tokens => tokens(input, options.format, header, out),
+            Command::Ast => ast(input, options.format, header, out),
+            Command::Check => check(input, options.format, &renderer, out),
+            Command::Fmt => fmt(input, options.check, out, err),
+            Command::Help => Ok(Vec::new()),
+        }
+        .map_err(write_error)?;
+        for diagnostic in &diagnostics {
+            if diagnostic.is_error() {
+                errors += 1;
+                success = false;
+            } else {
+                warnings += 1;
+            }
+            if options.command != Command::Check {
+                let report = renderer.render(diagnostic, &input.source, &input.name);
+                writeln!(err, "{}", report).map_err(write_error)?;
+            }
+        }
+        if options.command == Command::Fmt && options.check {
+            success &= diagnostics.is_empty() && format_template(&input.source) == input.source;
+        }
+    }
+    if options.command == Command::Check && options.format == Format::Text {
+        writeln!(
+            err,
+            "checked {} {}: {} {}, {} {}",
+            inputs.len(),
+            plural(inputs.len(), "template"),
+            errors,
+            plural(errors, "error"),
+            warnings,
+            plural(warnings, "warning")
+        )
+        .map_err(write_error)?;
+    }
+    Ok(success)
+}
+
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 {
+        word.to_string()
+    } else {
+        format!("{}s", word)
+    }
+}
+
+fn tokens(
+    input: &Input,
+    format: Format,
+    header: bool,
+    out: &mut dyn Write,
+) -> io::Result<Vec<Diagnostic>> {
+    let source_map = SourceMap::new(&input.source);
+    let (tokens, diagnostics) = Lexer::lossless(&input.source).tokenize_resilient();
+    match format {
+        Format::Text => {
+            if header {
+                writeln!(out, "==> {} <==", input.name)?;
+            }
+            for token in tokens.iter() {
+                let position = source_map.position(token.span.start);
+                writeln!(
+                    out,
+                    "{}:{}\t{:?}\t{:?}",
+                    position.line, position.column, token.token_type, token.lexeme
+                )?;
+            }
+        }
+        Format::Json => {
+            let document = json!({
+                "version": SCHEMA_VERSION,
+                "path": input.name,
+                "tokens": &*tokens,
+                "diagnostics": diagnostics_json(&source_map, &diagnostics),
+            });
+            writeln!(out, "{}", document)?;
+        }
+    }
+    Ok(diagnostics)
+}
+
+fn ast(
+    input: &Input,
+    format: Format,
+    header: bool,
+    out: &mut dyn Write,
+) -> io::Result<Vec<Diagnostic>> {
+    let (ast, diagnostics) = crate::parse_resilient(&input.source);
+    match format {
+        Format::Text => {
+            if header {
+                writeln!(out, "==> {} <==", input.name)?;
+            }
+            writeln!(out, "{:#?}", ast.nodes)?;
+        }
+        Format::Json => {
+            let source_map = SourceMap::new(&input.source);
+            let document = json!({
+                "version": SCHEMA_VERSION,
+                "path": input.name,
+                "ast": ast,
+                "diagnostics": diagnostics_json(&source_map, &diagnostics),
+            });
+            writeln!(out, "{}", document)?;
+        }
+    }
+    Ok(diagnostics)
+}
+
+fn check(
+    input: &Input,
+    format: Format,
+    renderer: &Renderer,
+    out: &mut dyn Write,
+) -> io::Result<Vec<Diagnostic>> {
+    let (_, diagnostics) = crate::parse_resilient(&input.source);
+    match format {
+        Format::Text => {
+            for diagnostic in &diagnostics {
+                writeln!(
+                    out,
+                    "{}",
+                    renderer.render(diagnostic, &input.source, &input.name)
+                )?;
+            }
+        }
+        Format::Json => {
+            let source_map = SourceMap::new(&input.source);
+            let document = json!({
+                "version": SCHEMA_VERSION,
+                "path": input.name,
+                "diagnostics": diagnostics_json(&source_map, &diagnostics),
+            });
+            writeln!(out, "{}", document)?;
+        }
+    }
+    Ok(diagnostics)
+}
+
+/// Reformat a template, or with `check` list it if it would change. Templates with
+/// errors are left alone.
+fn fmt(
+    input: &Input,
+    check: bool,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> io::Result<Vec<Diagnostic>> {
+    let (_, diagnostics) = crate::parse_resilient(&input.source);
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return Ok(diagnostics);
+    }
+    let formatted = format_template(&input.source);
+    match &input.file {
+        _ if check => {
+            if formatted != input.source {
+                writeln!(out, "{}", input.name)?;
+            }
+        }
+        Some(file) if formatted != input.source => {
+            fs::write(file, formatted)?;
+            writeln!(err, "formatted {}", input.name)?;
+        }
+        Some(_) => {}
+        None => write!(out, "{}", formatted)?,
+    }
+    Ok(diagnostics)
+}
+
+/// Put exactly one space between the delimiters and the contents of every variable
+/// and tag, and between the parts of a tag: `{%if  a%}` becomes `{% if a %}`.
+/// Everything else, comments included, is left as written.
+fn format_template(source: &str) -> String {
+    let (tokens, _) = Lexer::lossless(source).tokenize_resilient();
+    let mut output = String::with_capacity(source.len());
+    let mut inside = false;
+    let mut space = false;
+    for token in tokens.iter() {
+        match token.token_type {
+            TokenType::DoubleLeftBrace | TokenType::LeftBracePercent if !inside => {
+                output.push_str(&token.lexeme);
+                inside = true;
+                space = true;
+            }
+            TokenType::DoubleRightBrace | TokenType::PercentRightBrace if inside => {
+                output.push(' ');
+                output.push_str(&token.lexeme);
+                inside = false;
+            }
+            TokenType::Whitespace if inside => space = true,
+            _ if inside => {
+                if space {
+                    output.push(' ');
+                    space = false;
+                }
+                output.push_str(&token.lexeme);
+            }
+            _ => output.push_str(&token.lexeme),
+        }
+    }
+    output
+}
+
+/// Serialized diagnostics, each with the line and column of its primary span added
+/// for tools that do not want to count bytes.
+fn diagnostics_json(source_map: &SourceMap, diagnostics: &[Diagnostic]) -> Value {
+    diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let position = source_map.position(diagnostic.span().start);
+            let mut value = json!(diagnostic);
+            value["position"] = json!({"line": position.line, "column": position.column});
+            value
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn stdin(source: &str) -> Vec<Input> {
+        vec![Input {
+            name: "<stdin>".to_string(),
+            file: None,
+            source: source.to_string(),
+        }]
+    }
+
+    fn execute_to_string(command: &[&str], inputs: &[Input]) -> (bool, String, String) {
+        let options = parse_args(args(command)).unwrap();
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let success = execute(&options, inputs, false, &mut out, &mut err).unwrap();
+        (
+            success,
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse_args(args(&["check", "--format=json", "templates", "-"])).unwrap();
+        assert_eq!(
+            options,
+            Options {
+                command: Command::Check,
+                format: Format::Json,
+                color: Color::Auto,
+                check: false,
+                paths: args(&["templates", "-"]),
+            }
+        );
+        let options = parse_args(args(&["fmt", "--check", "--color", "never"])).unwrap();
+        assert!(options.check);
+        assert_eq!(options.color, Color::Never);
+
+        let test_cases = vec![
+            vec![],
+            vec!["lint"],
+            vec!["check", "--format"],
+            vec!["check", "--format", "xml"],
+            vec!["check", "--check"],
+            vec!["ast", "--verbose"],
+        ];
+        for input in test_cases {
+            println!("Testing input: {:?}", input);
+
+            assert!(matches!(parse_args(args(&input)), Err(CliError::Usage(_))));
+        }
+    }
+
+    #[test]
+    fn test_format_template() {
+        let test_cases = vec![
+            ("{{x}}", "{{ x }}"),
+            ("{{  x|default:'a  b'  }}", "{{ x|default:'a  b' }}"),
+            (
+                "{%if  a   and b%}c{%  endif %}",
+                "{% if a and b %}c{% endif %}",
+            ),
+            ("{#  kept  as is #}", "{#  kept  as is #}"),
+            (
+                "<p  class=\"{{a}}\">  text  </p>",
+                "<p  class=\"{{ a }}\">  text  </p>",
+            ),
+            (
+                "{% if a %}\n  {{ b }}\n{% endif %}",
+                "{% if a %}\n  {{ b }}\n{% endif %}",
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            println!("Testing input: {:?}", input);
+
+            assert_eq!(format_template(input), expected);
+        }
+    }
+
+    #[test]
+    fn test_tokens() {
+        let (success, out, _) = execute_to_string(&["tokens"], &stdin("a{{ b }}"));
+        assert!(success);
+        assert_eq!(
+            out,
+            "1:1\tText\t\"a\"\n\
+             1:2\tDoubleLeftBrace\t\"{{\"\n\
+             1:4\tWhitespace\t\" \"\n\
+             1:5\tIdentifier\t\"b\"\n\
+             1:6\tWhitespace\t\" \"\n\
+             1:7\tDoubleRightBrace\t\"}}\"\n\
+             1:9\tEof\t\"\"\n"
+        );
+    }
+
+    #[test]
+    fn test_ast_json() {
+        let (success, out, _) = execute_to_string(
+            &["ast", "--format", "json"],
+            &stdin("{% if a %}<b>{{ c|upper }}</b>{% endif %}"),
+        );
+        let document: Value = serde_json::from_str(&out).unwrap();
+
+        assert!(success);
+        assert_eq!(document["version"], SCHEMA_VERSION);
+        assert_eq!(document["path"], "<stdin>");
+        let ast: crate::Ast = serde_json::from_value(document["ast"].clone()).unwrap();
+        assert_eq!(ast.to_string(), "{% if a %}<b>{{ c|upper }}</b>{% endif %}");
+        assert_eq!(document["diagnostics"], json!([]));
+    }
+
+    #[test]
+    fn test_check() {
+        let source = "{% if a %}\n%}";
+        let (success, out, err) = execute_to_string(&["check"], &stdin(source));
+        assert!(!success);
+        assert!(out.starts_with("warning[E104]: unexpected '%}'\n --> <stdin>:2:1\n"));
+        assert!(out.contains("error[E102]: unclosed block tag 'if'\n --> <stdin>:2:3\n"));
+        assert_eq!(err, "checked 1 template: 1 error, 1 warning\n");
+
+        let (success, out, _) = execute_to_string(&["check", "--format", "json"], &stdin(source));
+        let document: Value = serde_json::from_str(&out).unwrap();
+        assert!(!success);
+        assert_eq!(document["diagnostics"][1]["code"], "E102");
+        assert_eq!(
+            document["diagnostics"][1]["labels"][0]["span"],
+            json!({"start": 13, "end": 13})
+        );
+        assert_eq!(
+            document["diagnostics"][1]["labels"][1]["message"],
+            "'if' opened here"
+        );
+        assert_eq!(
+            document["diagnostics"][0]["position"],
+            json!({"line": 2, "column": 1})
+        );
+
+        let (success, _, err) = execute_to_string(&["check"], &stdin("a %} b"));
+        assert!(success);
+        assert_eq!(err, "checked 1 template: 0 errors, 1 warning\n");
+    }
+
+    #[test]
+    fn test_fmt() {
+        let (success, out, _) = execute_to_string(&["fmt"], &stdin("{{x}}"));
+        assert!(success);
+        assert_eq!(out, "{{ x }}");
+
+        let (success, out, _) = execute_to_string(&["fmt", "--check"], &stdin("{{x}}"));
+        assert!(!success);
+        assert_eq!(out, "<stdin>\n");
+
+        let (success, out, err) = execute_to_string(&["fmt"], &stdin("{{x"));
+        assert!(!success);
+        assert_eq!(out, "");
+        assert!(err.starts_with("error[E101]: unclosed tag"));
+    }
+
+    #[test]
+    fn test_read_inputs() {
+        let directory = std::env::temp_dir().join(format!("cli-test-{}", std::process::id()));
+        fs::create_dir_all(directory.join("nested")).unwrap();
+        fs::create_dir_all(directory.join(".hidden")).unwrap();
+        fs::write(directory.join("b.html"), "b").unwrap();
+        fs::write(directory.join("nested/a.txt"), "a").unwrap();
+        fs::write(directory.join("notes.md"), "skipped").unwrap();
+        fs::write(directory.join(".hidden/c.html"), "skipped").unwrap();
+
+        let paths = vec![directory.display().to_string(), "-".to_string()];
+        let inputs = read_inputs(&paths, &mut "from stdin".as_bytes()).unwrap();
+        let sources: Vec<&str> = inputs.iter().map(|input| input.source.as_str()).collect();
+        assert_eq!(sources, vec!["b", "a", "from stdin"]);
+        assert_eq!(inputs[2].name, "<stdin>");
+
+        let missing = directory.join("missing.html").display().to_string();
+        assert!(matches!(
+            read_inputs(&[missing], &mut io::empty()),
+            Err(CliError::Io { .. })
+        ));
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
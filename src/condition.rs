@@ -0,0 +1,321 @@
+use crate::ast::{FilterExpression, Tag};
+use crate::builtins::{filter_expression, invalid, split_unquoted};
+use crate::error::ParserError;
+use std::fmt;
+
+/// The condition of an `{% if %}` or `{% elif %}` tag.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Condition {
+    Operand(FilterExpression),
+    Not(Box<Condition>),
+    Binary {
+        operator: Operator,
+        left: Box<Condition>,
+        right: Box<Condition>,
+    },
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Operand(operand) => write!(f, "{}", operand),
+            Condition::Not(operand) => write!(f, "not {}", operand),
+            Condition::Binary {
+                operator,
+                left,
+                right,
+            } => write!(f, "{} {} {}", left, operator, right),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Operator {
+    Or,
+    And,
+    In,
+    NotIn,
+    Is,
+    IsNot,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+impl Operator {
+    fn from_bit(bit: &str) -> Option<Operator> {
+        let operator = match bit {
+            "or" => Operator::Or,
+            "and" => Operator::And,
+            "in" => Operator::In,
+            "is" => Operator::Is,
+            "==" => Operator::Equal,
+            "!=" => Operator::NotEqual,
+            ">" => Operator::Greater,
+            ">=" => Operator::GreaterEqual,
+            "<" => Operator::Less,
+            "<=" => Operator::LessEqual,
+            _ => return None,
+        };
+        Some(operator)
+    }
+
+    /// Binding power, matching the precedence table in `django/template/smartif.py`.
+    fn binding_power(self) -> u8 {
+        match self {
+            Operator::Or => 6,
+            Operator::And => 7,
+            Operator::In | Operator::NotIn => 9,
+            Operator::Is
+            | Operator::IsNot
+            | Operator::Equal
+            | Operator::NotEqual
+            | Operator::Greater
+            | Operator::GreaterEqual
+            | Operator::Less
+            | Operator::LessEqual => 10,
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operator = match self {
+            Operator::Or => "or",
+            Operator::And => "and",
+            Operator::In => "in",
+            Operator::NotIn => "not in",
+            Operator::Is => "is",
+            Operator::IsNot => "is not",
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+            Operator::Greater => ">",
+            Operator::GreaterEqual => ">=",
+            Operator::Less => "<",
+            Operator::LessEqual => "<=",
+        };
+        write!(f, "{}", operator)
+    }
+}
+
+/// `not` binds tighter than `and` but looser than `in` and the comparisons.
+const NOT_BINDING_POWER: u8 = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Item {
+    Operand(String),
+    Operator(Operator),
+    Not,
+    End,
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Item::Operand(bit) => write!(f, "{}", bit),
+            Item::Operator(operator) => write!(f, "{}", operator),
+            Item::Not => write!(f, "not"),
+            Item::End => write!(f, "end of expression"),
+        }
+    }
+}
+
+/// Parse the bits of an `{% if %}` tag the way Django's `IfParser` does: a top down
+/// operator precedence parser where every bit is either an operator or an operand.
+pub(crate) fn parse_condition(tag: &Tag) -> Result<Condition, ParserError> {
+    let mut parser = ConditionParser {
+        tag,
+        items: items(&tag.bits),
+        current: 0,
+    };
+    let condition = parser.expression(0)?;
+    match parser.peek() {
+        Item::End => Ok(condition),
+        item => Err(invalid(
+            tag,
+            &format!("unused '{}' at end of if expression", item),
+        )),
+    }
+}
+
+/// Group `not in` and `is not` into single operators.
+fn items(bits: &[String]) -> Vec<Item> {
+    let mut items = Vec::new();
+    let mut bits = bits.iter().peekable();
+    while let Some(bit) = bits.next() {
+        let item = match bit.as_str() {
+            "not" if bits.next_if(|next| *next == "in").is_some() => {
+                Item::Operator(Operator::NotIn)
+            }
+            "is" if bits.next_if(|next| *next == "not").is_some() => {
+                Item::Operator(Operator::IsNot)
+            }
+            "not" => Item::Not,
+            bit => Operator::from_bit(bit)
+                .map_or_else(|| Item::Operand(bit.to_string()), Item::Operator),
+        };
+        items.push(item);
+    }
+    items
+}
+
+struct ConditionParser<'a> {
+    tag: &'a Tag,
+    items: Vec<Item>,
+    current: usize,
+}
+
+impl ConditionParser<'_> {
+    fn expression(&mut self, binding_power: u8) -> Result<Condition, ParserError> {
+        let mut left = self.prefix()?;
+        loop {
+            match self.peek() {
+                Item::Operator(operator) if operator.binding_power() > binding_power => {
+                    self.current += 1;
+                    let right = self.expression(operator.binding_power())?;
+                    left = Condition::Binary {
+                        operator,
+                        left: Box::new(left),
+                        right: Box::new(right),
+                    };
+                }
+                Item::Not if NOT_BINDING_POWER > binding_power => {
+                    return Err(invalid(
+                        self.tag,
+                        "not expecting 'not' as infix operator in if tag",
+                    ));
+                }
+                _ => return Ok(left),
+            }
+        }
+    }
+
+    fn prefix(&mut self) -> Result<Condition, ParserError> {
+        let item = self.peek();
+        self.current += 1;
+        match item {
+            Item::Operand(bit) => self.operand(&bit),
+            Item::Not => {
+                let operand = self.expression(NOT_BINDING_POWER)?;
+                Ok(Condition::Not(Box::new(operand)))
+            }
+            Item::Operator(operator) => Err(invalid(
+                self.tag,
+                &format!("not expecting '{}' in this position in if tag", operator),
+            )),
+            Item::End => Err(invalid(self.tag, "unexpected end of expression in if tag")),
+        }
+    }
+
+    fn operand(&self, bit: &str) -> Result<Condition, ParserError> {
+        // Django has no grouping in `if` expressions, so parentheses would otherwise
+        // end up inside a variable name.
+        if split_unquoted(bit, '(').len() > 1 || split_unquoted(bit, ')').len() > 1 {
+            return Err(invalid(
+                self.tag,
+                "parentheses are not supported in if expressions",
+            ));
+        }
+        Ok(Condition::Operand(filter_expression(self.tag, bit)?))
+    }
+
+    fn peek(&self) -> Item {
+        self.items.get(self.current).cloned().unwrap_or(Item::End)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::span::Span;
+
+    fn condition_tag(source: &str) -> Tag {
+        let bits: Vec<String> = split_unquoted(source, ' ')
+            .into_iter()
+            .map(String::from)
+            .collect();
+        Tag {
+            name: "if".to_string(),
+            trivia: vec![" ".to_string(); bits.len() + 2],
+            bits,
+            line: 1,
+            span: Span::default(),
+        }
+    }
+
+    /// Print the tree with explicit grouping so precedence is visible.
+    fn grouped(condition: &Condition) -> String {
+        match condition {
+            Condition::Operand(operand) => operand.to_string(),
+            Condition::Not(operand) => format!("(not {})", grouped(operand)),
+            Condition::Binary {
+                operator,
+                left,
+                right,
+            } => format!("({} {} {})", grouped(left), operator, grouped(right)),
+        }
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        let test_cases = vec![
+            ("a", "a"),
+            ("a or b and c", "(a or (b and c))"),
+            ("a and b or c", "((a and b) or c)"),
+            ("a or b or c", "((a or b) or c)"),
+            ("not a and b", "((not a) and b)"),
+            ("not a == b", "(not (a == b))"),
+            ("not not a", "(not (not a))"),
+            ("a not in b or c", "((a not in b) or c)"),
+            ("a in b == c", "(a in (b == c))"),
+            ("a is not None", "(a is not None)"),
+            ("x|length >= 2 and y", "((x|length >= 2) and y)"),
+            ("'a b' in c", "('a b' in c)"),
+            ("a < b <= c", "((a < b) <= c)"),
+            ("a != 'x'", "(a != 'x')"),
+        ];
+
+        for (input, expected) in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let condition = parse_condition(&condition_tag(input)).unwrap();
+            assert_eq!(grouped(&condition), expected);
+            assert_eq!(condition.to_string(), input);
+        }
+    }
+
+    #[test]
+    fn test_parse_condition_errors() {
+        let test_cases = vec![
+            "and",
+            "a and",
+            "a b",
+            "not",
+            "a == == b",
+            "a not b",
+            "(a or b) and c",
+            "a and (b)",
+            "a|",
+            "in a",
+        ];
+
+        for input in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let result = parse_condition(&condition_tag(input));
+            assert!(
+                matches!(result, Err(ParserError::InvalidArguments { .. })),
+                "Expected an error for input: {}, got {:?}",
+                input,
+                result
+            );
+        }
+    }
+}
@@ -0,0 +1,568 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Month abbreviations in Associated Press style.
+const MONTHS_AP: [&str; 12] = [
+    "Jan.", "Feb.", "March", "April", "May", "June", "July", "Aug.", "Sept.", "Oct.", "Nov.",
+    "Dec.",
+];
+
+/// Starting from Sunday, as the `w` format counts.
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+/// A calendar date, like Python's `datetime.date`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    year: i32,
+    month: u32,
+    day: u32,
+}
+
+impl Date {
+    /// The date, or `None` if there is no such day.
+    pub fn new(year: i32, month: u32, day: u32) -> Option<Date> {
+        let valid = (1..=9999).contains(&year)
+            && (1..=12).contains(&month)
+            && (1..=days_in_month(year, month)).contains(&day);
+        valid.then_some(Date { year, month, day })
+    }
+
+    pub fn year(&self) -> i32 {
+        self.year
+    }
+
+    pub fn month(&self) -> u32 {
+        self.month
+    }
+
+    pub fn day(&self) -> u32 {
+        self.day
+    }
+
+    /// Days since 1970-01-01, negative before it.
+    fn days(&self) -> i64 {
+        // Howard Hinnant's `days_from_civil`, with years starting in March
+        let year = i64::from(self.year) - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = i64::from(self.month);
+        let day_of_year =
+            (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146097 + day_of_era - 719468
+    }
+
+    fn from_days(days: i64) -> Date {
+        let days = days + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days - era * 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        } as u32;
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        Date {
+            year: year as i32,
+            month,
+            day,
+        }
+    }
+
+    /// The day of the week, from 0 for Sunday to 6 for Saturday.
+    fn weekday(&self) -> usize {
+        // 1970-01-01 was a Thursday
+        (self.days() + 4).rem_euclid(7) as usize
+    }
+
+    /// The day of the year, from 1.
+    fn ordinal(&self) -> u32 {
+        (1..self.month)
+            .map(|month| days_in_month(self.year, month))
+            .sum::<u32>()
+            + self.day
+    }
+
+    /// The ISO 8601 year and week number.
+    fn iso_week(&self) -> (i32, u32) {
+        // Weeks start on Monday, and the first week holds the year's first Thursday
+        let monday_weekday = (self.weekday() + 6) % 7;
+        let week = (self.ordinal() as i64 - monday_weekday as i64 + 9) / 7;
+        if week < 1 {
+            (self.year - 1, iso_weeks_in_year(self.year - 1))
+        } else if week as u32 > iso_weeks_in_year(self.year) {
+            (self.year + 1, 1)
+        } else {
+            (self.year, week as u32)
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// A time of day, like Python's `datetime.time`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Time {
+    hour: u32,
+    minute: u32,
+    second: u32,
+    microsecond: u32,
+}
+
+impl Time {
+    /// The time, or `None` if a part is out of range.
+    pub fn new(hour: u32, minute: u32, second: u32) -> Option<Time> {
+        (hour < 24 && minute < 60 && second < 60).then_some(Time {
+            hour,
+            minute,
+            second,
+            microsecond: 0,
+        })
+    }
+
+    pub fn with_microsecond(self, microsecond: u32) -> Option<Time> {
+        (microsecond < 1_000_000).then_some(Time {
+            microsecond,
+            ..self
+        })
+    }
+
+    pub fn hour(&self) -> u32 {
+        self.hour
+    }
+
+    pub fn minute(&self) -> u32 {
+        self.minute
+    }
+
+    pub fn second(&self) -> u32 {
+        self.second
+    }
+
+    pub fn microsecond(&self) -> u32 {
+        self.microsecond
+    }
+
+    fn seconds(&self) -> i64 {
+        i64::from(self.hour * 3600 + self.minute * 60 + self.second)
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}:{:02}", self.hour, self.minute, self.second)?;
+        if self.microsecond > 0 {
+            write!(f, ".{:06}", self.microsecond)?;
+        }
+        Ok(())
+    }
+}
+
+/// A date and time without a time zone, like a naive Python `datetime.datetime`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub struct DateTime {
+    pub date: Date,
+    pub time: Time,
+}
+
+impl DateTime {
+    pub fn new(date: Date, time: Time) -> DateTime {
+        DateTime { date, time }
+    }
+
+    /// The current time in UTC.
+    pub fn now() -> DateTime {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let seconds = since_epoch.as_secs() as i64;
+        let of_day = seconds.rem_euclid(86400) as u32;
+        DateTime {
+            date: Date::from_days(seconds.div_euclid(86400)),
+            time: Time {
+                hour: of_day / 3600,
+                minute: of_day / 60 % 60,
+                second: of_day % 60,
+                microsecond: since_epoch.subsec_micros(),
+            },
+        }
+    }
+
+    /// Seconds since 1970-01-01 00:00, ignoring microseconds.
+    fn timestamp(&self) -> i64 {
+        self.date.days() * 86400 + self.time.seconds()
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.date, self.time)
+    }
+}
+
+fn is_leap(year: i32) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn iso_weeks_in_year(year: i32) -> u32 {
+    let january_first = Date {
+        year,
+        month: 1,
+        day: 1,
+    }
+    .weekday();
+    if january_first == 4 || (january_first == 3 && is_leap(year)) {
+        53
+    } else {
+        52
+    }
+}
+
+/// What Django's `dateformat` can format: a date, a time, or both.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Formattable {
+    Date(Date),
+    Time(Time),
+    DateTime(DateTime),
+}
+
+/// Why a format string cannot be applied.
+#[derive(Debug, PartialEq)]
+pub(crate) enum FormatError {
+    /// A time format character used on a date, which Django rejects.
+    TimeOfDate(char),
+    /// A date format character used on a time, or in the `time` filter.
+    DateOfTime(char),
+}
+
+const TIME_FORMATS: &str = "aAefgGhHiOPsTuZ";
+const DATE_FORMATS: &str = "bcdDEFIjlLmMnNorStUwWyYz";
+
+/// Format a date or time the way Django's `dateformat.format` does, e.g. `N j, Y` for
+/// `Jan. 5, 2024`. Dates and times have no time zone, so the time zone formats are
+/// empty, and `r` and `U` treat them as UTC. With `time_only`, as for the `time`
+/// filter, only the time formats are allowed.
+pub(crate) fn format(
+    value: Formattable,
+    format: &str,
+    time_only: bool,
+) -> Result<String, FormatError> {
+    let (date, time) = match value {
+        Formattable::Date(date) => (Some(date), None),
+        Formattable::Time(time) => (None, Some(time)),
+        Formattable::DateTime(datetime) => (Some(datetime.date), Some(datetime.time)),
+    };
+    let mut output = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            output.extend(chars.next());
+        } else if TIME_FORMATS.contains(c) {
+            let time = time.ok_or(FormatError::TimeOfDate(c))?;
+            output.push_str(&format_time(time, c));
+        } else if DATE_FORMATS.contains(c) {
+            let date = date
+                .filter(|_| !time_only)
+                .ok_or(FormatError::DateOfTime(c))?;
+            output.push_str(&format_date(value, date, c));
+        } else {
+            output.push(c);
+        }
+    }
+    Ok(output)
+}
+
+fn format_time(time: Time, c: char) -> String {
+    let hour_12 = match time.hour % 12 {
+        0 => 12,
+        hour => hour,
+    };
+    let meridiem = if time.hour > 11 { "p.m." } else { "a.m." };
+    match c {
+        'a' => meridiem.to_string(),
+        'A' => if time.hour > 11 { "PM" } else { "AM" }.to_string(),
+        'f' if time.minute == 0 => hour_12.to_string(),
+        'f' => format!("{}:{:02}", hour_12, time.minute),
+        'g' => hour_12.to_string(),
+        'G' => time.hour.to_string(),
+        'h' => format!("{:02}", hour_12),
+        'H' => format!("{:02}", time.hour),
+        'i' => format!("{:02}", time.minute),
+        'P' => match (time.hour, time.minute) {
+            (0, 0) => "midnight".to_string(),
+            (12, 0) => "noon".to_string(),
+            _ => format!("{} {}", format_time(time, 'f'), meridiem),
+        },
+        's' => format!("{:02}", time.second),
+        'u' => format!("{:06}", time.microsecond),
+        // The time zone formats: 'e', 'O', 'T' and 'Z'
+        _ => String::new(),
+    }
+}
+
+fn format_date(value: Formattable, date: Date, c: char) -> String {
+    let month = MONTHS[date.month as usize - 1];
+    match c {
+        'b' => month[..3].to_lowercase(),
+        'c' => match value {
+            Formattable::DateTime(datetime) => format!("{}T{}", datetime.date, datetime.time),
+            _ => date.to_string(),
+        },
+        'd' => format!("{:02}", date.day),
+        'D' => WEEKDAYS[date.weekday()][..3].to_string(),
+        'E' | 'F' => month.to_string(),
+        'j' => date.day.to_string(),
+        'l' => WEEKDAYS[date.weekday()].to_string(),
+        'L' => if is_leap(date.year) { "True" } else { "False" }.to_string(),
+        'm' => format!("{:02}", date.month),
+        'M' => month[..3].to_string(),
+        'n' => date.month.to_string(),
+        'N' => MONTHS_AP[date.month as usize - 1].to_string(),
+        'o' => date.iso_week().0.to_string(),
+        'r' => {
+            let time = match value {
+                Formattable::DateTime(datetime) => datetime.time,
+                _ => Time::default(),
+            };
+            format!(
+                "{}, {} {} {:04} {:02}:{:02}:{:02} +0000",
+                &WEEKDAYS[date.weekday()][..3],
+                date.day,
+                &month[..3],
+                date.year,
+                time.hour,
+                time.minute,
+                time.second
+            )
+        }
+        'S' => match date.day {
+            11..=13 => "th",
+            day if day % 10 == 1 => "st",
+            day if day % 10 == 2 => "nd",
+            day if day % 10 == 3 => "rd",
+            _ => "th",
+        }
+        .to_string(),
+        't' => days_in_month(date.year, date.month).to_string(),
+        'U' => match value {
+            Formattable::DateTime(datetime) => datetime.timestamp(),
+            _ => date.days() * 86400,
+        }
+        .to_string(),
+        'w' => date.weekday().to_string(),
+        'W' => date.iso_week().1.to_string(),
+        'y' => format!("{:02}", date.year % 100),
+        'Y' => format!("{:04}", date.year),
+        'z' => date.ordinal().to_string(),
+        // 'I', whether daylight saving time is in effect
+        _ => String::new(),
+    }
+}
+
+const TIME_STRINGS: [(&str, &str); 6] = [
+    ("year", "years"),
+    ("month", "months"),
+    ("week", "weeks"),
+    ("day", "days"),
+    ("hour", "hours"),
+    ("minute", "minutes"),
+];
+
+/// The time between `start` and `end` as Django's `timesince` words it, e.g. `4 days,
+/// 6 hours`, with at most two parts and non-breaking spaces between numbers and units.
+pub(crate) fn timesince(start: DateTime, end: DateTime) -> String {
+    let zero = "0\u{a0}minutes".to_string();
+    if end.timestamp() - start.timestamp() <= 0 {
+        return zero;
+    }
+    let (start_date, end_date) = (start.date, end.date);
+    let mut total_months =
+        (end_date.year - start_date.year) * 12 + end_date.month as i32 - start_date.month as i32;
+    if start_date.day > end_date.day || (start_date.day == end_date.day && start.time > end.time) {
+        total_months -= 1;
+    }
+    let (years, months) = (total_months.div_euclid(12), total_months.rem_euclid(12));
+    // Count the rest from the same day of the month `years` and `months` later
+    let pivot = if total_months > 0 {
+        let mut year = start_date.year + years;
+        let mut month = start_date.month + months as u32;
+        if month > 12 {
+            month -= 12;
+            year += 1;
+        }
+        // Django's table of month lengths gives February 28 days, even in leap years
+        let month_days = if month == 2 {
+            28
+        } else {
+            days_in_month(year, month)
+        };
+        DateTime {
+            date: Date {
+                year,
+                month,
+                day: start_date.day.min(month_days),
+            },
+            time: Time {
+                microsecond: 0,
+                ..start.time
+            },
+        }
+    } else {
+        start
+    };
+    let mut remaining = end.timestamp() - pivot.timestamp();
+    let mut parts = vec![i64::from(years), i64::from(months)];
+    for chunk in [604800, 86400, 3600, 60] {
+        parts.push(remaining / chunk);
+        remaining %= chunk;
+    }
+
+    let words: Vec<String> = parts
+        .iter()
+        .zip(TIME_STRINGS)
+        .skip_while(|(count, _)| **count == 0)
+        .take(2)
+        .take_while(|(count, _)| **count != 0)
+        .map(|(count, (singular, plural))| {
+            let unit = if *count == 1 { singular } else { plural };
+            format!("{}\u{a0}{}", count, unit)
+        })
+        .collect();
+    if words.is_empty() {
+        zero
+    } else {
+        words.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime {
+        DateTime::new(
+            Date::new(year, month, day).unwrap(),
+            Time::new(hour, minute, 0).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_calendar() {
+        let test_cases = vec![
+            ((1970, 1, 1), 0, 4, (1970, 1)),
+            ((2000, 2, 29), 11016, 2, (2000, 9)),
+            ((2005, 1, 1), 12784, 6, (2004, 53)),
+            ((2008, 12, 29), 14242, 1, (2009, 1)),
+            ((1969, 12, 31), -1, 3, (1970, 1)),
+        ];
+
+        for ((year, month, day), days, weekday, iso_week) in test_cases {
+            println!("Testing input: {:?}", (year, month, day));
+            let date = Date::new(year, month, day).unwrap();
+            assert_eq!(date.days(), days);
+            assert_eq!(Date::from_days(days), date);
+            assert_eq!(date.weekday(), weekday);
+            assert_eq!(date.iso_week(), iso_week);
+        }
+        assert_eq!(Date::new(2023, 2, 29), None);
+        assert_eq!(Time::new(24, 0, 0), None);
+    }
+
+    #[test]
+    fn test_format() {
+        let value = Formattable::DateTime(DateTime::new(
+            Date::new(2003, 10, 9).unwrap(),
+            Time::new(15, 5, 7).unwrap().with_microsecond(1200).unwrap(),
+        ));
+
+        let test_cases = vec![
+            ("N j, Y, P", "Oct. 9, 2003, 3:05 p.m."),
+            (
+                "D d M y, l jS F, H:i:s.u",
+                "Thu 09 Oct 03, Thursday 9th October, 15:05:07.001200",
+            ),
+            ("c", "2003-10-09T15:05:07.001200"),
+            ("r", "Thu, 9 Oct 2003 15:05:07 +0000"),
+            ("U w W z t L o", "1065711907 4 41 282 31 False 2003"),
+            (
+                "a A f g G h b n m e O T Z I",
+                "p.m. PM 3:05 3 15 03 oct 10 10     ",
+            ),
+            ("\\Y\\e\\s Y", "Yes 2003"),
+        ];
+
+        for (input, expected) in test_cases {
+            println!("Testing input: {:?}", input);
+            assert_eq!(format(value, input, false).unwrap(), expected);
+        }
+
+        let date = Formattable::Date(Date::new(2024, 1, 1).unwrap());
+        assert_eq!(format(date, "jS N", false).unwrap(), "1st Jan.");
+        assert_eq!(format(date, "H", false), Err(FormatError::TimeOfDate('H')));
+        let time = Formattable::Time(Time::new(0, 0, 0).unwrap());
+        assert_eq!(format(time, "P", true).unwrap(), "midnight");
+        assert_eq!(format(value, "Y", true), Err(FormatError::DateOfTime('Y')));
+    }
+
+    #[test]
+    fn test_timesince() {
+        let start = datetime(2023, 1, 31, 10, 0);
+
+        let test_cases = vec![
+            (datetime(2023, 1, 31, 10, 0), "0\u{a0}minutes"),
+            (datetime(2023, 1, 31, 10, 1), "1\u{a0}minute"),
+            (datetime(2023, 2, 2, 16, 30), "2\u{a0}days, 6\u{a0}hours"),
+            (datetime(2023, 2, 28, 10, 0), "4\u{a0}weeks"),
+            (datetime(2023, 3, 31, 9, 0), "1\u{a0}month, 4\u{a0}weeks"),
+            (datetime(2025, 2, 1, 10, 0), "2\u{a0}years"),
+            (datetime(2022, 1, 1, 0, 0), "0\u{a0}minutes"),
+        ];
+
+        for (end, expected) in test_cases {
+            println!("Testing input: {:?}", end);
+            assert_eq!(timesince(start, end), expected);
+        }
+    }
+}
@@ -0,0 +1,504 @@
+use crate::error::{LexerError, ParserError};
+use crate::parser::closing_delimiter;
+use crate::span::{SourceMap, Span};
+use std::borrow::Cow;
+use std::fmt;
+
+/// How serious a diagnostic is. Only errors make a template fail to compile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A span of source pointed at by a diagnostic. The primary label marks where the
+/// problem is; secondary labels add context, such as the tag a block was opened by.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    pub primary: bool,
+}
+
+impl Label {
+    pub fn primary(span: Span, message: impl Into<String>) -> Self {
+        Label {
+            span,
+            message: message.into(),
+            primary: true,
+        }
+    }
+
+    pub fn secondary(span: Span, message: impl Into<String>) -> Self {
+        Label {
+            span,
+            message: message.into(),
+            primary: false,
+        }
+    }
+}
+
+/// A problem found in a template.
+///
+/// Codes are stable so tools can match on them:
+///
+/// | Code | Problem                                        |
+/// |------|------------------------------------------------|
+/// | E002 | unexpected character                           |
+/// | E003 | unterminated string literal                    |
+/// | E100 | internal error                                 |
+/// | E101 | unclosed tag                                   |
+/// | E102 | unclosed block tag                             |
+/// | E103 | unexpected tag                                 |
+/// | E104 | unexpected closing delimiter (a warning)       |
+/// | E105 | empty tag                                      |
+/// | E106 | empty variable                                 |
+/// | E107 | invalid tag arguments                          |
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Diagnostic {
+    pub code: Cow<'static, str>,
+    pub severity: Severity,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic {
+            code: Cow::Borrowed(code),
+            severity: Severity::Error,
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(code, message)
+        }
+    }
+
+    pub fn with_label(mut self, label: Label) -> Self {
+        self.labels.push(label);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// The span of the primary label, or of the first label if none is primary.
+    pub fn span(&self) -> Span {
+        self.labels
+            .iter()
+            .find(|label| label.primary)
+            .or(self.labels.first())
+            .map_or(Span::default(), |label| label.span)
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Move every label `offset` bytes, after an edit before the diagnostic.
+    pub(crate) fn shift(&mut self, offset: isize) {
+        for label in &mut self.labels {
+            label.span = label.span.shift(offset);
+        }
+    }
+
+    pub(crate) fn from_lexer_error(error: &LexerError, span: Span) -> Self {
+        match error {
+            LexerError::UnexpectedCharacter { character, .. } => {
+                Diagnostic::error("E002", format!("unexpected character '{}'", character))
+                    .with_label(Label::primary(span, "this character cannot start a token"))
+            }
+            LexerError::UnterminatedString { .. } => {
+                Diagnostic::error("E003", "unterminated string literal")
+                    .with_label(Label::primary(span, "string starts here"))
+                    .with_help("close the string with the same quote it was opened with")
+            }
+            error => Diagnostic::error("E100", error.to_string())
+                .with_label(Label::primary(span, ""))
+                .with_note("this is a bug in the template parser"),
+        }
+    }
+
+    pub(crate) fn from_parser_error(error: &ParserError, span: Span) -> Self {
+        match error {
+            ParserError::UnclosedTag { expected, .. } => {
+                let delimiter = closing_delimiter(*expected).unwrap_or("a closing delimiter");
+                Diagnostic::error("E101", "unclosed tag")
+                    .with_label(Label::primary(
+                        span,
+                        format!("expected '{}' after this", delimiter),
+                    ))
+                    .with_help(format!("add '{}'", delimiter))
+            }
+            ParserError::UnclosedBlock { name, .. } => {
+                Diagnostic::error("E102", format!("unclosed block tag '{}'", name))
+                    .with_label(Label::primary(span, "expected the end tag here"))
+                    .with_help("add the matching end tag")
+            }
+            ParserError::UnexpectedTag { name, .. } => {
+                Diagnostic::error("E103", format!("unexpected tag '{}'", name))
+                    .with_label(Label::primary(span, "no open block accepts this tag"))
+            }
+            ParserError::UnexpectedDelimiter { delimiter, .. } => {
+                let diagnostic = Diagnostic::warning("E104", format!("unexpected '{}'", delimiter))
+                    .with_label(Label::primary(span, "nothing is open here"))
+                    .with_note("Django outputs it as text");
+                match templatetag(delimiter) {
+                    Some(name) => diagnostic.with_help(format!(
+                        "use '{{% templatetag {} %}}' to output it on purpose",
+                        name
+                    )),
+                    None => diagnostic,
+                }
+            }
+            ParserError::EmptyTag { .. } => Diagnostic::error("E105", "empty tag")
+                .with_label(Label::primary(span, "expected a tag name")),
+            ParserError::EmptyVariable { .. } => Diagnostic::error("E106", "empty variable")
+                .with_label(Label::primary(span, "expected a variable")),
+            ParserError::InvalidArguments { name, reason, .. } => {
+                Diagnostic::error("E107", format!("invalid arguments to '{}'", name))
+                    .with_label(Label::primary(span, reason.as_str()))
+            }
+            ParserError::LexerError(error) => Diagnostic::from_lexer_error(error, span),
+            error => Diagnostic::error("E100", error.to_string())
+                .with_label(Label::primary(span, ""))
+                .with_note("this is a bug in the template parser"),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// The `{% templatetag %}` argument that outputs `delimiter`.
+fn templatetag(delimiter: &str) -> Option<&'static str> {
+    match delimiter {
+        "}}" => Some("closevariable"),
+        "%}" => Some("closeblock"),
+        "#}" => Some("closecomment"),
+        _ => None,
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Tabs are shown as this many spaces so underlines line up.
+const TAB_WIDTH: usize = 4;
+
+/// Renders diagnostics as annotated source snippets in the style of rustc:
+///
+/// ```text
+/// error[E102]: unclosed block tag 'if'
+///  --> page.html:2:8
+///   |
+/// 1 | {% if user %}
+///   | ------------- 'if' opened here
+/// 2 |   Hello
+///   |        ^ expected the end tag here
+///   |
+///   = help: add the matching end tag
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Renderer {
+    color: bool,
+}
+
+impl Renderer {
+    pub fn plain() -> Self {
+        Renderer { color: false }
+    }
+
+    /// Highlight the output with ANSI escape codes, for terminals.
+    pub fn styled() -> Self {
+        Renderer { color: true }
+    }
+
+    /// Render `diagnostic` against the `source` it was found in, naming the file
+    /// `path` in the location line.
+    pub fn render(&self, diagnostic: &Diagnostic, source: &str, path: &str) -> String {
+        let source_map = SourceMap::new(source);
+        let severity_style = match diagnostic.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+
+        let mut labels: Vec<(usize, usize, usize, &Label)> = diagnostic
+            .labels
+            .iter()
+            .map(|label| {
+                let start = source_map.position(label.span.start);
+                let end = source_map.position(label.span.end);
+                let line = source_map.line(start.line).unwrap_or("");
+                let start_width = display_width(line, start.column - 1);
+                let end_width = if end.line == start.line {
+                    display_width(line, end.column - 1)
+                } else {
+                    display_width(line, line.chars().count())
+                };
+                (start.line, start_width, end_width, label)
+            })
+            .collect();
+        labels.sort_by_key(|&(line, start, _, label)| (line, start, !label.primary));
+
+        let gutter = labels
+            .iter()
+            .map(|&(line, ..)| line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let padding = " ".repeat(gutter);
+
+        let mut output = format!(
+            "{}{}[{}]{}{}: {}{}\n",
+            self.style(severity_style),
+            diagnostic.severity,
+            diagnostic.code,
+            self.style(RESET),
+            self.style(BOLD),
+            diagnostic.message,
+            self.style(RESET),
+        );
+        let location = source_map.position(diagnostic.span().start);
+        output.push_str(&format!(
+            "{}{}-->{} {}:{}:{}\n",
+            padding,
+            self.style(BLUE),
+            self.style(RESET),
+            path,
+            location.line,
+            location.column
+        ));
+        output.push_str(&self.gutter(&padding, ""));
+
+        let mut previous_line = None;
+        for &(line, start, end, label) in &labels {
+            if previous_line != Some(line) {
+                if previous_line.is_some_and(|previous| line > previous + 1) {
+                    output.push_str(&format!("{}...{}\n", self.style(BLUE), self.style(RESET)));
+                }
+                let text = expand_tabs(source_map.line(line).unwrap_or(""));
+                let number = format!("{:>width$}", line, width = gutter);
+                output.push_str(&self.gutter(&number, &text));
+                previous_line = Some(line);
+            }
+            let (marker, style) = if label.primary {
+                ('^', severity_style)
+            } else {
+                ('-', BLUE)
+            };
+            let mut annotation = format!(
+                "{}{}{}",
+                " ".repeat(start),
+                self.style(style),
+                marker.to_string().repeat(end.saturating_sub(start).max(1))
+            );
+            if !label.message.is_empty() {
+                annotation.push_str(&format!(" {}", label.message));
+            }
+            annotation.push_str(self.style(RESET));
+            output.push_str(&self.gutter(&padding, &annotation));
+        }
+
+        if !diagnostic.notes.is_empty() || diagnostic.help.is_some() {
+            output.push_str(&self.gutter(&padding, ""));
+        }
+        for note in &diagnostic.notes {
+            output.push_str(&self.footer(&padding, "note", note));
+        }
+        if let Some(help) = &diagnostic.help {
+            output.push_str(&self.footer(&padding, "help", help));
+        }
+        output
+    }
+
+    fn gutter(&self, left: &str, text: &str) -> String {
+        let line = format!("{}{} |{}", self.style(BLUE), left, self.style(RESET));
+        if text.is_empty() {
+            format!("{}\n", line)
+        } else {
+            format!("{} {}\n", line, text)
+        }
+    }
+
+    fn footer(&self, padding: &str, kind: &str, message: &str) -> String {
+        format!(
+            "{}{} ={} {}{}:{} {}\n",
+            self.style(BLUE),
+            padding,
+            self.style(RESET),
+            self.style(BOLD),
+            kind,
+            self.style(RESET),
+            message
+        )
+    }
+
+    fn style(&self, code: &'static str) -> &'static str {
+        if self.color {
+            code
+        } else {
+            ""
+        }
+    }
+}
+
+/// The width of the first `chars` characters of `line` once tabs are expanded.
+fn display_width(line: &str, chars: usize) -> usize {
+    line.chars()
+        .take(chars)
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum()
+}
+
+fn expand_tabs(line: &str) -> String {
+    line.replace('\t', &" ".repeat(TAB_WIDTH))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = "<p>\n{% if user %}\n\tHello, {{ user.name }}\n";
+        let diagnostic = Diagnostic::error("E102", "unclosed block tag 'if'")
+            .with_label(Label::primary(
+                Span::new(4, 17),
+                "this block is never closed",
+            ))
+            .with_label(Label::secondary(Span::new(26, 40), "inside this block"))
+            .with_note("blocks must be closed before the end of the template")
+            .with_help("add the matching end tag");
+
+        let expected = "\
+error[E102]: unclosed block tag 'if'
+ --> page.html:2:1
+  |
+2 | {% if user %}
+  | ^^^^^^^^^^^^^ this block is never closed
+3 |     Hello, {{ user.name }}
+  |            -------------- inside this block
+  |
+  = note: blocks must be closed before the end of the template
+  = help: add the matching end tag
+";
+        assert_eq!(
+            Renderer::plain().render(&diagnostic, source, "page.html"),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_render_gap() {
+        let source = "{% for x in y %}\n\n\n\n\n\n\n\n\n{% endif %}";
+        let diagnostic = Diagnostic::error("E103", "unexpected tag 'endif'")
+            .with_label(Label::primary(Span::new(25, 36), ""))
+            .with_label(Label::secondary(Span::new(0, 16), "'for' opened here"));
+
+        let expected = "\
+error[E103]: unexpected tag 'endif'
+  --> page.html:10:1
+   |
+ 1 | {% for x in y %}
+   | ---------------- 'for' opened here
+...
+10 | {% endif %}
+   | ^^^^^^^^^^^
+";
+        assert_eq!(
+            Renderer::plain().render(&diagnostic, source, "page.html"),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_render_styled() {
+        let diagnostic = Diagnostic::warning("E104", "unexpected '%}'")
+            .with_label(Label::primary(Span::new(2, 4), "nothing is open here"));
+        let output = Renderer::styled().render(&diagnostic, "a %} b", "page.html");
+
+        assert!(output.starts_with("\x1b[1;33mwarning[E104]\x1b[0m"));
+        assert!(output.contains("\x1b[1;33m^^ nothing is open here\x1b[0m"));
+    }
+
+    #[test]
+    fn test_from_errors() {
+        let span = Span::new(0, 5);
+        let test_cases = vec![
+            (
+                Diagnostic::from_lexer_error(&LexerError::UnterminatedString { line: 3 }, span),
+                "error[E003]: unterminated string literal",
+            ),
+            (
+                Diagnostic::from_parser_error(
+                    &ParserError::UnclosedBlock {
+                        name: "if".to_string(),
+                        line: 1,
+                    },
+                    span,
+                ),
+                "error[E102]: unclosed block tag 'if'",
+            ),
+            (
+                Diagnostic::from_parser_error(
+                    &ParserError::UnexpectedDelimiter {
+                        delimiter: "}}".to_string(),
+                        line: 1,
+                    },
+                    span,
+                ),
+                "warning[E104]: unexpected '}}'",
+            ),
+            (
+                Diagnostic::from_parser_error(&ParserError::InvalidTokenAccess, span),
+                "error[E100]: invalid token access",
+            ),
+        ];
+
+        for (diagnostic, expected) in test_cases {
+            println!("Testing diagnostic: {:?}", diagnostic);
+
+            assert_eq!(diagnostic.to_string(), expected);
+            assert_eq!(diagnostic.span(), span);
+        }
+    }
+}
@@ -1,16 +1,36 @@
+use crate::diagnostic::Diagnostic;
 use crate::token::TokenType;
 use thiserror::Error;
 
+/// Any error the library reports, so callers can use `?` across lexing, parsing and
+/// compiling.
+#[non_exhaustive]
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Lexer(#[from] LexerError),
+    #[error(transparent)]
+    Parser(#[from] ParserError),
+    #[error(transparent)]
+    Render(#[from] RenderError),
+    /// The first error in a template, located in its source.
+    #[error(transparent)]
+    Diagnostic(Box<Diagnostic>),
+}
+
+impl From<Diagnostic> for Error {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Error::Diagnostic(Box::new(diagnostic))
+    }
+}
+
+#[non_exhaustive]
 #[derive(Error, Debug)]
 pub enum LexerError {
-    #[error("empty token at line {line:?}")]
-    EmptyToken { line: usize },
     #[error("unexpected character '{character}' at line {line}")]
     UnexpectedCharacter { character: char, line: usize },
-    #[error("source is empty")]
-    EmptySource,
-    #[error("at beginning of source")]
-    AtBeginningOfSource,
+    #[error("unterminated string literal at line {line}")]
+    UnterminatedString { line: usize },
     #[error("at end of source")]
     AtEndOfSource,
     #[error("invalid character access")]
@@ -23,20 +43,86 @@ pub enum LexerError {
     TokenError(#[from] TokenError),
 }
 
+#[non_exhaustive]
+#[derive(Error, Debug)]
+pub enum ParserError {
+    #[error("unclosed tag at line {line}, expected '{expected:?}'")]
+    UnclosedTag { expected: TokenType, line: usize },
+    #[error("unclosed block tag '{name}' at line {line}")]
+    UnclosedBlock { name: String, line: usize },
+    #[error("unexpected tag '{name}' at line {line}")]
+    UnexpectedTag { name: String, line: usize },
+    #[error("unexpected '{delimiter}' at line {line}")]
+    UnexpectedDelimiter { delimiter: String, line: usize },
+    #[error("empty tag at line {line}")]
+    EmptyTag { line: usize },
+    #[error("empty variable at line {line}")]
+    EmptyVariable { line: usize },
+    #[error("invalid arguments to '{name}' at line {line}: {reason}")]
+    InvalidArguments {
+        name: String,
+        reason: String,
+        line: usize,
+    },
+    #[error("unexpected token type '{0:?}'")]
+    UnexpectedTokenType(TokenType),
+    #[error("invalid token access")]
+    InvalidTokenAccess,
+    #[error(transparent)]
+    LexerError(#[from] LexerError),
+}
+
+#[non_exhaustive]
+#[derive(Error, Debug)]
+pub enum RenderError {
+    #[error("failed lookup for key [{name}]")]
+    VariableDoesNotExist { name: String },
+    #[error("variables and attributes may not begin with underscores: '{name}'")]
+    Underscore { name: String },
+    #[error("invalid filter: '{name}'")]
+    UnknownFilter { name: String },
+    #[error("{name} requires {expected} arguments, {given} provided")]
+    FilterArguments {
+        name: String,
+        expected: usize,
+        given: usize,
+    },
+    #[error("'{name}' filter failed: {reason}")]
+    Filter { name: String, reason: String },
+    #[error("'{name}' tag at line {line} cannot be rendered")]
+    UnsupportedTag { name: String, line: usize },
+    #[error("'{name}' object is not iterable at line {line}")]
+    NotIterable { name: String, line: usize },
+    #[error("need {expected} values to unpack in for loop at line {line}, got {got}")]
+    Unpack {
+        expected: usize,
+        got: usize,
+        line: usize,
+    },
+    #[error("no cycle named '{name}' at line {line}")]
+    UnknownCycle { name: String, line: usize },
+    #[error("no partial named '{name}' at line {line}")]
+    UnknownPartial { name: String, line: usize },
+    #[error("invalid arguments to '{name}' at line {line}: {reason}")]
+    InvalidArguments {
+        name: String,
+        reason: String,
+        line: usize,
+    },
+    #[error("cannot render a template with syntax errors")]
+    InvalidTemplate,
+}
+
+#[non_exhaustive]
 #[derive(Error, Debug)]
 pub enum ScannerError {
     #[error("attempted to get last line before reaching end of input")]
     NotAtEnd,
 }
 
+#[non_exhaustive]
 #[derive(Error, Debug)]
 pub enum TokenError {
-    #[error("unexpected character '{character}'")]
-    UnexpectedCharacter { character: char },
-    #[error("string did not match a token")]
-    NoTokenMatch,
-    #[error("unexpected end of input, expected string literal")]
-    UnexpectedEndOfInput,
     #[error("cannot call size, token type has dynamic size")]
     DynamicTokenSize,
 }
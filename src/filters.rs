@@ -0,0 +1,2483 @@
+use crate::date::{self, DateTime, FormatError, Formattable, Time};
+use crate::error::RenderError;
+use crate::value::Value;
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A filter's implementation, given the value and argument. It fails with the reason
+/// where Django's filter raises an exception.
+type Apply = fn(Value, Option<Value>) -> Result<Value, String>;
+
+/// A filter that also needs to know whether autoescaping is on, like Django's filters
+/// registered with `needs_autoescape`.
+type ApplyEscaping = fn(Value, Option<Value>, bool) -> Result<Value, String>;
+
+#[derive(Clone, Copy)]
+enum Implementation {
+    Plain(Apply),
+    Escaping(ApplyEscaping),
+}
+
+/// Whether a filter takes an argument, like the `"nobody"` of `default:"nobody"`.
+#[derive(Clone, Copy, PartialEq)]
+enum Arg {
+    None,
+    Optional,
+    Required,
+}
+
+/// One of Django's builtin filters.
+struct FilterSpec {
+    name: &'static str,
+    arg: Arg,
+    /// Django's `is_safe`: the filter does not make safe text unsafe, so its output is
+    /// marked safe when the value was.
+    is_safe: bool,
+    implementation: Implementation,
+}
+
+const fn spec(name: &'static str, arg: Arg, is_safe: bool, apply: Apply) -> FilterSpec {
+    FilterSpec {
+        name,
+        arg,
+        is_safe,
+        implementation: Implementation::Plain(apply),
+    }
+}
+
+/// A filter that escapes its output itself when autoescaping is on, which Django's
+/// filters of this kind all mark `is_safe`.
+const fn escaping(name: &'static str, arg: Arg, apply: ApplyEscaping) -> FilterSpec {
+    FilterSpec {
+        name,
+        arg,
+        is_safe: true,
+        implementation: Implementation::Escaping(apply),
+    }
+}
+
+const FILTERS: &[FilterSpec] = &[
+    spec("add", Arg::Required, false, add),
+    spec("addslashes", Arg::None, true, addslashes),
+    spec("capfirst", Arg::None, true, capfirst),
+    spec("center", Arg::Required, true, center),
+    spec("cut", Arg::Required, false, cut),
+    spec("date", Arg::Optional, false, date),
+    spec("default", Arg::Required, false, default),
+    spec("default_if_none", Arg::Required, false, default_if_none),
+    spec("dictsort", Arg::Required, false, dictsort),
+    spec("dictsortreversed", Arg::Required, false, dictsortreversed),
+    spec("divisibleby", Arg::Required, false, divisibleby),
+    spec("escape", Arg::None, true, escape),
+    spec("escapejs", Arg::None, false, escapejs),
+    spec("escapeseq", Arg::None, true, escapeseq),
+    spec("filesizeformat", Arg::None, true, filesizeformat),
+    spec("first", Arg::None, false, first),
+    spec("floatformat", Arg::Optional, true, floatformat),
+    spec("force_escape", Arg::None, true, force_escape),
+    spec("get_digit", Arg::Required, false, get_digit),
+    spec("iriencode", Arg::None, true, iriencode),
+    escaping("join", Arg::Required, join),
+    spec("json_script", Arg::Optional, true, json_script),
+    spec("last", Arg::None, true, last),
+    spec("length", Arg::None, false, length),
+    escaping("linebreaks", Arg::None, linebreaks),
+    escaping("linebreaksbr", Arg::None, linebreaksbr),
+    escaping("linenumbers", Arg::None, linenumbers),
+    spec("ljust", Arg::Required, true, ljust),
+    spec("lower", Arg::None, true, lower),
+    spec("make_list", Arg::None, false, make_list),
+    spec("phone2numeric", Arg::None, true, phone2numeric),
+    spec("pluralize", Arg::Optional, false, pluralize),
+    spec("pprint", Arg::None, true, pprint),
+    spec("random", Arg::None, true, random),
+    spec("rjust", Arg::Required, true, rjust),
+    spec("safe", Arg::None, true, safe),
+    spec("safeseq", Arg::None, true, safeseq),
+    spec("slice", Arg::Required, true, slice),
+    spec("slugify", Arg::None, true, slugify),
+    spec("stringformat", Arg::Required, true, stringformat),
+    spec("striptags", Arg::None, true, striptags),
+    spec("time", Arg::Optional, false, time),
+    spec("timesince", Arg::Optional, false, timesince),
+    spec("timeuntil", Arg::Optional, false, timeuntil),
+    spec("title", Arg::None, true, title),
+    spec("truncatechars", Arg::Required, true, truncatechars),
+    spec(
+        "truncatechars_html",
+        Arg::Required,
+        true,
+        truncatechars_html,
+    ),
+    spec("truncatewords", Arg::Required, true, truncatewords),
+    spec(
+        "truncatewords_html",
+        Arg::Required,
+        true,
+        truncatewords_html,
+    ),
+    escaping("unordered_list", Arg::None, unordered_list),
+    spec("upper", Arg::None, false, upper),
+    spec("urlencode", Arg::Optional, false, urlencode),
+    escaping("urlize", Arg::None, urlize),
+    escaping("urlizetrunc", Arg::Required, urlizetrunc),
+    spec("wordcount", Arg::None, false, wordcount),
+    spec("wordwrap", Arg::Required, true, wordwrap),
+    spec("yesno", Arg::Optional, false, yesno),
+];
+
+/// Apply the builtin filter `name` to `value`, with the argument it was given if any,
+/// e.g. the `"nothing"` of `{{ value|default:"nothing" }}`. Filters that produce HTML
+/// escape what they include when `autoescape` is on.
+pub(crate) fn apply(
+    name: &str,
+    value: Value,
+    arg: Option<Value>,
+    autoescape: bool,
+) -> Result<Value, RenderError> {
+    let filter = FILTERS
+        .iter()
+        .find(|filter| filter.name == name)
+        .ok_or_else(|| RenderError::UnknownFilter {
+            name: name.to_string(),
+        })?;
+    let given = 1 + usize::from(arg.is_some());
+    let expected = match (filter.arg, &arg) {
+        (Arg::Required, None) => 2,
+        (Arg::None, Some(_)) => 1,
+        _ => given,
+    };
+    if expected != given {
+        return Err(RenderError::FilterArguments {
+            name: name.to_string(),
+            expected,
+            given,
+        });
+    }
+    let was_safe = matches!(value, Value::SafeString(_));
+    let output = match filter.implementation {
+        Implementation::Plain(apply) => apply(value, arg),
+        Implementation::Escaping(apply) => apply(value, arg, autoescape),
+    }
+    .map_err(|reason| RenderError::Filter {
+        name: name.to_string(),
+        reason,
+    })?;
+    Ok(match output {
+        Value::SafeString(_) => output,
+        output if filter.is_safe && was_safe => Value::safe(output.to_string()),
+        output => output,
+    })
+}
+
+/// Python's `int()` of a filter's argument.
+fn int_arg(arg: &Option<Value>) -> Option<i64> {
+    arg.as_ref()?.to_int()
+}
+
+fn invalid_int(arg: &Option<Value>) -> String {
+    let arg = arg.clone().unwrap_or_default();
+    format!("invalid literal for int() with base 10: {}", arg.repr())
+}
+
+fn add(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let arg = arg.unwrap_or_default();
+    if let (Some(left), Some(right)) = (value.to_int(), arg.to_int()) {
+        return Ok(left.checked_add(right).map_or(Value::from(""), Value::Int));
+    }
+    Ok(match (value, arg) {
+        (Value::SafeString(left), Value::SafeString(right)) => Value::SafeString(left + &right),
+        (
+            Value::Str(left) | Value::SafeString(left),
+            Value::Str(right) | Value::SafeString(right),
+        ) => Value::Str(left + &right),
+        (Value::List(mut left), Value::List(right)) => {
+            left.extend(right);
+            Value::List(left)
+        }
+        _ => Value::from(""),
+    })
+}
+
+fn addslashes(value: Value, _: Option<Value>) -> Result<Value, String> {
+    let text = value
+        .to_string()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\'', "\\'");
+    Ok(Value::Str(text))
+}
+
+fn capfirst(value: Value, _: Option<Value>) -> Result<Value, String> {
+    let text = value.to_string();
+    let mut chars = text.chars();
+    let capitalized = match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    };
+    Ok(Value::Str(capitalized))
+}
+
+/// Pad the value to the width given by `arg`, putting `left(margin, width)` of the
+/// padding on the left, like Python's `str.ljust`, `str.rjust` and `str.center`.
+fn justify(
+    value: Value,
+    arg: Option<Value>,
+    left: fn(usize, usize) -> usize,
+) -> Result<Value, String> {
+    let width = int_arg(&arg).ok_or_else(|| invalid_int(&arg))?;
+    let width = usize::try_from(width).unwrap_or(0);
+    let text = value.to_string();
+    let margin = width.saturating_sub(text.chars().count());
+    let left = left(margin, width);
+    Ok(Value::Str(format!(
+        "{}{}{}",
+        " ".repeat(left),
+        text,
+        " ".repeat(margin - left)
+    )))
+}
+
+fn center(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    justify(value, arg, |margin, width| {
+        margin / 2 + (margin & width & 1)
+    })
+}
+
+fn ljust(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    justify(value, arg, |_, _| 0)
+}
+
+fn rjust(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    justify(value, arg, |margin, _| margin)
+}
+
+fn cut(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let arg = arg.unwrap_or_default().to_string();
+    let text = value.to_string().replace(&arg, "");
+    Ok(match value {
+        Value::SafeString(_) if arg != ";" => Value::SafeString(text),
+        _ => Value::Str(text),
+    })
+}
+
+fn formattable(value: &Value) -> Option<Formattable> {
+    match value {
+        Value::Date(date) => Some(Formattable::Date(*date)),
+        Value::Time(time) => Some(Formattable::Time(*time)),
+        Value::DateTime(datetime) => Some(Formattable::DateTime(*datetime)),
+        _ => None,
+    }
+}
+
+/// The format string a filter argument names, e.g. the `N j, Y` of `DATE_FORMAT`, as
+/// Django's default English locale defines them. Other arguments are format strings.
+fn named_format(arg: Option<Value>, default: &str) -> String {
+    let name = match arg.filter(Value::is_truthy) {
+        Some(arg) => arg.to_string(),
+        None => default.to_string(),
+    };
+    match name.as_str() {
+        "DATE_FORMAT" => "N j, Y",
+        "DATETIME_FORMAT" => "N j, Y, P",
+        "TIME_FORMAT" => "P",
+        "SHORT_DATE_FORMAT" => "m/d/Y",
+        "SHORT_DATETIME_FORMAT" => "m/d/Y P",
+        "YEAR_MONTH_FORMAT" => "F Y",
+        "MONTH_DAY_FORMAT" => "F j",
+        format => format,
+    }
+    .to_string()
+}
+
+fn date(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let Some(formattable) = formattable(&value) else {
+        return Ok(Value::from(""));
+    };
+    match date::format(formattable, &named_format(arg, "DATE_FORMAT"), false) {
+        Ok(text) => Ok(Value::Str(text)),
+        Err(FormatError::TimeOfDate(c)) => Err(format!(
+            "the format for date objects may not contain time-related format specifiers \
+             (found '{}')",
+            c
+        )),
+        Err(FormatError::DateOfTime(_)) => Ok(Value::from("")),
+    }
+}
+
+fn time(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let text = formattable(&value)
+        .and_then(|value| date::format(value, &named_format(arg, "TIME_FORMAT"), true).ok())
+        .unwrap_or_default();
+    Ok(Value::Str(text))
+}
+
+fn default(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    Ok(if value.is_truthy() {
+        value
+    } else {
+        arg.unwrap_or_default()
+    })
+}
+
+fn default_if_none(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    Ok(match value {
+        Value::None => arg.unwrap_or_default(),
+        value => value,
+    })
+}
+
+fn dictsort(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    Ok(sort_by_key(value, &arg.unwrap_or_default(), false))
+}
+
+fn dictsortreversed(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    Ok(sort_by_key(value, &arg.unwrap_or_default(), true))
+}
+
+/// Sort a list by the item or dotted key `arg` of each of its items, or return an
+/// empty string when they cannot be sorted, as `dictsort` does.
+fn sort_by_key(value: Value, arg: &Value, reverse: bool) -> Value {
+    let Value::List(items) = value else {
+        return Value::from("");
+    };
+    let key = |item: &Value| -> Option<Value> {
+        if arg.to_float().is_some() {
+            return match (item, arg) {
+                (Value::List(_), Value::Int(_)) => item.lookup(&arg.to_string()),
+                (Value::Map(_), Value::Str(key) | Value::SafeString(key)) => item.get(key).cloned(),
+                _ => None,
+            };
+        }
+        let name = arg.to_string();
+        if name.is_empty() || name.starts_with('_') || name.contains("._") {
+            return None;
+        }
+        name.split('.')
+            .try_fold(item.clone(), |value, part| value.get(part).cloned())
+    };
+    let Some(keys) = items.iter().map(key).collect::<Option<Vec<_>>>() else {
+        return Value::from("");
+    };
+    let mut order: Vec<usize> = (0..items.len()).collect();
+    let mut comparable = true;
+    order.sort_by(|&left, &right| {
+        let (left, right) = if reverse {
+            (right, left)
+        } else {
+            (left, right)
+        };
+        keys[left].partial_cmp(&keys[right]).unwrap_or_else(|| {
+            comparable = false;
+            Ordering::Equal
+        })
+    });
+    if !comparable {
+        return Value::from("");
+    }
+    Value::List(
+        order
+            .into_iter()
+            .map(|index| items[index].clone())
+            .collect(),
+    )
+}
+
+fn divisibleby(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let left = value
+        .to_int()
+        .ok_or_else(|| invalid_int(&Some(value.clone())))?;
+    let right = int_arg(&arg).ok_or_else(|| invalid_int(&arg))?;
+    if right == 0 {
+        return Err("integer modulo by zero".to_string());
+    }
+    Ok(Value::Bool(left.checked_rem(right).unwrap_or(0) == 0))
+}
+
+/// Escape the characters with meaning in HTML, like Django's `escape`.
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Django's `conditional_escape`, which leaves safe strings alone.
+pub(crate) fn conditional_escape(value: &Value) -> Value {
+    match value {
+        Value::SafeString(text) => Value::SafeString(text.clone()),
+        value => Value::SafeString(escape_html(&value.to_string())),
+    }
+}
+
+fn escape(value: Value, _: Option<Value>) -> Result<Value, String> {
+    Ok(conditional_escape(&value))
+}
+
+fn force_escape(value: Value, _: Option<Value>) -> Result<Value, String> {
+    Ok(Value::SafeString(escape_html(&value.to_string())))
+}
+
+fn escapejs(value: Value, _: Option<Value>) -> Result<Value, String> {
+    let mut escaped = String::new();
+    for c in value.to_string().chars() {
+        match c {
+            '\\'
+            | '\''
+            | '"'
+            | '>'
+            | '<'
+            | '&'
+            | '='
+            | '-'
+            | ';'
+            | '`'
+            | '\u{2028}'
+            | '\u{2029}'
+            | '\0'..='\u{1f}' => escaped.push_str(&format!("\\u{:04X}", u32::from(c))),
+            c => escaped.push(c),
+        }
+    }
+    Ok(Value::SafeString(escaped))
+}
+
+fn not_iterable(value: &Value) -> String {
+    format!("'{}' object is not iterable", value.type_name())
+}
+
+fn escapeseq(value: Value, _: Option<Value>) -> Result<Value, String> {
+    let items = value.iter_items().ok_or_else(|| not_iterable(&value))?;
+    Ok(Value::List(items.iter().map(conditional_escape).collect()))
+}
+
+fn filesizeformat(value: Value, _: Option<Value>) -> Result<Value, String> {
+    const UNITS: [(&str, u64); 5] = [
+        ("KB", 1 << 10),
+        ("MB", 1 << 20),
+        ("GB", 1 << 30),
+        ("TB", 1 << 40),
+        ("PB", 1 << 50),
+    ];
+    let bytes = value.to_int().unwrap_or(0);
+    let size = bytes.unsigned_abs();
+    let text = match UNITS.iter().rev().find(|(_, unit)| size >= *unit) {
+        Some((name, unit)) => format!("{:.1} {}", size as f64 / *unit as f64, name),
+        None if size == 1 => "1 byte".to_string(),
+        None => format!("{} bytes", size),
+    };
+    let sign = if bytes < 0 { "-" } else { "" };
+    Ok(Value::Str(
+        format!("{}{}", sign, text).replace(' ', "\u{a0}"),
+    ))
+}
+
+fn not_subscriptable(value: &Value) -> String {
+    format!("'{}' object is not subscriptable", value.type_name())
+}
+
+/// The item of a list or the character of a string at a possibly negative `index`, or
+/// an empty string if there is none, as `first` and `last` return.
+fn item_at(value: &Value, index: i64) -> Result<Value, String> {
+    match value {
+        Value::List(_) | Value::Str(_) | Value::SafeString(_) => Ok(value
+            .lookup(&index.to_string())
+            .unwrap_or_else(|| Value::from(""))),
+        value => Err(not_subscriptable(value)),
+    }
+}
+
+fn first(value: Value, _: Option<Value>) -> Result<Value, String> {
+    item_at(&value, 0)
+}
+
+fn last(value: Value, _: Option<Value>) -> Result<Value, String> {
+    item_at(&value, -1)
+}
+
+fn floatformat(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let (precision, grouping) = match arg.unwrap_or(Value::Int(-1)) {
+        Value::Str(arg) | Value::SafeString(arg) => {
+            // Grouping follows the locale, which `u` turns off
+            let (arg, grouping) = if let Some(arg) = arg.strip_suffix("gu") {
+                (arg, false)
+            } else if let Some(arg) = arg.strip_suffix("ug") {
+                (arg, false)
+            } else if let Some(arg) = arg.strip_suffix('g') {
+                (arg, true)
+            } else {
+                (arg.strip_suffix('u').unwrap_or(&arg), false)
+            };
+            let precision = if arg.is_empty() {
+                Some(-1)
+            } else {
+                arg.trim().parse().ok()
+            };
+            (precision, grouping)
+        }
+        arg => (arg.to_int(), false),
+    };
+    let input = value.to_string();
+    if is_special_decimal(&input) {
+        return Ok(Value::Str(input));
+    }
+    let decimal = Decimal::parse(&input).or_else(|| {
+        let number = value.to_float()?;
+        Decimal::parse(&Value::Float(number).to_string())
+    });
+    let Some(decimal) = decimal else {
+        return Ok(Value::from(""));
+    };
+    match precision {
+        Some(precision) => Ok(Value::SafeString(decimal.format(precision, grouping))),
+        None => Ok(Value::Str(input)),
+    }
+}
+
+/// Whether Python's `Decimal` reads `text` as infinity or not a number.
+fn is_special_decimal(text: &str) -> bool {
+    let text = text.trim().trim_start_matches(['+', '-']).to_lowercase();
+    ["inf", "infinity", "nan", "snan"].contains(&text.as_str())
+}
+
+/// A decimal number, like Python's `decimal.Decimal`: `digits` times ten to the power
+/// of `exponent`.
+struct Decimal {
+    negative: bool,
+    digits: Vec<u8>,
+    exponent: i64,
+}
+
+impl Decimal {
+    fn parse(text: &str) -> Option<Decimal> {
+        let text = text.trim();
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(text) => (true, text),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (mantissa, exponent.parse::<i64>().ok()?),
+            None => (text, 0),
+        };
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let digits: Vec<u8> = whole.bytes().chain(fraction.bytes()).collect();
+        if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) || exponent.abs() > 1000 {
+            return None;
+        }
+        let mut digits: Vec<u8> = digits.iter().map(|digit| digit - b'0').collect();
+        let zeros = digits.iter().take_while(|digit| **digit == 0).count();
+        digits.drain(..zeros.min(digits.len() - 1));
+        Some(Decimal {
+            negative,
+            digits,
+            exponent: exponent - fraction.len() as i64,
+        })
+    }
+
+    fn is_integer(&self) -> bool {
+        let fraction = usize::try_from(-self.exponent).unwrap_or(0);
+        self.digits
+            .iter()
+            .rev()
+            .take(fraction)
+            .all(|digit| *digit == 0)
+    }
+
+    /// The digits rounded half up to `places` decimal places, with at least one digit
+    /// before the decimal point.
+    fn quantize(&self, places: usize) -> Vec<u8> {
+        let target = -(places as i64);
+        let mut digits = self.digits.clone();
+        if self.exponent >= target {
+            digits.resize(digits.len() + (self.exponent - target) as usize, 0);
+        } else {
+            let dropped = (target - self.exponent) as usize;
+            let round_up = dropped <= digits.len() && digits[digits.len() - dropped] >= 5;
+            digits.truncate(digits.len().saturating_sub(dropped));
+            if round_up {
+                match digits.iter().rposition(|digit| *digit != 9) {
+                    Some(index) => {
+                        digits[index] += 1;
+                        digits[index + 1..].fill(0);
+                    }
+                    None => {
+                        digits.fill(0);
+                        digits.insert(0, 1);
+                    }
+                }
+            }
+        }
+        while digits.len() <= places {
+            digits.insert(0, 0);
+        }
+        digits
+    }
+
+    /// The number as `floatformat` prints it: with `precision` decimal places, or
+    /// with as many as `-precision` if it has a fraction when `precision` is negative.
+    fn format(&self, precision: i64, grouping: bool) -> String {
+        let places = if self.is_integer() && precision <= 0 {
+            0
+        } else {
+            precision.unsigned_abs() as usize
+        };
+        let digits = self.quantize(places);
+        let (whole, fraction) = digits.split_at(digits.len() - places);
+        let whole: String = whole.iter().map(|digit| char::from(b'0' + digit)).collect();
+        let whole = whole.trim_start_matches('0');
+        let mut number = String::new();
+        if self.negative && digits.iter().any(|digit| *digit != 0) {
+            number.push('-');
+        }
+        let whole = if whole.is_empty() { "0" } else { whole };
+        if grouping {
+            for (index, digit) in whole.chars().enumerate() {
+                if index > 0 && (whole.len() - index) % 3 == 0 {
+                    number.push(',');
+                }
+                number.push(digit);
+            }
+        } else {
+            number.push_str(whole);
+        }
+        if places > 0 {
+            number.push('.');
+            number.extend(fraction.iter().map(|digit| char::from(b'0' + digit)));
+        }
+        number
+    }
+}
+
+fn get_digit(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let (Some(number), Some(digit)) = (value.to_int(), int_arg(&arg)) else {
+        return Ok(value);
+    };
+    if digit < 1 {
+        return Ok(Value::Int(number));
+    }
+    let text = number.to_string();
+    let Some(index) = usize::try_from(digit)
+        .ok()
+        .and_then(|digit| text.len().checked_sub(digit))
+    else {
+        return Ok(Value::Int(0));
+    };
+    match text.as_bytes()[index] {
+        b'-' => Err("invalid literal for int() with base 10: '-'".to_string()),
+        digit => Ok(Value::Int(i64::from(digit - b'0'))),
+    }
+}
+
+/// Percent-encode `text` the way Python's `urllib.parse.quote` does, leaving ASCII
+/// letters, digits, `_.-~` and the characters in `safe` as they are.
+fn quote(text: &str, safe: &str) -> String {
+    let mut quoted = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric()
+            || b"_.-~".contains(&byte)
+            || safe.as_bytes().contains(&byte)
+        {
+            quoted.push(char::from(byte));
+        } else {
+            quoted.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    quoted
+}
+
+/// Python's `urllib.parse.quote_plus`, which encodes spaces as `+`.
+fn quote_plus(text: &str) -> String {
+    quote(text, " ").replace(' ', "+")
+}
+
+/// Decode percent-encoded UTF-8, like Python's `urllib.parse.unquote`.
+fn unquote(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .filter(|hex| hex.chars().all(|c| c.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn iriencode(value: Value, _: Option<Value>) -> Result<Value, String> {
+    Ok(Value::Str(quote(
+        &value.to_string(),
+        "/#%[]=:;$&()+,!?*@'~",
+    )))
+}
+
+fn urlencode(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let safe = arg.map_or_else(|| "/".to_string(), |arg| arg.to_string());
+    Ok(Value::Str(quote(&value.to_string(), &safe)))
+}
+
+fn join(value: Value, arg: Option<Value>, autoescape: bool) -> Result<Value, String> {
+    let Some(items) = value.iter_items() else {
+        return Ok(value);
+    };
+    let separator = arg.unwrap_or_default();
+    if autoescape {
+        let items: Vec<String> = items
+            .iter()
+            .map(|item| conditional_escape(item).to_string())
+            .collect();
+        return Ok(Value::SafeString(
+            items.join(&conditional_escape(&separator).to_string()),
+        ));
+    }
+    // Python's `str.join`, which only joins strings
+    let Some(separator) = separator.as_str() else {
+        let name = separator.type_name();
+        return Err(format!("'{}' object has no attribute 'join'", name));
+    };
+    let items: Option<Vec<&str>> = items.iter().map(Value::as_str).collect();
+    Ok(match items {
+        Some(items) => Value::SafeString(items.join(separator)),
+        None => value,
+    })
+}
+
+fn json_script(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let mut json = String::new();
+    write_json(&value, &mut json);
+    let json = json
+        .replace('>', "\\u003E")
+        .replace('<', "\\u003C")
+        .replace('&', "\\u0026");
+    let script = match arg.filter(Value::is_truthy) {
+        Some(id) => format!(
+            "<script id=\"{}\" type=\"application/json\">{}</script>",
+            conditional_escape(&id),
+            json
+        ),
+        None => format!("<script type=\"application/json\">{}</script>", json),
+    };
+    Ok(Value::SafeString(script))
+}
+
+/// Write the value as JSON, the way Python's `json.dumps` with Django's `JSONEncoder`
+/// does: escaping all non-ASCII characters, and with dates in ISO 8601 format.
+fn write_json(value: &Value, json: &mut String) {
+    match value {
+        Value::None => json.push_str("null"),
+        Value::Bool(true) => json.push_str("true"),
+        Value::Bool(false) => json.push_str("false"),
+        Value::Int(number) => json.push_str(&number.to_string()),
+        Value::Float(number) if number.is_nan() => json.push_str("NaN"),
+        Value::Float(number) if number.is_infinite() => json.push_str(if *number > 0.0 {
+            "Infinity"
+        } else {
+            "-Infinity"
+        }),
+        Value::Float(_) => json.push_str(&value.to_string()),
+        Value::Str(text) | Value::SafeString(text) => write_json_string(text, json),
+        Value::List(items) => {
+            json.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    json.push_str(", ");
+                }
+                write_json(item, json);
+            }
+            json.push(']');
+        }
+        Value::Map(entries) => {
+            json.push('{');
+            for (index, (key, value)) in entries.iter().enumerate() {
+                if index > 0 {
+                    json.push_str(", ");
+                }
+                write_json_string(key, json);
+                json.push_str(": ");
+                write_json(value, json);
+            }
+            json.push('}');
+        }
+        Value::Date(date) => write_json_string(&date.to_string(), json),
+        Value::Time(time) => write_json_string(&json_time(time), json),
+        Value::DateTime(datetime) => write_json_string(
+            &format!("{}T{}", datetime.date, json_time(&datetime.time)),
+            json,
+        ),
+    }
+}
+
+/// A time in ISO 8601 format, with microseconds cut to milliseconds as JavaScript
+/// expects.
+fn json_time(time: &Time) -> String {
+    let mut text = time.to_string();
+    text.truncate(12);
+    text
+}
+
+fn write_json_string(text: &str, json: &mut String) {
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            '\u{8}' => json.push_str("\\b"),
+            '\u{c}' => json.push_str("\\f"),
+            c if c < ' ' || !c.is_ascii() => {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    json.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+fn length(value: Value, _: Option<Value>) -> Result<Value, String> {
+    let length = match &value {
+        Value::Str(text) | Value::SafeString(text) => text.chars().count(),
+        Value::List(items) => items.len(),
+        Value::Map(entries) => entries.len(),
+        _ => 0,
+    };
+    Ok(Value::Int(length as i64))
+}
+
+/// Convert Windows and old Mac line endings to `\n`.
+fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// The text of a value for a filter that makes HTML of it, escaped when autoescaping is
+/// on unless it is already safe.
+fn html_text(value: &Value, autoescape: bool) -> String {
+    match value {
+        Value::SafeString(text) => text.clone(),
+        value if autoescape => escape_html(&value.to_string()),
+        value => value.to_string(),
+    }
+}
+
+fn linebreaks(value: Value, _: Option<Value>, autoescape: bool) -> Result<Value, String> {
+    let text = normalize_newlines(&html_text(&value, autoescape));
+    let mut paragraphs = Vec::new();
+    let mut rest = text.as_str();
+    // Paragraphs are separated by two or more newlines
+    while let Some(index) = rest.find("\n\n") {
+        paragraphs.push(&rest[..index]);
+        rest = rest[index..].trim_start_matches('\n');
+    }
+    paragraphs.push(rest);
+    let paragraphs: Vec<String> = paragraphs
+        .iter()
+        .map(|paragraph| format!("<p>{}</p>", paragraph.replace('\n', "<br>")))
+        .collect();
+    Ok(Value::SafeString(paragraphs.join("\n\n")))
+}
+
+fn linebreaksbr(value: Value, _: Option<Value>, autoescape: bool) -> Result<Value, String> {
+    let text = normalize_newlines(&html_text(&value, autoescape));
+    Ok(Value::SafeString(text.replace('\n', "<br>")))
+}
+
+fn linenumbers(value: Value, _: Option<Value>, autoescape: bool) -> Result<Value, String> {
+    let text = html_text(&value, autoescape);
+    let lines: Vec<&str> = text.split('\n').collect();
+    let width = lines.len().to_string().len();
+    let numbered: Vec<String> = lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| format!("{:0width$}. {}", index + 1, line, width = width))
+        .collect();
+    Ok(Value::SafeString(numbered.join("\n")))
+}
+
+fn lower(value: Value, _: Option<Value>) -> Result<Value, String> {
+    Ok(Value::Str(value.to_string().to_lowercase()))
+}
+
+fn upper(value: Value, _: Option<Value>) -> Result<Value, String> {
+    Ok(Value::Str(value.to_string().to_uppercase()))
+}
+
+fn make_list(value: Value, _: Option<Value>) -> Result<Value, String> {
+    Ok(value.to_string().chars().map(Value::from).collect())
+}
+
+fn phone2numeric(value: Value, _: Option<Value>) -> Result<Value, String> {
+    let numeric = value
+        .to_string()
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'a'..='c' => '2',
+            'd'..='f' => '3',
+            'g'..='i' => '4',
+            'j'..='l' => '5',
+            'm'..='o' => '6',
+            'p'..='s' => '7',
+            't'..='v' => '8',
+            'w'..='z' => '9',
+            c => c,
+        })
+        .collect();
+    Ok(Value::Str(numeric))
+}
+
+fn pluralize(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let arg = arg.map_or_else(|| "s".to_string(), |arg| arg.to_string());
+    let bits: Vec<&str> = arg.split(',').collect();
+    let (singular, plural) = match bits[..] {
+        [plural] => ("", plural),
+        [singular, plural] => (singular, plural),
+        _ => return Ok(Value::from("")),
+    };
+    let is_one = match &value {
+        Value::List(items) => items.len() == 1,
+        Value::Map(entries) => entries.len() == 1,
+        value => match value.to_float() {
+            Some(number) => number == 1.0,
+            None => return Ok(Value::from("")),
+        },
+    };
+    Ok(Value::from(if is_one { singular } else { plural }))
+}
+
+/// The value with its maps sorted by key, as Python's `pprint` prints them.
+fn sort_maps(value: &Value) -> Value {
+    match value {
+        Value::List(items) => Value::List(items.iter().map(sort_maps).collect()),
+        Value::Map(entries) => {
+            let mut entries: Vec<(String, Value)> = entries
+                .iter()
+                .map(|(key, value)| (key.clone(), sort_maps(value)))
+                .collect();
+            entries.sort_by(|(left, _), (right, _)| left.cmp(right));
+            Value::Map(entries)
+        }
+        value => value.clone(),
+    }
+}
+
+fn pprint(value: Value, _: Option<Value>) -> Result<Value, String> {
+    Ok(Value::Str(sort_maps(&value).repr()))
+}
+
+fn random(value: Value, _: Option<Value>) -> Result<Value, String> {
+    let items = match &value {
+        Value::Map(_) => None,
+        value => value.iter_items(),
+    }
+    .ok_or_else(|| format!("object of type '{}' has no len()", value.type_name()))?;
+    if items.is_empty() {
+        return Err("cannot choose from an empty sequence".to_string());
+    }
+    let index = RandomState::new().hash_one(items.len()) as usize % items.len();
+    Ok(items[index].clone())
+}
+
+fn safe(value: Value, _: Option<Value>) -> Result<Value, String> {
+    Ok(Value::SafeString(value.to_string()))
+}
+
+fn safeseq(value: Value, _: Option<Value>) -> Result<Value, String> {
+    let items = value.iter_items().ok_or_else(|| not_iterable(&value))?;
+    Ok(items
+        .iter()
+        .map(|item| Value::SafeString(item.to_string()))
+        .collect())
+}
+
+/// The indices a Python slice `[start:stop:step]` selects from a sequence of `len`
+/// items.
+fn slice_indices(len: usize, start: Option<i64>, stop: Option<i64>, step: i64) -> Vec<usize> {
+    let len = len as i64;
+    let clamp = |index: Option<i64>, default: i64| match index {
+        None => default,
+        Some(index) if index < 0 => (index + len).max(if step < 0 { -1 } else { 0 }),
+        Some(index) => index.min(if step < 0 { len - 1 } else { len }),
+    };
+    let (mut index, stop) = if step < 0 {
+        (clamp(start, len - 1), clamp(stop, -1))
+    } else {
+        (clamp(start, 0), clamp(stop, len))
+    };
+    let mut indices = Vec::new();
+    while (step > 0 && index < stop) || (step < 0 && index > stop) {
+        indices.push(index as usize);
+        index += step;
+    }
+    indices
+}
+
+fn slice(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let bits: Option<Vec<Option<i64>>> = arg
+        .unwrap_or_default()
+        .to_string()
+        .split(':')
+        .map(|bit| match bit {
+            "" => Some(None),
+            bit => bit.trim().parse().ok().map(Some),
+        })
+        .collect();
+    let (start, stop, step) = match bits.as_deref() {
+        Some([stop]) => (None, *stop, None),
+        Some([start, stop]) => (*start, *stop, None),
+        Some([start, stop, step]) => (*start, *stop, *step),
+        _ => return Ok(value),
+    };
+    let step = step.unwrap_or(1);
+    if step == 0 {
+        return Ok(value);
+    }
+    Ok(match &value {
+        Value::List(items) => slice_indices(items.len(), start, stop, step)
+            .into_iter()
+            .map(|index| items[index].clone())
+            .collect(),
+        Value::Str(text) | Value::SafeString(text) => {
+            let chars: Vec<char> = text.chars().collect();
+            let sliced = slice_indices(chars.len(), start, stop, step)
+                .into_iter()
+                .map(|index| chars[index])
+                .collect();
+            Value::Str(sliced)
+        }
+        _ => value,
+    })
+}
+
+/// The ASCII letters that Unicode's compatibility decomposition (NFKD) leaves of the
+/// characters from U+00C0 to U+017F, with `_` for those that leave none.
+const LATIN_FOLDS: &str = "AAAAAA_CEEEEIIII_NOOOOO__UUUUY__aaaaaa_ceeeeiiii_nooooo__uuuuy_y\
+                           AaAaAaCcCcCcCcDd__EeEeEeEeEeGgGgGgGgHh__IiIiIiIiI___JjKk_\
+                           LlLlLlLl__NnNnNnn__OoOoOo__RrRrRrSsSsSsSsTtTt__UuUuUuUuUuUu\
+                           WwYyYZzZzZzs";
+
+/// Push what is left of `c` when it is decomposed with NFKD and encoded to ASCII,
+/// ignoring errors, which is how `slugify` drops accents.
+fn fold_to_ascii(c: char, folded: &mut String) {
+    match c {
+        c if c.is_ascii() => folded.push(c),
+        'Ĳ' => folded.push_str("IJ"),
+        'ĳ' => folded.push_str("ij"),
+        '\u{c0}'..='\u{17f}' => {
+            let letter = LATIN_FOLDS.as_bytes()[c as usize - 0xc0];
+            if letter != b'_' {
+                folded.push(char::from(letter));
+            }
+        }
+        '\u{a0}' | '\u{a8}' | '\u{af}' | '\u{b4}' | '\u{b8}' | '\u{2000}'..='\u{200a}' => {
+            folded.push(' ')
+        }
+        'ª' => folded.push('a'),
+        'º' => folded.push('o'),
+        '¹' => folded.push('1'),
+        '²' => folded.push('2'),
+        '³' => folded.push('3'),
+        '¼' => folded.push_str("14"),
+        '½' => folded.push_str("12"),
+        '¾' => folded.push_str("34"),
+        '\u{2024}' => folded.push('.'),
+        '\u{2025}' => folded.push_str(".."),
+        '\u{2026}' => folded.push_str("..."),
+        '™' => folded.push_str("TM"),
+        '\u{fb00}' => folded.push_str("ff"),
+        '\u{fb01}' => folded.push_str("fi"),
+        '\u{fb02}' => folded.push_str("fl"),
+        '\u{fb03}' => folded.push_str("ffi"),
+        '\u{fb04}' => folded.push_str("ffl"),
+        '\u{fb05}' | '\u{fb06}' => folded.push_str("st"),
+        // Fullwidth forms of ASCII
+        '\u{ff01}'..='\u{ff5e}' => folded.extend(char::from_u32(c as u32 - 0xfee0)),
+        _ => {}
+    }
+}
+
+/// Whether `c` is whitespace to Python's `\s` in ASCII text.
+fn is_python_space(c: char) -> bool {
+    matches!(c, '\t'..='\r' | ' ' | '\x1c'..='\x1f')
+}
+
+fn slugify(value: Value, _: Option<Value>) -> Result<Value, String> {
+    let mut folded = String::new();
+    for c in value.to_string().chars() {
+        fold_to_ascii(c, &mut folded);
+    }
+    let mut slug = String::new();
+    let mut separated = false;
+    for c in folded.to_lowercase().chars() {
+        if c == '-' || is_python_space(c) {
+            separated = true;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            if separated {
+                slug.push('-');
+                separated = false;
+            }
+            slug.push(c);
+        }
+    }
+    if separated {
+        slug.push('-');
+    }
+    Ok(Value::Str(slug.trim_matches(['-', '_']).to_string()))
+}
+
+fn stringformat(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let format = format!("%{}", arg.unwrap_or_default());
+    Ok(Value::Str(
+        percent_format(&format, &value).unwrap_or_default(),
+    ))
+}
+
+/// Python's `format % value` for a format with one conversion, or `None` where Python
+/// raises an error.
+fn percent_format(format: &str, value: &Value) -> Option<String> {
+    let mut output = String::new();
+    let mut converted = false;
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        if chars.next_if_eq(&'%').is_some() {
+            output.push('%');
+            continue;
+        }
+        if converted {
+            return None;
+        }
+        converted = true;
+        let mut flags = String::new();
+        while let Some(flag) = chars.next_if(|c| "#0- +".contains(*c)) {
+            flags.push(flag);
+        }
+        let width = number(&mut chars).unwrap_or(0);
+        let precision = chars
+            .next_if_eq(&'.')
+            .map(|_| number(&mut chars).unwrap_or(0));
+        while chars.next_if(|c| "hlL".contains(*c)).is_some() {}
+        let conversion = chars.next()?;
+        output.push_str(&convert(value, conversion, &flags, width, precision)?);
+    }
+    converted.then_some(output)
+}
+
+fn number(chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut digits = String::new();
+    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+        digits.push(digit);
+    }
+    digits.parse().ok()
+}
+
+/// One `%` conversion of `value`, e.g. `%05.2f`.
+fn convert(
+    value: &Value,
+    conversion: char,
+    flags: &str,
+    width: usize,
+    precision: Option<usize>,
+) -> Option<String> {
+    let sign = |negative: bool| {
+        if negative {
+            "-"
+        } else if flags.contains('+') {
+            "+"
+        } else if flags.contains(' ') {
+            " "
+        } else {
+            ""
+        }
+        .to_string()
+    };
+    let integer = || match value {
+        Value::Bool(_) | Value::Int(_) => value.to_int(),
+        _ => None,
+    };
+    let (prefix, body, numeric) = match conversion {
+        's' | 'r' | 'a' => {
+            let text = match conversion {
+                's' => value.to_string(),
+                'r' => value.repr(),
+                _ => ascii(&value.repr()),
+            };
+            let text = match precision {
+                Some(precision) => text.chars().take(precision).collect(),
+                None => text,
+            };
+            (String::new(), text, false)
+        }
+        'c' => {
+            let text = match value {
+                Value::Int(code) => char::from_u32(u32::try_from(*code).ok()?)?.to_string(),
+                Value::Str(text) | Value::SafeString(text) if text.chars().count() == 1 => {
+                    text.clone()
+                }
+                _ => return None,
+            };
+            (String::new(), text, false)
+        }
+        'd' | 'i' | 'u' => {
+            let number = match value {
+                Value::Float(number) if number.is_finite() => number.trunc() as i64,
+                _ => integer()?,
+            };
+            let digits = number.unsigned_abs().to_string();
+            let digits = format!("{:0>1$}", digits, precision.unwrap_or(0));
+            (sign(number < 0), digits, true)
+        }
+        'x' | 'X' | 'o' => {
+            let number = integer()?;
+            let magnitude = number.unsigned_abs();
+            let (digits, base) = match conversion {
+                'x' => (format!("{:x}", magnitude), "0x"),
+                'X' => (format!("{:X}", magnitude), "0X"),
+                _ => (format!("{:o}", magnitude), "0o"),
+            };
+            let mut prefix = sign(number < 0);
+            if flags.contains('#') {
+                prefix.push_str(base);
+            }
+            let digits = format!("{:0>1$}", digits, precision.unwrap_or(0));
+            (prefix, digits, true)
+        }
+        'e' | 'E' | 'f' | 'F' | 'g' | 'G' => {
+            let number = match value {
+                Value::Bool(_) | Value::Int(_) | Value::Float(_) => value.to_float()?,
+                _ => return None,
+            };
+            let body = format_float(
+                number.abs(),
+                conversion,
+                precision.unwrap_or(6),
+                flags.contains('#'),
+            );
+            (
+                sign(number.is_sign_negative() && !number.is_nan()),
+                body,
+                true,
+            )
+        }
+        _ => return None,
+    };
+    let padding = width.saturating_sub(prefix.chars().count() + body.chars().count());
+    Some(if flags.contains('-') {
+        format!("{}{}{}", prefix, body, " ".repeat(padding))
+    } else if numeric && flags.contains('0') {
+        format!("{}{}{}", prefix, "0".repeat(padding), body)
+    } else {
+        format!("{}{}{}", " ".repeat(padding), prefix, body)
+    })
+}
+
+/// Python's `ascii()` of a repr, which escapes every non-ASCII character.
+fn ascii(repr: &str) -> String {
+    let mut escaped = String::new();
+    for c in repr.chars() {
+        match u32::from(c) {
+            code if code < 0x80 => escaped.push(c),
+            code if code <= 0xff => escaped.push_str(&format!("\\x{:02x}", code)),
+            code if code <= 0xffff => escaped.push_str(&format!("\\u{:04x}", code)),
+            code => escaped.push_str(&format!("\\U{:08x}", code)),
+        }
+    }
+    escaped
+}
+
+/// A non-negative float in `%e`, `%f` or `%g` format, with `alternate` for the `#`
+/// flag.
+fn format_float(number: f64, conversion: char, precision: usize, alternate: bool) -> String {
+    let text = if number.is_nan() {
+        "nan".to_string()
+    } else if number.is_infinite() {
+        "inf".to_string()
+    } else {
+        match conversion.to_ascii_lowercase() {
+            'e' => scientific(number, precision),
+            'f' if alternate && precision == 0 => format!("{:.0}.", number),
+            'f' => format!("{:.*}", precision, number),
+            _ => {
+                let precision = precision.max(1);
+                let exponent = scientific(number, precision - 1)
+                    .split_once('e')
+                    .and_then(|(_, exponent)| exponent.parse::<i64>().ok())
+                    .unwrap_or(0);
+                let text = if (-4..precision as i64).contains(&exponent) {
+                    format!("{:.*}", (precision as i64 - 1 - exponent) as usize, number)
+                } else {
+                    scientific(number, precision - 1)
+                };
+                if alternate {
+                    text
+                } else {
+                    let (mantissa, exponent) = match text.split_once('e') {
+                        Some((mantissa, exponent)) => (mantissa, format!("e{}", exponent)),
+                        None => (text.as_str(), String::new()),
+                    };
+                    let mantissa = if mantissa.contains('.') {
+                        mantissa.trim_end_matches('0').trim_end_matches('.')
+                    } else {
+                        mantissa
+                    };
+                    format!("{}{}", mantissa, exponent)
+                }
+            }
+        }
+    };
+    if conversion.is_ascii_uppercase() {
+        text.to_uppercase()
+    } else {
+        text
+    }
+}
+
+/// A float in Python's `%e` format, e.g. `1.500000e+03`.
+fn scientific(number: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, number);
+    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+/// A piece of HTML, as Python's `HTMLParser` reports it.
+enum Html<'a> {
+    StartTag {
+        name: String,
+        text: &'a str,
+        self_closing: bool,
+    },
+    EndTag {
+        name: String,
+    },
+    Data(&'a str),
+    /// A comment, doctype or processing instruction.
+    Other,
+}
+
+/// Split HTML into tags and the text between them. A `<` that starts no tag is text.
+fn parse_html(html: &str) -> Vec<Html<'_>> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut index = 0;
+    while let Some(offset) = html[index..].find('<') {
+        let at = index + offset;
+        if start < at {
+            pieces.push(Html::Data(&html[start..at]));
+        }
+        match parse_markup(&html[at..]) {
+            Some((piece, len)) => {
+                pieces.push(piece);
+                index = at + len;
+            }
+            None => {
+                pieces.push(Html::Data("<"));
+                index = at + 1;
+            }
+        }
+        start = index;
+    }
+    if start < html.len() {
+        pieces.push(Html::Data(&html[start..]));
+    }
+    pieces
+}
+
+/// The tag, comment or declaration at the start of `html`, and its length.
+fn parse_markup(html: &str) -> Option<(Html<'_>, usize)> {
+    let rest = &html[1..];
+    let first = rest.chars().next()?;
+    if first.is_ascii_alphabetic() {
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_lowercase();
+        let mut quote = None;
+        let mut previous = ' ';
+        for (index, c) in rest[name_end..].char_indices() {
+            match quote {
+                Some(open) if c == open => quote = None,
+                Some(_) => {}
+                None if c == '>' => {
+                    let len = 1 + name_end + index + 1;
+                    let text = &html[..len];
+                    let self_closing = text.ends_with("/>");
+                    let tag = Html::StartTag {
+                        name,
+                        text,
+                        self_closing,
+                    };
+                    return Some((tag, len));
+                }
+                None if (c == '"' || c == '\'') && previous == '=' => quote = Some(c),
+                None => {}
+            }
+            if !c.is_whitespace() {
+                previous = c;
+            }
+        }
+        return None;
+    }
+    if let Some(tag) = rest.strip_prefix('/') {
+        let end = html.find('>')?;
+        if !tag.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Some((Html::Other, end + 1));
+        }
+        let name = tag[..end - 2]
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        return Some((Html::EndTag { name }, end + 1));
+    }
+    if let Some(comment) = html.strip_prefix("<!--") {
+        let end = comment.find("-->")?;
+        return Some((Html::Other, 4 + end + 3));
+    }
+    if first == '!' || first == '?' {
+        let end = html.find('>')?;
+        return Some((Html::Other, end + 1));
+    }
+    None
+}
+
+/// Replace HTML character references with the characters they stand for, like
+/// Python's `html.unescape` for the common named references and numeric ones.
+fn unescape(text: &str) -> String {
+    const ENTITIES: [(&str, &str); 6] = [
+        ("amp", "&"),
+        ("lt", "<"),
+        ("gt", ">"),
+        ("quot", "\""),
+        ("nbsp", "\u{a0}"),
+        ("apos", "'"),
+    ];
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(index) = rest.find('&') {
+        unescaped.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+        if let Some(number) = rest.strip_prefix('#') {
+            let (digits, radix) = match number.strip_prefix(['x', 'X']) {
+                Some(digits) => (digits, 16),
+                None => (number, 10),
+            };
+            let len = digits
+                .find(|c: char| !c.is_digit(radix))
+                .unwrap_or(digits.len());
+            if len > 0 {
+                let c = u32::from_str_radix(&digits[..len], radix)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .filter(|c| *c != '\0')
+                    .unwrap_or('\u{fffd}');
+                unescaped.push(c);
+                let digits = &digits[len..];
+                rest = digits.strip_prefix(';').unwrap_or(digits);
+                continue;
+            }
+        }
+        // All but `&apos;` may leave out the semicolon, as in old HTML
+        let entity = ENTITIES.iter().find_map(|(name, c)| {
+            let after = rest.strip_prefix(name)?;
+            match after.strip_prefix(';') {
+                Some(after) => Some((*c, after)),
+                None => (*name != "apos").then_some((*c, after)),
+            }
+        });
+        match entity {
+            Some((c, after)) => {
+                unescaped.push_str(c);
+                rest = after;
+            }
+            None => unescaped.push('&'),
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Remove HTML tags, the way Django's `strip_tags` does: repeatedly, until no more can
+/// be found.
+fn strip_tags(text: &str) -> String {
+    let mut value = text.to_string();
+    while value.contains('<') && value.contains('>') {
+        let stripped: String = parse_html(&value)
+            .into_iter()
+            .filter_map(|piece| match piece {
+                Html::Data(data) => Some(data),
+                _ => None,
+            })
+            .collect();
+        if stripped.matches('<').count() == value.matches('<').count() {
+            break;
+        }
+        value = stripped;
+    }
+    value
+}
+
+fn striptags(value: Value, _: Option<Value>) -> Result<Value, String> {
+    Ok(Value::Str(strip_tags(&value.to_string())))
+}
+
+fn timesince(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    Ok(time_between(&value, arg, false))
+}
+
+fn timeuntil(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    Ok(time_between(&value, arg, true))
+}
+
+fn as_datetime(value: &Value) -> Option<DateTime> {
+    match value {
+        Value::Date(date) => Some(DateTime::new(*date, Time::default())),
+        Value::DateTime(datetime) => Some(*datetime),
+        _ => None,
+    }
+}
+
+/// The time since `value`, or until it with `until`, measured from the date in `arg`
+/// or else from now.
+fn time_between(value: &Value, arg: Option<Value>, until: bool) -> Value {
+    let Some(value) = as_datetime(value) else {
+        return Value::from("");
+    };
+    let other = match arg.filter(Value::is_truthy) {
+        Some(arg) => match as_datetime(&arg) {
+            Some(other) => other,
+            None => return Value::from(""),
+        },
+        None => DateTime::now(),
+    };
+    Value::Str(if until {
+        date::timesince(other, value)
+    } else {
+        date::timesince(value, other)
+    })
+}
+
+fn title(value: Value, _: Option<Value>) -> Result<Value, String> {
+    let mut chars: Vec<char> = Vec::new();
+    let mut previous_cased = false;
+    for c in value.to_string().chars() {
+        if previous_cased {
+            chars.extend(c.to_lowercase());
+        } else {
+            chars.extend(c.to_uppercase());
+        }
+        previous_cased = c.is_lowercase() || c.is_uppercase();
+    }
+    // Django lowercases letters after apostrophes and digits again, as in "they're"
+    // and "1st"
+    let mut index = 0;
+    while index + 1 < chars.len() {
+        if chars[index].is_ascii_lowercase()
+            && chars[index + 1] == '\''
+            && chars.get(index + 2).is_some_and(char::is_ascii_uppercase)
+        {
+            chars[index + 2] = chars[index + 2].to_ascii_lowercase();
+            index += 3;
+        } else if chars[index].is_numeric() && chars[index + 1].is_ascii_uppercase() {
+            chars[index + 1] = chars[index + 1].to_ascii_lowercase();
+            index += 2;
+        } else {
+            index += 1;
+        }
+    }
+    Ok(Value::Str(chars.into_iter().collect()))
+}
+
+/// Whether `c` is a combining character, which does not count towards a length.
+fn is_combining(c: char) -> bool {
+    matches!(
+        c,
+        '\u{300}'..='\u{36f}'
+            | '\u{1ab0}'..='\u{1aff}'
+            | '\u{1dc0}'..='\u{1dff}'
+            | '\u{20d0}'..='\u{20ff}'
+            | '\u{fe20}'..='\u{fe2f}'
+    )
+}
+
+fn add_truncation_text(text: &str, truncate: &str) -> String {
+    if text.ends_with(truncate) {
+        text.to_string()
+    } else {
+        format!("{}{}", text, truncate)
+    }
+}
+
+/// The length argument of a truncation filter, or `None` where Django returns the
+/// value unchanged.
+fn truncation_length(arg: &Option<Value>) -> Option<usize> {
+    let length = int_arg(arg)?;
+    Some(usize::try_from(length).unwrap_or(0))
+}
+
+fn truncatechars(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let Some(length) = truncation_length(&arg) else {
+        return Ok(value);
+    };
+    if length == 0 {
+        return Ok(Value::from(""));
+    }
+    let text = value.to_string();
+    let mut count = 0;
+    let mut end = None;
+    for (index, c) in text.char_indices() {
+        if is_combining(c) {
+            continue;
+        }
+        count += 1;
+        // Leave room for the ellipsis
+        if end.is_none() && count > length - 1 {
+            end = Some(index);
+        }
+        if count > length {
+            let truncated = &text[..end.unwrap_or(0)];
+            return Ok(Value::Str(add_truncation_text(truncated, "…")));
+        }
+    }
+    Ok(Value::Str(text))
+}
+
+fn truncatewords(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let Some(length) = truncation_length(&arg) else {
+        return Ok(value);
+    };
+    if length == 0 {
+        return Ok(Value::from(""));
+    }
+    let text = value.to_string();
+    let words: Vec<&str> = text.split_whitespace().collect();
+    Ok(Value::Str(if words.len() > length {
+        add_truncation_text(&words[..length].join(" "), " …")
+    } else {
+        words.join(" ")
+    }))
+}
+
+fn truncatechars_html(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    match truncation_length(&arg) {
+        Some(0) => Ok(Value::from("")),
+        Some(length) => Ok(Value::Str(truncate_html(&value.to_string(), length, false))),
+        None => Ok(value),
+    }
+}
+
+fn truncatewords_html(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    match truncation_length(&arg) {
+        Some(0) => Ok(Value::from("")),
+        Some(length) => Ok(Value::Str(truncate_html(&value.to_string(), length, true))),
+        None => Ok(value),
+    }
+}
+
+/// Elements that have no end tag.
+const VOID_ELEMENTS: [&str; 16] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr", "frame", "spacer",
+];
+
+/// Split text into words at whitespace between them, leaving whitespace at either end
+/// on the first and last word.
+fn split_words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut gap = None;
+    let mut in_word = false;
+    for (index, c) in text.char_indices() {
+        if c.is_whitespace() {
+            if in_word && gap.is_none() {
+                gap = Some(index);
+            }
+        } else {
+            if let Some(gap) = gap.take() {
+                words.push(&text[start..gap]);
+                start = index;
+            }
+            in_word = true;
+        }
+    }
+    words.push(&text[start..]);
+    words
+}
+
+/// Truncate HTML to `length` characters or words of text, closing the tags left open,
+/// the way Django's `TruncateHTMLParser` does. The text is re-escaped.
+fn truncate_html(html: &str, length: usize, words: bool) -> String {
+    let replacement = if words { " …" } else { "…" };
+    let mut remaining = if words { length } else { length - 1 };
+    let total = html.chars().count();
+    let mut processed = 0;
+    let mut output: Vec<String> = Vec::new();
+    let mut open: Vec<String> = Vec::new();
+    let mut truncated = false;
+    let close = |name: &str, output: &mut Vec<String>, open: &mut Vec<String>| {
+        if !VOID_ELEMENTS.contains(&name) {
+            output.push(format!("</{}>", name));
+            if let Some(index) = open.iter().position(|tag| tag == name) {
+                open.remove(index);
+            }
+        }
+    };
+    for piece in parse_html(html) {
+        match piece {
+            Html::StartTag {
+                name,
+                text,
+                self_closing,
+            } => {
+                output.push(text.to_string());
+                if !VOID_ELEMENTS.contains(&name.as_str()) {
+                    open.insert(0, name.clone());
+                }
+                if self_closing {
+                    close(&name, &mut output, &mut open);
+                }
+            }
+            Html::EndTag { name } => close(&name, &mut output, &mut open),
+            Html::Other => {}
+            Html::Data(data) => {
+                let data = unescape(data);
+                let (count, kept) = if words {
+                    let words = split_words(&data);
+                    let kept = words[..remaining.min(words.len())].join(" ");
+                    (words.len(), kept)
+                } else {
+                    let count = data.chars().count();
+                    processed += count;
+                    // Text exactly as long as allowed is kept whole
+                    if processed == length && output.len() + count == total {
+                        output.push(data);
+                        truncated = true;
+                        break;
+                    }
+                    (count, data.chars().take(remaining).collect())
+                };
+                if remaining < count {
+                    output.push(add_truncation_text(&escape_html(&kept), replacement));
+                    truncated = true;
+                    break;
+                }
+                remaining -= count;
+                output.push(escape_html(&kept));
+            }
+        }
+    }
+    if truncated {
+        output.extend(open.iter().map(|tag| format!("</{}>", tag)));
+    }
+    output.concat()
+}
+
+fn unordered_list(value: Value, _: Option<Value>, autoescape: bool) -> Result<Value, String> {
+    let items = value.iter_items().ok_or_else(|| not_iterable(&value))?;
+    Ok(Value::SafeString(list_items(&items, 1, autoescape)))
+}
+
+/// The `<li>` elements for a list, where an item followed by a list has that list as
+/// its sublist. With `autoescape`, items that are not safe are escaped.
+fn list_items(items: &[Value], tabs: usize, autoescape: bool) -> String {
+    let indent = "\t".repeat(tabs);
+    let mut output = Vec::new();
+    let mut index = 0;
+    while index < items.len() {
+        let item = &items[index];
+        let children = match items.get(index + 1) {
+            Some(Value::List(children)) => {
+                index += 1;
+                Some(children)
+            }
+            _ => None,
+        };
+        index += 1;
+        let sublist = match children {
+            Some(children) if !children.is_empty() => format!(
+                "\n{indent}<ul>\n{}\n{indent}</ul>\n{indent}",
+                list_items(children, tabs + 1, autoescape),
+                indent = indent
+            ),
+            _ => String::new(),
+        };
+        let item = if autoescape {
+            conditional_escape(item)
+        } else {
+            item.clone()
+        };
+        output.push(format!("{}<li>{}{}</li>", indent, item, sublist));
+    }
+    output.join("\n")
+}
+
+/// The longest URL `urlize` turns into a link.
+const MAX_URL_LENGTH: usize = 2048;
+
+fn urlize(value: Value, _: Option<Value>, autoescape: bool) -> Result<Value, String> {
+    Ok(Value::SafeString(urlize_text(&value, None, autoescape)))
+}
+
+fn urlizetrunc(value: Value, arg: Option<Value>, autoescape: bool) -> Result<Value, String> {
+    let limit = int_arg(&arg).ok_or_else(|| invalid_int(&arg))?;
+    let limit = usize::try_from(limit).unwrap_or(0);
+    Ok(Value::SafeString(urlize_text(
+        &value,
+        Some(limit),
+        autoescape,
+    )))
+}
+
+/// Turn the URLs and email addresses in a value into links, like Django's `urlize`,
+/// with link text cut to `limit` characters. With `autoescape`, the rest of the text
+/// is escaped unless the value is already safe.
+fn urlize_text(value: &Value, limit: Option<usize>, autoescape: bool) -> String {
+    let escape = autoescape && !matches!(value, Value::SafeString(_));
+    let text = value.to_string();
+    let escaped = |text: &str| {
+        if escape {
+            escape_html(text)
+        } else {
+            text.to_string()
+        }
+    };
+    let is_separator = |c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '\'');
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+    while !rest.is_empty() {
+        let word_end = rest.find(is_separator).unwrap_or(rest.len());
+        output.push_str(&urlize_word(&rest[..word_end], limit, escape));
+        rest = &rest[word_end..];
+        let separator_end = rest.find(|c| !is_separator(c)).unwrap_or(rest.len());
+        output.push_str(&escaped(&rest[..separator_end]));
+        rest = &rest[separator_end..];
+    }
+    output
+}
+
+/// A word of `urlize`'s text as a link, if it is a URL or email address. The word is
+/// escaped when `escape` is set.
+fn urlize_word(word: &str, limit: Option<usize>, escape: bool) -> String {
+    let escaped = |text: &str| {
+        if escape {
+            escape_html(text)
+        } else {
+            text.to_string()
+        }
+    };
+    if !word.contains(['.', '@', ':']) {
+        return escaped(word);
+    }
+    let (lead, middle, trail) = trim_punctuation(word);
+    let mut rel = " rel=\"nofollow\"";
+    let short = middle.chars().count() <= MAX_URL_LENGTH;
+    let url = if short && is_simple_url(&middle) {
+        smart_urlquote(&unescape(&middle))
+    } else if short && is_simple_domain_url(&middle) {
+        smart_urlquote(&format!("http://{}", unescape(&middle)))
+    } else if !middle.contains(':') && is_email_simple(&middle) {
+        rel = "";
+        format!("mailto:{}", middle)
+    } else {
+        return escaped(word);
+    };
+    let text = match limit {
+        Some(limit) if middle.chars().count() > limit => {
+            let kept: String = middle.chars().take(limit.saturating_sub(1)).collect();
+            format!("{}…", kept)
+        }
+        _ => middle,
+    };
+    format!(
+        "{}<a href=\"{}\"{}>{}</a>{}",
+        escaped(&lead),
+        escape_html(&url),
+        rel,
+        escaped(&text),
+        escaped(&trail)
+    )
+}
+
+/// Split a word into leading punctuation, the possible URL, and trailing punctuation.
+/// Closing brackets stay in the URL when they are balanced.
+fn trim_punctuation(word: &str) -> (String, String, String) {
+    let (mut lead, mut middle, mut trail) = (String::new(), word.to_string(), String::new());
+    let mut trimmed = true;
+    while trimmed {
+        trimmed = false;
+        for (opening, closing) in [('(', ')'), ('[', ']')] {
+            if middle.starts_with(opening) {
+                middle.remove(0);
+                lead.push(opening);
+                trimmed = true;
+            }
+            if middle.ends_with(closing)
+                && middle.matches(closing).count() == middle.matches(opening).count() + 1
+            {
+                middle.pop();
+                trail.insert(0, closing);
+                trimmed = true;
+            }
+        }
+        // Unescape entities, so that their semicolons are not taken for punctuation
+        let unescaped = unescape(&middle);
+        let stripped = unescaped.trim_end_matches(['.', ',', ':', ';', '!']);
+        if stripped.len() != unescaped.len() {
+            let count = unescaped[stripped.len()..].chars().count();
+            let split = middle
+                .char_indices()
+                .rev()
+                .nth(count - 1)
+                .map_or(0, |(index, _)| index);
+            trail.insert_str(0, &middle[split..]);
+            middle.truncate(split);
+            trimmed = true;
+        }
+    }
+    (lead, middle, trail)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Whether the text starts like an HTTP URL, e.g. `https://example.com`.
+fn is_simple_url(text: &str) -> bool {
+    let lower = text.to_ascii_lowercase();
+    let Some(rest) = lower
+        .strip_prefix("http://")
+        .or_else(|| lower.strip_prefix("https://"))
+    else {
+        return false;
+    };
+    let rest = rest.strip_prefix('[').unwrap_or(rest);
+    rest.starts_with(is_word_char)
+}
+
+/// Whether the text is a URL without a scheme, e.g. `www.example.com` or
+/// `example.org/page`.
+fn is_simple_domain_url(text: &str) -> bool {
+    let lower = text.to_ascii_lowercase();
+    if lower.starts_with("www.") {
+        return true;
+    }
+    let Some(first) = lower.chars().next().filter(|c| is_word_char(*c)) else {
+        return false;
+    };
+    if lower.starts_with("http") {
+        return false;
+    }
+    ["com", "edu", "gov", "int", "mil", "net", "org"]
+        .iter()
+        .any(|tld| {
+            let suffix = format!(".{}", tld);
+            lower.match_indices(&suffix).any(|(index, _)| {
+                let after = &lower[index + suffix.len()..];
+                index > first.len_utf8()
+                    && !lower[..index].contains('@')
+                    && (after.is_empty() || after.starts_with('/'))
+            })
+        })
+}
+
+fn is_email_simple(text: &str) -> bool {
+    match text.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+        }
+        None => false,
+    }
+}
+
+/// Quote a URL that may already be partly quoted, like Django's `smart_urlquote`.
+fn smart_urlquote(url: &str) -> String {
+    let unquote_quote = |segment: &str| quote(&unquote(segment), "!$&'()*+,;=:/?#[]@~");
+    let (scheme, rest) = match url.split_once(':') {
+        Some((scheme, rest))
+            if scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')) =>
+        {
+            (scheme.to_ascii_lowercase(), rest)
+        }
+        _ => (String::new(), url),
+    };
+    let (netloc, rest) = match rest.strip_prefix("//") {
+        Some(rest) => {
+            let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+            (Some(&rest[..end]), &rest[end..])
+        }
+        None => (None, rest),
+    };
+    let (rest, fragment) = rest.split_once('#').unwrap_or((rest, ""));
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+    let mut quoted = String::new();
+    if !scheme.is_empty() {
+        quoted.push_str(&scheme);
+        quoted.push(':');
+    }
+    if let Some(netloc) = netloc.filter(|netloc| !netloc.is_empty()) {
+        quoted.push_str("//");
+        quoted.push_str(netloc);
+        if !path.is_empty() && !path.starts_with('/') {
+            quoted.push('/');
+        }
+    }
+    quoted.push_str(&unquote_quote(path));
+    if !query.is_empty() {
+        // Decode the query like `parse_qsl`, then once more before encoding it again
+        let pairs: Vec<String> = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                let decode = |text: &str| unquote(&unquote(&text.replace('+', " ")));
+                format!(
+                    "{}={}",
+                    quote_plus(&decode(key)),
+                    quote_plus(&decode(value))
+                )
+            })
+            .collect();
+        if !pairs.is_empty() {
+            quoted.push('?');
+            quoted.push_str(&pairs.join("&"));
+        }
+    }
+    let fragment = unquote_quote(fragment);
+    if !fragment.is_empty() {
+        quoted.push('#');
+        quoted.push_str(&fragment);
+    }
+    quoted
+}
+
+fn wordcount(value: Value, _: Option<Value>) -> Result<Value, String> {
+    Ok(Value::Int(
+        value.to_string().split_whitespace().count() as i64
+    ))
+}
+
+/// Expand tabs to the next multiple of eight columns, like Python's `str.expandtabs`.
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::with_capacity(line.len());
+    let mut column = 0;
+    for c in line.chars() {
+        match c {
+            '\t' => {
+                let spaces = 8 - column % 8;
+                expanded.push_str(&" ".repeat(spaces));
+                column += spaces;
+            }
+            '\n' | '\r' => {
+                expanded.push(c);
+                column = 0;
+            }
+            c => {
+                expanded.push(c);
+                column += 1;
+            }
+        }
+    }
+    expanded
+}
+
+/// Wrap one line at `width`, the way Python's `textwrap` does without breaking long
+/// words or hyphenated ones.
+fn wrap_line(line: &str, width: usize) -> Vec<String> {
+    let is_space = |c: char| matches!(c, '\t' | '\n' | '\x0b' | '\x0c' | '\r' | ' ');
+    let line = expand_tabs(line);
+    let mut chunks: Vec<&str> = Vec::new();
+    let mut rest = line.as_str();
+    while let Some(c) = rest.chars().next() {
+        let end = if is_space(c) {
+            rest.find(|c| !is_space(c))
+        } else {
+            rest.find(is_space)
+        }
+        .unwrap_or(rest.len());
+        chunks.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    chunks.reverse();
+    let is_blank = |chunk: &str| chunk.chars().all(is_space);
+    let mut lines = Vec::new();
+    while !chunks.is_empty() {
+        if !lines.is_empty() && chunks.last().is_some_and(|chunk| is_blank(chunk)) {
+            chunks.pop();
+        }
+        let mut current = Vec::new();
+        let mut len = 0;
+        while let Some(chunk) = chunks.last() {
+            let chunk_len = chunk.chars().count();
+            if len + chunk_len > width {
+                break;
+            }
+            current.extend(chunks.pop());
+            len += chunk_len;
+        }
+        if current.is_empty() {
+            current.extend(chunks.pop());
+        }
+        if current.last().is_some_and(|chunk| is_blank(chunk)) {
+            current.pop();
+        }
+        if !current.is_empty() {
+            lines.push(current.concat());
+        }
+    }
+    lines
+}
+
+fn wordwrap(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let width = int_arg(&arg).ok_or_else(|| invalid_int(&arg))?;
+    let width = match usize::try_from(width) {
+        Ok(width) if width > 0 => width,
+        _ => return Err(format!("invalid width {} (must be > 0)", width)),
+    };
+    let text = value.to_string();
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let wrapped = wrap_line(line, width);
+        if wrapped.is_empty() {
+            // Keep lines of only whitespace, which wrapping drops
+            lines.push(line.to_string());
+        } else {
+            lines.extend(wrapped);
+        }
+    }
+    if text.ends_with('\n') {
+        lines.push(String::new());
+    }
+    Ok(Value::Str(lines.join("\n")))
+}
+
+fn yesno(value: Value, arg: Option<Value>) -> Result<Value, String> {
+    let arg = arg.map_or_else(|| "yes,no,maybe".to_string(), |arg| arg.to_string());
+    let bits: Vec<&str> = arg.split(',').collect();
+    let (yes, no, maybe) = match bits[..] {
+        [_] => return Ok(value),
+        [yes, no, maybe] => (yes, no, maybe),
+        [yes, no, ..] => (yes, no, no),
+        [] => unreachable!("split always yields a piece"),
+    };
+    Ok(Value::from(match value {
+        Value::None => maybe,
+        value if value.is_truthy() => yes,
+        _ => no,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(name: &str, value: impl Into<Value>, arg: Option<Value>) -> String {
+        apply(name, value.into(), arg, false).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_strings() {
+        let tests = vec![
+            (("addslashes", "I'm \"here\""), r#"I\'m \"here\""#),
+            (("capfirst", "élan vital"), "Élan vital"),
+            (("lower", "ÀBC"), "àbc"),
+            (("upper", "straße"), "STRASSE"),
+            (
+                ("title", "they're 1st of my friends' BFFs"),
+                "They're 1st Of My Friends' Bffs",
+            ),
+            (
+                (
+                    "slugify",
+                    " Jack & Jill like numbers 1,2,3 and 4 and silly characters ?%.$!/",
+                ),
+                "jack-jill-like-numbers-123-and-4-and-silly-characters",
+            ),
+            (
+                (
+                    "slugify",
+                    "Un \u{e9}l\u{e9}phant \u{e0} l'or\u{e9}e du bois",
+                ),
+                "un-elephant-a-loree-du-bois",
+            ),
+            (("slugify", "__strip__ --dashes--"), "strip__-dashes"),
+            (("phone2numeric", "1-800-COLLECT"), "1-800-2655328"),
+            (("wordcount", "  one two\tthree\n"), "3"),
+            (
+                ("striptags", "<p>Hello <b>world</b>!</p><!-- comment -->"),
+                "Hello world!",
+            ),
+            (("striptags", "a < b and c > d"), "a < b and c > d"),
+            (
+                ("escapejs", "</script>\n'\u{2028}"),
+                "\\u003C/script\\u003E\\u000A\\u0027\\u2028",
+            ),
+            (("iriencode", "?test=1&me=2 é"), "?test=1&me=2%20%C3%A9"),
+            (("urlencode", "/a b?c=d"), "/a%20b%3Fc%3Dd"),
+            (("linenumbers", "a\nb"), "1. a\n2. b"),
+            (
+                ("linebreaks", "a\nb\r\n\r\n\nc"),
+                "<p>a<br>b</p>\n\n<p>c</p>",
+            ),
+            (("linebreaksbr", "a\r\nb"), "a<br>b"),
+            (("make_list", "abc"), "['a', 'b', 'c']"),
+        ];
+        for ((name, input), expected) in tests {
+            println!("Testing input: {:?}", input);
+            assert_eq!(run(name, input, None), expected);
+        }
+        assert_eq!(LATIN_FOLDS.len(), 0x180 - 0xc0);
+    }
+
+    #[test]
+    fn test_arguments() {
+        let tests: Vec<((&str, Value, Value), &str)> = vec![
+            (("add", "3".into(), 4.into()), "7"),
+            (("add", "a".into(), "b".into()), "ab"),
+            (("add", "a".into(), 1.into()), ""),
+            (("center", "abc".into(), 8.into()), "  abc   "),
+            (("ljust", "abc".into(), 5.into()), "abc  "),
+            (("rjust", "abc".into(), 5.into()), "  abc"),
+            (("cut", "a b c".into(), " ".into()), "abc"),
+            (("divisibleby", 21.into(), 7.into()), "True"),
+            (("get_digit", 123456.into(), 2.into()), "5"),
+            (("get_digit", 123.into(), 5.into()), "0"),
+            (("join", vec!["a", "b"].into(), " // ".into()), "a // b"),
+            (("slice", vec![1, 2, 3, 4].into(), ":2".into()), "[1, 2]"),
+            (("slice", "abcdef".into(), "::-2".into()), "fdb"),
+            (("slice", vec![1, 2, 3].into(), "-2:".into()), "[2, 3]"),
+            (("pluralize", 1.into(), "y,ies".into()), "y"),
+            (("pluralize", 2.into(), "y,ies".into()), "ies"),
+            (("pluralize", vec![1].into(), "es".into()), ""),
+            (("stringformat", 7.into(), "03d".into()), "007"),
+            (("stringformat", 1.5.into(), ".3e".into()), "1.500e+00"),
+            (("stringformat", 255.into(), "#x".into()), "0xff"),
+            (("stringformat", 0.00001234.into(), "g".into()), "1.234e-05"),
+            (("stringformat", "x".into(), "d".into()), ""),
+            (
+                ("truncatechars", "Joel is a slug".into(), 7.into()),
+                "Joel i…",
+            ),
+            (
+                (
+                    "truncatewords",
+                    "A sentence with a few words".into(),
+                    2.into(),
+                ),
+                "A sentence …",
+            ),
+            (
+                ("wordwrap", "Joel is a slug".into(), 5.into()),
+                "Joel\nis a\nslug",
+            ),
+            (("yesno", Value::None, "yeah,no".into()), "no"),
+            (("yesno", true.into(), "yeah,no,perhaps".into()), "yeah"),
+            (
+                ("urlizetrunc", "see www.example.com/path".into(), 10.into()),
+                "see <a href=\"http://www.example.com/path\" rel=\"nofollow\">www.examp…</a>",
+            ),
+        ];
+        for ((name, input, arg), expected) in tests {
+            println!("Testing input: {:?}", input);
+            assert_eq!(run(name, input, Some(arg)), expected);
+        }
+    }
+
+    #[test]
+    fn test_floatformat() {
+        let tests: Vec<((Value, Option<&str>), &str)> = vec![
+            ((34.23234.into(), None), "34.2"),
+            ((34.00000.into(), None), "34"),
+            ((34.26000.into(), None), "34.3"),
+            ((34.23234.into(), Some("3")), "34.232"),
+            ((34.0.into(), Some("3")), "34.000"),
+            ((34.23234.into(), Some("-3")), "34.232"),
+            ((34.0.into(), Some("-3")), "34"),
+            ((39.56.into(), Some("0")), "40"),
+            ((6666.6666.into(), Some("2g")), "6,666.67"),
+            ((10000.into(), Some("g")), "10,000"),
+            ((66666.666.into(), Some("1u")), "66666.7"),
+            ((0.015.into(), Some("2")), "0.02"),
+            ((Value::from(-0.01), Some("1")), "0.0"),
+            ((Value::from(-1.05), Some("1")), "-1.1"),
+            (("1.0e3".into(), Some("2")), "1000.00"),
+            (("nan".into(), None), "nan"),
+            (("foo".into(), None), ""),
+            ((1.5.into(), Some("bar")), "1.5"),
+        ];
+        for ((input, arg), expected) in tests {
+            println!("Testing input: {:?}", input);
+            assert_eq!(run("floatformat", input, arg.map(Value::from)), expected);
+        }
+    }
+
+    #[test]
+    fn test_html() {
+        let html = "<p>one <a href=\"#\">two - three <br>four</a> five</p>";
+        let tests: Vec<((&str, &str, i64), &str)> = vec![
+            (("truncatewords_html", html, 0), ""),
+            (
+                ("truncatewords_html", html, 2),
+                "<p>one <a href=\"#\">two …</a></p>",
+            ),
+            (
+                ("truncatewords_html", html, 4),
+                "<p>one <a href=\"#\">two - three <br> …</a></p>",
+            ),
+            (("truncatewords_html", html, 100), html),
+            (
+                ("truncatechars_html", html, 9),
+                "<p>one <a href=\"#\">two …</a></p>",
+            ),
+            (
+                ("truncatechars_html", "<p>Joel &amp; a slug</p>", 6),
+                "<p>Joel …</p>",
+            ),
+            (("truncatechars_html", "abcde", 5), "abcde"),
+        ];
+        for ((name, input, length), expected) in tests {
+            println!("Testing input: {:?}", input);
+            assert_eq!(run(name, input, Some(length.into())), expected);
+        }
+    }
+
+    #[test]
+    fn test_urlize() {
+        let tests = vec![
+            ("Check out www.djangoproject.com.", "Check out <a href=\"http://www.djangoproject.com\" rel=\"nofollow\">www.djangoproject.com</a>."),
+            ("(see https://example.com/a_(b))", "(see <a href=\"https://example.com/a_(b)\" rel=\"nofollow\">https://example.com/a_(b)</a>)"),
+            ("mail foo@example.com", "mail <a href=\"mailto:foo@example.com\">foo@example.com</a>"),
+            ("djangoproject.org/path", "<a href=\"http://djangoproject.org/path\" rel=\"nofollow\">djangoproject.org/path</a>"),
+            ("http://example.com/?q=a b&x", "<a href=\"http://example.com/?q=a\" rel=\"nofollow\">http://example.com/?q=a</a> b&x"),
+            ("https://example.com/?x&y=%20", "<a href=\"https://example.com/?x=&amp;y=+\" rel=\"nofollow\">https://example.com/?x&y=%20</a>"),
+            ("not.a.url or @ either", "not.a.url or @ either"),
+        ];
+        for (input, expected) in tests {
+            println!("Testing input: {:?}", input);
+            assert_eq!(run("urlize", input, None), expected);
+        }
+    }
+
+    #[test]
+    fn test_autoescape() {
+        let tests: Vec<((&str, Value), Value)> = vec![
+            (("lower", Value::safe("<B>")), Value::safe("<b>")),
+            (("lower", "<B>".into()), "<b>".into()),
+            (("upper", Value::safe("<b>")), "<B>".into()),
+            (("escape", Value::safe("<b>")), Value::safe("<b>")),
+            (("force_escape", Value::safe("<b>")), Value::safe("&lt;b&gt;")),
+            (("safe", "<b>".into()), Value::safe("<b>")),
+            (("linebreaksbr", "<b>\n".into()), Value::safe("&lt;b&gt;<br>")),
+            (("linebreaksbr", Value::safe("<b>\n")), Value::safe("<b><br>")),
+            (("linebreaks", "a & b".into()), Value::safe("<p>a &amp; b</p>")),
+            (
+                ("linenumbers", "<a>\n<b>".into()),
+                Value::safe("1. &lt;a&gt;\n2. &lt;b&gt;"),
+            ),
+            (
+                (
+                    "unordered_list",
+                    vec![Value::from("<a>"), Value::safe("<b>")].into(),
+                ),
+                Value::safe("\t<li>&lt;a&gt;</li>\n\t<li><b></li>"),
+            ),
+            (
+                ("urlize", "<x> www.example.com/a&b".into()),
+                Value::safe("&lt;x&gt; <a href=\"http://www.example.com/a&amp;b\" rel=\"nofollow\">www.example.com/a&amp;b</a>"),
+            ),
+            (
+                ("urlize", Value::safe("<x> & ab.com")),
+                Value::safe("<x> & <a href=\"http://ab.com\" rel=\"nofollow\">ab.com</a>"),
+            ),
+        ];
+        for ((name, input), expected) in tests {
+            println!("Testing input: {:?}", input);
+            assert_eq!(apply(name, input, None, true).unwrap(), expected);
+        }
+
+        let tests: Vec<((&str, Value, Value), Value)> = vec![
+            (
+                ("slice", Value::safe("<b>"), ":2".into()),
+                Value::safe("<b"),
+            ),
+            (
+                ("join", vec!["<a>", "b"].into(), "<br>".into()),
+                Value::safe("&lt;a&gt;&lt;br&gt;b"),
+            ),
+            (
+                ("join", vec!["<a>", "b"].into(), Value::safe("<br>")),
+                Value::safe("&lt;a&gt;<br>b"),
+            ),
+        ];
+        for ((name, input, arg), expected) in tests {
+            println!("Testing input: {:?}", input);
+            assert_eq!(apply(name, input, Some(arg), true).unwrap(), expected);
+        }
+        // Without autoescaping, Python's `str.join` cannot join other values
+        let list = Value::from(vec![1, 2]);
+        assert_eq!(
+            apply("join", list.clone(), Some(",".into()), false).unwrap(),
+            list
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let tests = vec![
+            (
+                apply("nonexistent", Value::None, None, false),
+                "invalid filter: 'nonexistent'",
+            ),
+            (
+                apply("default", Value::None, None, false),
+                "default requires 2 arguments, 1 provided",
+            ),
+            (
+                apply("lower", Value::None, Some(Value::None), false),
+                "lower requires 1 arguments, 2 provided",
+            ),
+            (
+                apply("divisibleby", 4.into(), Some(0.into()), false),
+                "'divisibleby' filter failed: integer modulo by zero",
+            ),
+        ];
+        for (result, expected) in tests {
+            println!("Testing input: {:?}", expected);
+            assert_eq!(result.unwrap_err().to_string(), expected);
+        }
+    }
+}
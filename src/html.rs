@@ -0,0 +1,876 @@
+use crate::ast::Node;
+use crate::span::Span;
+use std::cell::Cell;
+use std::fmt;
+
+/// Elements that never have children or an end tag.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements whose content is not parsed as HTML, although it may contain Django nodes.
+/// The lexer uses the same list, so their content is never lexed as markup.
+pub(crate) const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea", "title"];
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<Attribute>,
+    /// Whitespace before the `>` or `/>` of the start tag.
+    pub trivia: String,
+    pub self_closing: bool,
+    pub children: Vec<Node>,
+    /// The end tag as written, or `None` for void elements and elements that are
+    /// never closed.
+    pub end: Option<String>,
+    /// From the `<` of the start tag to the end tag, or to the last child of an element
+    /// that is never closed.
+    pub span: Span,
+}
+
+impl Element {
+    pub fn is_void(&self) -> bool {
+        is_void(&self.name)
+    }
+
+    /// The first HTML attribute called `name`.
+    pub fn attribute(&self, name: &str) -> Option<&HtmlAttribute> {
+        self.attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                Attribute::Html(attribute) if attribute.name.eq_ignore_ascii_case(name) => {
+                    Some(attribute)
+                }
+                _ => None,
+            })
+    }
+}
+
+impl fmt::Display for Element {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{}", self.name)?;
+        for attribute in &self.attributes {
+            write!(f, "{}", attribute)?;
+        }
+        let close = if self.self_closing { "/>" } else { ">" };
+        write!(f, "{}{}", self.trivia, close)?;
+        for child in &self.children {
+            write!(f, "{}", child)?;
+        }
+        if let Some(end) = &self.end {
+            write!(f, "{}", end)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Attribute {
+    Html(HtmlAttribute),
+    /// A Django node in place of an attribute, e.g. `{% if a %}checked{% endif %}`.
+    Template {
+        node: Box<Node>,
+        trivia: String,
+    },
+}
+
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Attribute::Html(attribute) => write!(f, "{}", attribute),
+            Attribute::Template { node, trivia } => write!(f, "{}{}", trivia, node),
+        }
+    }
+}
+
+/// `name`, `name=value` or `name="value"`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct HtmlAttribute {
+    pub name: String,
+    /// `None` for boolean attributes.
+    pub value: Option<AttributeValue>,
+    /// Whitespace before the name, and the `=` with any whitespace around it.
+    pub trivia: Vec<String>,
+}
+
+impl fmt::Display for HtmlAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let trivia = |index| self.trivia.get(index).map_or("", String::as_str);
+        write!(f, "{}{}", trivia(0), self.name)?;
+        if let Some(value) = &self.value {
+            write!(f, "{}{}", trivia(1), value)?;
+        }
+        Ok(())
+    }
+}
+
+/// An attribute value, made of text and any Django nodes inside it.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct AttributeValue {
+    pub quote: Option<char>,
+    pub parts: Vec<Node>,
+}
+
+impl fmt::Display for AttributeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(quote) = self.quote {
+            write!(f, "{}", quote)?;
+        }
+        for part in &self.parts {
+            write!(f, "{}", part)?;
+        }
+        if let Some(quote) = self.quote {
+            write!(f, "{}", quote)?;
+        }
+        Ok(())
+    }
+}
+
+/// The content of a `<script>` or `<style>` element, which is in another language but
+/// may still contain Django nodes.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Embedded {
+    pub language: Language,
+    pub nodes: Vec<Node>,
+}
+
+impl fmt::Display for Embedded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for node in &self.nodes {
+            write!(f, "{}", node)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Language {
+    JavaScript,
+    Json,
+    Css,
+    /// A script with a `type` we do not recognize, e.g. `text/x-template`.
+    Other(String),
+}
+
+impl Language {
+    /// The language of a `<script>` or `<style>` element's content.
+    fn of(element: &Element) -> Option<Language> {
+        if element.name.eq_ignore_ascii_case("style") {
+            return Some(Language::Css);
+        }
+        if !element.name.eq_ignore_ascii_case("script") {
+            return None;
+        }
+        let script_type = element
+            .attribute("type")
+            .and_then(|attribute| attribute.value.as_ref())
+            .map(|value| {
+                let parts: String = value.parts.iter().map(Node::to_string).collect();
+                parts.trim().to_ascii_lowercase()
+            })
+            .unwrap_or_default();
+        let language = match script_type.as_str() {
+            "" | "module" | "text/javascript" | "application/javascript" | "text/ecmascript" => {
+                Language::JavaScript
+            }
+            "application/json" | "application/ld+json" | "importmap" | "speculationrules" => {
+                Language::Json
+            }
+            _ => Language::Other(script_type),
+        };
+        Some(language)
+    }
+}
+
+fn is_void(name: &str) -> bool {
+    VOID_ELEMENTS
+        .iter()
+        .any(|void| void.eq_ignore_ascii_case(name))
+}
+
+fn is_raw_text(name: &str) -> bool {
+    RAW_TEXT_ELEMENTS
+        .iter()
+        .any(|raw| raw.eq_ignore_ascii_case(name))
+}
+
+/// Assemble the HTML in a list of sibling nodes into elements. Markup that does not
+/// form a tag, and end tags without a matching start tag, are left as text. Elements
+/// do not span Django blocks, so an element opened inside a block but closed after it
+/// is left unclosed.
+pub fn build_elements(nodes: Vec<Node>) -> Vec<Node> {
+    build_elements_reach(&nodes).0
+}
+
+/// Like `build_elements`, also returning for each resulting node how many of `nodes`
+/// were read to build it and the nodes before it. That is one more than there are when
+/// nodes following these could change it: an element is still open, or a start tag,
+/// comment or raw text element ran into the end of the nodes.
+pub(crate) fn build_elements_reach(nodes: &[Node]) -> (Vec<Node>, Vec<usize>) {
+    let read = Cell::new(0);
+    let mut builder = TreeBuilder::default();
+    let mut cursor = Cursor::new(nodes, &read);
+    let mut reach = Vec::new();
+    while !cursor.is_at_end() {
+        build_next(&mut builder, &mut cursor);
+        reach.resize(builder.root.len(), read.get());
+    }
+    let read = if builder.open.is_empty() {
+        read.get()
+    } else {
+        nodes.len() + 1
+    };
+    let built = builder.finish();
+    reach.resize(built.len(), read);
+    (built, reach)
+}
+
+/// Build what starts at the cursor: a node, an element or a piece of text.
+fn build_next(builder: &mut TreeBuilder, cursor: &mut Cursor) {
+    if let Some(node) = cursor.node() {
+        builder.push(node.clone());
+        cursor.next_node();
+        return;
+    }
+    let start = cursor.position();
+    if cursor.starts_with("<!--") {
+        if let Some(comment) = html_comment(cursor) {
+            builder.push(comment);
+            return;
+        }
+    } else if cursor.starts_with("</") {
+        if let Some((name, end)) = end_tag(cursor) {
+            builder.close(&name, end, Span::new(start, cursor.position()));
+            return;
+        }
+    } else if let Some(mut element) = start_tag(cursor) {
+        if element.self_closing || element.is_void() {
+            builder.push(Node::Element(element));
+        } else if is_raw_text(&element.name) {
+            let nodes = raw_text(cursor, &element.name);
+            if let Some(last) = nodes.last() {
+                element.span.end = last.span().end;
+            }
+            element.children = match Language::of(&element) {
+                Some(language) if !nodes.is_empty() => {
+                    vec![Node::Embedded(Embedded { language, nodes })]
+                }
+                _ => nodes,
+            };
+            element.end = end_tag(cursor).map(|(_, end)| end);
+            if element.end.is_some() {
+                element.span.end = cursor.position();
+            }
+            builder.push(Node::Element(element));
+        } else {
+            builder.open(element);
+        }
+        return;
+    }
+
+    let text = cursor.text().unwrap_or_default();
+    let size = text
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| *c == '<')
+        .map_or(text.len(), |(index, _)| index);
+    builder.push_text(&text[..size], Span::new(start, start + size));
+    cursor.advance(size);
+}
+
+/// `<!-- ... -->`, which may contain Django nodes.
+fn html_comment(cursor: &mut Cursor) -> Option<Node> {
+    let start = cursor.position();
+    let mut inner = *cursor;
+    inner.advance("<!--".len());
+    let (parts, found) = parts_until(&mut inner, |text| text.find("-->"));
+    if !found {
+        return None;
+    }
+    inner.advance("-->".len());
+    *cursor = inner;
+    Some(Node::HtmlComment {
+        nodes: parts,
+        span: Span::new(start, cursor.position()),
+    })
+}
+
+/// `</name>`, returning the name and the tag as written.
+fn end_tag(cursor: &mut Cursor) -> Option<(String, String)> {
+    let text = cursor.text()?;
+    let rest = text.strip_prefix("</")?;
+    let name_size = tag_name_size(rest);
+    let after_name = &rest[name_size..];
+    let whitespace = after_name.len() - after_name.trim_start().len();
+    if name_size == 0 || !after_name[whitespace..].starts_with('>') {
+        return None;
+    }
+    let size = "</".len() + name_size + whitespace + ">".len();
+    let end = text[..size].to_string();
+    cursor.advance(size);
+    Some((rest[..name_size].to_string(), end))
+}
+
+/// `<name attributes>` or `<name attributes/>`, without its children.
+fn start_tag(cursor: &mut Cursor) -> Option<Element> {
+    let start = cursor.position();
+    let mut inner = *cursor;
+    let rest = inner.text()?.strip_prefix('<')?;
+    let name_size = tag_name_size(rest);
+    if name_size == 0 {
+        return None;
+    }
+    let name = rest[..name_size].to_string();
+    inner.advance(1 + name_size);
+
+    let mut attributes = Vec::new();
+    loop {
+        let trivia = inner.take_while(char::is_whitespace).to_string();
+        if let Some(node) = inner.node() {
+            attributes.push(Attribute::Template {
+                node: Box::new(node.clone()),
+                trivia,
+            });
+            inner.next_node();
+            continue;
+        }
+        let text = inner.text()?;
+        let self_closing = text.starts_with("/>");
+        if self_closing || text.starts_with('>') {
+            inner.advance(if self_closing { 2 } else { 1 });
+            *cursor = inner;
+            return Some(Element {
+                name,
+                attributes,
+                trivia,
+                self_closing,
+                children: Vec::new(),
+                end: None,
+                span: Span::new(start, cursor.position()),
+            });
+        }
+        attributes.push(Attribute::Html(attribute(&mut inner, trivia)?));
+    }
+}
+
+fn attribute(cursor: &mut Cursor, trivia: String) -> Option<HtmlAttribute> {
+    let name = cursor
+        .take_while(|c| !c.is_whitespace() && !matches!(c, '=' | '>' | '"' | '\'' | '<'))
+        .to_string();
+    let name = match name.strip_suffix('/') {
+        // `<br/>`: the slash belongs to the end of the tag
+        Some(name) => {
+            cursor.retreat(1);
+            name.to_string()
+        }
+        None => name,
+    };
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut value_cursor = *cursor;
+    let mut separator = value_cursor.take_while(char::is_whitespace).to_string();
+    if value_cursor.peek() != Some('=') {
+        return Some(HtmlAttribute {
+            name,
+            value: None,
+            trivia: vec![trivia],
+        });
+    }
+    value_cursor.advance(1);
+    separator.push('=');
+    separator.push_str(value_cursor.take_while(char::is_whitespace));
+
+    let value = match value_cursor.peek() {
+        Some(quote @ ('"' | '\'')) => {
+            value_cursor.advance(1);
+            let (parts, found) = parts_until(&mut value_cursor, |text| text.find(quote));
+            if !found {
+                return None;
+            }
+            value_cursor.advance(1);
+            AttributeValue {
+                quote: Some(quote),
+                parts,
+            }
+        }
+        _ => {
+            let (parts, _) = parts_until(&mut value_cursor, |text| {
+                text.find(|c: char| c.is_whitespace() || c == '>')
+            });
+            if parts.is_empty() {
+                return None;
+            }
+            AttributeValue { quote: None, parts }
+        }
+    };
+    *cursor = value_cursor;
+    Some(HtmlAttribute {
+        name,
+        value: Some(value),
+        trivia: vec![trivia, separator],
+    })
+}
+
+/// The content of a raw text element, up to its end tag.
+fn raw_text(cursor: &mut Cursor, name: &str) -> Vec<Node> {
+    let end = format!("</{}", name.to_ascii_lowercase());
+    let (parts, _) = parts_until(cursor, |text| text.to_ascii_lowercase().find(&end));
+    parts
+}
+
+/// Collect text and nodes up to the point where `find` matches in a text node,
+/// returning whether it matched before the end of the nodes.
+fn parts_until(cursor: &mut Cursor, find: impl Fn(&str) -> Option<usize>) -> (Vec<Node>, bool) {
+    let mut parts = Vec::new();
+    while !cursor.is_at_end() {
+        if let Some(node) = cursor.node() {
+            parts.push(node.clone());
+            cursor.next_node();
+            continue;
+        }
+        let text = cursor.text().unwrap_or_default();
+        let size = find(text).unwrap_or(text.len());
+        if size > 0 {
+            let start = cursor.position();
+            parts.push(Node::Text {
+                text: text[..size].to_string(),
+                span: Span::new(start, start + size),
+            });
+            cursor.advance(size);
+        }
+        if size < text.len() {
+            return (parts, true);
+        }
+    }
+    cursor.read.set(cursor.nodes.len() + 1);
+    (parts, false)
+}
+
+fn tag_name_size(source: &str) -> usize {
+    if !source.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return 0;
+    }
+    source
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == ':' || c == '_'))
+        .unwrap_or(source.len())
+}
+
+/// Keeps the elements that are still open, innermost last.
+#[derive(Default)]
+struct TreeBuilder {
+    root: Vec<Node>,
+    open: Vec<Element>,
+    text: String,
+    text_span: Span,
+}
+
+impl TreeBuilder {
+    fn children(&mut self) -> &mut Vec<Node> {
+        match self.open.last_mut() {
+            Some(element) => &mut element.children,
+            None => &mut self.root,
+        }
+    }
+
+    fn flush_text(&mut self) {
+        if !self.text.is_empty() {
+            let text = std::mem::take(&mut self.text);
+            let span = self.text_span;
+            self.children().push(Node::Text { text, span });
+        }
+    }
+
+    fn push_text(&mut self, text: &str, span: Span) {
+        self.text_span = if self.text.is_empty() {
+            span
+        } else {
+            self.text_span.join(span)
+        };
+        self.text.push_str(text);
+    }
+
+    fn push(&mut self, node: Node) {
+        self.flush_text();
+        self.children().push(node);
+    }
+
+    fn open(&mut self, element: Element) {
+        self.flush_text();
+        self.open.push(element);
+    }
+
+    /// Close the innermost open element called `name`, leaving any elements opened
+    /// inside it unclosed.
+    fn close(&mut self, name: &str, end: String, span: Span) {
+        let Some(index) = self
+            .open
+            .iter()
+            .rposition(|element| element.name.eq_ignore_ascii_case(name))
+        else {
+            self.push_text(&end, span);
+            return;
+        };
+        self.flush_text();
+        while self.open.len() > index + 1 {
+            self.pop();
+        }
+        if let Some(element) = self.open.last_mut() {
+            element.end = Some(end);
+            element.span.end = span.end;
+        }
+        self.pop();
+    }
+
+    fn pop(&mut self) {
+        if let Some(mut element) = self.open.pop() {
+            if element.end.is_none() {
+                if let Some(last) = element.children.last() {
+                    element.span.end = last.span().end;
+                }
+            }
+            self.children().push(Node::Element(element));
+        }
+    }
+
+    fn finish(mut self) -> Vec<Node> {
+        self.flush_text();
+        while !self.open.is_empty() {
+            self.pop();
+        }
+        self.root
+    }
+}
+
+/// A position in a list of nodes, inside a text node or on another node.
+#[derive(Clone, Copy)]
+struct Cursor<'a> {
+    nodes: &'a [Node],
+    index: usize,
+    offset: usize,
+    /// How many nodes have been looked at, one more than there are once text was looked
+    /// for past the end.
+    read: &'a Cell<usize>,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(nodes: &'a [Node], read: &'a Cell<usize>) -> Self {
+        let mut cursor = Cursor {
+            nodes,
+            index: 0,
+            offset: 0,
+            read,
+        };
+        cursor.settle();
+        cursor
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.index >= self.nodes.len()
+    }
+
+    /// The rest of the current text node.
+    fn text(&self) -> Option<&'a str> {
+        self.mark_read();
+        match self.nodes.get(self.index) {
+            Some(Node::Text { text, .. }) => Some(&text[self.offset..]),
+            _ => None,
+        }
+    }
+
+    /// The current node, unless it is text.
+    fn node(&self) -> Option<&'a Node> {
+        self.mark_read();
+        match self.nodes.get(self.index) {
+            Some(Node::Text { .. }) | None => None,
+            node => node,
+        }
+    }
+
+    /// The offset of the cursor in the source. Text whose whitespace was normalized
+    /// is shorter or longer than its span, so offsets inside it are clamped to it.
+    fn position(&self) -> usize {
+        match self.nodes.get(self.index) {
+            Some(node) => {
+                let span = node.span();
+                (span.start + self.offset).min(span.end)
+            }
+            None => self.nodes.last().map_or(0, |node| node.span().end),
+        }
+    }
+
+    fn mark_read(&self) {
+        self.read.set(self.read.get().max(self.index + 1));
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text()?.chars().next()
+    }
+
+    fn starts_with(&self, prefix: &str) -> bool {
+        self.text().is_some_and(|text| text.starts_with(prefix))
+    }
+
+    fn next_node(&mut self) {
+        self.index += 1;
+        self.offset = 0;
+        self.settle();
+    }
+
+    /// Move forward `size` bytes within the current text node.
+    fn advance(&mut self, size: usize) {
+        self.offset += size;
+        self.settle();
+    }
+
+    /// Move back `size` bytes within the current text node.
+    fn retreat(&mut self, size: usize) {
+        if self.offset < size {
+            // Text nodes are never empty, so this was the end of the previous one
+            self.index -= 1;
+            if let Some(Node::Text { text, .. }) = self.nodes.get(self.index) {
+                self.offset = text.len();
+            }
+        }
+        self.offset -= size;
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'a str {
+        let text = self.text().unwrap_or_default();
+        let size = text.find(|c| !predicate(c)).unwrap_or(text.len());
+        self.advance(size);
+        &text[..size]
+    }
+
+    /// Step past the end of a text node, and past empty text nodes.
+    fn settle(&mut self) {
+        while let Some(Node::Text { text, .. }) = self.nodes.get(self.index) {
+            if self.offset < text.len() {
+                break;
+            }
+            self.index += 1;
+            self.offset = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::Builtin;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Vec<Node> {
+        let tokens = Lexer::lossless(source).tokenize().unwrap();
+        Parser::new(tokens).parse().unwrap().nodes
+    }
+
+    fn text(text: &str, start: usize) -> Node {
+        Node::Text {
+            text: text.to_string(),
+            span: Span::new(start, start + text.len()),
+        }
+    }
+
+    fn element(node: &Node) -> &Element {
+        match node {
+            Node::Element(element) => element,
+            node => panic!("Expected an element, got {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_elements() {
+        let nodes = parse("<ul>\n  <li>One</li>\n  <li>{{ two }}</li>\n</ul>");
+
+        assert_eq!(nodes.len(), 1);
+        let list = element(&nodes[0]);
+        assert_eq!(list.name, "ul");
+        assert_eq!(list.end.as_deref(), Some("</ul>"));
+        let items: Vec<&Element> = list
+            .children
+            .iter()
+            .filter_map(|child| match child {
+                Node::Element(element) => Some(element),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].children, vec![text("One", 11)]);
+        assert!(matches!(items[1].children[0], Node::Variable(_)));
+    }
+
+    #[test]
+    fn test_attributes() {
+        let nodes = parse(r#"<input type="checkbox" value=1 checked data-x = 'a b'>"#);
+
+        let input = element(&nodes[0]);
+        assert!(input.is_void());
+        assert_eq!(input.end, None);
+        let test_cases = vec![
+            ("type", Some(Some('"')), "checkbox", 13),
+            ("value", Some(None), "1", 29),
+            ("checked", None, "", 0),
+            ("data-x", Some(Some('\'')), "a b", 49),
+        ];
+        for (name, quote, value_text, start) in test_cases {
+            println!("Testing attribute: {:?}", name);
+
+            let attribute = input.attribute(name).unwrap();
+            assert_eq!(attribute.value.as_ref().map(|value| value.quote), quote);
+            if let Some(value) = &attribute.value {
+                assert_eq!(value.parts, vec![text(value_text, start)]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_django_in_attributes() {
+        let nodes = parse(
+            r#"<div class="a {% if b %}c{% endif %}" {% if d %}hidden{% endif %} id={{ e }}></div>"#,
+        );
+
+        let div = element(&nodes[0]);
+        let class = div.attribute("class").unwrap().value.as_ref().unwrap();
+        assert_eq!(class.parts[0], text("a ", 12));
+        assert!(matches!(class.parts[1], Node::Builtin(Builtin::If(_))));
+        let Attribute::Template { node, .. } = &div.attributes[1] else {
+            panic!("Expected a template attribute, got {:?}", div.attributes[1]);
+        };
+        assert!(matches!(**node, Node::Builtin(Builtin::If(_))));
+        let id = div.attribute("id").unwrap().value.as_ref().unwrap();
+        assert!(matches!(id.parts[0], Node::Variable(_)));
+    }
+
+    #[test]
+    fn test_comments_and_unmatched_markup() {
+        let nodes = parse("<!-- {{ a }} --><p>a < b</span><br/>");
+
+        let Node::HtmlComment { nodes: parts, span } = &nodes[0] else {
+            panic!("Expected a comment, got {:?}", nodes[0]);
+        };
+        assert!(matches!(parts[1], Node::Variable(_)));
+        assert_eq!(*span, Span::new(0, 16));
+        let paragraph = element(&nodes[1]);
+        assert_eq!(paragraph.end, None);
+        assert_eq!(paragraph.children[0], text("a < b</span>", 19));
+        assert!(element(&paragraph.children[1]).self_closing);
+    }
+
+    #[test]
+    fn test_elements_inside_blocks() {
+        let nodes = parse("{% if a %}<b>{% else %}<i>{% endif %}x</b>");
+
+        let Node::Builtin(Builtin::If(block)) = &nodes[0] else {
+            panic!("Expected an if block, got {:?}", nodes[0]);
+        };
+        assert_eq!(element(&block.branches[0].nodes[0]).name, "b");
+        assert_eq!(element(&block.branches[1].nodes[0]).name, "i");
+        assert_eq!(nodes[1], text("x</b>", 37));
+    }
+
+    #[test]
+    fn test_element_spans() {
+        let source = "<ul><li>a<li>b</ul><p>{{ c }}<script>d";
+        let nodes = parse(source);
+
+        let list = element(&nodes[0]);
+        assert_eq!(list.span, Span::new(0, 19));
+        let item = element(&list.children[0]);
+        assert_eq!(item.span, Span::new(4, 14));
+        assert_eq!(item.children[0].span(), Span::new(8, 9));
+        assert_eq!(item.children[1].span(), Span::new(9, 14));
+        // Elements that are never closed end with their last child
+        let paragraph = element(&nodes[1]);
+        assert_eq!(paragraph.span, Span::new(19, 38));
+        assert_eq!(paragraph.children[0].span(), Span::new(22, 29));
+        assert_eq!(paragraph.children[1].span(), Span::new(29, 38));
+    }
+
+    #[test]
+    fn test_raw_text_elements() {
+        let nodes = parse(
+            "<script>if (a<b) { x = '{{ y }}'; }</script><textarea><b></textarea><title>a<b</title>",
+        );
+
+        let script = element(&nodes[0]);
+        let Node::Embedded(embedded) = &script.children[0] else {
+            panic!("Expected embedded code, got {:?}", script.children);
+        };
+        assert_eq!(embedded.language, Language::JavaScript);
+        assert_eq!(embedded.nodes.len(), 3);
+        assert_eq!(embedded.nodes[0], text("if (a<b) { x = '", 8));
+        assert_eq!(script.end.as_deref(), Some("</script>"));
+
+        let textarea = element(&nodes[1]);
+        assert_eq!(textarea.children, vec![text("<b>", 54)]);
+
+        let title = element(&nodes[2]);
+        assert_eq!(title.children, vec![text("a<b", 75)]);
+        assert_eq!(title.end.as_deref(), Some("</title>"));
+    }
+
+    #[test]
+    fn test_django_in_raw_text_elements() {
+        for name in RAW_TEXT_ELEMENTS {
+            let input = format!("<{name}><b>{{{{ a }}}}</b></{name}>");
+            println!("Testing input: {:?}", input);
+
+            let nodes = parse(&input);
+            assert_eq!(nodes.len(), 1, "For input: {}", input);
+            let raw = element(&nodes[0]);
+            assert_eq!(raw.name, *name);
+            let children = match &raw.children[..] {
+                [Node::Embedded(embedded)] => &embedded.nodes,
+                children => children,
+            };
+            assert_eq!(children.len(), 3, "For input: {}", input);
+            let start = name.len() + 2;
+            assert_eq!(children[0], text("<b>", start));
+            assert!(matches!(children[1], Node::Variable(_)));
+            assert_eq!(children[2], text("</b>", start + 10));
+            assert_eq!(nodes[0].to_string(), input);
+        }
+    }
+
+    #[test]
+    fn test_embedded_languages() {
+        let test_cases = vec![
+            ("<style>a {}</style>", Language::Css),
+            ("<script type=module>a</script>", Language::JavaScript),
+            (
+                r#"<script type="application/ld+json">{}</script>"#,
+                Language::Json,
+            ),
+            (
+                r#"<script type="text/x-template"><div></div></script>"#,
+                Language::Other("text/x-template".to_string()),
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let nodes = parse(input);
+            let Node::Embedded(embedded) = &element(&nodes[0]).children[0] else {
+                panic!("Expected embedded code, got {:?}", nodes[0]);
+            };
+            assert_eq!(embedded.language, expected);
+        }
+    }
+}
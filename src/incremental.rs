@@ -0,0 +1,529 @@
+use crate::ast::{Ast, Node};
+use crate::diagnostic::Diagnostic;
+use crate::html::build_elements_reach;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::span::Span;
+use crate::token::{Token, TokenStream, TokenType};
+
+/// How far past the end of a token the lexer may read to decide what the token is, in
+/// bytes. Lexing restarts at least this far before an edit.
+const LOOKAHEAD: usize = 32;
+
+/// A change to a template: the text in `span` of the old source is replaced by `text`.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Edit {
+    pub span: Span,
+    pub text: String,
+}
+
+impl Edit {
+    pub fn new(span: Span, text: &str) -> Self {
+        Edit {
+            span,
+            text: text.to_string(),
+        }
+    }
+}
+
+/// A template that stays parsed as it is edited, e.g. in an editor.
+///
+/// The tokens, tree and diagnostics are always those `parse_resilient` gives for the
+/// current source, but an edit only re-lexes the tokens around it, and only re-parses
+/// the top-level nodes it touches. Lexing stops once the new tokens line up with old
+/// ones again, and parsing once the new nodes end where an old node ended; everything
+/// after is reused, moved to its new offsets.
+pub struct IncrementalParser {
+    source: String,
+    /// The lossless tokens of the source, ending with `Eof`.
+    tokens: Vec<Lexed>,
+    lexer_diagnostics: Vec<Diagnostic>,
+    ast: Ast,
+    /// How far into the source building each top-level node read, or `None` when it
+    /// read to the end.
+    reach: Vec<Option<usize>>,
+    parser_diagnostics: Vec<Diagnostic>,
+}
+
+impl IncrementalParser {
+    pub fn new(source: &str) -> Self {
+        let mut lexer = Lexer::lossless(source);
+        let mut tokens = Vec::new();
+        let mut lexer_diagnostics = Vec::new();
+        while !lexer.is_at_end() {
+            let resumable = lexer.is_resumable();
+            let (token, diagnostic) = lexer.next_token_resilient();
+            lexer_diagnostics.extend(diagnostic);
+            tokens.push(Lexed::new(&token, resumable));
+        }
+        tokens.push(Lexed::new(&Token::eof(lexer.offset(), lexer.line()), true));
+
+        let mut parser = IncrementalParser {
+            source: source.to_string(),
+            tokens,
+            lexer_diagnostics,
+            ast: Ast::default(),
+            reach: Vec::new(),
+            parser_diagnostics: Vec::new(),
+        };
+        parser.reparse(Span::new(0, source.len()), 0, 0);
+        parser
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The lossless tokens of the source, ending with `Eof`.
+    pub fn tokens(&self) -> TokenStream<'_> {
+        self.token_stream(0)
+    }
+
+    fn token_stream(&self, from: usize) -> TokenStream<'_> {
+        let mut tokens = TokenStream::lossless();
+        for token in &self.tokens[from..] {
+            tokens.add_token(token.token(&self.source));
+        }
+        tokens
+    }
+
+    pub fn ast(&self) -> &Ast {
+        &self.ast
+    }
+
+    /// The problems found while lexing, then those found while parsing.
+    pub fn diagnostics(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.lexer_diagnostics
+            .iter()
+            .chain(&self.parser_diagnostics)
+    }
+
+    /// Apply `edit` and bring the tokens, tree and diagnostics up to date, returning
+    /// the span of the new source that was parsed again.
+    ///
+    /// # Panics
+    ///
+    /// If the span of the edit is out of bounds or does not lie on char boundaries.
+    pub fn edit(&mut self, edit: &Edit) -> Span {
+        let Edit { span, text } = edit;
+        let removed_lines = self.source[span.start..span.end].matches('\n').count();
+        let lines = text.matches('\n').count() as isize - removed_lines as isize;
+        let delta = text.len() as isize - span.len() as isize;
+        self.source.replace_range(span.start..span.end, text);
+
+        let relexed = self.relex(*span, text.len(), delta, lines);
+        self.reparse(relexed, delta, lines)
+    }
+
+    /// Lex again from the last point, far enough before the edit, where the lexer can
+    /// resume, until the new tokens line up with old ones. Returns the span of the new
+    /// source covered by the new tokens that changed.
+    fn relex(&mut self, edit: Span, inserted: usize, delta: isize, lines: isize) -> Span {
+        let mut limit = edit.start.saturating_sub(LOOKAHEAD);
+        // A string is only found to be unterminated by reading to the end of the source,
+        // so the edit may be what terminates it.
+        if let Some(unterminated) = self
+            .lexer_diagnostics
+            .iter()
+            .find(|diagnostic| diagnostic.code == "E003")
+        {
+            limit = limit.min(unterminated.span().start);
+        }
+        let mut restart = self
+            .tokens
+            .partition_point(|token| token.span.start <= limit)
+            .saturating_sub(1);
+        while restart > 0 && !self.tokens[restart].resumable {
+            restart -= 1;
+        }
+        let start = self.tokens[restart].span.start;
+
+        let mut lexer = Lexer::resume(&self.source, start, self.tokens[restart].line);
+        let mut tokens = Vec::new();
+        let mut diagnostics = Vec::new();
+        let resume = loop {
+            if lexer.is_resumable() && lexer.offset() >= edit.start + inserted {
+                let old = lexer.offset().saturating_add_signed(-delta);
+                let index = self.tokens.partition_point(|token| token.span.start < old);
+                let lines_up = self
+                    .tokens
+                    .get(index)
+                    .is_some_and(|token| token.span.start == old);
+                if lines_up && self.tokens[index].resumable {
+                    break index;
+                }
+            }
+            if lexer.is_at_end() {
+                break self.tokens.len() - 1;
+            }
+            let resumable = lexer.is_resumable();
+            let (token, diagnostic) = lexer.next_token_resilient();
+            diagnostics.extend(diagnostic);
+            tokens.push(Lexed::new(&token, resumable));
+        };
+
+        let end = self.tokens[resume].span.start;
+        let new_end = end.saturating_add_signed(delta);
+        // The tokens before the edit that came out as they were have not changed
+        let unchanged = tokens
+            .iter()
+            .zip(&self.tokens[restart..resume])
+            .take_while(|(new, old)| new == old && new.span.end <= edit.start)
+            .count();
+        let changed = tokens
+            .get(unchanged)
+            .map_or(new_end, |token| token.span.start);
+        for token in &mut self.tokens[resume..] {
+            token.span = token.span.shift(delta);
+            token.line = token.line.saturating_add_signed(lines);
+        }
+        self.tokens.splice(restart..resume, tokens);
+        splice_diagnostics(
+            &mut self.lexer_diagnostics,
+            Span::new(start, end),
+            diagnostics,
+            delta,
+        );
+        Span::new(changed, new_end)
+    }
+
+    /// Parse again from the first top-level node whose building read up to what changed,
+    /// until the new nodes end where an old node ended without having read past what
+    /// was parsed. Returns the span of the new source covered by the new nodes.
+    fn reparse(&mut self, relexed: Span, delta: isize, lines: isize) -> Span {
+        let nodes = &self.ast.nodes;
+        // Reading up to the change is enough, as the parser looks at the token after a
+        // node to end it
+        let mut first = self
+            .reach
+            .iter()
+            .position(|reach| reach.is_none_or(|reach| reach >= relexed.start))
+            .unwrap_or(nodes.len());
+        // Start on a token, and not right after text the new nodes might continue
+        while first > 0 {
+            let start = nodes[first - 1].span().end;
+            let on_token = self
+                .tokens
+                .binary_search_by_key(&start, |token| token.span.start)
+                .is_ok();
+            if on_token && !matches!(nodes[first - 1], Node::Text { .. }) {
+                break;
+            }
+            first -= 1;
+        }
+        let start = first
+            .checked_sub(1)
+            .map_or(0, |index| nodes[index].span().end);
+        let ends_old_node = |end: usize| {
+            let old = end.saturating_add_signed(-delta);
+            nodes.binary_search_by_key(&old, |node| node.span().end)
+        };
+
+        let from = self
+            .tokens
+            .partition_point(|token| token.span.start < start);
+        let mut parser = Parser::new(self.token_stream(from));
+        let mut index = 0;
+        let mut raw_nodes: Vec<Node> = Vec::new();
+        let mut next_check = 0;
+        let (mut new_nodes, reach, mut last) = loop {
+            let (node, next) = parser.parse_top_level_node(index);
+            index = next;
+            let Some(node) = node else {
+                let (new_nodes, reach) = build_elements_reach(&raw_nodes);
+                break (new_nodes, reach, nodes.len());
+            };
+            let end = node.span().end;
+            raw_nodes.push(node);
+            if end < relexed.end || raw_nodes.len() < next_check {
+                continue;
+            }
+            let (mut new_nodes, mut reach) = build_elements_reach(&raw_nodes);
+            let cut = new_nodes.iter().zip(&reach).position(|(node, &read)| {
+                let end = node.span().end;
+                end >= relexed.end && read <= raw_nodes.len() && ends_old_node(end).is_ok()
+            });
+            if let Some(cut) = cut {
+                new_nodes.truncate(cut + 1);
+                reach.truncate(cut + 1);
+                let last = match ends_old_node(new_nodes[cut].span().end) {
+                    Ok(last) | Err(last) => last,
+                };
+                break (new_nodes, reach, last + 1);
+            }
+            // Building elements is linear in the nodes so far, so try less and less
+            // often while nothing lines up.
+            next_check = raw_nodes.len() * 2;
+        };
+        let end = new_nodes.last().map_or(start, |node| node.span().end);
+        let mut diagnostics = parser.take_diagnostics();
+        diagnostics.retain(|diagnostic| anchor(diagnostic) < end);
+
+        let mut reach: Vec<Option<usize>> = reach
+            .into_iter()
+            .map(|read| match read {
+                0 => Some(start),
+                read => raw_nodes.get(read - 1).map(|node| node.span().end),
+            })
+            .collect();
+        // Text that ends the new nodes continues into old text after them
+        if let (
+            Some(Node::Text { text, span }),
+            Some(Node::Text {
+                text: next,
+                span: next_span,
+            }),
+        ) = (new_nodes.last_mut(), self.ast.nodes.get(last))
+        {
+            text.push_str(next);
+            *span = span.join(next_span.shift(delta));
+            if let Some(read) = reach.last_mut() {
+                *read = self.reach[last].map(|reach| reach.saturating_add_signed(delta));
+            }
+            last += 1;
+        }
+        let old_end = last
+            .checked_sub(1)
+            .map_or(0, |index| self.ast.nodes[index].span().end);
+
+        if delta != 0 || lines != 0 {
+            for node in &mut self.ast.nodes[last..] {
+                node.shift(delta, lines);
+            }
+        }
+        for reach in self.reach[last..].iter_mut().flatten() {
+            *reach = reach.saturating_add_signed(delta);
+        }
+        let end = new_nodes.last().map_or(start, |node| node.span().end);
+        self.ast.nodes.splice(first..last, new_nodes);
+        self.reach.splice(first..last, reach);
+        splice_diagnostics(
+            &mut self.parser_diagnostics,
+            Span::new(start, old_end),
+            diagnostics,
+            delta,
+        );
+        Span::new(start, end)
+    }
+}
+
+/// A token of the source without its lexeme, which would borrow from the source as it
+/// changes, and whether lexing can resume at it, see `Lexer::is_resumable`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Lexed {
+    token_type: TokenType,
+    span: Span,
+    line: usize,
+    resumable: bool,
+}
+
+impl Lexed {
+    fn new(token: &Token, resumable: bool) -> Self {
+        Lexed {
+            token_type: token.token_type,
+            span: token.span,
+            line: token.line,
+            resumable,
+        }
+    }
+
+    fn token<'a>(&self, source: &'a str) -> Token<'a> {
+        let lexeme = &source[self.span.start..self.span.end];
+        Token::new(self.token_type, lexeme, self.span.start, self.line)
+    }
+}
+
+/// Replace the diagnostics found in `replaced`, a span of the old source, with
+/// `diagnostics`, and move those after it by `delta`.
+/// The first offset `diagnostic` points at, which lies in the top-level node it was
+/// found in. Its primary label may not: an unclosed block is reported at the end of
+/// the template, with a secondary label at the tag that opened it.
+fn anchor(diagnostic: &Diagnostic) -> usize {
+    diagnostic
+        .labels
+        .iter()
+        .map(|label| label.span.start)
+        .min()
+        .unwrap_or_default()
+}
+
+fn splice_diagnostics(
+    old: &mut Vec<Diagnostic>,
+    replaced: Span,
+    diagnostics: Vec<Diagnostic>,
+    delta: isize,
+) {
+    let before = old.partition_point(|diagnostic| anchor(diagnostic) < replaced.start);
+    let after = old.partition_point(|diagnostic| anchor(diagnostic) < replaced.end);
+    for diagnostic in &mut old[after..] {
+        diagnostic.shift(delta);
+    }
+    old.splice(before..after.max(before), diagnostics);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_resilient;
+
+    /// Check that `parser` holds what parsing its source from scratch gives.
+    fn assert_up_to_date(parser: &IncrementalParser) {
+        let source = parser.source();
+        let (tokens, _) = Lexer::lossless(source).tokenize_resilient();
+        let (ast, diagnostics) = parse_resilient(source);
+
+        assert_eq!(parser.tokens().as_ref(), tokens.as_ref());
+        assert_eq!(parser.ast(), &ast);
+        assert_eq!(
+            parser.diagnostics().cloned().collect::<Vec<_>>(),
+            diagnostics
+        );
+        assert_eq!(parser.ast().to_string(), source);
+    }
+
+    #[test]
+    fn test_edit() {
+        let test_cases = vec![
+            (
+                "Hello {{ name }}!",
+                Edit::new(Span::new(9, 13), "user.name"),
+            ),
+            ("{% if a %}b{% endif %}", Edit::new(Span::new(11, 22), "")),
+            ("a {% if b %}c", Edit::new(Span::new(13, 13), "{% endif %}")),
+            ("<div>\n<p>a</p>\n</div>", Edit::new(Span::new(0, 5), "")),
+            ("<p>a</p> b", Edit::new(Span::new(4, 8), "")),
+            (
+                "a\n{% comment %}b{% endcomment %}\n{{ c }}",
+                Edit::new(Span::new(20, 22), "x"),
+            ),
+            ("<scr>{{ a }}</script>", Edit::new(Span::new(4, 4), "ipt")),
+            ("{{ a }}<!- {{ b }} -->", Edit::new(Span::new(9, 9), "-")),
+            ("<a href=\"{{ a }}\">b</a>", Edit::new(Span::new(8, 9), "")),
+            (
+                "{% for x in y %}\n{{ x }}\n{% endfor %}\n{{ z",
+                Edit::new(Span::new(0, 16), ""),
+            ),
+            (
+                "",
+                Edit::new(Span::new(0, 0), "{% block a %}\n{% endblock %}"),
+            ),
+        ];
+
+        for (input, edit) in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let mut parser = IncrementalParser::new(input);
+            assert_up_to_date(&parser);
+            parser.edit(&edit);
+            assert_up_to_date(&parser);
+        }
+    }
+
+    #[test]
+    fn test_edit_reuses_nodes() {
+        let section = "<section>\n  {% if a %}<p>{{ a|upper }}</p>{% endif %}\n</section>\n";
+        let source = section.repeat(100);
+        let mut parser = IncrementalParser::new(&source);
+
+        let offset = section.len() * 50 + section.find("upper").unwrap();
+        let reparsed = parser.edit(&Edit::new(Span::new(offset, offset + 5), "lower"));
+        // From the newline before the section, which the new nodes might have continued,
+        // to the end of the section
+        let end = section.len() * 51 - 1;
+        assert_eq!(reparsed, Span::new(section.len() * 50 - 1, end));
+        assert_up_to_date(&parser);
+
+        let reparsed = parser.edit(&Edit::new(Span::new(0, 0), "{% load static %}\n"));
+        assert_eq!(reparsed.start, 0);
+        assert!(reparsed.end < section.len() * 2);
+        assert_up_to_date(&parser);
+    }
+
+    #[test]
+    fn test_random_edits() {
+        const FRAGMENTS: &[&str] = &[
+            "{% if a %}",
+            "{% elif b %}",
+            "{% endif %}",
+            "{% for x in y %}",
+            "{% empty %}",
+            "{% endfor %}",
+            "{% block c %}",
+            "{% endblock %}",
+            "{% comment %}",
+            "{% endcomment %}",
+            "{% verbatim %}",
+            "{% endverbatim %}",
+            "{{ d|upper }}",
+            "{# e #}",
+            "<div class=\"f\">",
+            "</div>",
+            "<p>",
+            "</p>",
+            "<br/>",
+            "<script>",
+            "</script>",
+            "<!--",
+            "-->",
+            "<a href=\"{{ g }}\">",
+            "\"",
+            "'h'",
+            "{{",
+            "}}",
+            "{%",
+            "%}",
+            "{#",
+            "#}",
+            "<",
+            ">",
+            " ",
+            "\n",
+            "text",
+            "é😀",
+        ];
+
+        // A fixed linear congruential generator, so failures can be reproduced
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = |bound: usize| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 33) as usize % bound
+        };
+
+        for round in 0..20 {
+            let mut source = String::new();
+            for _ in 0..next(30) {
+                source.push_str(FRAGMENTS[next(FRAGMENTS.len())]);
+            }
+            let mut parser = IncrementalParser::new(&source);
+            for _ in 0..25 {
+                let source = parser.source();
+                let boundaries: Vec<usize> = source
+                    .char_indices()
+                    .map(|(index, _)| index)
+                    .chain(std::iter::once(source.len()))
+                    .collect();
+                let start = boundaries[next(boundaries.len())];
+                let end = boundaries
+                    .iter()
+                    .copied()
+                    .filter(|&end| end >= start && end <= start + 20)
+                    .nth(next(4))
+                    .unwrap_or(start);
+                let text = if next(3) == 0 {
+                    ""
+                } else {
+                    FRAGMENTS[next(FRAGMENTS.len())]
+                };
+                let edit = Edit::new(Span::new(start, end), text);
+                println!("Testing round {}: {:?} on {:?}", round, edit, source);
+
+                parser.edit(&edit);
+                assert_up_to_date(&parser);
+            }
+        }
+    }
+}
@@ -1,34 +1,272 @@
-use crate::error::LexerError;
+use crate::diagnostic::Diagnostic;
+use crate::error::{LexerError, ScannerError};
+use crate::html::RAW_TEXT_ELEMENTS;
+use crate::scanner::Scanner;
 use crate::token::{Token, TokenStream, TokenType};
 
-pub struct Lexer {
-    source: String,
+/// What the lexer is currently inside of, which decides how the next token is read.
+#[derive(Clone, Debug, PartialEq)]
+enum Mode {
+    Template,
+    Variable,
+    /// Inside `{% %}`, with `start` the offset just after `{%`.
+    Tag {
+        start: usize,
+    },
+    Comment,
+    /// The body of `{% verbatim %}` or `{% comment %}`, read as one text token of `size`
+    /// bytes.
+    Raw {
+        size: usize,
+    },
+}
+
+/// The content of raw text elements such as `<script>` is not HTML, but Django syntax
+/// inside it is still lexed. This is tracked apart from `Mode` so that it
+/// survives leaving and re-entering `Mode::Template`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RawText {
+    Outside,
+    /// Between `<script` and the `>` ending the start tag.
+    StartTag(&'static str),
+    /// Between the start tag and `</script`.
+    Content(&'static str),
+}
+
+/// `start` and `current` are byte offsets into `source`. Characters are only decoded
+/// around the cursor, so tokenizing stays linear in the size of the template.
+///
+/// As a [`Scanner`] the lexer walks the characters of the source, and as an `Iterator`
+/// it reads tokens one at a time, so lexing can stop early.
+pub struct Lexer<'src> {
+    source: &'src str,
     start: usize,
     current: usize,
     line: usize,
+    lossless: bool,
+    mode: Mode,
+    raw_text: RawText,
+    /// The problems found while iterating over tokens.
+    diagnostics: Vec<Diagnostic>,
+    /// Whether iterating has returned the `Eof` token.
+    finished: bool,
 }
 
-impl Lexer {
-    pub fn new(source: &str) -> Self {
+impl<'src> Lexer<'src> {
+    pub fn new(source: &'src str) -> Self {
         Lexer {
-            source: String::from(source),
+            source,
             start: 0,
             current: 0,
             line: 1,
+            lossless: false,
+            mode: Mode::Template,
+            raw_text: RawText::Outside,
+            diagnostics: Vec::new(),
+            finished: false,
         }
     }
 
-    pub fn tokenize(&mut self) -> Result<TokenStream, LexerError> {
-        let mut tokens = TokenStream::new();
+    /// A lexer whose token stream keeps whitespace, so the template parsed from it
+    /// prints back to the original source byte-for-byte.
+    pub fn lossless(source: &'src str) -> Self {
+        Lexer {
+            lossless: true,
+            ..Lexer::new(source)
+        }
+    }
+
+    pub fn tokenize(&mut self) -> Result<TokenStream<'src>, LexerError> {
+        let mut tokens = self.token_stream();
         while !self.is_at_end() {
             let token = self.next_token()?;
             tokens.add_token(token);
         }
-        tokens.finalize(self.line);
+        tokens.add_token(self.eof()?);
         Ok(tokens)
     }
 
-    fn next_token(&mut self) -> Result<Token, LexerError> {
+    /// Like `tokenize`, but never fails. A character that cannot start a token is
+    /// reported and read as a text token of its own.
+    pub fn tokenize_resilient(&mut self) -> (TokenStream<'src>, Vec<Diagnostic>) {
+        let mut tokens = self.token_stream();
+        for token in self.by_ref() {
+            tokens.add_token(token);
+        }
+        (tokens, std::mem::take(&mut self.diagnostics))
+    }
+
+    /// The problems found so far while iterating over tokens.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// The `Eof` token ending the token stream, once the whole source has been read.
+    fn eof(&self) -> Result<Token<'src>, ScannerError> {
+        if !self.is_at_end() {
+            return Err(ScannerError::NotAtEnd);
+        }
+        Ok(Token::eof(self.current, self.line))
+    }
+
+    /// A lossless lexer that starts reading `source` at byte `offset`, on `line`, as if
+    /// everything before it had been lexed and left it between two template nodes.
+    pub(crate) fn resume(source: &'src str, offset: usize, line: usize) -> Self {
+        Lexer {
+            current: offset,
+            start: offset,
+            line,
+            ..Lexer::lossless(source)
+        }
+    }
+
+    /// Read one token, reporting a character that cannot start a token instead of
+    /// failing. The lexer must not be at the end.
+    pub(crate) fn next_token_resilient(&mut self) -> (Token<'src>, Option<Diagnostic>) {
+        match self.next_token() {
+            Ok(token) => (token, None),
+            Err(error) => {
+                let token = self.skip_char();
+                let diagnostic = Diagnostic::from_lexer_error(&error, token.span);
+                (token, Some(diagnostic))
+            }
+        }
+    }
+
+    /// Whether the lexer is between template nodes, outside of any Django or raw text
+    /// construct, where `resume` can pick up lexing again.
+    pub(crate) fn is_resumable(&self) -> bool {
+        self.mode == Mode::Template && self.raw_text == RawText::Outside
+    }
+
+    pub(crate) fn offset(&self) -> usize {
+        self.current
+    }
+
+    pub(crate) fn line(&self) -> usize {
+        self.line
+    }
+
+    fn token_stream(&self) -> TokenStream<'src> {
+        if self.lossless {
+            TokenStream::lossless()
+        } else {
+            TokenStream::new()
+        }
+    }
+
+    fn skip_char(&mut self) -> Token<'src> {
+        let remaining_source = &self.source[self.current..];
+        let size = remaining_source.chars().next().map_or(0, char::len_utf8);
+        let token = Token::new(
+            TokenType::Text,
+            &remaining_source[..size],
+            self.current,
+            self.line,
+        );
+        self.start = self.current;
+        self.current += size;
+        self.line += token.lines();
+        token
+    }
+
+    fn next_token(&mut self) -> Result<Token<'src>, LexerError> {
+        let token_type = match (&self.mode, self.raw_text) {
+            (Mode::Template, RawText::Content(element)) => self.raw_text_token(element)?,
+            (Mode::Template, _) => self.template_token()?,
+            (Mode::Variable | Mode::Tag { .. }, _) => self.django_token()?,
+            (Mode::Comment, _) => self.comment_token()?,
+            (Mode::Raw { .. }, _) => TokenType::Text,
+        };
+        let lexeme = self.extract_lexeme(token_type)?;
+        let token = Token::new(token_type, lexeme, self.current, self.line);
+        self.advance_by(token.size(), token.lines())?;
+        self.raw_text = self.next_raw_text(&token);
+        self.mode = self.next_mode(&token);
+        Ok(token)
+    }
+
+    fn next_mode(&self, token: &Token) -> Mode {
+        match (&self.mode, token.token_type) {
+            // An opener inside `{{ }}` or `{% %}` means the previous one was never
+            // closed, so it is not allowed to swallow the rest of the template.
+            (Mode::Template | Mode::Variable | Mode::Tag { .. }, TokenType::DoubleLeftBrace) => {
+                Mode::Variable
+            }
+            (Mode::Template | Mode::Variable | Mode::Tag { .. }, TokenType::LeftBracePercent) => {
+                Mode::Tag {
+                    start: token.span.end,
+                }
+            }
+            (Mode::Template | Mode::Variable | Mode::Tag { .. }, TokenType::LeftBraceHash) => {
+                Mode::Comment
+            }
+            (Mode::Tag { start }, TokenType::PercentRightBrace) => {
+                self.raw_mode(&self.source[*start..token.span.start])
+            }
+            (Mode::Variable, TokenType::DoubleRightBrace)
+            | (Mode::Comment, TokenType::HashRightBrace)
+            | (Mode::Raw { .. }, _) => Mode::Template,
+            (mode, _) => mode.clone(),
+        }
+    }
+
+    fn next_raw_text(&self, token: &Token) -> RawText {
+        if self.mode != Mode::Template {
+            return self.raw_text;
+        }
+        match (self.raw_text, token.token_type) {
+            (RawText::Outside, TokenType::LeftAngle) => {
+                match raw_text_element(&self.source[token.span.end..]) {
+                    Some(element) => RawText::StartTag(element),
+                    None => RawText::Outside,
+                }
+            }
+            (RawText::StartTag(element), TokenType::RightAngle) => RawText::Content(element),
+            (RawText::StartTag(_), TokenType::SlashRightAngle)
+            | (RawText::Content(_), TokenType::LeftAngleSlash) => RawText::Outside,
+            (raw_text, _) => raw_text,
+        }
+    }
+
+    /// The bodies of `{% verbatim %}` and `{% comment %}` are not lexed. Given the
+    /// content of the tag just closed, find the matching end tag and return a mode that
+    /// reads everything before it as a single text token.
+    fn raw_mode(&self, tag_content: &str) -> Mode {
+        let mut bits = tag_content.split_whitespace();
+        let end = match bits.next() {
+            // A named `{% verbatim myblock %}` only ends at `{% endverbatim myblock %}`
+            Some("verbatim") => std::iter::once("endverbatim")
+                .chain(bits)
+                .collect::<Vec<_>>(),
+            Some("comment") => vec!["endcomment"],
+            _ => return Mode::Template,
+        };
+
+        let remaining_source = &self.source[self.current..];
+        let mut offset = 0;
+        let size = loop {
+            let Some(open) = remaining_source[offset..].find("{%") else {
+                break remaining_source.len();
+            };
+            let content_start = offset + open + 2;
+            let Some(close) = remaining_source[content_start..].find("%}") else {
+                break remaining_source.len();
+            };
+            let content = &remaining_source[content_start..content_start + close];
+            if content.split_whitespace().eq(end.iter().copied()) {
+                break offset + open;
+            }
+            offset = content_start;
+        };
+
+        match size {
+            0 => Mode::Template,
+            size => Mode::Raw { size },
+        }
+    }
+
+    fn template_token(&self) -> Result<TokenType, LexerError> {
         let c = self.peek()?;
         let token_type = match c {
             ',' | '.' | '+' | ':' | '|' | '\'' | '"' => self.single_char(c)?,
@@ -46,10 +284,67 @@ impl Lexer {
             c if c.is_whitespace() => self.whitespace()?,
             _ => self.text()?,
         };
-        let lexeme = self.extract_lexeme(token_type)?;
-        let token = Token::new(token_type, lexeme, self.line);
-        self.advance(token.size(), token.lines())?;
-        Ok(token)
+        Ok(token_type)
+    }
+
+    /// The content of a raw text element: text up to the next Django opener or the
+    /// element's end tag.
+    fn raw_text_token(&self, element: &str) -> Result<TokenType, LexerError> {
+        let token_type = match self.peek()? {
+            '{' => self.left_brace()?,
+            '<' if is_end_tag(&self.source[self.current..], element) => TokenType::LeftAngleSlash,
+            _ => self.text()?,
+        };
+        Ok(token_type)
+    }
+
+    /// The inside of `{{ }}` and `{% %}`: identifiers, literals, filter syntax and the
+    /// operators understood by `{% if %}`.
+    fn django_token(&self) -> Result<TokenType, LexerError> {
+        let c = self.peek()?;
+        let token_type = match c {
+            ',' | '.' | ':' | '|' => self.single_char(c)?,
+            '{' => self.left_brace()?,
+            '}' => self.right_brace()?,
+            '%' => self.percent()?,
+            '!' => self.bang()?,
+            '=' => self.equal()?,
+            '>' => self.right_angle()?,
+            '<' => match self.peek_next()? {
+                '=' => TokenType::LeftAngleEqual,
+                _ => TokenType::LeftAngle,
+            },
+            '\'' | '"' => TokenType::StringLiteral,
+            // `2fa` or `1e` is one invalid bit to Django, not a number and a name
+            _ if self.at_number()? => match number_word_size(&self.source[self.current..]) {
+                (number, word) if number == word => TokenType::NumberLiteral,
+                _ => self.text()?,
+            },
+            c if c.is_alphanumeric() || c == '_' => TokenType::Identifier,
+            c if c.is_whitespace() => self.whitespace()?,
+            _ => self.text()?,
+        };
+        Ok(token_type)
+    }
+
+    /// Whether a number starts here, inside `{{ }}` or `{% %}`.
+    fn at_number(&self) -> Result<bool, LexerError> {
+        let at_number = match self.peek()? {
+            '-' | '+' => self.peek_next()?.is_ascii_digit(),
+            // `items.0` is a lookup of index 0, not the number 0
+            c => c.is_ascii_digit() && self.peek_previous()? != '.',
+        };
+        Ok(at_number)
+    }
+
+    /// The inside of `{# #}`, which is kept verbatim apart from surrounding whitespace.
+    fn comment_token(&self) -> Result<TokenType, LexerError> {
+        let token_type = match self.peek()? {
+            '#' if self.peek_next()? == '}' => TokenType::HashRightBrace,
+            c if c.is_whitespace() => self.whitespace()?,
+            _ => self.text()?,
+        };
+        Ok(token_type)
     }
 
     fn single_char(&self, c: char) -> Result<TokenType, LexerError> {
@@ -171,7 +466,7 @@ impl Lexer {
         Ok(token_type)
     }
 
-    fn whitespace(&mut self) -> Result<TokenType, LexerError> {
+    fn whitespace(&self) -> Result<TokenType, LexerError> {
         let token_type = TokenType::Whitespace;
         Ok(token_type)
     }
@@ -181,7 +476,7 @@ impl Lexer {
         Ok(token_type)
     }
 
-    fn extract_lexeme(&self, token_type: TokenType) -> Result<&str, LexerError> {
+    fn extract_lexeme(&self, token_type: TokenType) -> Result<&'src str, LexerError> {
         let remaining_source = &self.source[self.current..];
 
         let size = match token_type.size() {
@@ -192,17 +487,32 @@ impl Lexer {
                     .take_while(|&c| c.is_whitespace() && c != '\0')
                     .map(|c| c.len_utf8())
                     .sum(),
-                TokenType::Text => {
-                    const TOKEN_BOUNDARIES: &[char] = &['>', '=', '\'', '"'];
-
-                    remaining_source
-                        .chars()
-                        .take_while(|&c| {
-                            !c.is_whitespace() && c != '\0' && !TOKEN_BOUNDARIES.contains(&c)
-                        })
-                        .map(|c| c.len_utf8())
-                        .sum()
-                }
+                TokenType::Identifier => remaining_source
+                    .chars()
+                    .take_while(|&c| c.is_alphanumeric() || c == '_')
+                    .map(|c| c.len_utf8())
+                    .sum(),
+                TokenType::NumberLiteral => number_word_size(remaining_source).0,
+                TokenType::StringLiteral => self.string_literal_size(remaining_source)?,
+                TokenType::Text => match self.mode {
+                    Mode::Template => match self.raw_text {
+                        RawText::Content(element) => raw_text_size(remaining_source, element),
+                        _ => Self::text_size(remaining_source),
+                    },
+                    Mode::Raw { size } => size,
+                    Mode::Variable | Mode::Tag { .. } if self.at_number()? => {
+                        number_word_size(remaining_source).1
+                    }
+                    Mode::Variable | Mode::Tag { .. } => {
+                        remaining_source.chars().next().map_or(0, |c| c.len_utf8())
+                    }
+                    Mode::Comment => {
+                        let end = remaining_source
+                            .find("#}")
+                            .unwrap_or(remaining_source.len());
+                        remaining_source[..end].trim_end().len()
+                    }
+                },
                 _ => return Err(LexerError::UnexpectedTokenType(token_type)),
             },
         };
@@ -212,16 +522,114 @@ impl Lexer {
         Ok(result)
     }
 
-    fn advance(&mut self, chars: usize, lines: usize) -> Result<(), LexerError> {
+    /// Text runs up to whitespace, an HTML delimiter, or the start of a Django construct.
+    /// It always includes its first character, so the lexer keeps moving, and a NUL in
+    /// the source is text like any other character.
+    fn text_size(source: &str) -> usize {
+        const TOKEN_BOUNDARIES: &[char] = &['>', '=', '\'', '"'];
+
+        let mut chars = source.char_indices().skip(1).peekable();
+        while let Some((index, c)) = chars.next() {
+            let opens_django = c == '{' && matches!(chars.peek(), Some((_, '{' | '%' | '#')));
+            if c.is_whitespace() || TOKEN_BOUNDARIES.contains(&c) || opens_django {
+                return index;
+            }
+        }
+        source.len()
+    }
+
+    /// The size of a quoted string, quotes included. A backslash escapes the next
+    /// character, as in Django's constant strings.
+    fn string_literal_size(&self, source: &str) -> Result<usize, LexerError> {
+        let mut chars = source.char_indices();
+        let quote = match chars.next() {
+            Some((_, quote)) => quote,
+            None => return Err(LexerError::AtEndOfSource),
+        };
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                c if c == quote => return Ok(index + c.len_utf8()),
+                _ => {}
+            }
+        }
+        Err(LexerError::UnterminatedString { line: self.line })
+    }
+
+    fn advance_by(&mut self, bytes: usize, lines: usize) -> Result<(), LexerError> {
         if self.is_at_end() {
             return Err(LexerError::AtEndOfSource);
         }
         self.start = self.current;
-        self.current += chars;
+        self.current += bytes;
         self.line += lines;
         Ok(())
     }
 
+    /// Peek `offset` characters away from the current position. `current` is a byte
+    /// index, so the offset is walked char by char rather than added to it.
+    fn peek_at(&self, offset: isize) -> Result<char, LexerError> {
+        if offset < 0 {
+            let before = match self.source.get(..self.current) {
+                Some(before) => before,
+                None if self.current > self.source.len() => return Ok('\0'),
+                None => return Err(LexerError::InvalidCharacterAccess),
+            };
+            let c = before.chars().rev().nth(offset.unsigned_abs() - 1);
+            return Ok(c.unwrap_or('\0'));
+        }
+        if self.is_at_end() {
+            return Ok('\0');
+        }
+        let after = self
+            .source
+            .get(self.current..)
+            .ok_or(LexerError::InvalidCharacterAccess)?;
+        match after.char_indices().nth(offset as usize) {
+            Some((index, _)) => self.item_at(self.current + index),
+            None => Ok('\0'),
+        }
+    }
+}
+
+impl<'src> Iterator for Lexer<'src> {
+    type Item = Token<'src>;
+
+    /// Read the next token, ending with `Eof`. As in `tokenize_resilient`, a character
+    /// that cannot start a token is read as a text token of its own, and reported in
+    /// `diagnostics`.
+    fn next(&mut self) -> Option<Token<'src>> {
+        loop {
+            if self.is_at_end() {
+                if self.finished {
+                    return None;
+                }
+                self.finished = true;
+                return self.eof().ok();
+            }
+            let (token, diagnostic) = self.next_token_resilient();
+            self.diagnostics.extend(diagnostic);
+            if self.lossless || !token.is_throwaway() {
+                return Some(token);
+            }
+        }
+    }
+}
+
+/// Moves through the source a character at a time. Reading past either end gives
+/// `'\0'` rather than an error.
+impl Scanner for Lexer<'_> {
+    type Item = char;
+    type Error = LexerError;
+
+    fn advance(&mut self) -> Result<char, LexerError> {
+        let c = self.peek()?;
+        self.advance_by(c.len_utf8(), usize::from(c == '\n'))?;
+        Ok(c)
+    }
+
     fn peek(&self) -> Result<char, LexerError> {
         self.peek_at(0)
     }
@@ -234,20 +642,18 @@ impl Lexer {
         self.peek_at(-1)
     }
 
-    fn peek_at(&self, offset: isize) -> Result<char, LexerError> {
-        let index = self.current as isize + offset;
-        self.item_at(index as usize)
-    }
-
+    /// The character starting at byte `index`, which must lie on a char boundary.
     fn item_at(&self, index: usize) -> Result<char, LexerError> {
-        println!("index: {}", index);
         if index >= self.source.len() {
             // Return a null character when past the end, a bit of a departure from
             // idiomatic Rust code, but makes writing the matching above and testing
             // much easier
             Ok('\0')
         } else {
-            Ok(self.source.chars().nth(index).unwrap())
+            self.source
+                .get(index..)
+                .and_then(|rest| rest.chars().next())
+                .ok_or(LexerError::InvalidCharacterAccess)
         }
     }
 
@@ -256,9 +662,98 @@ impl Lexer {
     }
 }
 
+/// The size of the number `source` starts with, and of the word it is part of. An
+/// exponent needs digits, so in `1e` or `1e+` the number is just `1`. Identifier
+/// characters run into the number, like the `fa` of `2fa`, make the word longer.
+fn number_word_size(source: &str) -> (usize, usize) {
+    let bytes = source.as_bytes();
+    let mut number = usize::from(matches!(bytes.first(), Some(b'-' | b'+')));
+    number += bytes[number..]
+        .iter()
+        .take_while(|&&b| b.is_ascii_digit() || b == b'.')
+        .count();
+    if let Some(b'e' | b'E') = bytes.get(number) {
+        let sign = usize::from(matches!(bytes.get(number + 1), Some(b'-' | b'+')));
+        let exponent = bytes[number + 1 + sign..]
+            .iter()
+            .take_while(|&&b| b.is_ascii_digit())
+            .count();
+        if exponent > 0 {
+            number += 1 + sign + exponent;
+        }
+    }
+
+    let mut word = number;
+    let mut previous = None;
+    for c in source[number..].chars() {
+        let signed_exponent = matches!(c, '-' | '+') && matches!(previous, Some('e' | 'E'));
+        if !(c.is_alphanumeric() || c == '_' || signed_exponent) {
+            break;
+        }
+        word += c.len_utf8();
+        previous = Some(c);
+    }
+    (number, word)
+}
+
+/// The raw text element whose name `source` starts with, if any.
+fn raw_text_element(source: &str) -> Option<&'static str> {
+    RAW_TEXT_ELEMENTS.iter().copied().find(|element| {
+        source
+            .get(..element.len())
+            .is_some_and(|name| name.eq_ignore_ascii_case(element))
+            && source[element.len()..]
+                .chars()
+                .next()
+                .is_none_or(|c| c.is_whitespace() || c == '>' || c == '/')
+    })
+}
+
+fn is_end_tag(source: &str, element: &str) -> bool {
+    source.starts_with("</")
+        && source
+            .get(2..2 + element.len())
+            .is_some_and(|name| name.eq_ignore_ascii_case(element))
+}
+
+/// The size of raw text, stopping before Django syntax and before the end tag of the
+/// enclosing `element`.
+fn raw_text_size(source: &str, element: &str) -> usize {
+    source
+        .char_indices()
+        .skip(1)
+        .find(|&(index, c)| {
+            let rest = &source[index..];
+            (c == '{' && matches!(rest.as_bytes().get(1), Some(b'{' | b'%' | b'#')))
+                || (c == '<' && is_end_tag(rest, element))
+        })
+        .map_or(source.len(), |(index, _)| index)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::span::{SourceMap, Span};
+    use std::borrow::Cow;
+
+    const UNICODE_CORPUS: &[(&str, &str)] = &[
+        (
+            "accents.html",
+            include_str!("../tests/fixtures/unicode/accents.html"),
+        ),
+        (
+            "cjk.html",
+            include_str!("../tests/fixtures/unicode/cjk.html"),
+        ),
+        (
+            "emoji.html",
+            include_str!("../tests/fixtures/unicode/emoji.html"),
+        ),
+        (
+            "mixed.html",
+            include_str!("../tests/fixtures/unicode/mixed.html"),
+        ),
+    ];
 
     #[test]
     fn test_tokenize() {
@@ -299,7 +794,7 @@ mod tests {
                 "{{ variable }}",
                 vec![
                     TokenType::DoubleLeftBrace,
-                    TokenType::Text,
+                    TokenType::Identifier,
                     TokenType::DoubleRightBrace,
                     TokenType::Eof,
                 ],
@@ -308,8 +803,8 @@ mod tests {
                 "{% if condition %}",
                 vec![
                     TokenType::LeftBracePercent,
-                    TokenType::Text,
-                    TokenType::Text,
+                    TokenType::Identifier,
+                    TokenType::Identifier,
                     TokenType::PercentRightBrace,
                     TokenType::Eof,
                 ],
@@ -319,7 +814,6 @@ mod tests {
                 vec![
                     TokenType::LeftBraceHash,
                     TokenType::Text,
-                    TokenType::Text,
                     TokenType::HashRightBrace,
                     TokenType::Eof,
                 ],
@@ -328,10 +822,11 @@ mod tests {
                 "{{ value|default:'default' }}",
                 vec![
                     TokenType::DoubleLeftBrace,
-                    TokenType::Text,
-                    TokenType::SingleQuote,
-                    TokenType::Text,
-                    TokenType::SingleQuote,
+                    TokenType::Identifier,
+                    TokenType::Pipe,
+                    TokenType::Identifier,
+                    TokenType::Colon,
+                    TokenType::StringLiteral,
                     TokenType::DoubleRightBrace,
                     TokenType::Eof,
                 ],
@@ -341,15 +836,72 @@ mod tests {
                 vec![
                     TokenType::SingleQuote,
                     TokenType::LeftBracePercent,
-                    TokenType::Text,
-                    TokenType::DoubleQuote,
-                    TokenType::Text,
-                    TokenType::DoubleQuote,
+                    TokenType::Identifier,
+                    TokenType::StringLiteral,
                     TokenType::PercentRightBrace,
                     TokenType::SingleQuote,
                     TokenType::Eof,
                 ],
             ),
+            (
+                "{{ user.items.0|slice:-2 }}",
+                vec![
+                    TokenType::DoubleLeftBrace,
+                    TokenType::Identifier,
+                    TokenType::Dot,
+                    TokenType::Identifier,
+                    TokenType::Dot,
+                    TokenType::Identifier,
+                    TokenType::Pipe,
+                    TokenType::Identifier,
+                    TokenType::Colon,
+                    TokenType::NumberLiteral,
+                    TokenType::DoubleRightBrace,
+                    TokenType::Eof,
+                ],
+            ),
+            (
+                "{% if a >= 1.5 and b != 'x' or c < d %}",
+                vec![
+                    TokenType::LeftBracePercent,
+                    TokenType::Identifier,
+                    TokenType::Identifier,
+                    TokenType::RightAngleEqual,
+                    TokenType::NumberLiteral,
+                    TokenType::Identifier,
+                    TokenType::Identifier,
+                    TokenType::BangEqual,
+                    TokenType::StringLiteral,
+                    TokenType::Identifier,
+                    TokenType::Identifier,
+                    TokenType::LeftAngle,
+                    TokenType::Identifier,
+                    TokenType::PercentRightBrace,
+                    TokenType::Eof,
+                ],
+            ),
+            (
+                "Hello{% with x=y %}",
+                vec![
+                    TokenType::Text,
+                    TokenType::LeftBracePercent,
+                    TokenType::Identifier,
+                    TokenType::Identifier,
+                    TokenType::Equal,
+                    TokenType::Identifier,
+                    TokenType::PercentRightBrace,
+                    TokenType::Eof,
+                ],
+            ),
+            (
+                "{# {{ not a variable }} #}",
+                vec![
+                    TokenType::LeftBraceHash,
+                    TokenType::Text,
+                    TokenType::HashRightBrace,
+                    TokenType::Eof,
+                ],
+            ),
         ];
 
         for (input, expected_token_types) in test_cases {
@@ -379,6 +931,361 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_tokenize_unicode() {
+        let test_cases = vec![
+            (
+                "<p>café</p>",
+                vec![
+                    ("<", TokenType::LeftAngle),
+                    ("p", TokenType::Text),
+                    (">", TokenType::RightAngle),
+                    ("café</p", TokenType::Text),
+                    (">", TokenType::RightAngle),
+                    ("", TokenType::Eof),
+                ],
+            ),
+            (
+                "{{ 名前 }}",
+                vec![
+                    ("{{", TokenType::DoubleLeftBrace),
+                    ("名前", TokenType::Identifier),
+                    ("}}", TokenType::DoubleRightBrace),
+                    ("", TokenType::Eof),
+                ],
+            ),
+            (
+                "🎉='✔️'",
+                vec![
+                    ("🎉", TokenType::Text),
+                    ("=", TokenType::Equal),
+                    ("'", TokenType::SingleQuote),
+                    ("✔️", TokenType::Text),
+                    ("'", TokenType::SingleQuote),
+                    ("", TokenType::Eof),
+                ],
+            ),
+        ];
+
+        for (input, expected_tokens) in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let mut lexer = Lexer::new(input);
+            let tokens = lexer.tokenize().unwrap();
+
+            let actual: Vec<(&str, TokenType)> = tokens
+                .iter()
+                .map(|token| (token.lexeme.as_ref(), token.token_type))
+                .collect();
+            assert_eq!(actual, expected_tokens, "For input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_tokenize_unicode_corpus() {
+        for (name, source) in UNICODE_CORPUS {
+            println!("Testing fixture: {}", name);
+
+            let mut lexer = Lexer::new(source);
+            let tokens = lexer.tokenize().unwrap();
+            let source_map = SourceMap::new(source);
+
+            let mut end = 0;
+            for token in tokens.iter() {
+                assert_eq!(
+                    &source[token.span.start..token.span.end],
+                    token.lexeme.as_ref(),
+                    "Lexeme does not match its span in {}",
+                    name
+                );
+                assert!(
+                    token.is_token_type(TokenType::Eof)
+                        || matches!(token.lexeme, Cow::Borrowed(lexeme)
+                            if std::ptr::eq(lexeme, &source[token.span.start..token.span.end])),
+                    "Lexeme is not borrowed from the source in {}",
+                    name
+                );
+                assert!(
+                    source[end..token.span.start]
+                        .chars()
+                        .all(char::is_whitespace),
+                    "Non-whitespace source skipped before {:?} in {}",
+                    token,
+                    name
+                );
+                assert_eq!(
+                    source_map.position(token.span.start).line,
+                    token.line,
+                    "Line mismatch for {:?} in {}",
+                    token,
+                    name
+                );
+                end = token.span.end;
+            }
+            assert_eq!(end, source.len(), "Source not fully consumed in {}", name);
+        }
+    }
+
+    #[test]
+    fn test_tokenize_raw_blocks() {
+        let test_cases = vec![
+            (
+                "{% verbatim %}{{ not a var }}{% endverbatim %}",
+                vec![
+                    "{%",
+                    "verbatim",
+                    "%}",
+                    "{{ not a var }}",
+                    "{%",
+                    "endverbatim",
+                    "%}",
+                    "",
+                ],
+            ),
+            (
+                "{% verbatim myblock %}{% endverbatim %}{% endverbatim myblock %}",
+                vec![
+                    "{%",
+                    "verbatim",
+                    "myblock",
+                    "%}",
+                    "{% endverbatim %}",
+                    "{%",
+                    "endverbatim",
+                    "myblock",
+                    "%}",
+                    "",
+                ],
+            ),
+            (
+                "{% comment \"note\" %}\n  {% if %}\n{%endcomment %}",
+                vec![
+                    "{%",
+                    "comment",
+                    "\"note\"",
+                    "%}",
+                    "\n  {% if %}\n",
+                    "{%",
+                    "endcomment",
+                    "%}",
+                    "",
+                ],
+            ),
+            (
+                "{% verbatim %}{% endverbatim %}",
+                vec!["{%", "verbatim", "%}", "{%", "endverbatim", "%}", ""],
+            ),
+            (
+                "{% verbatim %}{{ unclosed",
+                vec!["{%", "verbatim", "%}", "{{ unclosed", ""],
+            ),
+        ];
+
+        for (input, expected_lexemes) in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let mut lexer = Lexer::new(input);
+            let tokens = lexer.tokenize().unwrap();
+
+            let lexemes: Vec<&str> = tokens.iter().map(|token| token.lexeme.as_ref()).collect();
+            assert_eq!(lexemes, expected_lexemes, "For input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_tokenize_raw_text_elements() {
+        let test_cases = vec![
+            (
+                "<script>if (a < b && c/*x*/) {}</script>",
+                vec![
+                    "<",
+                    "script",
+                    ">",
+                    "if (a < b && c/*x*/) {}",
+                    "</",
+                    "script",
+                    ">",
+                    "",
+                ],
+            ),
+            (
+                "<style media=\"print\">a { b: '{{ c }}' }</STYLE>",
+                vec![
+                    "<", "style", "media", "=", "\"", "print", "\"", ">", "a { b: '", "{{", "c",
+                    "}}", "' }", "</", "STYLE", ">", "",
+                ],
+            ),
+            (
+                "<script>{% if a %}x = 1;{% endif %}</script ><p>",
+                vec![
+                    "<", "script", ">", "{%", "if", "a", "%}", "x = 1;", "{%", "endif", "%}", "</",
+                    "script", ">", "<", "p", ">", "",
+                ],
+            ),
+            (
+                "<textarea><b>{% if a %}</textarea>",
+                vec![
+                    "<", "textarea", ">", "<b>", "{%", "if", "a", "%}", "</", "textarea", ">", "",
+                ],
+            ),
+            (
+                "<title>a<b {{ c }}</title>",
+                vec![
+                    "<", "title", ">", "a<b ", "{{", "c", "}}", "</", "title", ">", "",
+                ],
+            ),
+            (
+                "<scripts>a<b</scripts>",
+                vec!["<", "scripts", ">", "a<b</scripts", ">", ""],
+            ),
+        ];
+
+        for (input, expected_lexemes) in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let mut lexer = Lexer::new(input);
+            let tokens = lexer.tokenize().unwrap();
+
+            let lexemes: Vec<&str> = tokens.iter().map(|token| token.lexeme.as_ref()).collect();
+            assert_eq!(lexemes, expected_lexemes, "For input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_tokenize_raw_block_lines() {
+        let mut lexer = Lexer::new("{% comment %}\n\n{% endcomment %}\n{{ a }}");
+        let tokens = lexer.tokenize().unwrap();
+
+        let endcomment = tokens.iter().find(|token| token.lexeme == "endcomment");
+        assert_eq!(endcomment.unwrap().line, 3);
+        let a = tokens.iter().find(|token| token.lexeme == "a");
+        assert_eq!(a.unwrap().line, 4);
+    }
+
+    #[test]
+    fn test_tokenize_literals() {
+        let test_cases = vec![
+            (r#"{{ "a b" }}"#, r#""a b""#, TokenType::StringLiteral),
+            (r#"{{ 'it\'s' }}"#, r#"'it\'s'"#, TokenType::StringLiteral),
+            ("{{ 42 }}", "42", TokenType::NumberLiteral),
+            ("{{ -1.5e3 }}", "-1.5e3", TokenType::NumberLiteral),
+            ("{{ 2E-4 }}", "2E-4", TokenType::NumberLiteral),
+            ("{{ 1e }}", "1e", TokenType::Text),
+            ("{{ 1e+ }}", "1e+", TokenType::Text),
+            ("{{ 2fa }}", "2fa", TokenType::Text),
+            ("{{ snake_case }}", "snake_case", TokenType::Identifier),
+            ("{# a  comment #}", "a  comment", TokenType::Text),
+        ];
+
+        for (input, expected_lexeme, expected_type) in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let mut lexer = Lexer::new(input);
+            let tokens = lexer.tokenize().unwrap();
+
+            assert_eq!(tokens[1].lexeme, expected_lexeme, "For input: {}", input);
+            assert_eq!(tokens[1].token_type, expected_type, "For input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_tokenize_unterminated_string() {
+        let mut lexer = Lexer::new("{% url 'home %}");
+        assert!(matches!(
+            lexer.tokenize(),
+            Err(LexerError::UnterminatedString { line: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_tokenize_resilient() {
+        let mut lexer = Lexer::new("{% url 'home %}{{ a");
+        let (tokens, diagnostics) = lexer.tokenize_resilient();
+
+        let lexemes: Vec<&str> = tokens.iter().map(|token| token.lexeme.as_ref()).collect();
+        assert_eq!(lexemes, vec!["{%", "url", "'", "home", "%}", "{{", "a", ""]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "unterminated string literal");
+        assert_eq!(diagnostics[0].span(), Span::new(7, 8));
+    }
+
+    #[test]
+    fn test_tokenize_nul() {
+        let test_cases = vec![
+            ("a\0b", vec!["a\0b", ""]),
+            ("\0", vec!["\0", ""]),
+            ("\0{{ a }}", vec!["\0", "{{", "a", "}}", ""]),
+        ];
+
+        for (input, expected) in test_cases {
+            println!("Testing input: {:?}", input);
+            let tokens = Lexer::new(input).tokenize().unwrap();
+            let lexemes: Vec<&str> = tokens.iter().map(|token| token.lexeme.as_ref()).collect();
+            assert_eq!(lexemes, expected);
+            assert_eq!(Lexer::new(input).count(), expected.len());
+        }
+    }
+
+    #[test]
+    fn test_iterate_tokens() {
+        let mut lexer = Lexer::new("{% url 'home %} {{ a }}");
+        let first: Vec<String> = lexer
+            .by_ref()
+            .take(3)
+            .map(|token| token.lexeme.into_owned())
+            .collect();
+        assert_eq!(first, vec!["{%", "url", "'"]);
+        assert_eq!(lexer.diagnostics().len(), 1);
+
+        let rest: Vec<TokenType> = lexer.by_ref().map(|token| token.token_type).collect();
+        assert_eq!(rest.last(), Some(&TokenType::Eof));
+        assert_eq!(lexer.next(), None);
+
+        let lossless = Lexer::lossless("{{ a }}").count();
+        assert_eq!(lossless, 6);
+    }
+
+    #[test]
+    fn test_scan_characters() {
+        let mut lexer = Lexer::new("a\né");
+
+        assert_eq!(lexer.advance().unwrap(), 'a');
+        assert_eq!(lexer.advance().unwrap(), '\n');
+        assert_eq!(lexer.line, 2);
+        assert_eq!(lexer.peek_previous().unwrap(), '\n');
+        assert_eq!(lexer.advance().unwrap(), 'é');
+        assert!(lexer.is_at_end());
+        assert!(lexer.advance().is_err());
+        assert!(matches!(
+            lexer.eof(),
+            Ok(Token {
+                token_type: TokenType::Eof,
+                ..
+            })
+        ));
+        assert!(Lexer::new("a").eof().is_err());
+    }
+
+    #[test]
+    fn test_tokenize_reopened_tag() {
+        let mut lexer = Lexer::new("{{ a {% if b %}");
+        let tokens = lexer.tokenize().unwrap();
+
+        let types: Vec<TokenType> = tokens.iter().map(|token| token.token_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                TokenType::DoubleLeftBrace,
+                TokenType::Identifier,
+                TokenType::LeftBracePercent,
+                TokenType::Identifier,
+                TokenType::Identifier,
+                TokenType::PercentRightBrace,
+                TokenType::Eof,
+            ]
+        );
+    }
+
     #[test]
     fn test_token_from_source() {
         let line = 1;
@@ -388,7 +1295,8 @@ mod tests {
                 "<",
                 Token {
                     token_type: TokenType::LeftAngle,
-                    lexeme: "<".to_string(),
+                    lexeme: Cow::Borrowed("<"),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -396,7 +1304,8 @@ mod tests {
                 ">",
                 Token {
                     token_type: TokenType::RightAngle,
-                    lexeme: ">".to_string(),
+                    lexeme: Cow::Borrowed(">"),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -404,7 +1313,8 @@ mod tests {
                 ",",
                 Token {
                     token_type: TokenType::Comma,
-                    lexeme: ",".to_string(),
+                    lexeme: Cow::Borrowed(","),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -412,7 +1322,8 @@ mod tests {
                 ".",
                 Token {
                     token_type: TokenType::Dot,
-                    lexeme: ".".to_string(),
+                    lexeme: Cow::Borrowed("."),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -420,7 +1331,8 @@ mod tests {
                 "-",
                 Token {
                     token_type: TokenType::Dash,
-                    lexeme: "-".to_string(),
+                    lexeme: Cow::Borrowed("-"),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -428,7 +1340,8 @@ mod tests {
                 "+",
                 Token {
                     token_type: TokenType::Plus,
-                    lexeme: "+".to_string(),
+                    lexeme: Cow::Borrowed("+"),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -436,7 +1349,8 @@ mod tests {
                 ":",
                 Token {
                     token_type: TokenType::Colon,
-                    lexeme: ":".to_string(),
+                    lexeme: Cow::Borrowed(":"),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -444,7 +1358,8 @@ mod tests {
                 "/",
                 Token {
                     token_type: TokenType::Slash,
-                    lexeme: "/".to_string(),
+                    lexeme: Cow::Borrowed("/"),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -452,7 +1367,8 @@ mod tests {
                 "!",
                 Token {
                     token_type: TokenType::Bang,
-                    lexeme: "!".to_string(),
+                    lexeme: Cow::Borrowed("!"),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -460,7 +1376,8 @@ mod tests {
                 "=",
                 Token {
                     token_type: TokenType::Equal,
-                    lexeme: "=".to_string(),
+                    lexeme: Cow::Borrowed("="),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -468,7 +1385,8 @@ mod tests {
                 "|",
                 Token {
                     token_type: TokenType::Pipe,
-                    lexeme: "|".to_string(),
+                    lexeme: Cow::Borrowed("|"),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -476,7 +1394,8 @@ mod tests {
                 "%",
                 Token {
                     token_type: TokenType::Percent,
-                    lexeme: "%".to_string(),
+                    lexeme: Cow::Borrowed("%"),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -484,7 +1403,8 @@ mod tests {
                 "'",
                 Token {
                     token_type: TokenType::SingleQuote,
-                    lexeme: "'".to_string(),
+                    lexeme: Cow::Borrowed("'"),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -492,7 +1412,8 @@ mod tests {
                 "\"",
                 Token {
                     token_type: TokenType::DoubleQuote,
-                    lexeme: "\"".to_string(),
+                    lexeme: Cow::Borrowed("\""),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -500,7 +1421,8 @@ mod tests {
                 "{{",
                 Token {
                     token_type: TokenType::DoubleLeftBrace,
-                    lexeme: "{{".to_string(),
+                    lexeme: Cow::Borrowed("{{"),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -508,7 +1430,8 @@ mod tests {
                 "}}",
                 Token {
                     token_type: TokenType::DoubleRightBrace,
-                    lexeme: "}}".to_string(),
+                    lexeme: Cow::Borrowed("}}"),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -516,7 +1439,8 @@ mod tests {
                 "{%",
                 Token {
                     token_type: TokenType::LeftBracePercent,
-                    lexeme: "{%".to_string(),
+                    lexeme: Cow::Borrowed("{%"),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -524,7 +1448,8 @@ mod tests {
                 "%}",
                 Token {
                     token_type: TokenType::PercentRightBrace,
-                    lexeme: "%}".to_string(),
+                    lexeme: Cow::Borrowed("%}"),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -532,7 +1457,8 @@ mod tests {
                 "{#",
                 Token {
                     token_type: TokenType::LeftBraceHash,
-                    lexeme: "{#".to_string(),
+                    lexeme: Cow::Borrowed("{#"),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -540,7 +1466,8 @@ mod tests {
                 "#}",
                 Token {
                     token_type: TokenType::HashRightBrace,
-                    lexeme: "#}".to_string(),
+                    lexeme: Cow::Borrowed("#}"),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -548,7 +1475,8 @@ mod tests {
                 "!=",
                 Token {
                     token_type: TokenType::BangEqual,
-                    lexeme: "!=".to_string(),
+                    lexeme: Cow::Borrowed("!="),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -556,7 +1484,8 @@ mod tests {
                 "==",
                 Token {
                     token_type: TokenType::DoubleEqual,
-                    lexeme: "==".to_string(),
+                    lexeme: Cow::Borrowed("=="),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -564,7 +1493,8 @@ mod tests {
                 "<=",
                 Token {
                     token_type: TokenType::LeftAngleEqual,
-                    lexeme: "<=".to_string(),
+                    lexeme: Cow::Borrowed("<="),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -572,7 +1502,8 @@ mod tests {
                 ">=",
                 Token {
                     token_type: TokenType::RightAngleEqual,
-                    lexeme: ">=".to_string(),
+                    lexeme: Cow::Borrowed(">="),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -580,7 +1511,8 @@ mod tests {
                 "<!--",
                 Token {
                     token_type: TokenType::LeftAngleBangDashDash,
-                    lexeme: "<!--".to_string(),
+                    lexeme: Cow::Borrowed("<!--"),
+                    span: Span::new(0, 4),
                     line,
                 },
             ),
@@ -588,7 +1520,8 @@ mod tests {
                 "-->",
                 Token {
                     token_type: TokenType::DashDashRightAngle,
-                    lexeme: "-->".to_string(),
+                    lexeme: Cow::Borrowed("-->"),
+                    span: Span::new(0, 3),
                     line,
                 },
             ),
@@ -596,7 +1529,8 @@ mod tests {
                 "/>",
                 Token {
                     token_type: TokenType::SlashRightAngle,
-                    lexeme: "/>".to_string(),
+                    lexeme: Cow::Borrowed("/>"),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -604,7 +1538,8 @@ mod tests {
                 "//",
                 Token {
                     token_type: TokenType::DoubleSlash,
-                    lexeme: "//".to_string(),
+                    lexeme: Cow::Borrowed("//"),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -612,7 +1547,8 @@ mod tests {
                 "/*",
                 Token {
                     token_type: TokenType::SlashStar,
-                    lexeme: "/*".to_string(),
+                    lexeme: Cow::Borrowed("/*"),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -620,7 +1556,8 @@ mod tests {
                 "*/",
                 Token {
                     token_type: TokenType::StarSlash,
-                    lexeme: "*/".to_string(),
+                    lexeme: Cow::Borrowed("*/"),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -628,7 +1565,8 @@ mod tests {
                 " ",
                 Token {
                     token_type: TokenType::Whitespace,
-                    lexeme: " ".to_string(),
+                    lexeme: Cow::Borrowed(" "),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -636,7 +1574,8 @@ mod tests {
                 "\r",
                 Token {
                     token_type: TokenType::Whitespace,
-                    lexeme: "\r".to_string(),
+                    lexeme: Cow::Borrowed("\r"),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -644,7 +1583,8 @@ mod tests {
                 "\t",
                 Token {
                     token_type: TokenType::Whitespace,
-                    lexeme: "\t".to_string(),
+                    lexeme: Cow::Borrowed("\t"),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -652,7 +1592,8 @@ mod tests {
                 "\n",
                 Token {
                     token_type: TokenType::Whitespace,
-                    lexeme: "\n".to_string(),
+                    lexeme: Cow::Borrowed("\n"),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -660,7 +1601,8 @@ mod tests {
                 "  ",
                 Token {
                     token_type: TokenType::Whitespace,
-                    lexeme: "  ".to_string(),
+                    lexeme: Cow::Borrowed("  "),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -668,7 +1610,8 @@ mod tests {
                 " \n",
                 Token {
                     token_type: TokenType::Whitespace,
-                    lexeme: " \n".to_string(),
+                    lexeme: Cow::Borrowed(" \n"),
+                    span: Span::new(0, 2),
                     line,
                 },
             ),
@@ -676,7 +1619,8 @@ mod tests {
                 "a",
                 Token {
                     token_type: TokenType::Text,
-                    lexeme: "a".to_string(),
+                    lexeme: Cow::Borrowed("a"),
+                    span: Span::new(0, 1),
                     line,
                 },
             ),
@@ -684,7 +1628,8 @@ mod tests {
                 "Hello",
                 Token {
                     token_type: TokenType::Text,
-                    lexeme: "Hello".to_string(),
+                    lexeme: Cow::Borrowed("Hello"),
+                    span: Span::new(0, 5),
                     line,
                 },
             ),
@@ -924,37 +1869,37 @@ mod tests {
         assert_eq!(lexer.line, 1);
 
         // "Hello"
-        lexer.advance(5, 0).unwrap();
+        lexer.advance_by(5, 0).unwrap();
         assert_eq!(lexer.current, 5);
         assert_eq!(lexer.start, 0);
         assert_eq!(lexer.line, 1);
 
         // newline
-        lexer.advance(1, 1).unwrap();
+        lexer.advance_by(1, 1).unwrap();
         assert_eq!(lexer.current, 6);
         assert_eq!(lexer.start, 5);
         assert_eq!(lexer.line, 2);
 
         // "World"
-        lexer.advance(5, 0).unwrap();
+        lexer.advance_by(5, 0).unwrap();
         assert_eq!(lexer.current, 11);
         assert_eq!(lexer.start, 6);
         assert_eq!(lexer.line, 2);
 
         // newline
-        lexer.advance(1, 1).unwrap();
+        lexer.advance_by(1, 1).unwrap();
         assert_eq!(lexer.current, 12);
         assert_eq!(lexer.start, 11);
         assert_eq!(lexer.line, 3);
 
         // "Test"
-        lexer.advance(4, 0).unwrap();
+        lexer.advance_by(4, 0).unwrap();
         assert_eq!(lexer.current, 16);
         assert_eq!(lexer.start, 12);
         assert_eq!(lexer.line, 3);
 
         // past the end of the input
-        let result = lexer.advance(1, 0);
+        let result = lexer.advance_by(1, 0);
         assert!(result.is_err());
         assert_eq!(lexer.current, 16);
         assert_eq!(lexer.start, 12);
@@ -1060,6 +2005,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_peek_multibyte() {
+        let input = "aé日🎉b";
+        let mut lexer = Lexer::new(input);
+        lexer.current = 1;
+
+        assert_eq!(lexer.peek().unwrap(), 'é');
+        assert_eq!(lexer.peek_next().unwrap(), '日');
+        assert_eq!(lexer.peek_at(2).unwrap(), '🎉');
+        assert_eq!(lexer.peek_at(3).unwrap(), 'b');
+        assert_eq!(lexer.peek_previous().unwrap(), 'a');
+
+        lexer.current = 6;
+        assert_eq!(lexer.peek().unwrap(), '🎉');
+        assert_eq!(lexer.peek_at(-1).unwrap(), '日');
+        assert_eq!(lexer.peek_at(-2).unwrap(), 'é');
+
+        // byte 2 is inside 'é'
+        lexer.current = 2;
+        assert!(lexer.peek().is_err());
+        assert!(lexer.item_at(2).is_err());
+    }
+
     #[test]
     fn test_item_at() {
         let test_cases = vec![
@@ -1,18 +1,113 @@
+mod ast;
+mod builtins;
+/// The command line tool run by the `django-template-ast` binary.
+#[cfg(feature = "cli")]
+pub mod cli;
+mod condition;
+mod date;
+mod diagnostic;
 mod error;
+mod filters;
+mod html;
+mod incremental;
 mod lexer;
+/// The language server run by the `django-template-lsp` binary.
+#[cfg(feature = "lsp")]
+pub mod lsp;
+mod parser;
+#[cfg(feature = "python")]
+mod python;
+mod registry;
+mod render;
 mod scanner;
+#[cfg(feature = "serde")]
+mod schema;
+mod span;
 mod token;
+mod value;
 
-use lexer::Lexer;
-use std::error::Error;
+pub use ast::{Ast, Block, Branch, Filter, FilterExpression, Node, Tag, Variable};
+pub use builtins::Builtin;
+pub use condition::{Condition, Operator};
+pub use date::{Date, DateTime, Time};
+pub use diagnostic::{Diagnostic, Label, Renderer, Severity};
+pub use error::{Error, LexerError, ParserError, RenderError, ScannerError, TokenError};
+pub use html::{Attribute, AttributeValue, Element, Embedded, HtmlAttribute, Language};
+pub use incremental::{Edit, IncrementalParser};
+pub use lexer::Lexer;
+pub use parser::Parser;
+pub use registry::{ArgSpec, TagRegistry, TagSpec};
+pub use render::Context;
+pub use scanner::Scanner;
+#[cfg(feature = "serde")]
+pub use schema::{Document, SCHEMA_VERSION};
+pub use span::{Position, SourceMap, Span};
+pub use token::{Token, TokenStream, TokenType};
+pub use value::Value;
 
-pub fn compile(template: &str) -> Result<String, Box<dyn Error>> {
+/// Split a template into tokens, leaving out the whitespace inside Django tags. Use
+/// [`Lexer::lossless`] to keep it.
+pub fn tokenize(template: &str) -> Result<TokenStream<'_>, Error> {
+    Ok(Lexer::new(template).tokenize()?)
+}
+
+/// Parse a template into a tree that prints back to the original source, failing with
+/// a [`Diagnostic`] for the first error found.
+pub fn parse(template: &str) -> Result<Ast, Error> {
+    let lexer = Lexer::lossless(template);
+    parse_with_lexer(template, lexer, registry::default_registry())
+}
+
+/// Compile a template, failing with a [`Diagnostic`] for the first error found.
+pub fn compile(template: &str) -> Result<String, Error> {
+    compile_with_registry(template, registry::default_registry())
+}
+
+/// Like [`compile`], using `registry` to find where custom block tags end.
+pub fn compile_with_registry(template: &str, registry: &TagRegistry) -> Result<String, Error> {
+    let ast = parse_with_lexer(template, Lexer::new(template), registry)?;
+    Ok(ast.to_string())
+}
+
+/// Render a parsed template against `context`, the way Django's `Template.render` does.
+/// As in Django, variables are escaped for HTML unless they are marked safe, with
+/// [`Value::safe`] or the `safe` filter, or autoescaping is turned off.
+pub fn render(ast: &Ast, context: &Context) -> Result<String, Error> {
+    Ok(render::Renderer::new(ast, context).render(&ast.nodes)?)
+}
+
+fn parse_with_lexer(
+    template: &str,
+    mut lexer: Lexer,
+    registry: &TagRegistry,
+) -> Result<Ast, Error> {
+    lexer
+        .tokenize()
+        .map_err(ParserError::from)
+        .and_then(|tokens| Parser::with_registry(tokens, registry).parse())
+        .map_err(|error| locate_error(template, registry, &error).into())
+}
+
+/// Strict parsing only knows the line an error is on, so parse again resiliently to
+/// find the span of the first error.
+fn locate_error(template: &str, registry: &TagRegistry, error: &ParserError) -> Diagnostic {
     let mut lexer = Lexer::new(template);
-    let tokens = lexer.tokenize()?;
-    println!("{:?}", tokens);
-    todo!("Implement compilation process")
+    let (tokens, mut diagnostics) = lexer.tokenize_resilient();
+    let (_, parser_diagnostics) = Parser::with_registry(tokens, registry).parse_resilient();
+    diagnostics.extend(parser_diagnostics);
+    diagnostics
+        .into_iter()
+        .find(Diagnostic::is_error)
+        .unwrap_or_else(|| Diagnostic::from_parser_error(error, Span::default()))
 }
 
-// re-export important types or functions from modules here, e.g.
-// pub use lexer::Lexer;
-// that should be part of the public API
+/// Parse a possibly broken template, such as one being edited, into a tree that prints
+/// back to the original source, along with every problem found on the way.
+pub fn parse_resilient(template: &str) -> (Ast, Vec<Diagnostic>) {
+    let mut lexer = Lexer::lossless(template);
+    let (tokens, mut diagnostics) = lexer.tokenize_resilient();
+    let mut parser = Parser::new(tokens);
+    let (ast, parser_diagnostics) = parser.parse_resilient();
+    diagnostics.extend(parser_diagnostics);
+    (ast, diagnostics)
+}
@@ -0,0 +1,1089 @@
+use crate::ast::{Ast, Node, Tag, Variable};
+use crate::builtins::{unquote, Builtin};
+use crate::diagnostic::Severity;
+use crate::html::Attribute;
+use crate::incremental::{Edit, IncrementalParser};
+use crate::span::{SourceMap, Span};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as LspNotification, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, FoldingRangeRequest, GotoDefinition, HoverRequest,
+    Request as LspRequest,
+};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    DiagnosticRelatedInformation, DiagnosticSeverity, DocumentSymbol, DocumentSymbolParams,
+    DocumentSymbolResponse, FoldingRange, FoldingRangeKind, FoldingRangeParams,
+    FoldingRangeProviderCapability, GotoDefinitionParams, GotoDefinitionResponse, Hover,
+    HoverContents, HoverParams, HoverProviderCapability, InitializeParams, Location, MarkupContent,
+    MarkupKind, NumberOrString, OneOf, Position, PublishDiagnosticsParams, Range,
+    ServerCapabilities, SymbolKind, TextDocumentContentChangeEvent, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Url,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Django's builtin tags.
+const TAGS: &[(&str, &str)] = &[
+    (
+        "autoescape",
+        "Controls the current auto-escaping behavior. Takes `on` or `off`.",
+    ),
+    (
+        "block",
+        "Defines a block that can be overridden by child templates.",
+    ),
+    (
+        "comment",
+        "Ignores everything between `{% comment %}` and `{% endcomment %}`.",
+    ),
+    (
+        "csrf_token",
+        "Outputs a hidden input with the CSRF token, for POST forms.",
+    ),
+    (
+        "cycle",
+        "Produces one of its arguments each time this tag is encountered.",
+    ),
+    (
+        "debug",
+        "Outputs debugging information, including the current context.",
+    ),
+    (
+        "extends",
+        "Signals that this template extends a parent template.",
+    ),
+    (
+        "filter",
+        "Filters the contents of the block through one or more filters.",
+    ),
+    (
+        "firstof",
+        "Outputs the first argument variable that is not false.",
+    ),
+    (
+        "for",
+        "Loops over each item in a sequence, making the item available in a variable.",
+    ),
+    (
+        "if",
+        "Outputs the contents of the block if the condition is true.",
+    ),
+    (
+        "ifchanged",
+        "Checks if a value has changed from the last iteration of a loop.",
+    ),
+    (
+        "include",
+        "Loads a template and renders it with the current context.",
+    ),
+    ("load", "Loads a custom template tag library."),
+    ("lorem", "Displays random \"lorem ipsum\" Latin text."),
+    (
+        "now",
+        "Displays the current date and/or time, using the given format.",
+    ),
+    (
+        "partial",
+        "Renders a fragment defined with `{% partialdef %}`.",
+    ),
+    (
+        "partialdef",
+        "Defines a reusable template fragment, rendered with `{% partial %}`.",
+    ),
+    (
+        "regroup",
+        "Regroups a list of alike objects by a common attribute.",
+    ),
+    (
+        "resetcycle",
+        "Resets a previous cycle so that it restarts from its first item.",
+    ),
+    ("spaceless", "Removes whitespace between HTML tags."),
+    (
+        "templatetag",
+        "Outputs one of the syntax characters used to compose template tags.",
+    ),
+    (
+        "url",
+        "Returns an absolute path reference matching a view and optional parameters.",
+    ),
+    (
+        "verbatim",
+        "Stops the template engine from rendering the contents of this block.",
+    ),
+    (
+        "widthratio",
+        "Calculates the ratio of a value to a maximum value, e.g. for bar charts.",
+    ),
+    ("with", "Caches a complex variable under a simpler name."),
+];
+
+/// Django's builtin filters.
+const FILTERS: &[(&str, &str)] = &[
+    ("add", "Adds the argument to the value."),
+    ("addslashes", "Adds slashes before quotes."),
+    ("capfirst", "Capitalizes the first character of the value."),
+    ("center", "Centers the value in a field of a given width."),
+    (
+        "cut",
+        "Removes all values of the argument from the given string.",
+    ),
+    ("date", "Formats a date according to the given format."),
+    (
+        "default",
+        "Uses the argument if the value evaluates to false.",
+    ),
+    (
+        "default_if_none",
+        "Uses the argument if, and only if, the value is `None`.",
+    ),
+    (
+        "dictsort",
+        "Sorts a list of dictionaries by the key given in the argument.",
+    ),
+    (
+        "dictsortreversed",
+        "Sorts a list of dictionaries by the key, in reverse order.",
+    ),
+    (
+        "divisibleby",
+        "Returns `True` if the value is divisible by the argument.",
+    ),
+    ("escape", "Escapes a string's HTML."),
+    (
+        "escapejs",
+        "Escapes characters for use as a whole JavaScript string literal.",
+    ),
+    (
+        "escapeseq",
+        "Applies the `escape` filter to each element of a sequence.",
+    ),
+    (
+        "filesizeformat",
+        "Formats the value like a human-readable file size.",
+    ),
+    ("first", "Returns the first item in a list."),
+    (
+        "floatformat",
+        "Rounds a floating-point number to the given number of decimal places.",
+    ),
+    (
+        "force_escape",
+        "Applies HTML escaping to a string immediately.",
+    ),
+    (
+        "get_digit",
+        "Returns the requested digit of a whole number, counting from the right.",
+    ),
+    (
+        "iriencode",
+        "Converts an IRI to a string that is suitable for a URL.",
+    ),
+    ("join", "Joins a list with a string."),
+    (
+        "json_script",
+        "Outputs a Python object as JSON, wrapped in a `<script>` tag.",
+    ),
+    ("last", "Returns the last item in a list."),
+    ("length", "Returns the length of the value."),
+    (
+        "linebreaks",
+        "Replaces line breaks with `<br>` and `<p>` tags.",
+    ),
+    ("linebreaksbr", "Converts all newlines to `<br>` tags."),
+    ("linenumbers", "Displays text with line numbers."),
+    (
+        "ljust",
+        "Left-aligns the value in a field of a given width.",
+    ),
+    ("lower", "Converts a string into all lowercase."),
+    ("make_list", "Returns the value turned into a list."),
+    (
+        "phone2numeric",
+        "Converts a phone number with letters to its numerical equivalent.",
+    ),
+    (
+        "pluralize",
+        "Returns a plural suffix if the value is not 1.",
+    ),
+    ("pprint", "A wrapper around `pprint.pprint`, for debugging."),
+    ("random", "Returns a random item from the given list."),
+    (
+        "rjust",
+        "Right-aligns the value in a field of a given width.",
+    ),
+    (
+        "safe",
+        "Marks a string as not requiring further HTML escaping.",
+    ),
+    (
+        "safeseq",
+        "Applies the `safe` filter to each element of a sequence.",
+    ),
+    (
+        "slice",
+        "Returns a slice of the list, using Python's slice syntax.",
+    ),
+    (
+        "slugify",
+        "Converts to ASCII, lowercases and replaces spaces with hyphens.",
+    ),
+    (
+        "stringformat",
+        "Formats the variable according to the %-formatting argument.",
+    ),
+    ("striptags", "Makes all efforts to strip all HTML tags."),
+    ("time", "Formats a time according to the given format."),
+    (
+        "timesince",
+        "Formats a date as the time since that date, e.g. \"4 days, 6 hours\".",
+    ),
+    (
+        "timeuntil",
+        "Measures the time from now until the given date or datetime.",
+    ),
+    ("title", "Converts a string into titlecase."),
+    (
+        "truncatechars",
+        "Truncates a string if it is longer than the given number of characters.",
+    ),
+    (
+        "truncatechars_html",
+        "Like `truncatechars`, but aware of HTML tags.",
+    ),
+    (
+        "truncatewords",
+        "Truncates a string after a certain number of words.",
+    ),
+    (
+        "truncatewords_html",
+        "Like `truncatewords`, but aware of HTML tags.",
+    ),
+    (
+        "unordered_list",
+        "Recursively turns a nested list into an HTML unordered list.",
+    ),
+    ("upper", "Converts a string into all uppercase."),
+    ("urlencode", "Escapes a value for use in a URL."),
+    (
+        "urlize",
+        "Converts URLs and email addresses in text into clickable links.",
+    ),
+    (
+        "urlizetrunc",
+        "Like `urlize`, but truncates link text longer than the given limit.",
+    ),
+    ("wordcount", "Returns the number of words."),
+    ("wordwrap", "Wraps words at the specified line length."),
+    (
+        "yesno",
+        "Maps values for `True`, `False` and `None` to the given strings.",
+    ),
+];
+
+/// Django's tag libraries, with the tags and filters that `{% load %}` makes available.
+const LIBRARIES: &[(&str, &[&str], &[&str])] = &[
+    ("cache", &["cache"], &[]),
+    (
+        "humanize",
+        &[],
+        &[
+            "apnumber",
+            "intcomma",
+            "intword",
+            "naturalday",
+            "naturaltime",
+            "ordinal",
+        ],
+    ),
+    (
+        "i18n",
+        &[
+            "blocktrans",
+            "blocktranslate",
+            "get_available_languages",
+            "get_current_language",
+            "get_current_language_bidi",
+            "get_language_info",
+            "get_language_info_list",
+            "language",
+            "trans",
+            "translate",
+        ],
+        &[
+            "language_bidi",
+            "language_name",
+            "language_name_local",
+            "language_name_translated",
+        ],
+    ),
+    ("l10n", &["localize"], &["localize", "unlocalize"]),
+    (
+        "static",
+        &["get_media_prefix", "get_static_prefix", "static"],
+        &[],
+    ),
+    (
+        "tz",
+        &["get_current_timezone", "localtime", "timezone"],
+        &["localtime", "timezone", "utc"],
+    ),
+];
+
+/// Directories never searched for templates.
+const SKIPPED_DIRECTORIES: &[&str] = &["node_modules", "target", "venv", "__pycache__"];
+
+type ServerError = Box<dyn Error + Send + Sync>;
+
+/// Run the language server over `connection` until the client shuts it down.
+pub fn run(connection: Connection) -> Result<(), ServerError> {
+    let capabilities = serde_json::to_value(capabilities())?;
+    let params: InitializeParams = serde_json::from_value(connection.initialize(capabilities)?)?;
+    let mut server = Server::new(&params);
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                let response = server.request(request);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                if let Some(message) = server.notification(notification)? {
+                    connection.sender.send(message)?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(
+            TextDocumentSyncKind::INCREMENTAL,
+        )),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec!["%".to_string(), " ".to_string(), "|".to_string()]),
+            ..CompletionOptions::default()
+        }),
+        ..ServerCapabilities::default()
+    }
+}
+
+struct Server {
+    /// The open templates, parsed again around each change.
+    documents: HashMap<Url, IncrementalParser>,
+    /// Where `{% extends %}` and `{% include %}` look for templates, besides the
+    /// directories containing the template itself.
+    template_dirs: Vec<PathBuf>,
+}
+
+impl Server {
+    /// Template directories come from the `templateDirs` initialization option, relative
+    /// to the workspace root, or else are the directories named `templates` in it.
+    fn new(params: &InitializeParams) -> Self {
+        #[allow(deprecated)]
+        let root = params
+            .workspace_folders
+            .as_ref()
+            .and_then(|folders| folders.first())
+            .map(|folder| folder.uri.clone())
+            .or_else(|| params.root_uri.clone())
+            .and_then(|uri| uri.to_file_path().ok());
+        let configured: Option<Vec<String>> = params
+            .initialization_options
+            .as_ref()
+            .and_then(|options| options.get("templateDirs"))
+            .and_then(|dirs| serde_json::from_value(dirs.clone()).ok());
+        let template_dirs = match (&root, configured) {
+            (Some(root), Some(dirs)) => dirs.iter().map(|dir| root.join(dir)).collect(),
+            (None, Some(dirs)) => dirs.iter().map(PathBuf::from).collect(),
+            (Some(root), None) => {
+                let mut dirs = Vec::new();
+                find_template_dirs(root, &mut dirs);
+                dirs
+            }
+            (None, None) => Vec::new(),
+        };
+        Server {
+            documents: HashMap::new(),
+            template_dirs,
+        }
+    }
+
+    fn request(&mut self, request: Request) -> Response {
+        let id = request.id.clone();
+        let result = match request.method.as_str() {
+            DocumentSymbolRequest::METHOD => self.handle(request, Server::document_symbols),
+            FoldingRangeRequest::METHOD => self.handle(request, Server::folding_ranges),
+            HoverRequest::METHOD => self.handle(request, Server::hover),
+            GotoDefinition::METHOD => self.handle(request, Server::definition),
+            Completion::METHOD => self.handle(request, Server::completion),
+            method => {
+                return Response::new_err(
+                    id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("unsupported request '{}'", method),
+                );
+            }
+        };
+        match result {
+            Ok(result) => Response::new_ok(id, result),
+            Err(error) => Response::new_err(id, ErrorCode::InvalidParams as i32, error.to_string()),
+        }
+    }
+
+    fn handle<P, R>(
+        &self,
+        request: Request,
+        handler: fn(&Server, P) -> Option<R>,
+    ) -> Result<Value, serde_json::Error>
+    where
+        P: serde::de::DeserializeOwned,
+        R: serde::Serialize,
+    {
+        let params = serde_json::from_value(request.params)?;
+        serde_json::to_value(handler(self, params))
+    }
+
+    /// Apply a notification, returning the diagnostics to publish if a document changed.
+    fn notification(&mut self, notification: Notification) -> Result<Option<Message>, ServerError> {
+        let uri = match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: <DidOpenTextDocument as LspNotification>::Params =
+                    serde_json::from_value(notification.params)?;
+                let uri = params.text_document.uri;
+                self.documents.insert(
+                    uri.clone(),
+                    IncrementalParser::new(&params.text_document.text),
+                );
+                uri
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: <DidChangeTextDocument as LspNotification>::Params =
+                    serde_json::from_value(notification.params)?;
+                let uri = params.text_document.uri;
+                if let Some(document) = self.documents.get_mut(&uri) {
+                    for change in params.content_changes {
+                        apply_change(document, change);
+                    }
+                }
+                uri
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: <DidCloseTextDocument as LspNotification>::Params =
+                    serde_json::from_value(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+                params.text_document.uri
+            }
+            _ => return Ok(None),
+        };
+        let diagnostics = self
+            .documents
+            .get(&uri)
+            .map(|document| lsp_diagnostics(&uri, document))
+            .unwrap_or_default();
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        };
+        Ok(Some(Message::Notification(Notification::new(
+            PublishDiagnostics::METHOD.to_string(),
+            params,
+        ))))
+    }
+
+    fn document_symbols(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let document = self.documents.get(&params.text_document.uri)?;
+        let source_map = SourceMap::new(document.source());
+        let outline = Outline::new(document.ast());
+        let mut symbols: Vec<(Span, DocumentSymbol)> = Vec::new();
+        for &(tag, end) in &outline.blocks {
+            let kind = match tag.name.as_str() {
+                "block" => SymbolKind::NAMESPACE,
+                "partialdef" => SymbolKind::FUNCTION,
+                _ => continue,
+            };
+            let Some(name) = tag.bits.first() else {
+                continue;
+            };
+            let span = tag.span.join(end.span);
+            #[allow(deprecated)]
+            let symbol = DocumentSymbol {
+                name: name.clone(),
+                detail: Some(tag.name.clone()),
+                kind,
+                tags: None,
+                deprecated: None,
+                range: range(&source_map, document.source(), span),
+                selection_range: range(&source_map, document.source(), tag.span),
+                children: None,
+            };
+            symbols.push((span, symbol));
+        }
+        Some(DocumentSymbolResponse::Nested(nest(symbols)))
+    }
+
+    fn folding_ranges(&self, params: FoldingRangeParams) -> Option<Vec<FoldingRange>> {
+        let document = self.documents.get(&params.text_document.uri)?;
+        let source_map = SourceMap::new(document.source());
+        let outline = Outline::new(document.ast());
+        let line = |offset| source_map.position(offset).line as u32 - 1;
+        let mut ranges = Vec::new();
+        for (tag, end) in &outline.blocks {
+            // Keep the end tag visible when the block is folded.
+            let (start, end) = (line(tag.span.start), line(end.span.start));
+            if end > start + 1 {
+                ranges.push(FoldingRange {
+                    start_line: start,
+                    end_line: end - 1,
+                    kind: Some(FoldingRangeKind::Region),
+                    ..FoldingRange::default()
+                });
+            }
+        }
+        for comment in &outline.comments {
+            let (start, end) = (line(comment.start), line(comment.end));
+            if end > start {
+                ranges.push(FoldingRange {
+                    start_line: start,
+                    end_line: end,
+                    kind: Some(FoldingRangeKind::Comment),
+                    ..FoldingRange::default()
+                });
+            }
+        }
+        ranges.sort_by_key(|range| range.start_line);
+        Some(ranges)
+    }
+
+    /// Describe the builtin tag or filter under the cursor.
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let position = params.text_document_position_params;
+        let document = self.documents.get(&position.text_document.uri)?;
+        let source_map = SourceMap::new(document.source());
+        let offset = offset(&source_map, document.source(), position.position);
+        let outline = Outline::new(document.ast());
+        let (word, span) = word_at(document.source(), offset)?;
+        let before = document.source()[..span.start].trim_end();
+
+        let (title, description) = if before.ends_with('|') {
+            let description =
+                lookup(FILTERS, word).or_else(|| library_filter(word).map(library_description))?;
+            (format!("`|{}`", word), description)
+        } else {
+            let tag = outline
+                .tags
+                .iter()
+                .find(|tag| tag.span.contains(offset) && tag.name == word)?;
+            let name = tag.name.strip_prefix("end").unwrap_or(&tag.name);
+            let description =
+                lookup(TAGS, name).or_else(|| library_tag(name).map(library_description))?;
+            (format!("`{{% {} %}}`", name), description)
+        };
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("{}\n\n{}", title, description),
+            }),
+            range: Some(range(&source_map, document.source(), span)),
+        })
+    }
+
+    /// Go from `{% extends %}` or `{% include %}` to the template it names.
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let position = params.text_document_position_params;
+        let uri = position.text_document.uri;
+        let document = self.documents.get(&uri)?;
+        let source_map = SourceMap::new(document.source());
+        let offset = offset(&source_map, document.source(), position.position);
+        let outline = Outline::new(document.ast());
+        let name = outline.builtins.iter().find_map(|(builtin, span)| {
+            let template = match builtin {
+                Builtin::Extends(extends) => &extends.parent,
+                Builtin::Include(include) => &include.template,
+                _ => return None,
+            };
+            (span.contains(offset) && template.filters.is_empty())
+                .then(|| unquote(&template.var))
+                .flatten()
+        })?;
+        let path = self.resolve_template(name, uri.to_file_path().ok().as_deref())?;
+        let location = Location {
+            uri: Url::from_file_path(path).ok()?,
+            range: Range::default(),
+        };
+        Some(GotoDefinitionResponse::Scalar(location))
+    }
+
+    fn resolve_template(&self, name: &str, document: Option<&Path>) -> Option<PathBuf> {
+        let directory = document.and_then(Path::parent);
+        if name.starts_with("./") || name.starts_with("../") {
+            let path = directory?.join(name);
+            return path.is_file().then_some(path);
+        }
+        let ancestors = directory.into_iter().flat_map(Path::ancestors);
+        self.template_dirs
+            .iter()
+            .map(PathBuf::as_path)
+            .chain(ancestors)
+            .map(|directory| directory.join(name))
+            .find(|path| path.is_file())
+    }
+
+    /// Complete tag names after `{%`, libraries in `{% load %}` and filters after `|`,
+    /// including the tags and filters of the libraries the template loads.
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let position = params.text_document_position;
+        let document = self.documents.get(&position.text_document.uri)?;
+        let source_map = SourceMap::new(document.source());
+        let offset = offset(&source_map, document.source(), position.position);
+        let line_start = document.source()[..offset]
+            .rfind('\n')
+            .map_or(0, |index| index + 1);
+        let line = &document.source()[line_start..offset];
+
+        let loaded = Outline::new(document.ast()).loaded_libraries();
+        let opener = line.rfind("{%").max(line.rfind("{{"));
+        let inside = opener
+            .map(|opener| &line[opener + 2..])
+            .filter(|inside| !inside.contains("%}") && !inside.contains("}}"))?;
+        let word_start = inside
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .map_or(0, |index| index + 1);
+        let before = inside[..word_start].trim_end();
+        let is_tag = line[opener?..].starts_with("{%");
+
+        let items = if before.ends_with('|') {
+            let mut items = completion_items(FILTERS, CompletionItemKind::FUNCTION);
+            for (library, _, filters) in LIBRARIES {
+                if loaded.contains(library) {
+                    items.extend(library_items(
+                        library,
+                        filters,
+                        CompletionItemKind::FUNCTION,
+                    ));
+                }
+            }
+            items
+        } else if is_tag && before.is_empty() {
+            let mut items = completion_items(TAGS, CompletionItemKind::KEYWORD);
+            for (library, tags, _) in LIBRARIES {
+                if loaded.contains(library) {
+                    items.extend(library_items(library, tags, CompletionItemKind::KEYWORD));
+                }
+            }
+            items
+        } else if is_tag && before.split_whitespace().next() == Some("load") {
+            LIBRARIES
+                .iter()
+                .map(|(library, _, _)| CompletionItem {
+                    label: library.to_string(),
+                    kind: Some(CompletionItemKind::MODULE),
+                    ..CompletionItem::default()
+                })
+                .collect()
+        } else {
+            return None;
+        };
+        Some(CompletionResponse::Array(items))
+    }
+}
+
+fn lsp_diagnostics(uri: &Url, document: &IncrementalParser) -> Vec<lsp_types::Diagnostic> {
+    let source_map = SourceMap::new(document.source());
+    let range = |span| range(&source_map, document.source(), span);
+    document
+        .diagnostics()
+        .map(|diagnostic| {
+            let mut message = diagnostic.message.clone();
+            for note in &diagnostic.notes {
+                message.push_str(&format!("\nnote: {}", note));
+            }
+            if let Some(help) = &diagnostic.help {
+                message.push_str(&format!("\nhelp: {}", help));
+            }
+            let related: Vec<DiagnosticRelatedInformation> = diagnostic
+                .labels
+                .iter()
+                .filter(|label| !label.primary)
+                .map(|label| DiagnosticRelatedInformation {
+                    location: Location {
+                        uri: uri.clone(),
+                        range: range(label.span),
+                    },
+                    message: label.message.clone(),
+                })
+                .collect();
+            lsp_types::Diagnostic {
+                range: range(diagnostic.span()),
+                severity: Some(match diagnostic.severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                }),
+                code: Some(NumberOrString::String(diagnostic.code.to_string())),
+                source: Some("django-template-ast".to_string()),
+                message,
+                related_information: (!related.is_empty()).then_some(related),
+                ..lsp_types::Diagnostic::default()
+            }
+        })
+        .collect()
+}
+
+/// Nest symbols inside the symbols whose spans contain them.
+fn nest(mut symbols: Vec<(Span, DocumentSymbol)>) -> Vec<DocumentSymbol> {
+    symbols.sort_by_key(|(span, _)| (span.start, std::cmp::Reverse(span.end)));
+    let mut roots = Vec::new();
+    let mut stack: Vec<(Span, DocumentSymbol)> = Vec::new();
+    for (span, symbol) in symbols {
+        while stack
+            .last()
+            .is_some_and(|(parent, _)| parent.end <= span.start)
+        {
+            pop_symbol(&mut stack, &mut roots);
+        }
+        stack.push((span, symbol));
+    }
+    while !stack.is_empty() {
+        pop_symbol(&mut stack, &mut roots);
+    }
+    roots
+}
+
+fn pop_symbol(stack: &mut Vec<(Span, DocumentSymbol)>, roots: &mut Vec<DocumentSymbol>) {
+    let Some((_, symbol)) = stack.pop() else {
+        return;
+    };
+    match stack.last_mut() {
+        Some((_, parent)) => parent.children.get_or_insert_with(Vec::new).push(symbol),
+        None => roots.push(symbol),
+    }
+}
+
+fn find_template_dirs(directory: &Path, dirs: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return;
+    };
+    let mut entries: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir())
+        .collect();
+    entries.sort();
+    for entry in entries {
+        let name = entry
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        let Some(name) = name else {
+            continue;
+        };
+        if name.starts_with('.') || SKIPPED_DIRECTORIES.contains(&name.as_str()) {
+            continue;
+        }
+        if name == "templates" {
+            dirs.push(entry.clone());
+        }
+        find_template_dirs(&entry, dirs);
+    }
+}
+
+fn lookup(table: &[(&str, &'static str)], name: &str) -> Option<String> {
+    table
+        .iter()
+        .find(|(entry, _)| *entry == name)
+        .map(|(_, description)| description.to_string())
+}
+
+fn library_tag(name: &str) -> Option<&'static str> {
+    LIBRARIES
+        .iter()
+        .find(|(_, tags, _)| tags.contains(&name))
+        .map(|(library, _, _)| *library)
+}
+
+fn library_filter(name: &str) -> Option<&'static str> {
+    LIBRARIES
+        .iter()
+        .find(|(_, _, filters)| filters.contains(&name))
+        .map(|(library, _, _)| *library)
+}
+
+fn library_description(library: &str) -> String {
+    format!("From the `{}` library: `{{% load {} %}}`", library, library)
+}
+
+fn completion_items(table: &[(&str, &str)], kind: CompletionItemKind) -> Vec<CompletionItem> {
+    table
+        .iter()
+        .map(|(name, description)| CompletionItem {
+            label: name.to_string(),
+            kind: Some(kind),
+            detail: Some(description.to_string()),
+            ..CompletionItem::default()
+        })
+        .collect()
+}
+
+fn library_items(library: &str, names: &[&str], kind: CompletionItemKind) -> Vec<CompletionItem> {
+    names
+        .iter()
+        .map(|name| CompletionItem {
+            label: name.to_string(),
+            kind: Some(kind),
+            detail: Some(library_description(library)),
+            ..CompletionItem::default()
+        })
+        .collect()
+}
+
+/// The identifier at `offset`, and its span.
+fn word_at(source: &str, offset: usize) -> Option<(&str, Span)> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let start = source[..offset].rfind(|c| !is_word(c)).map_or(0, |index| {
+        index + source[index..].chars().next().map_or(1, char::len_utf8)
+    });
+    let end = source[offset..]
+        .find(|c| !is_word(c))
+        .map_or(source.len(), |index| offset + index);
+    (start < end).then(|| (&source[start..end], Span::new(start, end)))
+}
+
+/// LSP positions count lines from 0 and columns in UTF-16 code units.
+fn position(source_map: &SourceMap, source: &str, offset: usize) -> Position {
+    let line = source_map.position(offset).line;
+    let line_start = source_map.line_start(line).unwrap_or(0);
+    let character = source[line_start..offset.min(source.len())]
+        .encode_utf16()
+        .count();
+    Position::new(line as u32 - 1, character as u32)
+}
+
+fn range(source_map: &SourceMap, source: &str, span: Span) -> Range {
+    Range::new(
+        position(source_map, source, span.start),
+        position(source_map, source, span.end),
+    )
+}
+
+/// Apply a change from the editor: a range of the document replaced by new text, or
+/// else the whole of it.
+fn apply_change(document: &mut IncrementalParser, change: TextDocumentContentChangeEvent) {
+    let Some(lsp_range) = change.range else {
+        *document = IncrementalParser::new(&change.text);
+        return;
+    };
+    let source = document.source();
+    let source_map = SourceMap::new(source);
+    let start = offset(&source_map, source, lsp_range.start);
+    let end = offset(&source_map, source, lsp_range.end).max(start);
+    document.edit(&Edit::new(Span::new(start, end), &change.text));
+}
+
+/// The byte offset of an LSP position, clamped to the end of its line.
+fn offset(source_map: &SourceMap, source: &str, position: Position) -> usize {
+    let Some(line_start) = source_map.line_start(position.line as usize + 1) else {
+        return source.len();
+    };
+    let line = source_map.line(position.line as usize + 1).unwrap_or("");
+    let mut units = 0;
+    for (index, c) in line.char_indices() {
+        if units >= position.character as usize {
+            return line_start + index;
+        }
+        units += c.len_utf16();
+    }
+    line_start + line.len()
+}
+
+/// The tags, variables and blocks of a tree, found anywhere in it.
+#[derive(Default)]
+struct Outline<'a> {
+    tags: Vec<&'a Tag>,
+    variables: Vec<&'a Variable>,
+    /// The opening and end tag of each block, inner blocks first.
+    blocks: Vec<(&'a Tag, &'a Tag)>,
+    builtins: Vec<(&'a Builtin, Span)>,
+    comments: Vec<Span>,
+}
+
+impl<'a> Outline<'a> {
+    fn new(ast: &'a Ast) -> Self {
+        let mut outline = Outline::default();
+        outline.nodes(&ast.nodes);
+        outline
+    }
+
+    fn loaded_libraries(&self) -> Vec<&'a str> {
+        self.builtins
+            .iter()
+            .filter_map(|(builtin, _)| match builtin {
+                Builtin::Load(load) => Some(load.libraries.iter().map(String::as_str)),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    fn nodes(&mut self, nodes: &'a [Node]) {
+        for node in nodes {
+            self.node(node);
+        }
+    }
+
+    /// Record a block made of `sections`, each a tag and the nodes after it, closed by
+    /// `end`.
+    fn block(&mut self, sections: &[(&'a Tag, &'a [Node])], end: &'a Tag) {
+        for (tag, nodes) in sections {
+            self.tags.push(tag);
+            self.nodes(nodes);
+        }
+        self.tags.push(end);
+        self.blocks.push((sections[0].0, end));
+    }
+
+    fn node(&mut self, node: &'a Node) {
+        match node {
+            Node::Text { .. } | Node::Missing { .. } => {}
+            Node::Variable(variable) => self.variables.push(variable),
+            Node::Tag(tag) => self.tags.push(tag),
+            Node::Comment { span, .. } => self.comments.push(*span),
+            Node::Block(block) => {
+                let mut sections = vec![(&block.tag, block.nodes.as_slice())];
+                sections.extend(
+                    block
+                        .branches
+                        .iter()
+                        .map(|branch| (&branch.tag, branch.nodes.as_slice())),
+                );
+                self.block(&sections, &block.end);
+            }
+            Node::Builtin(builtin) => {
+                let sections = builtin.sections();
+                match builtin.end() {
+                    Some(end) => self.block(&sections, end),
+                    None => self.tags.push(sections[0].0),
+                }
+                self.builtins.push((builtin, node.span()));
+            }
+            Node::Element(element) => {
+                for attribute in &element.attributes {
+                    match attribute {
+                        Attribute::Html(attribute) => {
+                            if let Some(value) = &attribute.value {
+                                self.nodes(&value.parts);
+                            }
+                        }
+                        Attribute::Template { node, .. } => self.node(node),
+                    }
+                }
+                self.nodes(&element.children);
+            }
+            Node::Embedded(embedded) => self.nodes(&embedded.nodes),
+            Node::HtmlComment { nodes, span } => {
+                self.nodes(nodes);
+                self.comments.push(*span);
+            }
+            Node::Error(nodes) => self.nodes(nodes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outline() {
+        let source = "<a href=\"{% url 'home' %}\" {% if b %}c{% endif %}>{{ d }}</a>\
+                      {% block e %}{% for f in g %}{% empty %}<!-- h -->{% endfor %}{% endblock %}";
+        let (ast, _) = crate::parse_resilient(source);
+        let outline = Outline::new(&ast);
+
+        let tags: Vec<&str> = outline
+            .tags
+            .iter()
+            .map(|tag| &source[tag.span.start..tag.span.end])
+            .collect();
+        assert_eq!(
+            tags,
+            vec![
+                "{% url 'home' %}",
+                "{% if b %}",
+                "{% endif %}",
+                "{% block e %}",
+                "{% for f in g %}",
+                "{% empty %}",
+                "{% endfor %}",
+                "{% endblock %}",
+            ]
+        );
+        let span = outline.variables[0].span;
+        assert_eq!(&source[span.start..span.end], "{{ d }}");
+        let blocks: Vec<&str> = outline
+            .blocks
+            .iter()
+            .map(|(tag, end)| &source[tag.span.start..end.span.end])
+            .collect();
+        assert_eq!(
+            blocks,
+            vec![
+                "{% if b %}c{% endif %}",
+                "{% for f in g %}{% empty %}<!-- h -->{% endfor %}",
+                "{% block e %}{% for f in g %}{% empty %}<!-- h -->{% endfor %}{% endblock %}",
+            ]
+        );
+        let comment = outline.comments[0];
+        assert_eq!(&source[comment.start..comment.end], "<!-- h -->");
+    }
+
+    #[test]
+    fn test_position() {
+        let source = "a\né😀b\n";
+        let source_map = SourceMap::new(source);
+
+        let test_cases = vec![
+            (0, Position::new(0, 0)),
+            (2, Position::new(1, 0)),
+            (4, Position::new(1, 1)),
+            (8, Position::new(1, 3)),
+            (10, Position::new(2, 0)),
+        ];
+
+        for (byte_offset, expected) in test_cases {
+            println!("Testing offset: {:?}", byte_offset);
+
+            assert_eq!(position(&source_map, source, byte_offset), expected);
+            assert_eq!(offset(&source_map, source, expected), byte_offset);
+        }
+        assert_eq!(offset(&source_map, source, Position::new(0, 9)), 1);
+        assert_eq!(
+            offset(&source_map, source, Position::new(7, 0)),
+            source.len()
+        );
+    }
+
+    #[test]
+    fn test_word_at() {
+        let source = "{{ name|upper }}";
+
+        assert_eq!(word_at(source, 9), Some(("upper", Span::new(8, 13))));
+        assert_eq!(word_at(source, 8), Some(("upper", Span::new(8, 13))));
+        assert_eq!(word_at(source, 3), Some(("name", Span::new(3, 7))));
+        assert_eq!(word_at(source, 0), None);
+    }
+}
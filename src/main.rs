@@ -0,0 +1,6 @@
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let status = django_template_ast::cli::run(std::env::args().skip(1));
+    ExitCode::from(status)
+}
@@ -0,0 +1,1056 @@
+use crate::ast::{Ast, Block, Branch, Filter, Node, Tag, Variable};
+use crate::builtins::{block_node, tag_node};
+use crate::diagnostic::{Diagnostic, Label};
+use crate::error::ParserError;
+use crate::html::build_elements;
+use crate::registry::{default_registry, TagRegistry, TagSpec};
+use crate::scanner::Scanner;
+use crate::span::Span;
+use crate::token::{Token, TokenStream, TokenType, TokenVecToString};
+use std::ops::ControlFlow;
+
+/// The nodes and branches of a block, and its end tag if it was found.
+type BlockBody = (Vec<Node>, Vec<Branch>, Option<Tag>);
+
+/// A block whose end tag has not been reached yet.
+struct OpenBlock {
+    name: String,
+    /// The span of the tag that opened the block.
+    span: Span,
+    /// The tags that end a section of the block.
+    terminators: Vec<String>,
+}
+
+impl OpenBlock {
+    fn opened_here(&self) -> Label {
+        Label::secondary(self.span, format!("'{}' opened here", self.name))
+    }
+}
+
+pub struct Parser<'a> {
+    tokens: TokenStream<'a>,
+    current: usize,
+    registry: &'a TagRegistry,
+    resilient: bool,
+    diagnostics: Vec<Diagnostic>,
+    /// Every block being parsed, innermost last.
+    open_blocks: Vec<OpenBlock>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: TokenStream<'a>) -> Self {
+        Parser::with_registry(tokens, default_registry())
+    }
+
+    /// Parse using `registry` to find where custom block tags end.
+    pub fn with_registry(tokens: TokenStream<'a>, registry: &'a TagRegistry) -> Self {
+        Parser {
+            tokens,
+            current: 0,
+            registry,
+            resilient: false,
+            diagnostics: Vec::new(),
+            open_blocks: Vec::new(),
+        }
+    }
+
+    pub fn parse(&mut self) -> Result<Ast, ParserError> {
+        let (nodes, _) = self.parse_until(&[])?;
+        Ok(Ast::new(build_elements(nodes)))
+    }
+
+    /// Like `parse`, but always returns a tree. Errors are reported as diagnostics and
+    /// the source they cover is kept in `Node::Error`, with `Node::Missing` marking
+    /// where a closing delimiter or end tag was expected.
+    pub fn parse_resilient(&mut self) -> (Ast, Vec<Diagnostic>) {
+        self.resilient = true;
+        let nodes = match self.parse_until(&[]) {
+            Ok((nodes, _)) => nodes,
+            Err(error) => {
+                let span = self.span_from(0);
+                self.diagnostics
+                    .push(Diagnostic::from_parser_error(&error, span));
+                Vec::new()
+            }
+        };
+        let diagnostics = std::mem::take(&mut self.diagnostics);
+        (Ast::new(build_elements(nodes)), diagnostics)
+    }
+
+    fn parse_until(
+        &mut self,
+        terminators: &[&str],
+    ) -> Result<(Vec<Node>, Option<Tag>), ParserError> {
+        let mut nodes = Vec::new();
+        while !self.is_at_end() {
+            match self.parse_node(terminators)? {
+                ControlFlow::Continue(node) => nodes.push(node),
+                ControlFlow::Break(terminator) => return Ok((nodes, terminator)),
+            }
+        }
+        Ok((nodes, None))
+    }
+
+    /// Resiliently parse the top-level node starting at token `index`, returning it
+    /// along with the index of the token after it. Nothing is carried over from one
+    /// top-level node to the next, so parsing can start at any of them.
+    pub(crate) fn parse_top_level_node(&mut self, index: usize) -> (Option<Node>, usize) {
+        self.resilient = true;
+        self.current = index;
+        if self.is_at_end() {
+            return (None, self.current);
+        }
+        match self.parse_node(&[]) {
+            Ok(ControlFlow::Continue(node)) => (Some(node), self.current),
+            Ok(ControlFlow::Break(_)) => (None, self.current),
+            Err(error) => {
+                let span = self.span_from(index);
+                self.diagnostics
+                    .push(Diagnostic::from_parser_error(&error, span));
+                (None, self.current)
+            }
+        }
+    }
+
+    pub(crate) fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    /// Parse the next node, or stop at one of `terminators` and return it.
+    fn parse_node(
+        &mut self,
+        terminators: &[&str],
+    ) -> Result<ControlFlow<Option<Tag>, Node>, ParserError> {
+        let start = self.current;
+        let node = self.next_node();
+        let span = self.span_from(start);
+        let node = match node {
+            Ok(node) => node,
+            Err(error) => {
+                let missing = match &error {
+                    ParserError::UnclosedTag { expected, .. } => closing_delimiter(*expected),
+                    _ => None,
+                };
+                self.recover(error, span)?;
+                let mut error_nodes = vec![Node::Text {
+                    text: self.tokens[start..self.current].to_string(),
+                    span,
+                }];
+                error_nodes.extend(missing.map(|missing| Node::Missing {
+                    expected: missing.to_string(),
+                    span: Span::new(span.end, span.end),
+                }));
+                return Ok(ControlFlow::Continue(Node::Error(error_nodes)));
+            }
+        };
+        let node = match node {
+            Node::Tag(tag) if terminators.contains(&tag.name.as_str()) => {
+                return Ok(ControlFlow::Break(Some(tag)));
+            }
+            Node::Tag(tag) if self.resilient && self.closes_outer_block(&tag.name) => {
+                // Leave the tag for the enclosing block, which makes the current block
+                // unclosed.
+                self.current = start;
+                return Ok(ControlFlow::Break(None));
+            }
+            Node::Tag(tag) if self.registry.is_closing_tag(&tag.name) => {
+                let opener = self.open_blocks.last().map(OpenBlock::opened_here);
+                self.recover_with(
+                    ParserError::UnexpectedTag {
+                        name: tag.name.clone(),
+                        line: tag.line,
+                    },
+                    span,
+                    opener,
+                )?;
+                Node::Error(vec![Node::Tag(tag)])
+            }
+            Node::Tag(tag) => {
+                let registry = self.registry;
+                let spec = registry.get(&tag.name);
+                if let Some(Err(error)) = spec.map(|spec| spec.validate(&tag)) {
+                    self.recover(error, span)?;
+                }
+                match spec {
+                    Some(spec) if spec.is_block() => self.block(tag, spec, span)?,
+                    _ => self.builtin(tag_node, Node::Tag, tag, span)?,
+                }
+            }
+            node => node,
+        };
+        Ok(ControlFlow::Continue(node))
+    }
+
+    fn next_node(&mut self) -> Result<Node, ParserError> {
+        let token = self.peek()?;
+        let node = match token.token_type {
+            TokenType::DoubleLeftBrace => self.variable()?,
+            TokenType::LeftBracePercent => self.tag()?,
+            TokenType::LeftBraceHash => self.comment()?,
+            TokenType::DoubleRightBrace
+            | TokenType::PercentRightBrace
+            | TokenType::HashRightBrace
+                if self.resilient =>
+            {
+                self.advance()?;
+                return Err(ParserError::UnexpectedDelimiter {
+                    delimiter: token.lexeme.to_string(),
+                    line: token.line,
+                });
+            }
+            _ => self.text()?,
+        };
+        Ok(node)
+    }
+
+    fn block(&mut self, tag: Tag, spec: &TagSpec, span: Span) -> Result<Node, ParserError> {
+        let end = spec.end.as_deref().unwrap_or_default();
+        let mut terminators: Vec<&str> = spec.intermediates.iter().map(String::as_str).collect();
+        terminators.push(end);
+
+        // The bodies of raw blocks are a single text node that is not HTML to us
+        let elements = match tag.name.as_str() {
+            "comment" | "verbatim" => |nodes| nodes,
+            _ => build_elements,
+        };
+        self.open_blocks.push(OpenBlock {
+            name: tag.name.clone(),
+            span: tag.span,
+            terminators: terminators.iter().map(|name| name.to_string()).collect(),
+        });
+        let body = self.block_body(&terminators, end, elements);
+        let block = self.open_blocks.pop();
+
+        match body? {
+            (nodes, branches, Some(closing)) => {
+                let block = Block {
+                    tag,
+                    nodes,
+                    branches,
+                    end: closing,
+                };
+                self.builtin(block_node, Node::Block, block, span)
+            }
+            (nodes, branches, None) => {
+                let error = ParserError::UnclosedBlock {
+                    name: tag.name.clone(),
+                    line: tag.line,
+                };
+                let mut error_nodes = vec![Node::Tag(tag)];
+                error_nodes.extend(nodes);
+                for branch in branches {
+                    error_nodes.push(Node::Tag(branch.tag));
+                    error_nodes.extend(branch.nodes);
+                }
+                let missing_at = error_nodes.last().map_or(span.end, |node| node.span().end);
+                let missing = Span::new(missing_at, missing_at);
+                // Reported where the end tag is missing, pointing back at the opening tag
+                self.recover_with(error, missing, block.as_ref().map(OpenBlock::opened_here))?;
+                error_nodes.push(Node::Missing {
+                    expected: end.to_string(),
+                    span: missing,
+                });
+                Ok(Node::Error(error_nodes))
+            }
+        }
+    }
+
+    fn block_body(
+        &mut self,
+        terminators: &[&str],
+        end: &str,
+        elements: fn(Vec<Node>) -> Vec<Node>,
+    ) -> Result<BlockBody, ParserError> {
+        let (nodes, mut terminator) = self.parse_until(terminators)?;
+        let nodes = elements(nodes);
+        let mut branches = Vec::new();
+        loop {
+            match terminator {
+                Some(closing) if closing.name == end => {
+                    return Ok((nodes, branches, Some(closing)));
+                }
+                Some(intermediate) => {
+                    let (branch_nodes, next) = self.parse_until(terminators)?;
+                    branches.push(Branch {
+                        tag: intermediate,
+                        nodes: elements(branch_nodes),
+                    });
+                    terminator = next;
+                }
+                None => return Ok((nodes, branches, None)),
+            }
+        }
+    }
+
+    /// Convert a tag or block into its builtin node. When resilient, a builtin with
+    /// invalid arguments is reported and kept as the generic node instead.
+    fn builtin<T: Clone>(
+        &mut self,
+        convert: fn(T) -> Result<Node, ParserError>,
+        generic: fn(T) -> Node,
+        value: T,
+        span: Span,
+    ) -> Result<Node, ParserError> {
+        if !self.resilient {
+            return convert(value);
+        }
+        match convert(value.clone()) {
+            Ok(node) => Ok(node),
+            Err(error) => {
+                self.recover(error, span)?;
+                Ok(generic(value))
+            }
+        }
+    }
+
+    /// Report `error` as a diagnostic when resilient, or return it otherwise.
+    fn recover(&mut self, error: ParserError, span: Span) -> Result<(), ParserError> {
+        self.recover_with(error, span, None)
+    }
+
+    /// Like `recover`, adding `context` to the diagnostic, such as the tag that opened
+    /// the block the error is in.
+    fn recover_with(
+        &mut self,
+        error: ParserError,
+        span: Span,
+        context: Option<Label>,
+    ) -> Result<(), ParserError> {
+        if !self.resilient {
+            return Err(error);
+        }
+        let mut diagnostic = Diagnostic::from_parser_error(&error, span);
+        diagnostic.labels.extend(context);
+        self.diagnostics.push(diagnostic);
+        Ok(())
+    }
+
+    fn closes_outer_block(&self, name: &str) -> bool {
+        self.open_blocks.iter().any(|block| {
+            block
+                .terminators
+                .iter()
+                .any(|terminator| terminator == name)
+        })
+    }
+
+    /// The span of the tokens consumed since `start`.
+    fn span_from(&self, start: usize) -> Span {
+        let first = self.tokens.get(start).map(|token| token.span);
+        let last = self
+            .current
+            .checked_sub(1)
+            .and_then(|index| self.tokens.get(index));
+        match (first, last) {
+            (Some(first), Some(last)) if self.current > start => first.join(last.span),
+            (Some(first), _) => Span::new(first.start, first.start),
+            _ => Span::default(),
+        }
+    }
+
+    fn variable(&mut self) -> Result<Node, ParserError> {
+        let start = self.current;
+        let line = self.peek()?.line;
+        let tokens = self.tokens_until(TokenType::DoubleRightBrace)?;
+        let (bits, trivia) = split_bits(&tokens);
+        if bits.is_empty() {
+            return Err(ParserError::EmptyVariable { line });
+        }
+
+        let mut expression = bits[0].clone();
+        for (bit, whitespace) in bits[1..].iter().zip(&trivia[1..]) {
+            expression.push_str(whitespace);
+            expression.push_str(bit);
+        }
+
+        let inner: Vec<Token> = tokens[1..tokens.len() - 1]
+            .iter()
+            .filter(|token| !token.is_throwaway())
+            .cloned()
+            .collect();
+        let mut parts = inner.split(|token| token.is_token_type(TokenType::Pipe));
+        let var = match parts.next() {
+            Some(var) if !var.is_empty() => var.to_string(),
+            _ => return Err(ParserError::EmptyVariable { line }),
+        };
+        let filters = parts
+            .map(|part| {
+                let mut pieces = part.splitn(2, |token| token.is_token_type(TokenType::Colon));
+                Filter {
+                    name: pieces.next().unwrap_or_default().to_string(),
+                    arg: pieces.next().map(|arg| arg.to_string()),
+                }
+            })
+            .collect();
+
+        Ok(Node::Variable(Variable {
+            var,
+            filters,
+            expression,
+            trivia: vec![trivia[0].clone(), trivia[bits.len()].clone()],
+            span: self.span_from(start),
+        }))
+    }
+
+    fn tag(&mut self) -> Result<Node, ParserError> {
+        let start = self.current;
+        let line = self.peek()?.line;
+        let tokens = self.tokens_until(TokenType::PercentRightBrace)?;
+        let (bits, trivia) = split_bits(&tokens);
+        let mut bits = bits.into_iter();
+        let name = bits.next().ok_or(ParserError::EmptyTag { line })?;
+
+        Ok(Node::Tag(Tag {
+            name,
+            bits: bits.collect(),
+            line,
+            trivia,
+            span: self.span_from(start),
+        }))
+    }
+
+    fn comment(&mut self) -> Result<Node, ParserError> {
+        let start = self.current;
+        let tokens = self.tokens_until(TokenType::HashRightBrace)?;
+        let (opening, closing) = (&tokens[0], &tokens[tokens.len() - 1]);
+        let text = tokens.to_string();
+        let content = &text[opening.size()..text.len() - closing.size()];
+        Ok(Node::Comment {
+            text: content.to_string(),
+            span: self.span_from(start),
+        })
+    }
+
+    fn text(&mut self) -> Result<Node, ParserError> {
+        let start = self.current;
+        let mut tokens = Vec::new();
+        while !self.is_at_end() {
+            let token = self.peek()?;
+            match token.token_type {
+                TokenType::DoubleLeftBrace
+                | TokenType::LeftBracePercent
+                | TokenType::LeftBraceHash => break,
+                TokenType::DoubleRightBrace
+                | TokenType::PercentRightBrace
+                | TokenType::HashRightBrace
+                    if self.resilient =>
+                {
+                    break
+                }
+                _ => tokens.push(self.advance()?),
+            }
+        }
+        Ok(Node::Text {
+            text: tokens.to_string(),
+            span: self.span_from(start),
+        })
+    }
+
+    /// Consume an opening delimiter and every token up to and including `closing`,
+    /// returning all of them, delimiters included.
+    fn tokens_until(&mut self, closing: TokenType) -> Result<Vec<Token<'a>>, ParserError> {
+        let opening = self.advance()?;
+        let line = opening.line;
+        let mut tokens = vec![opening];
+        loop {
+            // The lexer starts a new tag at an opener, even before the previous one is
+            // closed.
+            let reopened = matches!(
+                self.peek()?.token_type,
+                TokenType::DoubleLeftBrace | TokenType::LeftBracePercent | TokenType::LeftBraceHash
+            );
+            if self.is_at_end() || reopened {
+                return Err(ParserError::UnclosedTag {
+                    expected: closing,
+                    line,
+                });
+            }
+            let token = self.advance()?;
+            let is_closing = token.is_token_type(closing);
+            tokens.push(token);
+            if is_closing {
+                return Ok(tokens);
+            }
+        }
+    }
+
+    fn peek_at(&self, offset: isize) -> Result<Token<'a>, ParserError> {
+        let index = self.current as isize + offset;
+        if index < 0 {
+            return Err(ParserError::InvalidTokenAccess);
+        }
+        self.item_at(index as usize)
+    }
+}
+
+impl<'a> Scanner for Parser<'a> {
+    type Item = Token<'a>;
+    type Error = ParserError;
+
+    fn advance(&mut self) -> Result<Self::Item, Self::Error> {
+        if self.is_at_end() {
+            return Err(ParserError::UnexpectedTokenType(TokenType::Eof));
+        }
+        let token = self.peek()?;
+        self.current += 1;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Result<Self::Item, Self::Error> {
+        self.peek_at(0)
+    }
+
+    fn peek_next(&self) -> Result<Self::Item, Self::Error> {
+        self.peek_at(1)
+    }
+
+    fn peek_previous(&self) -> Result<Self::Item, Self::Error> {
+        self.peek_at(-1)
+    }
+
+    fn item_at(&self, index: usize) -> Result<Self::Item, Self::Error> {
+        self.tokens
+            .get(index)
+            .cloned()
+            .ok_or(ParserError::InvalidTokenAccess)
+    }
+
+    fn is_at_end(&self) -> bool {
+        match self.tokens.get(self.current) {
+            Some(token) => token.is_token_type(TokenType::Eof),
+            None => true,
+        }
+    }
+}
+
+pub(crate) fn closing_delimiter(token_type: TokenType) -> Option<&'static str> {
+    match token_type {
+        TokenType::DoubleRightBrace => Some("}}"),
+        TokenType::PercentRightBrace => Some("%}"),
+        TokenType::HashRightBrace => Some("#}"),
+        _ => None,
+    }
+}
+
+/// Split the tokens of a tag, delimiters included, into whitespace separated bits.
+/// Also returns the whitespace found before each bit and before the closing delimiter.
+/// Whitespace that was dropped from the token stream shows up as a gap between spans
+/// and is normalized to one space.
+fn split_bits(tokens: &[Token]) -> (Vec<String>, Vec<String>) {
+    let mut bits = Vec::new();
+    let mut trivia = vec![String::new()];
+    let mut bit: Vec<Token> = Vec::new();
+
+    let Some((opening, rest)) = tokens.split_first() else {
+        return (bits, trivia);
+    };
+    let mut previous_end = opening.span.end;
+    for (index, token) in rest.iter().enumerate() {
+        let gap = token.span.start != previous_end;
+        let is_closing = index == rest.len() - 1;
+        previous_end = token.span.end;
+
+        if (gap || token.is_throwaway() || is_closing) && !bit.is_empty() {
+            bits.push(bit.to_string());
+            bit.clear();
+            trivia.push(String::new());
+        }
+        let whitespace = trivia.last_mut().expect("trivia is never empty");
+        if gap {
+            whitespace.push(' ');
+        }
+        if token.is_throwaway() {
+            whitespace.push_str(&token.lexeme);
+        } else if !is_closing {
+            bit.push(token.clone());
+        }
+    }
+    (bits, trivia)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::FilterExpression;
+    use crate::builtins::{Builtin, If, IfBranch};
+    use crate::condition::Condition;
+    use crate::html::Element;
+    use crate::lexer::Lexer;
+    use crate::registry::ArgSpec;
+
+    fn parse(source: &str) -> Result<Ast, ParserError> {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.tokenize()?;
+        let mut parser = Parser::new(tokens);
+        parser.parse()
+    }
+
+    fn parse_lossless(source: &str) -> Result<Ast, ParserError> {
+        let mut lexer = Lexer::lossless(source);
+        let tokens = lexer.tokenize()?;
+        let mut parser = Parser::new(tokens);
+        parser.parse()
+    }
+
+    /// A tag on the first line, written with single spaces, starting at `start`.
+    fn tag(name: &str, bits: &[&str], start: usize) -> Tag {
+        let mut tag = Tag {
+            name: name.to_string(),
+            bits: bits.iter().map(|bit| bit.to_string()).collect(),
+            line: 1,
+            trivia: vec![" ".to_string(); bits.len() + 2],
+            span: Span::default(),
+        };
+        tag.span = Span::new(start, start + tag.to_string().len());
+        tag
+    }
+
+    fn variable(expression: &str, var: &str, filters: Vec<Filter>, start: usize) -> Variable {
+        let mut variable = Variable {
+            var: var.to_string(),
+            filters,
+            expression: expression.to_string(),
+            trivia: vec![" ".to_string(); 2],
+            span: Span::default(),
+        };
+        variable.span = Span::new(start, start + variable.to_string().len());
+        variable
+    }
+
+    fn text(text: &str, start: usize) -> Node {
+        Node::Text {
+            text: text.to_string(),
+            span: Span::new(start, start + text.len()),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let test_cases = vec![
+            ("", vec![]),
+            ("Hello", vec![text("Hello", 0)]),
+            (
+                "{{ name }}",
+                vec![Node::Variable(variable("name", "name", vec![], 0))],
+            ),
+            (
+                "{{ value|default:'default'|lower }}",
+                vec![Node::Variable(variable(
+                    "value|default:'default'|lower",
+                    "value",
+                    vec![
+                        Filter {
+                            name: "default".to_string(),
+                            arg: Some("'default'".to_string()),
+                        },
+                        Filter {
+                            name: "lower".to_string(),
+                            arg: None,
+                        },
+                    ],
+                    0,
+                ))],
+            ),
+            (
+                "{{ a.b | default : 'x y' }}",
+                vec![Node::Variable(variable(
+                    "a.b | default : 'x y'",
+                    "a.b",
+                    vec![Filter {
+                        name: "default".to_string(),
+                        arg: Some("'x y'".to_string()),
+                    }],
+                    0,
+                ))],
+            ),
+            (
+                r#"{% static "css/app.css" %}"#,
+                vec![Node::Tag(tag("static", &[r#""css/app.css""#], 0))],
+            ),
+            (
+                r#"{% render "a.html" with x="y z" only %}"#,
+                vec![Node::Tag(tag(
+                    "render",
+                    &[r#""a.html""#, "with", r#"x="y z""#, "only"],
+                    0,
+                ))],
+            ),
+            (
+                "{# A comment #}",
+                vec![Node::Comment {
+                    text: " A comment ".to_string(),
+                    span: Span::new(0, 15),
+                }],
+            ),
+            (
+                "<p>{{ name }}</p>",
+                vec![Node::Element(Element {
+                    name: "p".to_string(),
+                    attributes: vec![],
+                    trivia: String::new(),
+                    self_closing: false,
+                    children: vec![Node::Variable(variable("name", "name", vec![], 3))],
+                    end: Some("</p>".to_string()),
+                    span: Span::new(0, 17),
+                })],
+            ),
+        ];
+
+        for (input, expected_nodes) in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let ast = parse(input).unwrap();
+            assert_eq!(ast.nodes, expected_nodes, "For input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_block() {
+        let ast = parse(
+            "{% if user.is_authenticated %} Hello {% elif guest %} Hi {% else %} Bye {% endif %}",
+        )
+        .unwrap();
+
+        assert_eq!(
+            ast.nodes,
+            vec![Node::Builtin(Builtin::If(If {
+                branches: vec![
+                    IfBranch {
+                        condition: Some(Condition::Operand(FilterExpression {
+                            var: "user.is_authenticated".to_string(),
+                            filters: vec![],
+                        })),
+                        nodes: vec![text("Hello", 31)],
+                        tag: tag("if", &["user.is_authenticated"], 0),
+                    },
+                    IfBranch {
+                        condition: Some(Condition::Operand(FilterExpression {
+                            var: "guest".to_string(),
+                            filters: vec![],
+                        })),
+                        nodes: vec![text("Hi", 54)],
+                        tag: tag("elif", &["guest"], 37),
+                    },
+                    IfBranch {
+                        condition: None,
+                        nodes: vec![text("Bye", 68)],
+                        tag: tag("else", &[], 57),
+                    },
+                ],
+                end: tag("endif", &[], 72),
+            }))]
+        );
+    }
+
+    #[test]
+    fn test_parse_nested_blocks() {
+        let ast = parse(
+            "{% for item in items %}{% if item %}{{ item }}{% endif %}{% empty %}None {% endfor %}",
+        )
+        .unwrap();
+
+        let Node::Builtin(Builtin::For(block)) = &ast.nodes[0] else {
+            panic!("Expected a for loop, got {:?}", ast.nodes[0]);
+        };
+        assert_eq!(block.tag, tag("for", &["item", "in", "items"], 0));
+        assert!(matches!(&block.body[0], Node::Builtin(Builtin::If(_))));
+        assert_eq!(block.empty.as_ref().unwrap().tag.name, "empty");
+        assert_eq!(block.end.name, "endfor");
+    }
+
+    #[test]
+    fn test_parse_custom_blocks() {
+        let mut registry = TagRegistry::builtins();
+        registry.register(
+            TagSpec::block("switch")
+                .with_intermediates(&["case"])
+                .with_args(vec![ArgSpec::Value("value".to_string())]),
+        );
+        let parse = |source: &str| {
+            let tokens = Lexer::new(source).tokenize()?;
+            Parser::with_registry(tokens, &registry).parse()
+        };
+
+        let ast = parse("{% switch a %}{% case 1 %}One{% endswitch %}").unwrap();
+        assert_eq!(
+            ast.nodes,
+            vec![Node::Block(Block {
+                tag: tag("switch", &["a"], 0),
+                nodes: vec![],
+                branches: vec![Branch {
+                    tag: tag("case", &["1"], 14),
+                    nodes: vec![text("One", 26)],
+                }],
+                end: tag("endswitch", &[], 29),
+            })]
+        );
+
+        assert!(matches!(
+            parse("{% switch %}{% endswitch %}"),
+            Err(ParserError::InvalidArguments { .. })
+        ));
+        assert!(matches!(
+            parse("{% switch a %}"),
+            Err(ParserError::UnclosedBlock { .. })
+        ));
+        assert!(matches!(
+            parse("{% case 1 %}"),
+            Err(ParserError::UnexpectedTag { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_end_prefixed_tags() {
+        let mut registry = TagRegistry::builtins();
+        registry.register(TagSpec::new("endpoint"));
+        let tokens = Lexer::new("{% endpoint 'users' %}{% endless %}")
+            .tokenize()
+            .unwrap();
+        let ast = Parser::with_registry(tokens, &registry).parse().unwrap();
+
+        assert_eq!(
+            ast.nodes,
+            vec![
+                Node::Tag(tag("endpoint", &["'users'"], 0)),
+                Node::Tag(tag("endless", &[], 22)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_raw_blocks() {
+        let ast = parse(
+            "{% verbatim %}{% if %}{{ vue }}{% endverbatim %}{% comment %} {{ x }} {% endcomment %}",
+        )
+        .unwrap();
+
+        assert_eq!(
+            ast.nodes,
+            vec![
+                Node::Block(Block {
+                    tag: tag("verbatim", &[], 0),
+                    nodes: vec![text("{% if %}{{ vue }}", 14)],
+                    branches: vec![],
+                    end: tag("endverbatim", &[], 31),
+                }),
+                Node::Block(Block {
+                    tag: tag("comment", &[], 48),
+                    nodes: vec![text(" {{ x }} ", 61)],
+                    branches: vec![],
+                    end: tag("endcomment", &[], 70),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let test_cases = vec![
+            "{{ name",
+            "{% if a %}",
+            "{% endif %}",
+            "{% else %}",
+            "{% for a in b %}{% endif %}",
+            "{% %}",
+            "{{ }}",
+            "{# comment",
+            "{% verbatim %}{{ a }}",
+            "{% verbatim a %}{% endverbatim %}",
+        ];
+
+        for input in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let result = parse(input);
+            assert!(result.is_err(), "Expected error for input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_resilient() {
+        let test_cases = vec![
+            ("Hello {{ name }}", vec![]),
+            ("{{ name", vec!["error[E101]: unclosed tag"]),
+            (
+                "{{ na\n{% if a %}b{% endif %}",
+                vec!["error[E101]: unclosed tag"],
+            ),
+            ("{% if a %}b", vec!["error[E102]: unclosed block tag 'if'"]),
+            (
+                "a %} b }}",
+                vec![
+                    "warning[E104]: unexpected '%}'",
+                    "warning[E104]: unexpected '}}'",
+                ],
+            ),
+            (
+                "{% endfor %}{% else %}",
+                vec![
+                    "error[E103]: unexpected tag 'endfor'",
+                    "error[E103]: unexpected tag 'else'",
+                ],
+            ),
+            (
+                "{{ }}{% %}",
+                vec!["error[E106]: empty variable", "error[E105]: empty tag"],
+            ),
+            (
+                "{% if a %}{% for x in y %}{% endif %}",
+                vec!["error[E102]: unclosed block tag 'for'"],
+            ),
+            (
+                "{% for x %}{% endfor %}",
+                vec!["error[E107]: invalid arguments to 'for'"],
+            ),
+            ("a\0b", vec![]),
+            ("\0", vec![]),
+        ];
+
+        for (input, expected_messages) in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let tokens = Lexer::lossless(input).tokenize().unwrap();
+            let (ast, diagnostics) = Parser::new(tokens).parse_resilient();
+            let messages: Vec<String> = diagnostics.iter().map(Diagnostic::to_string).collect();
+            assert_eq!(messages, expected_messages, "For input: {}", input);
+            assert_eq!(ast.to_string(), input);
+        }
+    }
+
+    #[test]
+    fn test_parse_resilient_nodes() {
+        let source = "{% if a %}{% for x in y %}{{ x }}{% endif %}{{ b";
+        let tokens = Lexer::lossless(source).tokenize().unwrap();
+        let (ast, diagnostics) = Parser::new(tokens).parse_resilient();
+
+        let Node::Builtin(Builtin::If(block)) = &ast.nodes[0] else {
+            panic!("Expected an if block, got {:?}", ast.nodes[0]);
+        };
+        let Node::Error(nodes) = &block.branches[0].nodes[0] else {
+            panic!("Expected an error, got {:?}", block.branches[0].nodes[0]);
+        };
+        assert_eq!(nodes[0], Node::Tag(tag("for", &["x", "in", "y"], 10)));
+        assert_eq!(
+            nodes.last(),
+            Some(&Node::Missing {
+                expected: "endfor".to_string(),
+                span: Span::new(33, 33),
+            })
+        );
+        assert_eq!(
+            ast.nodes[1],
+            Node::Error(vec![
+                text("{{ b", 44),
+                Node::Missing {
+                    expected: "}}".to_string(),
+                    span: Span::new(48, 48),
+                },
+            ])
+        );
+        assert_eq!(diagnostics[0].span(), Span::new(33, 33));
+        assert_eq!(diagnostics[1].span(), Span::new(44, 48));
+    }
+
+    #[test]
+    fn test_parse_resilient_labels() {
+        let opened_here = |start, end, name| {
+            Label::secondary(Span::new(start, end), format!("'{}' opened here", name))
+        };
+        let test_cases = vec![
+            (
+                "{% endif %}",
+                vec![vec![Label::primary(
+                    Span::new(0, 11),
+                    "no open block accepts this tag",
+                )]],
+            ),
+            (
+                "{% for x in y %}{% endif %}",
+                vec![
+                    vec![
+                        Label::primary(Span::new(16, 27), "no open block accepts this tag"),
+                        opened_here(0, 16, "for"),
+                    ],
+                    vec![
+                        Label::primary(Span::new(27, 27), "expected the end tag here"),
+                        opened_here(0, 16, "for"),
+                    ],
+                ],
+            ),
+            (
+                "{% if a %}{% for x in y %}{% endif %}",
+                vec![vec![
+                    Label::primary(Span::new(26, 26), "expected the end tag here"),
+                    opened_here(10, 26, "for"),
+                ]],
+            ),
+        ];
+
+        for (input, expected_labels) in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let tokens = Lexer::lossless(input).tokenize().unwrap();
+            let (_, diagnostics) = Parser::new(tokens).parse_resilient();
+            let labels: Vec<Vec<Label>> = diagnostics
+                .into_iter()
+                .map(|diagnostic| diagnostic.labels)
+                .collect();
+            assert_eq!(labels, expected_labels, "For input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let test_cases = vec![
+            "{{ name }}",
+            "{{ value|default:'x' }}",
+            "{% if a %}{{ b }}{% else %}{{ c }}{% endif %}",
+            "{# comment #}",
+        ];
+
+        for input in test_cases {
+            let ast = parse(input).unwrap();
+            assert_eq!(ast.to_string(), input);
+        }
+    }
+
+    #[test]
+    fn test_lossless_round_trip() {
+        let test_cases = vec![
+            "",
+            "  \n",
+            "Hello,   {{  name  }}!\n",
+            "{%  if   a  %}\n\t{{ b|default:'x  y' }}\r\n{% else %} c {% endif %}  ",
+            "{#   spaced    comment #}",
+            "<p class=\"x\">\n  {% url \"api:index\"   as  url %}\n</p>\n",
+            "{% verbatim  x %}\n  {{ raw }}\n{%   endverbatim x %}",
+            include_str!("../tests/fixtures/unicode/accents.html"),
+            include_str!("../tests/fixtures/unicode/cjk.html"),
+            include_str!("../tests/fixtures/unicode/emoji.html"),
+            include_str!("../tests/fixtures/unicode/mixed.html"),
+            include_str!("../benches/templates/base.html"),
+        ];
+
+        for input in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let ast = parse_lossless(input).unwrap();
+            assert_eq!(ast.to_string(), input);
+            for node in &ast.nodes {
+                let span = node.span();
+                assert_eq!(&input[span.start..span.end], node.to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn test_lossless_trivia() {
+        let ast = parse_lossless("{%  if\ta  %}{%endif %}").unwrap();
+
+        let Node::Builtin(Builtin::If(block)) = &ast.nodes[0] else {
+            panic!("Expected an if block, got {:?}", ast.nodes[0]);
+        };
+        assert_eq!(block.branches[0].tag.trivia, vec!["  ", "\t", "  "]);
+        assert_eq!(block.end.trivia, vec!["", " "]);
+    }
+}
@@ -0,0 +1,446 @@
+use crate::ast::{Node, Tag};
+use crate::diagnostic::{Diagnostic, Renderer, Severity};
+use crate::html::{Attribute, Language};
+use crate::lexer::Lexer;
+use crate::span::SourceMap;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyList;
+use std::sync::Arc;
+
+create_exception!(
+    django_template_ast,
+    TemplateSyntaxError,
+    PyException,
+    "Raised by `parse` for a template with errors. `diagnostics` lists all of them."
+);
+
+#[pyclass(name = "Token", module = "django_template_ast", frozen, get_all)]
+struct PyToken {
+    /// The `TokenType` name, e.g. `"Identifier"`.
+    kind: String,
+    lexeme: String,
+    /// Code point indices, so `source[start:end]` is the lexeme.
+    start: usize,
+    end: usize,
+    line: usize,
+}
+
+#[pymethods]
+impl PyToken {
+    fn __repr__(&self) -> String {
+        format!("Token({}, {:?})", self.kind, self.lexeme)
+    }
+}
+
+/// A node of the tree. Builtin and custom tags share the `"tag"` and `"block"` kinds;
+/// the sections after the first of a block, e.g. `{% else %}`, are its `branches`.
+#[pyclass(name = "Node", module = "django_template_ast", frozen)]
+struct PyNode {
+    #[pyo3(get)]
+    kind: &'static str,
+    /// The tag, element or variable name, or the language of embedded code.
+    #[pyo3(get)]
+    name: Option<String>,
+    #[pyo3(get)]
+    bits: Vec<String>,
+    #[pyo3(get)]
+    filters: Vec<(String, Option<String>)>,
+    #[pyo3(get)]
+    attributes: Vec<(String, Option<String>)>,
+    children: Vec<Py<PyNode>>,
+    branches: Vec<Py<PyNode>>,
+    source: String,
+}
+
+#[pymethods]
+impl PyNode {
+    #[getter]
+    fn children<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        PyList::new(py, self.children.iter().map(|child| child.clone_ref(py)))
+    }
+
+    #[getter]
+    fn branches<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        PyList::new(py, self.branches.iter().map(|branch| branch.clone_ref(py)))
+    }
+
+    /// The source the node was parsed from.
+    fn __str__(&self) -> &str {
+        &self.source
+    }
+
+    fn __repr__(&self) -> String {
+        match &self.name {
+            Some(name) => format!("Node({}, {:?})", self.kind, name),
+            None => format!("Node({})", self.kind),
+        }
+    }
+}
+
+#[pyclass(name = "Diagnostic", module = "django_template_ast", frozen)]
+struct PyDiagnostic {
+    diagnostic: Diagnostic,
+    /// The template, shared by every diagnostic found in it, for `render`.
+    source: Arc<str>,
+    /// Code point indices of the primary span, like `PyToken`'s.
+    #[pyo3(get)]
+    start: usize,
+    #[pyo3(get)]
+    end: usize,
+    #[pyo3(get)]
+    line: usize,
+    #[pyo3(get)]
+    column: usize,
+}
+
+impl PyDiagnostic {
+    /// Wrap each of `diagnostics`, found in `source`.
+    fn all(source: &str, diagnostics: Vec<Diagnostic>) -> Vec<PyDiagnostic> {
+        let source_map = SourceMap::new(source);
+        let code_points = CodePoints::new(source);
+        let source: Arc<str> = Arc::from(source);
+        diagnostics
+            .into_iter()
+            .map(|diagnostic| {
+                let span = diagnostic.span();
+                let position = source_map.position(span.start);
+                PyDiagnostic {
+                    diagnostic,
+                    source: Arc::clone(&source),
+                    start: code_points.index(span.start),
+                    end: code_points.index(span.end),
+                    line: position.line,
+                    column: position.column,
+                }
+            })
+            .collect()
+    }
+}
+
+#[pymethods]
+impl PyDiagnostic {
+    #[getter]
+    fn code(&self) -> &str {
+        &self.diagnostic.code
+    }
+
+    /// `"error"` or `"warning"`.
+    #[getter]
+    fn severity(&self) -> String {
+        self.diagnostic.severity.to_string()
+    }
+
+    #[getter]
+    fn message(&self) -> &str {
+        &self.diagnostic.message
+    }
+
+    #[getter]
+    fn notes(&self) -> Vec<String> {
+        self.diagnostic.notes.clone()
+    }
+
+    #[getter]
+    fn help(&self) -> Option<String> {
+        self.diagnostic.help.clone()
+    }
+
+    /// The diagnostic as an annotated snippet of the template.
+    #[pyo3(signature = (path = "<template>", color = false))]
+    fn render(&self, path: &str, color: bool) -> String {
+        let renderer = if color {
+            Renderer::styled()
+        } else {
+            Renderer::plain()
+        };
+        renderer.render(&self.diagnostic, &self.source, path)
+    }
+
+    fn __str__(&self) -> String {
+        self.diagnostic.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Diagnostic({}, {:?})",
+            self.diagnostic.code, self.diagnostic.message
+        )
+    }
+}
+
+/// Converts the byte offsets of spans into the code point indices Python strings are
+/// sliced by.
+struct CodePoints {
+    /// The byte offset of each character of the source.
+    offsets: Vec<usize>,
+}
+
+impl CodePoints {
+    fn new(source: &str) -> Self {
+        CodePoints {
+            offsets: source.char_indices().map(|(offset, _)| offset).collect(),
+        }
+    }
+
+    /// The index of the character starting at byte `offset`, or the number of
+    /// characters for the end of the source.
+    fn index(&self, offset: usize) -> usize {
+        self.offsets.partition_point(|&start| start < offset)
+    }
+}
+
+/// Split a template into tokens. With `lossless`, whitespace tokens are kept too.
+#[pyfunction]
+#[pyo3(signature = (source, lossless = false))]
+fn tokenize(py: Python<'_>, source: &str, lossless: bool) -> PyResult<Vec<PyToken>> {
+    let mut lexer = if lossless {
+        Lexer::lossless(source)
+    } else {
+        Lexer::new(source)
+    };
+    let (tokens, diagnostics) = lexer.tokenize_resilient();
+    raise_errors(py, source, diagnostics)?;
+    let code_points = CodePoints::new(source);
+    Ok(tokens
+        .iter()
+        .map(|token| PyToken {
+            kind: format!("{:?}", token.token_type),
+            lexeme: token.lexeme.to_string(),
+            start: code_points.index(token.span.start),
+            end: code_points.index(token.span.end),
+            line: token.line,
+        })
+        .collect())
+}
+
+/// Parse a template into nodes, raising `TemplateSyntaxError` if it has errors.
+#[pyfunction]
+fn parse(py: Python<'_>, source: &str) -> PyResult<Vec<Py<PyNode>>> {
+    let (ast, diagnostics) = crate::parse_resilient(source);
+    raise_errors(py, source, diagnostics)?;
+    nodes(py, &ast.nodes)
+}
+
+/// Every problem found in a template, warnings included.
+#[pyfunction]
+fn check(source: &str) -> Vec<PyDiagnostic> {
+    let (_, diagnostics) = crate::parse_resilient(source);
+    PyDiagnostic::all(source, diagnostics)
+}
+
+fn raise_errors(py: Python<'_>, source: &str, diagnostics: Vec<Diagnostic>) -> PyResult<()> {
+    let errors: Vec<Diagnostic> = diagnostics
+        .into_iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .collect();
+    let Some(first) = errors.first() else {
+        return Ok(());
+    };
+    let error = TemplateSyntaxError::new_err(first.to_string());
+    let diagnostics = PyDiagnostic::all(source, errors);
+    error.value(py).setattr("diagnostics", diagnostics)?;
+    Err(error)
+}
+
+fn nodes(py: Python<'_>, nodes: &[Node]) -> PyResult<Vec<Py<PyNode>>> {
+    nodes.iter().map(|node| self::node(py, node)).collect()
+}
+
+fn node(py: Python<'_>, node: &Node) -> PyResult<Py<PyNode>> {
+    let mut result = PyNode {
+        kind: "",
+        name: None,
+        bits: Vec::new(),
+        filters: Vec::new(),
+        attributes: Vec::new(),
+        children: Vec::new(),
+        branches: Vec::new(),
+        source: node.to_string(),
+    };
+    match node {
+        Node::Text { .. } => result.kind = "text",
+        Node::Variable(variable) => {
+            result.kind = "variable";
+            result.name = Some(variable.var.clone());
+            result.filters = variable
+                .filters
+                .iter()
+                .map(|filter| (filter.name.clone(), filter.arg.clone()))
+                .collect();
+        }
+        Node::Tag(tag) => tag_node(&mut result, "tag", tag),
+        Node::Comment { .. } => result.kind = "comment",
+        Node::Block(block) => {
+            tag_node(&mut result, "block", &block.tag);
+            result.children = nodes(py, &block.nodes)?;
+            for branch in &block.branches {
+                result
+                    .branches
+                    .push(branch_node(py, &branch.tag, &branch.nodes)?);
+            }
+        }
+        Node::Builtin(builtin) => {
+            let kind = if builtin.end().is_some() {
+                "block"
+            } else {
+                "tag"
+            };
+            let sections = builtin.sections();
+            let (tag, body) = sections[0];
+            tag_node(&mut result, kind, tag);
+            result.children = nodes(py, body)?;
+            for (tag, nodes) in &sections[1..] {
+                result.branches.push(branch_node(py, tag, nodes)?);
+            }
+        }
+        Node::Element(element) => {
+            result.kind = "element";
+            result.name = Some(element.name.clone());
+            result.attributes = element
+                .attributes
+                .iter()
+                .filter_map(|attribute| match attribute {
+                    Attribute::Html(attribute) => Some((
+                        attribute.name.clone(),
+                        attribute.value.as_ref().map(|value| {
+                            value.parts.iter().map(Node::to_string).collect::<String>()
+                        }),
+                    )),
+                    Attribute::Template { .. } => None,
+                })
+                .collect();
+            result.children = nodes(py, &element.children)?;
+        }
+        Node::Embedded(embedded) => {
+            result.kind = "embedded";
+            result.name = Some(match &embedded.language {
+                Language::JavaScript => "javascript".to_string(),
+                Language::Json => "json".to_string(),
+                Language::Css => "css".to_string(),
+                Language::Other(language) => language.clone(),
+            });
+            result.children = nodes(py, &embedded.nodes)?;
+        }
+        Node::HtmlComment {
+            nodes: children, ..
+        } => {
+            result.kind = "html_comment";
+            result.children = nodes(py, children)?;
+        }
+        Node::Error(children) => {
+            result.kind = "error";
+            result.children = nodes(py, children)?;
+        }
+        Node::Missing { expected, .. } => {
+            result.kind = "missing";
+            result.name = Some(expected.clone());
+        }
+    }
+    Py::new(py, result)
+}
+
+fn tag_node(result: &mut PyNode, kind: &'static str, tag: &Tag) {
+    result.kind = kind;
+    result.name = Some(tag.name.clone());
+    result.bits = tag.bits.clone();
+}
+
+/// The intermediate section of a block started by `tag`, e.g. `{% else %}`.
+fn branch_node(py: Python<'_>, tag: &Tag, children: &[Node]) -> PyResult<Py<PyNode>> {
+    let mut source = tag.to_string();
+    source.extend(children.iter().map(Node::to_string));
+    let mut result = PyNode {
+        kind: "",
+        name: None,
+        bits: Vec::new(),
+        filters: Vec::new(),
+        attributes: Vec::new(),
+        children: nodes(py, children)?,
+        branches: Vec::new(),
+        source,
+    };
+    tag_node(&mut result, "branch", tag);
+    Py::new(py, result)
+}
+
+#[pymodule]
+fn django_template_ast(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(tokenize, module)?)?;
+    module.add_function(wrap_pyfunction!(parse, module)?)?;
+    module.add_function(wrap_pyfunction!(check, module)?)?;
+    module.add_class::<PyToken>()?;
+    module.add_class::<PyNode>()?;
+    module.add_class::<PyDiagnostic>()?;
+    module.add(
+        "TemplateSyntaxError",
+        module.py().get_type::<TemplateSyntaxError>(),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+    use std::ffi::CString;
+
+    /// Run Python `code` with the module imported as `dta`.
+    fn run_python(code: &str) {
+        pyo3::append_to_inittab!(django_template_ast);
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            let module = py.import("django_template_ast").unwrap();
+            globals.set_item("dta", module).unwrap();
+            let code = CString::new(code).unwrap();
+            if let Err(error) = py.run(&code, Some(&globals), None) {
+                error.display(py);
+                panic!("Python code failed: {}", error);
+            }
+        });
+    }
+
+    #[test]
+    fn test_python_module() {
+        run_python(
+            r#"
+tokens = dta.tokenize("{{ a }}")
+assert [token.kind for token in tokens] == ["DoubleLeftBrace", "Identifier", "DoubleRightBrace", "Eof"], tokens
+assert tokens[1].lexeme == "a" and (tokens[1].start, tokens[1].end) == (3, 4)
+assert len(dta.tokenize("{{ a }}", lossless=True)) == 6
+
+source = '{% if a %}<p class="x">{{ b|default:"c" }}</p>{% else %}d{% endif %}'
+[node] = dta.parse(source)
+assert (node.kind, node.name, node.bits) == ("block", "if", ["a"])
+assert str(node) == source
+[element] = node.children
+assert (element.kind, element.name, element.attributes) == ("element", "p", [("class", "x")])
+assert element.children[0].filters == [("default", '"c"')]
+[branch] = node.branches
+assert (branch.kind, branch.name, str(branch)) == ("branch", "else", "{% else %}d")
+
+try:
+    dta.parse("{% if a %}\n{{ b")
+except dta.TemplateSyntaxError as error:
+    assert str(error) == "error[E101]: unclosed tag", str(error)
+    assert [d.code for d in error.diagnostics] == ["E101", "E102"]
+    assert (error.diagnostics[0].line, error.diagnostics[0].column) == (2, 1)
+else:
+    raise AssertionError("expected TemplateSyntaxError")
+
+[warning] = dta.check("a %} b")
+assert (warning.code, warning.severity, warning.start, warning.end) == ("E104", "warning", 2, 4)
+assert warning.render("page.html").startswith("warning[E104]: unexpected '%}'\n --> page.html:1:3\n")
+assert warning.help == "use '{% templatetag closeblock %}' to output it on purpose"
+
+source = "café {{ 名前 }}\n名前 %}"
+tokens = dta.tokenize(source)
+assert [source[token.start:token.end] for token in tokens] == [token.lexeme for token in tokens]
+[warning] = dta.check(source)
+assert (source[warning.start:warning.end], warning.line, warning.column) == ("%}", 2, 4)
+"#,
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use crate::ast::Tag;
+use crate::builtins::{filter_expression, invalid, kwarg, unquote};
+use crate::error::ParserError;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Django's builtin block tags and the intermediate tags each one accepts. Every
+/// block is closed by a tag named `end` followed by the opening tag's name.
+const BLOCK_TAGS: &[(&str, &[&str])] = &[
+    ("autoescape", &[]),
+    ("block", &[]),
+    ("comment", &[]),
+    ("filter", &[]),
+    ("for", &["empty"]),
+    ("if", &["elif", "else"]),
+    ("ifchanged", &["else"]),
+    ("partialdef", &[]),
+    ("spaceless", &[]),
+    ("verbatim", &[]),
+    ("with", &[]),
+];
+
+/// Describes a template tag so the parser knows whether it opens a block, which
+/// tags continue and close that block, and optionally which arguments it takes.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct TagSpec {
+    pub name: String,
+    /// The tag closing the block, or `None` if this is not a block tag.
+    pub end: Option<String>,
+    pub intermediates: Vec<String>,
+    /// The argument grammar. Arguments are not checked when this is `None`.
+    pub args: Option<Vec<ArgSpec>>,
+}
+
+impl TagSpec {
+    /// A tag that does not open a block, e.g. `{% csrf_token %}`.
+    pub fn new(name: &str) -> Self {
+        TagSpec {
+            name: name.to_string(),
+            end: None,
+            intermediates: Vec::new(),
+            args: None,
+        }
+    }
+
+    /// A block tag closed by `end` followed by its name, e.g. `{% cache %}` and
+    /// `{% endcache %}`.
+    pub fn block(name: &str) -> Self {
+        TagSpec {
+            end: Some(format!("end{}", name)),
+            ..TagSpec::new(name)
+        }
+    }
+
+    /// Close the block with a differently named tag.
+    pub fn with_end(mut self, end: &str) -> Self {
+        self.end = Some(end.to_string());
+        self
+    }
+
+    pub fn with_intermediates(mut self, intermediates: &[&str]) -> Self {
+        self.intermediates = intermediates.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    pub fn with_args(mut self, args: Vec<ArgSpec>) -> Self {
+        self.args = Some(args);
+        self
+    }
+
+    pub fn is_block(&self) -> bool {
+        self.end.is_some()
+    }
+
+    /// Check a tag's bits against the argument grammar, if there is one.
+    pub fn validate(&self, tag: &Tag) -> Result<(), ParserError> {
+        let Some(args) = &self.args else {
+            return Ok(());
+        };
+        if matches_args(tag, args, &tag.bits) {
+            return Ok(());
+        }
+        let mut usage = self.name.clone();
+        for arg in args {
+            usage.push_str(&format!(" {}", arg));
+        }
+        Err(invalid(tag, &format!("expected '{}'", usage)))
+    }
+}
+
+/// One piece of a tag's argument grammar. Each matches a single bit, except for
+/// `Optional` and `VarArgs`.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ArgSpec {
+    /// A fixed word, e.g. the `as` in `{% tag value as name %}`.
+    Keyword(String),
+    /// A variable or literal, optionally filtered.
+    Value(String),
+    /// A quoted string literal.
+    String(String),
+    /// A `name=value` pair.
+    Kwarg(String),
+    /// A group of arguments that may be left out entirely.
+    Optional(Vec<ArgSpec>),
+    /// Any number of values.
+    VarArgs(String),
+}
+
+impl ArgSpec {
+    fn accepts(&self, tag: &Tag, bit: &str) -> bool {
+        match self {
+            ArgSpec::Keyword(keyword) => bit == keyword,
+            ArgSpec::Value(_) | ArgSpec::VarArgs(_) => filter_expression(tag, bit).is_ok(),
+            ArgSpec::String(_) => unquote(bit).is_some(),
+            ArgSpec::Kwarg(_) => kwarg(bit).is_some(),
+            ArgSpec::Optional(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for ArgSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgSpec::Keyword(keyword) => write!(f, "{}", keyword),
+            ArgSpec::Value(name) => write!(f, "<{}>", name),
+            ArgSpec::String(name) => write!(f, "\"{}\"", name),
+            ArgSpec::Kwarg(name) => write!(f, "{}=<value>", name),
+            ArgSpec::Optional(group) => {
+                let group: Vec<String> = group.iter().map(ArgSpec::to_string).collect();
+                write!(f, "[{}]", group.join(" "))
+            }
+            ArgSpec::VarArgs(name) => write!(f, "<{}>...", name),
+        }
+    }
+}
+
+fn matches_args(tag: &Tag, args: &[ArgSpec], bits: &[String]) -> bool {
+    match args.split_first() {
+        None => bits.is_empty(),
+        Some((ArgSpec::Optional(group), rest)) => {
+            let with_group = [group.as_slice(), rest].concat();
+            matches_args(tag, &with_group, bits) || matches_args(tag, rest, bits)
+        }
+        Some((ArgSpec::VarArgs(_), rest)) => (0..=bits.len()).rev().any(|count| {
+            bits[..count].iter().all(|bit| args[0].accepts(tag, bit))
+                && matches_args(tag, rest, &bits[count..])
+        }),
+        Some((arg, rest)) => match bits.split_first() {
+            Some((bit, bits)) => arg.accepts(tag, bit) && matches_args(tag, rest, bits),
+            None => false,
+        },
+    }
+}
+
+/// The tags the parser knows about. Tags that are not registered are parsed as
+/// standalone tags.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TagRegistry {
+    specs: HashMap<String, TagSpec>,
+}
+
+impl TagRegistry {
+    /// A registry without any tags, not even Django's builtins.
+    pub fn empty() -> Self {
+        TagRegistry {
+            specs: HashMap::new(),
+        }
+    }
+
+    /// Django's builtin block tags.
+    pub fn builtins() -> Self {
+        let mut registry = TagRegistry::empty();
+        for (name, intermediates) in BLOCK_TAGS {
+            registry.register(TagSpec::block(name).with_intermediates(intermediates));
+        }
+        registry
+    }
+
+    /// Add a tag, replacing any existing tag with the same name.
+    pub fn register(&mut self, spec: TagSpec) {
+        self.specs.insert(spec.name.clone(), spec);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TagSpec> {
+        self.specs.get(name)
+    }
+
+    /// Whether `name` can only appear inside a block: the end or an intermediate tag of
+    /// a registered block. Other names starting with `end`, such as `{% endpoint %}`,
+    /// are ordinary tags.
+    pub fn is_closing_tag(&self, name: &str) -> bool {
+        self.specs.values().any(|spec| {
+            spec.end.as_deref() == Some(name) || spec.intermediates.iter().any(|i| i == name)
+        })
+    }
+}
+
+impl Default for TagRegistry {
+    fn default() -> Self {
+        TagRegistry::builtins()
+    }
+}
+
+/// The registry used when a parser is not given one.
+pub(crate) fn default_registry() -> &'static TagRegistry {
+    static REGISTRY: OnceLock<TagRegistry> = OnceLock::new();
+    REGISTRY.get_or_init(TagRegistry::builtins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::span::Span;
+
+    fn tag(name: &str, bits: &[&str]) -> Tag {
+        Tag {
+            name: name.to_string(),
+            bits: bits.iter().map(|bit| bit.to_string()).collect(),
+            line: 1,
+            trivia: vec![],
+            span: Span::default(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let spec = TagSpec::new("thumbnail").with_args(vec![
+            ArgSpec::Value("image".to_string()),
+            ArgSpec::String("size".to_string()),
+            ArgSpec::VarArgs("option".to_string()),
+            ArgSpec::Optional(vec![
+                ArgSpec::Keyword("as".to_string()),
+                ArgSpec::Value("name".to_string()),
+            ]),
+        ]);
+
+        let test_cases = vec![
+            (vec!["img", "'100x100'"], true),
+            (vec!["img|first", "'100x100'", "crop", "upscale"], true),
+            (vec!["img", "'100x100'", "as", "thumb"], true),
+            (vec!["img", "'100x100'", "crop", "as", "thumb"], true),
+            (vec!["img"], false),
+            (vec!["img", "size"], false),
+            (vec!["img", "'100x100'", "|"], false),
+            (vec![], false),
+        ];
+
+        for (bits, valid) in test_cases {
+            println!("Testing bits: {:?}", bits);
+
+            let result = spec.validate(&tag("thumbnail", &bits));
+            assert_eq!(result.is_ok(), valid, "Got {:?}", result);
+        }
+    }
+
+    #[test]
+    fn test_validate_message() {
+        let spec = TagSpec::new("cache").with_args(vec![
+            ArgSpec::Value("timeout".to_string()),
+            ArgSpec::Keyword("using".to_string()),
+            ArgSpec::Kwarg("key".to_string()),
+        ]);
+
+        let error = spec.validate(&tag("cache", &[])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid arguments to 'cache' at line 1: expected 'cache <timeout> using key=<value>'"
+        );
+        assert!(TagSpec::new("csrf_token")
+            .validate(&tag("csrf_token", &["anything"]))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = TagRegistry::builtins();
+        registry.register(
+            TagSpec::block("switch")
+                .with_intermediates(&["case", "default"])
+                .with_end("stop"),
+        );
+
+        assert!(registry.get("for").is_some_and(TagSpec::is_block));
+        assert!(registry.get("switch").is_some_and(TagSpec::is_block));
+        assert!(registry.get("url").is_none());
+        assert!(registry.is_closing_tag("endfor"));
+        assert!(registry.is_closing_tag("case"));
+        assert!(registry.is_closing_tag("stop"));
+        assert!(!registry.is_closing_tag("switch"));
+        assert!(!registry.is_closing_tag("endless"));
+        assert!(TagRegistry::empty().get("if").is_none());
+    }
+}
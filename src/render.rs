@@ -0,0 +1,1070 @@
+use crate::ast::{Ast, Filter, FilterExpression, Node, Tag};
+use crate::builtins::{
+    Builtin, Cycle, Firstof, For, If, Ifchanged, Partialdef, Regroup, Resetcycle, Widthratio, With,
+};
+use crate::condition::{Condition, Operator};
+use crate::date::DateTime;
+use crate::error::RenderError;
+use crate::filters;
+use crate::html::{Attribute, Element};
+use crate::value::Value;
+use std::collections::HashMap;
+
+/// The variables a template is rendered with.
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+    values: HashMap<String, Value>,
+    string_if_invalid: String,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Context::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<Value>) {
+        self.values.insert(name.into(), value.into());
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.values.get(name)
+    }
+
+    /// Print `text` in place of variables that do not exist, like Django's
+    /// `string_if_invalid` option, with any `%s` in it replaced by the variable. Tags
+    /// such as `{% if %}` and `{% for %}` still see a missing variable as `None`.
+    pub fn with_string_if_invalid(mut self, text: impl Into<String>) -> Self {
+        self.string_if_invalid = text.into();
+        self
+    }
+}
+
+impl<K: Into<String>, V: Into<Value>> FromIterator<(K, V)> for Context {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(values: I) -> Self {
+        let mut context = Context::new();
+        for (name, value) in values {
+            context.insert(name, value);
+        }
+        context
+    }
+}
+
+/// Renders a tree the way Django's `Template.render` does. Tags that need more than
+/// the template itself, such as `{% include %}` or `{% url %}`, and custom tags fail
+/// with [`RenderError::UnsupportedTag`].
+pub(crate) struct Renderer<'a> {
+    context: &'a Context,
+    /// Variables set while rendering, such as loop variables, innermost last. There is
+    /// always at least one.
+    scopes: Vec<HashMap<String, Value>>,
+    partials: HashMap<&'a str, &'a Partialdef>,
+    named_cycles: HashMap<&'a str, &'a Cycle>,
+    /// How many times each cycle has been rendered, by the address of its node.
+    cycles: HashMap<*const Cycle, usize>,
+    /// The cycle each `{% resetcycle %}` without a name resets: as in Django, the last
+    /// one defined before it in the source, whichever cycle was rendered last.
+    reset_cycles: HashMap<*const Resetcycle, &'a Cycle>,
+    /// The last cycle defined so far, while collecting definitions.
+    last_cycle: Option<&'a Cycle>,
+    /// What each `{% ifchanged %}` last saw, for every loop being rendered.
+    changes: Vec<HashMap<*const Ifchanged, Value>>,
+    /// Whether variables are escaped as they are output, which `{% autoescape %}` sets.
+    autoescape: bool,
+}
+
+impl<'a> Renderer<'a> {
+    pub(crate) fn new(ast: &'a Ast, context: &'a Context) -> Self {
+        let mut renderer = Renderer {
+            context,
+            scopes: vec![HashMap::new()],
+            partials: HashMap::new(),
+            named_cycles: HashMap::new(),
+            cycles: HashMap::new(),
+            reset_cycles: HashMap::new(),
+            last_cycle: None,
+            changes: vec![HashMap::new()],
+            autoescape: true,
+        };
+        // Django finds these while compiling, so they can be used before they appear
+        renderer.collect_definitions(&ast.nodes);
+        renderer
+    }
+
+    pub(crate) fn render(mut self, nodes: &'a [Node]) -> Result<String, RenderError> {
+        let mut output = String::new();
+        self.render_nodes(nodes, &mut output)?;
+        Ok(output)
+    }
+
+    fn collect_definitions(&mut self, nodes: &'a [Node]) {
+        for node in nodes {
+            match node {
+                Node::Builtin(builtin) => {
+                    match builtin {
+                        Builtin::Partialdef(partial) => {
+                            self.partials.insert(&partial.name, partial);
+                        }
+                        Builtin::Cycle(cycle) if !cycle.values.is_empty() => {
+                            if let Some(name) = &cycle.name {
+                                self.named_cycles.insert(name, cycle);
+                            }
+                            self.last_cycle = Some(cycle);
+                        }
+                        Builtin::Resetcycle(reset) if reset.name.is_none() => {
+                            if let Some(cycle) = self.last_cycle {
+                                self.reset_cycles.insert(reset, cycle);
+                            }
+                        }
+                        _ => {}
+                    }
+                    for (_, nodes) in builtin.sections() {
+                        self.collect_definitions(nodes);
+                    }
+                }
+                Node::Element(element) => {
+                    for attribute in &element.attributes {
+                        match attribute {
+                            Attribute::Html(attribute) => {
+                                if let Some(value) = &attribute.value {
+                                    self.collect_definitions(&value.parts);
+                                }
+                            }
+                            Attribute::Template { node, .. } => {
+                                self.collect_definitions(std::slice::from_ref(node))
+                            }
+                        }
+                    }
+                    self.collect_definitions(&element.children)
+                }
+                Node::Embedded(embedded) => self.collect_definitions(&embedded.nodes),
+                Node::HtmlComment { nodes, .. } => self.collect_definitions(nodes),
+                _ => {}
+            }
+        }
+    }
+
+    fn render_nodes(&mut self, nodes: &'a [Node], output: &mut String) -> Result<(), RenderError> {
+        for node in nodes {
+            self.render_node(node, output)?;
+        }
+        Ok(())
+    }
+
+    fn render_node(&mut self, node: &'a Node, output: &mut String) -> Result<(), RenderError> {
+        match node {
+            Node::Text { text, .. } => output.push_str(text),
+            Node::Variable(variable) => {
+                let value = self.resolve(&variable.var, &variable.filters, false)?;
+                output.push_str(&self.render_value(&value).to_string());
+            }
+            Node::Comment { .. } => {}
+            Node::Tag(tag) => return Err(unsupported(tag)),
+            Node::Block(block) => match block.tag.name.as_str() {
+                "comment" => {}
+                "verbatim" => self.render_nodes(&block.nodes, output)?,
+                _ => return Err(unsupported(&block.tag)),
+            },
+            Node::Builtin(builtin) => self.render_builtin(builtin, output)?,
+            Node::Element(element) => self.render_element(element, output)?,
+            Node::Embedded(embedded) => self.render_nodes(&embedded.nodes, output)?,
+            Node::HtmlComment { nodes, .. } => {
+                output.push_str("<!--");
+                self.render_nodes(nodes, output)?;
+                output.push_str("-->");
+            }
+            Node::Error(_) | Node::Missing { .. } => return Err(RenderError::InvalidTemplate),
+        }
+        Ok(())
+    }
+
+    fn render_element(
+        &mut self,
+        element: &'a Element,
+        output: &mut String,
+    ) -> Result<(), RenderError> {
+        output.push('<');
+        output.push_str(&element.name);
+        for attribute in &element.attributes {
+            match attribute {
+                Attribute::Html(attribute) => {
+                    let trivia = |index| attribute.trivia.get(index).map_or("", String::as_str);
+                    output.push_str(trivia(0));
+                    output.push_str(&attribute.name);
+                    if let Some(value) = &attribute.value {
+                        output.push_str(trivia(1));
+                        output.extend(value.quote);
+                        self.render_nodes(&value.parts, output)?;
+                        output.extend(value.quote);
+                    }
+                }
+                Attribute::Template { node, trivia } => {
+                    output.push_str(trivia);
+                    self.render_node(node, output)?;
+                }
+            }
+        }
+        output.push_str(&element.trivia);
+        output.push_str(if element.self_closing { "/>" } else { ">" });
+        self.render_nodes(&element.children, output)?;
+        if let Some(end) = &element.end {
+            output.push_str(end);
+        }
+        Ok(())
+    }
+
+    fn render_builtin(
+        &mut self,
+        builtin: &'a Builtin,
+        output: &mut String,
+    ) -> Result<(), RenderError> {
+        match builtin {
+            Builtin::Autoescape(node) => {
+                let autoescape = std::mem::replace(&mut self.autoescape, node.enabled);
+                self.render_nodes(&node.body, output)?;
+                self.autoescape = autoescape;
+            }
+            Builtin::Block(node) => self.render_nodes(&node.body, output)?,
+            Builtin::CsrfToken(_) => {
+                let token = self.get("csrf_token").filter(|token| token.is_truthy());
+                if let Some(token) = token.filter(|token| token.as_str() != Some("NOTPROVIDED")) {
+                    output.push_str(&format!(
+                        "<input type=\"hidden\" name=\"csrfmiddlewaretoken\" value=\"{}\">",
+                        filters::escape_html(&token.to_string())
+                    ));
+                }
+            }
+            Builtin::Cycle(node) => self.render_cycle(node, output)?,
+            Builtin::Filter(node) => {
+                let mut body = String::new();
+                self.render_nodes(&node.body, &mut body)?;
+                // Like any rendered template text, the body is already safe
+                let mut value = Value::safe(body);
+                for filter in &node.filters {
+                    value = self.apply_filter(filter, value)?;
+                }
+                output.push_str(&value.to_string());
+            }
+            Builtin::Firstof(node) => self.render_firstof(node, output)?,
+            Builtin::For(node) => self.render_for(node, output)?,
+            Builtin::If(node) => self.render_if(node, output)?,
+            Builtin::Ifchanged(node) => self.render_ifchanged(node, output)?,
+            Builtin::Load(_) => {}
+            Builtin::Now(node) => {
+                // Like Django, format the time with the `date` filter
+                let now = Value::DateTime(DateTime::now());
+                let format = Value::from(node.format.as_str());
+                let now = filters::apply("date", now, Some(format), self.autoescape)?;
+                match &node.asvar {
+                    Some(name) => self.set(name, now),
+                    None => output.push_str(&now.to_string()),
+                }
+            }
+            Builtin::Partial(node) => {
+                let partial = self
+                    .partials
+                    .get(node.name.as_str())
+                    .copied()
+                    .ok_or_else(|| RenderError::UnknownPartial {
+                        name: node.name.clone(),
+                        line: node.tag.line,
+                    })?;
+                self.render_nodes(&partial.body, output)?;
+            }
+            Builtin::Partialdef(node) => {
+                if node.inline {
+                    self.render_nodes(&node.body, output)?;
+                }
+            }
+            Builtin::Regroup(node) => self.regroup(node)?,
+            Builtin::Resetcycle(node) => self.reset_cycle(node)?,
+            Builtin::Spaceless(node) => {
+                let mut body = String::new();
+                self.render_nodes(&node.body, &mut body)?;
+                output.push_str(&strip_spaces_between_tags(body.trim()));
+            }
+            Builtin::Templatetag(node) => output.push_str(node.output()),
+            Builtin::Widthratio(node) => self.render_widthratio(node, output)?,
+            Builtin::With(node) => self.render_with(node, output)?,
+            Builtin::Debug(node) => return Err(unsupported(&node.tag)),
+            Builtin::Extends(node) => return Err(unsupported(&node.tag)),
+            Builtin::Include(node) => return Err(unsupported(&node.tag)),
+            Builtin::Lorem(node) => return Err(unsupported(&node.tag)),
+            Builtin::Url(node) => return Err(unsupported(&node.tag)),
+        }
+        Ok(())
+    }
+
+    fn render_for(&mut self, node: &'a For, output: &mut String) -> Result<(), RenderError> {
+        let mut items = match self.resolve_expression(&node.iterable, true)? {
+            Value::None => Vec::new(),
+            value => value.iter_items().ok_or_else(|| RenderError::NotIterable {
+                name: value.type_name().to_string(),
+                line: node.tag.line,
+            })?,
+        };
+        if items.is_empty() {
+            if let Some(empty) = &node.empty {
+                self.render_nodes(&empty.nodes, output)?;
+            }
+            return Ok(());
+        }
+        if node.reversed {
+            items.reverse();
+        }
+
+        let parentloop = self
+            .get("forloop")
+            .cloned()
+            .unwrap_or(Value::Map(Vec::new()));
+        let length = items.len();
+        self.scopes.push(HashMap::new());
+        self.changes.push(HashMap::new());
+        for (index, item) in items.into_iter().enumerate() {
+            let forloop = Value::map([
+                ("counter0", Value::from(index)),
+                ("counter", Value::from(index + 1)),
+                ("revcounter", Value::from(length - index)),
+                ("revcounter0", Value::from(length - index - 1)),
+                ("first", Value::from(index == 0)),
+                ("last", Value::from(index == length - 1)),
+                ("length", Value::from(length)),
+                ("parentloop", parentloop.clone()),
+            ]);
+            self.set("forloop", forloop);
+            if let [target] = node.targets.as_slice() {
+                self.set(target, item);
+            } else {
+                // Like Python, anything that is not a sequence unpacks as a single value
+                let values = item.iter_items().unwrap_or_else(|| vec![item]);
+                if values.len() != node.targets.len() {
+                    return Err(RenderError::Unpack {
+                        expected: node.targets.len(),
+                        got: values.len(),
+                        line: node.tag.line,
+                    });
+                }
+                for (target, value) in node.targets.iter().zip(values) {
+                    self.set(target, value);
+                }
+            }
+            self.render_nodes(&node.body, output)?;
+        }
+        self.changes.pop();
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn render_if(&mut self, node: &'a If, output: &mut String) -> Result<(), RenderError> {
+        for branch in &node.branches {
+            let matched = match &branch.condition {
+                Some(condition) => self.evaluate(condition)?.is_truthy(),
+                None => true,
+            };
+            if matched {
+                return self.render_nodes(&branch.nodes, output);
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate a condition the way Django's `smartif` does: missing variables are
+    /// `None`, `and` and `or` give one of their operands, and comparisons that Python
+    /// would reject are false.
+    fn evaluate(&self, condition: &Condition) -> Result<Value, RenderError> {
+        let (operator, left, right) = match condition {
+            Condition::Operand(operand) => return self.resolve_expression(operand, true),
+            Condition::Not(operand) => {
+                return Ok(Value::from(!self.evaluate(operand)?.is_truthy()))
+            }
+            Condition::Binary {
+                operator,
+                left,
+                right,
+            } => (*operator, left, right),
+        };
+        let left = self.evaluate(left)?;
+        match operator {
+            Operator::Or if left.is_truthy() => return Ok(left),
+            Operator::And if !left.is_truthy() => return Ok(left),
+            Operator::Or | Operator::And => return self.evaluate(right),
+            _ => {}
+        }
+        let right = self.evaluate(right)?;
+        let result = match operator {
+            Operator::In => right.contains(&left).unwrap_or(false),
+            Operator::NotIn => right.contains(&left).is_some_and(|contains| !contains),
+            // Identity only has a meaning here for the singletons `None`, `True` and `False`
+            Operator::Is => is_identical(&left, &right),
+            Operator::IsNot => !is_identical(&left, &right),
+            Operator::Equal => left == right,
+            Operator::NotEqual => left != right,
+            Operator::Greater => left > right,
+            Operator::GreaterEqual => left >= right,
+            Operator::Less => left < right,
+            Operator::LessEqual => left <= right,
+            Operator::Or | Operator::And => unreachable!(),
+        };
+        Ok(Value::from(result))
+    }
+
+    fn render_with(&mut self, node: &'a With, output: &mut String) -> Result<(), RenderError> {
+        let mut scope = HashMap::new();
+        for (name, expression) in &node.assignments {
+            scope.insert(name.clone(), self.resolve_expression(expression, false)?);
+        }
+        self.scopes.push(scope);
+        self.render_nodes(&node.body, output)?;
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn render_cycle(&mut self, node: &'a Cycle, output: &mut String) -> Result<(), RenderError> {
+        // `{% cycle name %}` is the named cycle itself, so they advance together
+        let cycle = match &node.name {
+            Some(name) if node.values.is_empty() => self
+                .named_cycles
+                .get(name.as_str())
+                .copied()
+                .ok_or_else(|| RenderError::UnknownCycle {
+                name: name.clone(),
+                line: node.tag.line,
+            })?,
+            _ => node,
+        };
+        if cycle.values.is_empty() {
+            return Ok(());
+        }
+        let count = self.cycles.entry(cycle).or_insert(0);
+        let expression = &cycle.values[*count % cycle.values.len()];
+        *count += 1;
+
+        let value = self.resolve_expression(expression, false)?;
+        if let Some(name) = &cycle.name {
+            self.set_upward(name, value.clone());
+        }
+        if !cycle.silent {
+            output.push_str(&self.render_value(&value).to_string());
+        }
+        Ok(())
+    }
+
+    fn reset_cycle(&mut self, node: &Resetcycle) -> Result<(), RenderError> {
+        let cycle =
+            match &node.name {
+                Some(name) => Some(self.named_cycles.get(name.as_str()).copied().ok_or_else(
+                    || RenderError::UnknownCycle {
+                        name: name.clone(),
+                        line: node.tag.line,
+                    },
+                )?),
+                None => self.reset_cycles.get(&(node as *const Resetcycle)).copied(),
+            };
+        if let Some(cycle) = cycle {
+            self.cycles.remove(&(cycle as *const Cycle));
+        }
+        Ok(())
+    }
+
+    fn render_firstof(&mut self, node: &Firstof, output: &mut String) -> Result<(), RenderError> {
+        let mut first = Value::from("");
+        for expression in &node.values {
+            let value = self.resolve_expression(expression, true)?;
+            if value.is_truthy() {
+                first = self.render_value(&value);
+                break;
+            }
+        }
+        match &node.asvar {
+            Some(asvar) => self.set(asvar, first),
+            None => output.push_str(&first.to_string()),
+        }
+        Ok(())
+    }
+
+    fn render_ifchanged(
+        &mut self,
+        node: &'a Ifchanged,
+        output: &mut String,
+    ) -> Result<(), RenderError> {
+        // Without arguments the rendered body is what has to change
+        let mut body = None;
+        let compare_to = if node.values.is_empty() {
+            let mut rendered = String::new();
+            self.render_nodes(&node.body, &mut rendered)?;
+            body = Some(rendered.clone());
+            Value::from(rendered)
+        } else {
+            node.values
+                .iter()
+                .map(|expression| self.resolve_expression(expression, true))
+                .collect::<Result<_, _>>()?
+        };
+        let changes = self.changes.last_mut().expect("changes always has a frame");
+        if changes.get(&(node as *const Ifchanged)) != Some(&compare_to) {
+            changes.insert(node, compare_to);
+            match body {
+                Some(body) => output.push_str(&body),
+                None => self.render_nodes(&node.body, output)?,
+            }
+        } else if let Some(branch) = &node.else_branch {
+            self.render_nodes(&branch.nodes, output)?;
+        }
+        Ok(())
+    }
+
+    fn regroup(&mut self, node: &Regroup) -> Result<(), RenderError> {
+        let items = match self.resolve_expression(&node.target, true)? {
+            Value::None => Vec::new(),
+            value => value.iter_items().ok_or_else(|| RenderError::NotIterable {
+                name: value.type_name().to_string(),
+                line: node.tag.line,
+            })?,
+        };
+        // Django looks the grouper up with the item in place of the result
+        let grouper = format!("{}.{}", node.asvar, node.grouper);
+        let mut groups: Vec<(Value, Vec<Value>)> = Vec::new();
+        for item in items {
+            self.set(&node.asvar, item.clone());
+            let key = self.resolve(&grouper, &[], true)?;
+            match groups.last_mut() {
+                Some((last, list)) if *last == key => list.push(item),
+                _ => groups.push((key, vec![item])),
+            }
+        }
+        let groups = groups
+            .into_iter()
+            .map(|(key, list)| Value::map([("grouper", key), ("list", Value::List(list))]))
+            .collect();
+        self.set(&node.asvar, groups);
+        Ok(())
+    }
+
+    fn render_widthratio(
+        &mut self,
+        node: &Widthratio,
+        output: &mut String,
+    ) -> Result<(), RenderError> {
+        let value = self.resolve_expression(&node.value, false)?;
+        let max_value = self.resolve_expression(&node.max_value, false)?;
+        let max_width = self
+            .resolve_expression(&node.max_width, false)?
+            .to_int()
+            .ok_or_else(|| RenderError::InvalidArguments {
+                name: node.tag.name.clone(),
+                reason: "final argument must be a number".to_string(),
+                line: node.tag.line,
+            })?;
+        let result = match (value.to_float(), max_value.to_float()) {
+            (Some(_), Some(0.0)) => "0".to_string(),
+            (Some(value), Some(max_value)) => {
+                let ratio = value / max_value * max_width as f64;
+                if ratio.is_finite() {
+                    format!("{:.0}", ratio.round_ties_even())
+                } else {
+                    String::new()
+                }
+            }
+            _ => String::new(),
+        };
+        match &node.asvar {
+            Some(asvar) => self.set(asvar, Value::from(result)),
+            None => output.push_str(&result),
+        }
+        Ok(())
+    }
+
+    fn resolve_expression(
+        &self,
+        expression: &FilterExpression,
+        ignore_failures: bool,
+    ) -> Result<Value, RenderError> {
+        self.resolve(&expression.var, &expression.filters, ignore_failures)
+    }
+
+    /// Resolve a variable and apply its filters, like Django's `FilterExpression`. A
+    /// variable that does not exist is `None` when `ignore_failures` is set, as it is
+    /// for tags, and otherwise the context's string for invalid variables.
+    fn resolve(
+        &self,
+        var: &str,
+        filters: &[Filter],
+        ignore_failures: bool,
+    ) -> Result<Value, RenderError> {
+        let string_if_invalid = &self.context.string_if_invalid;
+        let mut value = match self.lookup(var)? {
+            Some(value) => value,
+            None if ignore_failures => Value::None,
+            // Filters are not applied to the invalid string
+            None if !string_if_invalid.is_empty() => {
+                return Ok(Value::from(string_if_invalid.replacen("%s", var, 1)));
+            }
+            None => Value::from(""),
+        };
+        for filter in filters {
+            value = self.apply_filter(filter, value)?;
+        }
+        Ok(value)
+    }
+
+    /// A literal's value, or a variable looked up in the context, or `None` when it does
+    /// not exist.
+    fn lookup(&self, var: &str) -> Result<Option<Value>, RenderError> {
+        // Templates are not translated, so `_("text")` is just its content
+        let var = var
+            .strip_prefix("_(")
+            .and_then(|var| var.strip_suffix(')'))
+            .unwrap_or(var);
+        if let Some(literal) = literal(var) {
+            return Ok(Some(literal));
+        }
+        if var.starts_with('_') || var.contains("._") {
+            return Err(RenderError::Underscore {
+                name: var.to_string(),
+            });
+        }
+        let mut bits = var.split('.');
+        let Some(mut value) = bits.next().and_then(|name| self.get(name)).cloned() else {
+            return Ok(None);
+        };
+        for bit in bits {
+            match value.lookup(bit) {
+                Some(next) => value = next,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(value))
+    }
+
+    fn apply_filter(&self, filter: &Filter, value: Value) -> Result<Value, RenderError> {
+        let arg = match &filter.arg {
+            Some(arg) => Some(
+                self.lookup(arg)?
+                    .ok_or_else(|| RenderError::VariableDoesNotExist { name: arg.clone() })?,
+            ),
+            None => None,
+        };
+        filters::apply(&filter.name, value, arg, self.autoescape)
+    }
+
+    /// A value as it is output, escaped when autoescaping is on and it is not already
+    /// safe, like Django's `render_value_in_context`.
+    fn render_value(&self, value: &Value) -> Value {
+        match value {
+            Value::SafeString(_) => value.clone(),
+            value if self.autoescape => filters::conditional_escape(value),
+            value => Value::Str(value.to_string()),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&Value> {
+        const BUILTINS: [(&str, Value); 3] = [
+            ("None", Value::None),
+            ("True", Value::Bool(true)),
+            ("False", Value::Bool(false)),
+        ];
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.context.get(name))
+            .or_else(|| {
+                BUILTINS
+                    .iter()
+                    .find_map(|(builtin, value)| (*builtin == name).then_some(value))
+            })
+    }
+
+    /// Set a variable in the innermost scope, like assigning to a Django context.
+    fn set(&mut self, name: &str, value: Value) {
+        let scope = self.scopes.last_mut().expect("scopes always has a scope");
+        scope.insert(name.to_string(), value);
+    }
+
+    /// Set a variable in the innermost scope that already has it, or else in the
+    /// innermost scope, like Django's `Context.set_upward`.
+    fn set_upward(&mut self, name: &str, value: Value) {
+        match self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find(|scope| scope.contains_key(name))
+        {
+            Some(scope) => {
+                scope.insert(name.to_string(), value);
+            }
+            None => self.set(name, value),
+        }
+    }
+}
+
+/// The value of a number or string literal, as Django's `Variable` reads them. String
+/// literals are safe.
+fn literal(var: &str) -> Option<Value> {
+    if var.contains('.') || var.contains(['e', 'E']) {
+        if !var.ends_with('.') {
+            if let Ok(number) = var.parse() {
+                return Some(Value::Float(number));
+            }
+        }
+    } else if let Ok(number) = var.parse() {
+        return Some(Value::Int(number));
+    }
+    let quote = var.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let content = var.strip_prefix(quote)?.strip_suffix(quote)?;
+    let unescaped = content
+        .replace(&format!("\\{}", quote), &quote.to_string())
+        .replace("\\\\", "\\");
+    Some(Value::SafeString(unescaped))
+}
+
+fn is_identical(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::None, Value::None) => true,
+        (Value::Bool(left), Value::Bool(right)) => left == right,
+        _ => false,
+    }
+}
+
+/// Remove whitespace between HTML tags, like Django's `strip_spaces_between_tags`.
+fn strip_spaces_between_tags(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(index) = rest.find('>') {
+        result.push_str(&rest[..=index]);
+        rest = &rest[index + 1..];
+        let trimmed = rest.trim_start();
+        if trimmed.len() < rest.len() && trimmed.starts_with('<') {
+            rest = trimmed;
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+fn unsupported(tag: &Tag) -> RenderError {
+    RenderError::UnsupportedTag {
+        name: tag.name.clone(),
+        line: tag.line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn render(template: &str, context: &Context) -> Result<String, RenderError> {
+        let ast = parse(template).unwrap();
+        Renderer::new(&ast, context).render(&ast.nodes)
+    }
+
+    fn context() -> Context {
+        Context::from_iter([
+            (
+                "user",
+                Value::map([
+                    ("name", Value::from("Ada")),
+                    ("is_authenticated", Value::from(true)),
+                    ("roles", Value::from(vec!["admin", "staff"])),
+                ]),
+            ),
+            ("items", Value::from(vec![1, 2, 3])),
+            (
+                "pairs",
+                Value::from(vec![
+                    Value::from(vec!["a", "b"]),
+                    Value::from(vec!["c", "d"]),
+                ]),
+            ),
+            ("prices", Value::map([("tea", 2.5), ("cake", 4.0)])),
+            ("empty", Value::List(Vec::new())),
+            ("zero", Value::Int(0)),
+            ("none", Value::None),
+            ("ratio", Value::Float(0.5)),
+        ])
+    }
+
+    #[test]
+    fn test_variables() {
+        let test_cases = vec![
+            ("Hello, {{ user.name }}!", "Hello, Ada!"),
+            ("{{ user.roles.0 }} {{ user.roles.-1 }}", "admin staff"),
+            ("{{ user.name.0 }}", "A"),
+            ("{{ user.roles }}", "[&#x27;admin&#x27;, &#x27;staff&#x27;]"),
+            ("{{ items.1 }}{{ items.3 }}", "2"),
+            ("[{{ missing }}{{ user.missing.name }}]", "[]"),
+            (
+                "{{ none }} {{ True }} {{ ratio }} {{ zero }}",
+                "None True 0.5 0",
+            ),
+            (
+                "{{ 'it\\'s' }} {{ 42 }} {{ 1.5e3 }} {{ _(\"hi\") }}",
+                "it's 42 1500.0 hi",
+            ),
+            ("{{ missing|default:user.name }}", "Ada"),
+            (
+                "{{ none|default_if_none:'-' }}{{ zero|default_if_none:'-' }}",
+                "-0",
+            ),
+            (
+                "{% for key, value in prices.items %}{{ key }}={{ value }};{% endfor %}",
+                "tea=2.5;cake=4.0;",
+            ),
+            (
+                "<a href=\"/u/{{ user.name }}\" {% if zero %}hidden{% endif %}>",
+                "<a href=\"/u/Ada\" >",
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            println!("Testing input: {:?}", input);
+            assert_eq!(render(input, &context()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_string_if_invalid() {
+        let context = context().with_string_if_invalid("INVALID(%s)");
+
+        let test_cases = vec![
+            ("{{ missing }}", "INVALID(missing)"),
+            ("{{ user.missing|default:'x' }}", "INVALID(user.missing)"),
+            ("{% if missing %}yes{% else %}no{% endif %}", "no"),
+            (
+                "{% for x in missing %}{{ x }}{% empty %}none{% endfor %}",
+                "none",
+            ),
+            (
+                "{% with a=missing %}{{ a }}{% endwith %}",
+                "INVALID(missing)",
+            ),
+            ("{{ user.name }}", "Ada"),
+        ];
+
+        for (input, expected) in test_cases {
+            println!("Testing input: {:?}", input);
+            assert_eq!(render(input, &context).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_if() {
+        let test_cases = vec![
+            (
+                "{% if user.is_authenticated %}in{% else %}out{% endif %}",
+                "in",
+            ),
+            ("{% if zero %}a{% elif empty %}b{% else %}c{% endif %}", "c"),
+            ("{% if not missing %}a{% endif %}", "a"),
+            ("{% if 'admin' in user.roles %}a{% endif %}", "a"),
+            ("{% if 'root' not in user.roles %}a{% endif %}", "a"),
+            ("{% if 'da' in user.name %}a{% endif %}", "a"),
+            ("{% if 1 in user.name %}a{% else %}b{% endif %}", "b"),
+            ("{% if 1 not in user.name %}a{% else %}b{% endif %}", "b"),
+            ("{% if 'tea' in prices %}a{% endif %}", "a"),
+            ("{% if items.0 == 1.0 and ratio < 1 %}a{% endif %}", "a"),
+            ("{% if user.name > 1 %}a{% else %}b{% endif %}", "b"),
+            (
+                "{% if missing is None and zero is not None %}a{% endif %}",
+                "a",
+            ),
+            ("{% if zero or none or ratio %}a{% endif %}", "a"),
+            ("{% if items|default:zero %}a{% endif %}", "a"),
+        ];
+
+        for (input, expected) in test_cases {
+            println!("Testing input: {:?}", input);
+            assert_eq!(render(input, &context()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_for() {
+        let test_cases = vec![
+            ("{% for x in items %}{{ x }}{% endfor %}", "123"),
+            ("{% for x in items reversed %}{{ x }}{% endfor %}", "321"),
+            (
+                "{% for x in empty %}{{ x }}{% empty %}none{% endfor %}",
+                "none",
+            ),
+            ("{% for c in user.name %}{{ c }},{% endfor %}", "A,d,a,"),
+            ("{% for a, b in pairs %}{{ b }}{{ a }}{% endfor %}", "badc"),
+            (
+                "{% for x in items %}{{ forloop.counter }}{{ forloop.revcounter0 }}\
+                 {% if forloop.first %}F{% endif %}{% if forloop.last %}L{% endif %} {% endfor %}",
+                "12F 21 30L ",
+            ),
+            (
+                "{% for x in pairs %}{% for y in x %}{{ forloop.parentloop.counter }}{{ y }}\
+                 {% endfor %}{% endfor %}",
+                "1a1b2c2d",
+            ),
+            ("{% for x in items %}{% endfor %}{{ x }}{{ forloop }}", ""),
+            ("{% for x in items %}{% cycle 'a' 'b' %}{% endfor %}", "aba"),
+            (
+                "{% for x in items %}{% cycle 'a' 'b' as c silent %}{{ c }}{% endfor %}",
+                "aba",
+            ),
+            (
+                "{% for x in items %}{% cycle 'a' 'b' as c %}{% endfor %}\
+                 {% resetcycle c %}{% cycle c %}",
+                "abaa",
+            ),
+            // An unnamed reset is bound to the last cycle before it in the source,
+            // not the last one rendered
+            (
+                "{% for x in items %}{% if x != 2 %}{% cycle 'a' 'b' %}\
+                 {% else %}{% cycle 'c' 'd' %}{% endif %}{% resetcycle %}{% endfor %}",
+                "acb",
+            ),
+            (
+                "{% for x in items %}<tr class=\"{% cycle 'a' 'b' %}\">{% resetcycle %}\
+                 {% endfor %}",
+                "<tr class=\"a\"><tr class=\"a\"><tr class=\"a\">",
+            ),
+            (
+                "{% for x in pairs %}{% for y in items %}{% ifchanged x %}{{ y }}\
+                 {% else %}-{% endifchanged %}{% endfor %}{% endfor %}",
+                "1--1--",
+            ),
+            (
+                "{% for x in pairs %}{% ifchanged %}{{ user.name }}{% endifchanged %}{% endfor %}",
+                "Ada",
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            println!("Testing input: {:?}", input);
+            assert_eq!(render(input, &context()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_tags() {
+        let test_cases = vec![
+            ("{% with name=user.name n=2 %}{{ name }}{{ n }}{% endwith %}{{ name }}", "Ada2"),
+            ("{% with user.name as name %}{{ name }}{% endwith %}", "Ada"),
+            ("{% firstof zero missing user.name 'x' %}", "Ada"),
+            ("{% firstof zero as first %}[{{ first }}]", "[]"),
+            ("{% widthratio 175 200 100 %}", "88"),
+            ("{% widthratio 5 0 100 %}|{% widthratio 'x' 2 100 %}", "0|"),
+            ("{% widthratio 1 8 4 as w %}{{ w }}", "0"),
+            ("{% spaceless %} <p> <a>x </a> </p> {% endspaceless %}", "<p><a>x </a></p>"),
+            ("{% templatetag openblock %}", "{%"),
+            ("{% load static %}{# note #}{% comment %}{{ x }}{% endcomment %}", ""),
+            ("{% verbatim %}{{ x }}{% endverbatim %}", "{{ x }}"),
+            ("{% block main %}{{ user.name }}{% endblock %}", "Ada"),
+            ("{% autoescape off %}{{ user.name }}{% endautoescape %}", "Ada"),
+            ("{% filter default:'x' %}{% endfilter %}", "x"),
+            ("{% partialdef card %}[{{ n }}]{% endpartialdef %}{% with n=1 %}{% partial card %}{% endwith %}", "[1]"),
+            ("{% partialdef card inline %}c{% endpartialdef %}", "c"),
+            ("{% csrf_token %}", ""),
+            ("{% now 'Y' as year %}{{ year|length }}", "4"),
+            ("{% filter lower|cut:' ' %}A B{% endfilter %}", "ab"),
+            (
+                "{% regroup pairs by 0 as groups %}{% for group in groups %}\
+                 {{ group.grouper }}:{{ group.list.0.1 }};{% endfor %}",
+                "a:b;c:d;",
+            ),
+            ("<!-- {{ user.name }} -->", "<!-- Ada -->"),
+        ];
+
+        for (input, expected) in test_cases {
+            println!("Testing input: {:?}", input);
+            assert_eq!(render(input, &context()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_autoescape() {
+        let context = Context::from_iter([
+            ("html", Value::from("<b>\"Tom\" & 'Jerry'</b>")),
+            ("safe", Value::safe("<b>safe</b>")),
+            ("text", Value::from("a\n<b>")),
+            ("list", Value::from(vec!["<a>", "<b>"])),
+            ("csrf_token", Value::from("\"><x")),
+        ]);
+
+        let test_cases = vec![
+            (
+                "{{ html }}",
+                "&lt;b&gt;&quot;Tom&quot; &amp; &#x27;Jerry&#x27;&lt;/b&gt;",
+            ),
+            ("{{ safe }} {{ '<i>' }}", "<b>safe</b> <i>"),
+            ("{{ html|safe }}", "<b>\"Tom\" & 'Jerry'</b>"),
+            ("{{ safe|escape }} {{ safe|force_escape }}", "<b>safe</b> &lt;b&gt;safe&lt;/b&gt;"),
+            ("{{ safe|lower }} {{ safe|upper }}", "<b>safe</b> &lt;B&gt;SAFE&lt;/B&gt;"),
+            ("{{ html|safe|slice:':3' }}", "<b>"),
+            ("{{ html|escape|escape }}", "&lt;b&gt;&quot;Tom&quot; &amp; &#x27;Jerry&#x27;&lt;/b&gt;"),
+            ("{{ text|linebreaksbr }}", "a<br>&lt;b&gt;"),
+            ("{{ list|join:'<br>' }}", "&lt;a&gt;<br>&lt;b&gt;"),
+            ("{{ list|safeseq|join:', ' }} {{ list|first }}", "<a>, <b> &lt;a&gt;"),
+            ("{{ missing|default:html }}", "&lt;b&gt;&quot;Tom&quot; &amp; &#x27;Jerry&#x27;&lt;/b&gt;"),
+            ("{% autoescape off %}{{ html }}{% endautoescape %}", "<b>\"Tom\" & 'Jerry'</b>"),
+            ("{% autoescape off %}{{ html|escape }}{% endautoescape %}", "&lt;b&gt;&quot;Tom&quot; &amp; &#x27;Jerry&#x27;&lt;/b&gt;"),
+            ("{% autoescape off %}{{ text|linebreaksbr }}{% endautoescape %}", "a<br><b>"),
+            ("{% autoescape off %}{{ list|join:'' }}{% endautoescape %}", "<a><b>"),
+            ("{% autoescape off %}{% autoescape on %}{{ list.0 }}{% endautoescape %}{{ list.0 }}{% endautoescape %}", "&lt;a&gt;<a>"),
+            ("{% firstof missing html %}", "&lt;b&gt;&quot;Tom&quot; &amp; &#x27;Jerry&#x27;&lt;/b&gt;"),
+            ("{% firstof list.0 as first %}{{ first }}", "&lt;a&gt;"),
+            ("{% for x in list %}{% cycle x 'y' %}{% endfor %}", "&lt;a&gt;y"),
+            ("{% filter upper %}<a>{{ list.0 }}{% endfilter %}", "<A>&LT;A&GT;"),
+            ("{% csrf_token %}", "<input type=\"hidden\" name=\"csrfmiddlewaretoken\" value=\"&quot;&gt;&lt;x\">"),
+        ];
+
+        for (input, expected) in test_cases {
+            println!("Testing input: {:?}", input);
+            assert_eq!(render(input, &context).unwrap(), expected);
+        }
+
+        let context = Context::new().with_string_if_invalid("<%s>");
+        assert_eq!(
+            render("{{ missing }}", &context).unwrap(),
+            "&lt;missing&gt;"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let test_cases = vec![
+            (
+                "{{ user._secret }}",
+                "variables and attributes may not begin with underscores: 'user._secret'",
+            ),
+            ("{{ x|capitalize }}", "invalid filter: 'capitalize'"),
+            (
+                "{{ x|default }}",
+                "default requires 2 arguments, 1 provided",
+            ),
+            ("{{ x|default:missing }}", "failed lookup for key [missing]"),
+            (
+                "\n{% include 'a.html' %}",
+                "'include' tag at line 2 cannot be rendered",
+            ),
+            (
+                "{% static 'a.css' %}",
+                "'static' tag at line 1 cannot be rendered",
+            ),
+            (
+                "{% for x in zero %}{% endfor %}",
+                "'int' object is not iterable at line 1",
+            ),
+            (
+                "{% for a, b in items %}{% endfor %}",
+                "need 2 values to unpack in for loop at line 1, got 1",
+            ),
+            ("{% cycle rows %}", "no cycle named 'rows' at line 1"),
+            ("{% partial card %}", "no partial named 'card' at line 1"),
+            (
+                "{% widthratio 1 2 'x' %}",
+                "invalid arguments to 'widthratio' at line 1: final argument must be a number",
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            println!("Testing input: {:?}", input);
+            let error = render(input, &context()).unwrap_err();
+            assert_eq!(error.to_string(), expected);
+        }
+    }
+}
@@ -1,9 +1,12 @@
 use std::fmt::Debug;
 
+/// A cursor over a sequence of items, shared by the lexer, which scans the characters
+/// of a template, and the parser, which scans its tokens.
 pub trait Scanner {
     type Item: Debug;
     type Error: Debug + std::error::Error;
 
+    /// Move past the current item and return it.
     fn advance(&mut self) -> Result<Self::Item, Self::Error>;
     fn peek(&self) -> Result<Self::Item, Self::Error>;
     fn peek_next(&self) -> Result<Self::Item, Self::Error>;
@@ -0,0 +1,117 @@
+use crate::ast::Ast;
+use crate::diagnostic::Diagnostic;
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// The version of the serialized form of tokens, trees and diagnostics, described in
+/// `docs/schema.md`. It changes whenever a change to those types changes what they
+/// serialize to.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// A parsed template and the problems found in it, tagged with the schema version so
+/// that readers can tell which shape to expect.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Document {
+    #[serde(deserialize_with = "supported_version")]
+    pub version: u32,
+    pub ast: Ast,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Document {
+    pub fn new(ast: Ast, diagnostics: Vec<Diagnostic>) -> Self {
+        Document {
+            version: SCHEMA_VERSION,
+            ast,
+            diagnostics,
+        }
+    }
+}
+
+/// Refuse documents written with a different schema, rather than misreading them.
+fn supported_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let version = u32::deserialize(deserializer)?;
+    if version != SCHEMA_VERSION {
+        return Err(serde::de::Error::custom(format!(
+            "unsupported schema version {}, expected {}",
+            version, SCHEMA_VERSION
+        )));
+    }
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parse_resilient;
+    use crate::token::TokenStream;
+
+    #[test]
+    fn test_round_trip() {
+        let test_cases = vec![
+            "Hello, {{ user.name|default:'you' }}!",
+            "{% for x in items %}<li class=\"{{ x.kind }}\">{{ x }}</li>{% empty %}none{% endfor %}",
+            "{% if a and not b %}<script>var c = {{ c }};</script>{% endif %}",
+            "{% block content %}{# note #}<!-- {{ d }} -->{% endblock %}",
+            "{% if a %}{{ b",
+        ];
+
+        for input in test_cases {
+            println!("Testing input: {:?}", input);
+
+            let (ast, diagnostics) = parse_resilient(input);
+            let document = Document::new(ast, diagnostics);
+            let json = serde_json::to_string(&document).unwrap();
+            let parsed: Document = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed, document);
+            assert_eq!(parsed.ast.to_string(), input);
+        }
+    }
+
+    #[test]
+    fn test_token_round_trip() {
+        let source = "<ul>\n  {% for x in items %}\n    <li>{{ x|default:\"\\\"none\\\"\" }}</li>\n  {% endfor %}\n</ul>\n";
+        let tokens = Lexer::lossless(source).tokenize().unwrap();
+
+        let json = serde_json::to_string(&tokens).unwrap();
+        let parsed: TokenStream = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.as_ref(), tokens.as_ref());
+        let lexemes: String = parsed.iter().map(|token| token.lexeme.as_ref()).collect();
+        assert_eq!(lexemes, source);
+    }
+
+    #[test]
+    fn test_shape() {
+        let (ast, diagnostics) = parse_resilient("a{{ b|upper }}");
+        let value = serde_json::to_value(Document::new(ast, diagnostics)).unwrap();
+
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "version": 2,
+                "ast": {"nodes": [
+                    {"Text": {"text": "a", "span": {"start": 0, "end": 1}}},
+                    {"Variable": {
+                        "var": "b",
+                        "filters": [{"name": "upper", "arg": null}],
+                        "expression": "b|upper",
+                        "trivia": [" ", " "],
+                        "span": {"start": 1, "end": 14},
+                    }},
+                ]},
+                "diagnostics": [],
+            })
+        );
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let json = r#"{"version": 1, "ast": {"nodes": []}, "diagnostics": []}"#;
+        let error = serde_json::from_str::<Document>(json).unwrap_err();
+
+        assert!(error
+            .to_string()
+            .starts_with("unsupported schema version 1, expected 2"));
+    }
+}
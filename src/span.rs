@@ -0,0 +1,178 @@
+/// A half-open byte range `[start, end)` into the template source.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+
+    /// The span moved `offset` bytes later, or earlier when negative, such as after an
+    /// edit before it.
+    pub fn shift(&self, offset: isize) -> Span {
+        Span {
+            start: self.start.saturating_add_signed(offset),
+            end: self.end.saturating_add_signed(offset),
+        }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn join(&self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+/// A 1-based line and column, with the column counted in characters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Converts byte offsets into line/column positions for a given source.
+pub struct SourceMap<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        SourceMap {
+            source,
+            line_starts,
+        }
+    }
+
+    /// The position of `offset`, clamped to the end of the source. Offsets that fall
+    /// inside a multi-byte character resolve to that character.
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.source.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        let line_start = self.line_starts[line];
+        let column = self.source[line_start..]
+            .char_indices()
+            .take_while(|(index, _)| line_start + index < offset)
+            .count();
+        Position {
+            line: line + 1,
+            column: column + 1,
+        }
+    }
+
+    pub fn span_positions(&self, span: Span) -> (Position, Position) {
+        (self.position(span.start), self.position(span.end))
+    }
+
+    pub fn slice(&self, span: Span) -> Option<&'a str> {
+        self.source.get(span.start..span.end)
+    }
+
+    /// The byte offset where the 1-based `line` starts.
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        self.line_starts.get(line.checked_sub(1)?).copied()
+    }
+
+    /// The text of the 1-based `line`, without its line ending.
+    pub fn line(&self, line: usize) -> Option<&'a str> {
+        let start = self.line_start(line)?;
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.source.len(), |next| next - 1);
+        let text = &self.source[start..end];
+        Some(text.strip_suffix('\r').unwrap_or(text))
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position() {
+        let source = "Hello\nWorld\n\nTest";
+        let source_map = SourceMap::new(source);
+
+        let test_cases = vec![
+            (0, 1, 1),
+            (4, 1, 5),
+            (5, 1, 6),
+            (6, 2, 1),
+            (11, 2, 6),
+            (12, 3, 1),
+            (13, 4, 1),
+            (17, 4, 5),
+            (usize::MAX, 4, 5),
+        ];
+
+        for (offset, line, column) in test_cases {
+            assert_eq!(
+                source_map.position(offset),
+                Position { line, column },
+                "position() failed for offset: {}",
+                offset
+            );
+        }
+        assert_eq!(source_map.line_count(), 4);
+        assert_eq!(source_map.line(2), Some("World"));
+        assert_eq!(source_map.line(3), Some(""));
+        assert_eq!(source_map.line(4), Some("Test"));
+        assert_eq!(source_map.line(5), None);
+        assert_eq!(source_map.line_start(2), Some(6));
+        assert_eq!(source_map.line_start(0), None);
+    }
+
+    #[test]
+    fn test_position_multibyte() {
+        let source = "héllo\n日本語";
+        let source_map = SourceMap::new(source);
+
+        assert_eq!(source_map.position(3), Position { line: 1, column: 3 });
+        assert_eq!(source_map.position(7), Position { line: 2, column: 1 });
+        assert_eq!(source_map.position(10), Position { line: 2, column: 2 });
+    }
+
+    #[test]
+    fn test_span() {
+        let span = Span::new(2, 5);
+
+        assert_eq!(span.len(), 3);
+        assert!(!span.is_empty());
+        assert!(span.contains(2));
+        assert!(!span.contains(5));
+        assert_eq!(span.join(Span::new(7, 9)), Span::new(2, 9));
+        assert_eq!(span.shift(3), Span::new(5, 8));
+        assert_eq!(span.shift(-2), Span::new(0, 3));
+        assert_eq!(SourceMap::new("Hello").slice(span), Some("llo"));
+    }
+}
@@ -1,10 +1,13 @@
 use crate::error::TokenError;
+use crate::span::Span;
+use std::borrow::Cow;
 use std::fmt;
 use std::fmt::Debug;
 use std::ops::{Deref, DerefMut};
-use std::string::ToString;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum TokenType {
     LeftAngle,             // <
     RightAngle,            // >
@@ -38,6 +41,9 @@ pub enum TokenType {
     SlashStar,             // /*
     StarSlash,             // */
     Whitespace,            // special token to account for whitespace
+    Identifier,            // name, inside {{ }} and {% %}
+    StringLiteral,         // 'text' or "text", inside {{ }} and {% %}
+    NumberLiteral,         // 42 or -1.5, inside {{ }} and {% %}
     Text,
     Eof,
 }
@@ -83,49 +89,53 @@ impl TokenType {
     }
 }
 
+/// A token of a template. Lexed tokens borrow their lexeme from the source; a
+/// deserialized one owns it when the input had to be unescaped.
 #[derive(Clone, Debug, PartialEq)]
-pub struct Token {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Token<'src> {
     pub token_type: TokenType,
-    pub lexeme: String,
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub lexeme: Cow<'src, str>,
+    pub span: Span,
     pub line: usize,
 }
 
-impl fmt::Display for Token {
+impl fmt::Display for Token<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.lexeme)
     }
 }
 
-impl<'a> Token {
-    pub fn new(token_type: TokenType, lexeme: &'a str, line: usize) -> Self {
+impl<'src> Token<'src> {
+    pub fn new(token_type: TokenType, lexeme: &'src str, start: usize, line: usize) -> Self {
         Token {
             token_type,
-            lexeme: String::from(lexeme),
+            lexeme: Cow::Borrowed(lexeme),
+            span: Span::new(start, start + lexeme.len()),
             line,
         }
     }
 
-    pub fn eof(line: usize) -> Self {
+    pub fn eof(start: usize, line: usize) -> Self {
         Token {
             token_type: TokenType::Eof,
-            lexeme: "".to_string(),
+            lexeme: Cow::Borrowed(""),
+            span: Span::new(start, start),
             line,
         }
     }
 
+    /// The size of the lexeme in bytes, matching the byte offsets used by `Span`.
     pub fn size(&self) -> usize {
-        self.lexeme.chars().count()
+        self.lexeme.len()
     }
 
+    /// The number of line breaks in the lexeme. Whitespace is not the only token that
+    /// can span lines: raw `{% verbatim %}` bodies and comments can too.
     pub fn lines(&self) -> usize {
-        match self.token_type {
-            TokenType::Whitespace => self
-                .lexeme
-                .chars()
-                .filter(|&c| c == '\n' || c == '\r')
-                .count(),
-            _ => 0,
-        }
+        self.lexeme.matches('\n').count()
     }
 
     pub fn is_throwaway(&self) -> bool {
@@ -141,66 +151,91 @@ pub trait TokenVecToString {
     fn to_string(&self) -> String;
 }
 
-impl TokenVecToString for Vec<Token> {
+impl TokenVecToString for [Token<'_>] {
+    /// Join the lexemes, separating tokens that were not adjacent in the source by a
+    /// single space.
     fn to_string(&self) -> String {
-        self.iter()
-            .map(|token| token.to_string())
-            .collect::<Vec<String>>()
-            .join(" ")
+        let mut result = String::new();
+        let mut previous: Option<&Token> = None;
+        for token in self {
+            if previous.is_some_and(|previous| previous.span.end != token.span.start) {
+                result.push(' ');
+            }
+            result.push_str(&token.lexeme);
+            previous = Some(token);
+        }
+        result
     }
 }
 
 #[derive(Clone, Debug)]
-pub struct TokenStream {
-    tokens: Vec<Token>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TokenStream<'src> {
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    tokens: Vec<Token<'src>>,
+    lossless: bool,
 }
 
-impl TokenStream {
+impl<'src> TokenStream<'src> {
     pub fn new() -> Self {
-        TokenStream { tokens: Vec::new() }
+        TokenStream {
+            tokens: Vec::new(),
+            lossless: false,
+        }
+    }
+
+    /// A stream that keeps throwaway tokens, so the source can be rebuilt exactly.
+    pub fn lossless() -> Self {
+        TokenStream {
+            tokens: Vec::new(),
+            lossless: true,
+        }
+    }
+
+    pub fn is_lossless(&self) -> bool {
+        self.lossless
     }
 
-    pub fn add_token(&mut self, token: Token) {
-        if !token.is_throwaway() {
+    pub fn add_token(&mut self, token: Token<'src>) {
+        if self.lossless || !token.is_throwaway() {
             self.tokens.push(token);
         }
     }
 
-    pub fn finalize(&mut self, last_line: usize) -> TokenStream {
-        let eof_token = Token::eof(last_line);
-        self.add_token(eof_token);
-        self.clone()
+    /// End the stream with an `Eof` token.
+    pub fn finalize(&mut self, end: usize, last_line: usize) {
+        self.add_token(Token::eof(end, last_line));
     }
 }
 
-impl Default for TokenStream {
+impl Default for TokenStream<'_> {
     fn default() -> Self {
         TokenStream::new()
     }
 }
 
-impl AsRef<[Token]> for TokenStream {
-    fn as_ref(&self) -> &[Token] {
+impl<'src> AsRef<[Token<'src>]> for TokenStream<'src> {
+    fn as_ref(&self) -> &[Token<'src>] {
         &self.tokens
     }
 }
 
-impl Deref for TokenStream {
-    type Target = Vec<Token>;
+impl<'src> Deref for TokenStream<'src> {
+    type Target = Vec<Token<'src>>;
 
     fn deref(&self) -> &Self::Target {
         &self.tokens
     }
 }
 
-impl DerefMut for TokenStream {
+impl DerefMut for TokenStream<'_> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.tokens
     }
 }
 
-impl IntoIterator for TokenStream {
-    type Item = Token;
+impl<'src> IntoIterator for TokenStream<'src> {
+    type Item = Token<'src>;
     type IntoIter = std::vec::IntoIter<Self::Item>;
 
     fn into_iter(self) -> Self::IntoIter {
@@ -208,9 +243,9 @@ impl IntoIterator for TokenStream {
     }
 }
 
-impl<'a> IntoIterator for &'a TokenStream {
-    type Item = &'a Token;
-    type IntoIter = std::slice::Iter<'a, Token>;
+impl<'a, 'src> IntoIterator for &'a TokenStream<'src> {
+    type Item = &'a Token<'src>;
+    type IntoIter = std::slice::Iter<'a, Token<'src>>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.tokens.iter()
@@ -220,4 +255,26 @@ impl<'a> IntoIterator for &'a TokenStream {
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn test_add_token() {
+        let whitespace = Token::new(TokenType::Whitespace, " ", 0, 1);
+
+        let mut tokens = TokenStream::new();
+        tokens.add_token(whitespace.clone());
+        assert!(tokens.is_empty());
+
+        let mut tokens = TokenStream::lossless();
+        tokens.add_token(whitespace.clone());
+        assert_eq!(tokens.as_ref(), [whitespace]);
+    }
+
+    #[test]
+    fn test_token_span() {
+        let token = Token::new(TokenType::DoubleLeftBrace, "{{", 4, 1);
+        assert_eq!(token.span, Span::new(4, 6));
+
+        let eof = Token::eof(10, 2);
+        assert_eq!(eof.span, Span::new(10, 10));
+    }
 }
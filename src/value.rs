@@ -0,0 +1,548 @@
+use crate::date::{Date, DateTime, Time};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A value in a template context, modelled on the Python values Django templates see.
+/// Maps keep their insertion order, like Python dicts.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub enum Value {
+    #[default]
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    /// A string that is already safe to include in HTML, like Django's `SafeString`.
+    SafeString(String),
+    List(Vec<Value>),
+    Map(Vec<(String, Value)>),
+    Date(Date),
+    Time(Time),
+    DateTime(DateTime),
+}
+
+impl Value {
+    /// A map with `entries` in order.
+    pub fn map<K, V>(entries: impl IntoIterator<Item = (K, V)>) -> Value
+    where
+        K: Into<String>,
+        V: Into<Value>,
+    {
+        Value::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+        )
+    }
+
+    /// Text that is output as it is, without escaping, like Django's `mark_safe`.
+    pub fn safe(text: impl Into<String>) -> Value {
+        Value::SafeString(text.into())
+    }
+
+    /// Python truthiness: `None`, `False`, zero and empty strings, lists and maps are
+    /// false.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::None => false,
+            Value::Bool(value) => *value,
+            Value::Int(value) => *value != 0,
+            Value::Float(value) => *value != 0.0,
+            Value::Str(text) | Value::SafeString(text) => !text.is_empty(),
+            Value::List(items) => !items.is_empty(),
+            Value::Map(entries) => !entries.is_empty(),
+            Value::Date(_) | Value::Time(_) | Value::DateTime(_) => true,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(text) | Value::SafeString(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The value looked up from a map by key.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries
+                .iter()
+                .find_map(|(name, value)| (name == key).then_some(value)),
+            _ => None,
+        }
+    }
+
+    /// One part of a dotted variable, e.g. the `name` of `user.name`, looked up the way
+    /// Django does: as a dictionary key, then as an attribute, then as a list index.
+    /// Maps have the `items`, `keys` and `values` attributes.
+    pub fn lookup(&self, bit: &str) -> Option<Value> {
+        if let Some(value) = self.get(bit) {
+            return Some(value.clone());
+        }
+        if let Value::Map(entries) = self {
+            let attribute = match bit {
+                "items" => entries
+                    .iter()
+                    .map(|(key, value)| Value::List(vec![Value::from(key.as_str()), value.clone()]))
+                    .collect(),
+                "keys" => entries
+                    .iter()
+                    .map(|(key, _)| Value::from(key.as_str()))
+                    .collect(),
+                "values" => entries.iter().map(|(_, value)| value.clone()).collect(),
+                _ => return None,
+            };
+            return Some(Value::List(attribute));
+        }
+        let index: i64 = bit.parse().ok()?;
+        match self {
+            Value::List(items) => {
+                python_index(items.len(), index).map(|index| items[index].clone())
+            }
+            Value::Str(text) | Value::SafeString(text) => {
+                let index = python_index(text.chars().count(), index)?;
+                text.chars().nth(index).map(Value::from)
+            }
+            _ => None,
+        }
+    }
+
+    /// The items a `{% for %}` loop visits: a list's items, a map's keys or a string's
+    /// characters. Other values are not iterable.
+    pub fn iter_items(&self) -> Option<Vec<Value>> {
+        match self {
+            Value::List(items) => Some(items.clone()),
+            Value::Map(entries) => Some(
+                entries
+                    .iter()
+                    .map(|(key, _)| Value::from(key.as_str()))
+                    .collect(),
+            ),
+            Value::Str(text) | Value::SafeString(text) => {
+                Some(text.chars().map(Value::from).collect())
+            }
+            _ => None,
+        }
+    }
+
+    /// Python's `in` operator with `self` on the right, or `None` where Python raises a
+    /// `TypeError`.
+    pub fn contains(&self, item: &Value) -> Option<bool> {
+        match self {
+            Value::Str(text) | Value::SafeString(text) => Some(text.contains(item.as_str()?)),
+            Value::List(items) => Some(items.iter().any(|value| value == item)),
+            Value::Map(_) => Some(item.as_str().is_some_and(|key| self.get(key).is_some())),
+            _ => None,
+        }
+    }
+
+    /// The name of the Python type the value stands for, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::None => "NoneType",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "str",
+            Value::SafeString(_) => "SafeString",
+            Value::List(_) => "list",
+            Value::Map(_) => "dict",
+            Value::Date(_) => "date",
+            Value::Time(_) => "time",
+            Value::DateTime(_) => "datetime",
+        }
+    }
+
+    /// Python's `float()` of the value.
+    pub(crate) fn to_float(&self) -> Option<f64> {
+        match self {
+            Value::Str(text) | Value::SafeString(text) => text.trim().parse().ok(),
+            value => value.number(),
+        }
+    }
+
+    /// Python's `int()` of the value, which truncates floats.
+    pub(crate) fn to_int(&self) -> Option<i64> {
+        match self {
+            Value::Str(text) | Value::SafeString(text) => text.trim().parse().ok(),
+            Value::Float(value) if value.is_finite() => Some(value.trunc() as i64),
+            Value::Float(_) => None,
+            value => value.number().map(|number| number as i64),
+        }
+    }
+
+    /// The value as a Python number, with `True` and `False` counting as 1 and 0.
+    fn number(&self) -> Option<f64> {
+        match self {
+            Value::Bool(value) => Some(f64::from(u8::from(*value))),
+            Value::Int(value) => Some(*value as f64),
+            Value::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The Python `repr()` of the value, used for strings inside lists and maps.
+    pub fn repr(&self) -> String {
+        match self {
+            Value::Str(text) | Value::SafeString(text) => {
+                let quote = if text.contains('\'') && !text.contains('"') {
+                    '"'
+                } else {
+                    '\''
+                };
+                let mut repr = String::from(quote);
+                for c in text.chars() {
+                    match c {
+                        '\\' => repr.push_str("\\\\"),
+                        '\n' => repr.push_str("\\n"),
+                        '\r' => repr.push_str("\\r"),
+                        '\t' => repr.push_str("\\t"),
+                        c if c == quote => {
+                            repr.push('\\');
+                            repr.push(c);
+                        }
+                        c => repr.push(c),
+                    }
+                }
+                repr.push(quote);
+                repr
+            }
+            Value::Date(date) => format!("datetime.date{}", constructor_args(Some(date), None)),
+            Value::Time(time) => format!("datetime.time{}", constructor_args(None, Some(time))),
+            Value::DateTime(datetime) => format!(
+                "datetime.datetime{}",
+                constructor_args(Some(&datetime.date), Some(&datetime.time))
+            ),
+            value => value.to_string(),
+        }
+    }
+}
+
+/// The arguments of a Python `date`, `time` or `datetime` constructor, which leave out
+/// trailing zero seconds and microseconds.
+fn constructor_args(date: Option<&Date>, time: Option<&Time>) -> String {
+    let mut parts = Vec::new();
+    if let Some(date) = date {
+        parts.extend([
+            date.year().to_string(),
+            date.month().to_string(),
+            date.day().to_string(),
+        ]);
+    }
+    if let Some(time) = time {
+        parts.extend([time.hour().to_string(), time.minute().to_string()]);
+        if time.second() > 0 || time.microsecond() > 0 {
+            parts.push(time.second().to_string());
+        }
+        if time.microsecond() > 0 {
+            parts.push(time.microsecond().to_string());
+        }
+    }
+    format!("({})", parts.join(", "))
+}
+
+/// The index of a possibly negative Python index into a sequence of `len` items.
+fn python_index(len: usize, index: i64) -> Option<usize> {
+    let index = if index < 0 {
+        len.checked_sub(usize::try_from(index.unsigned_abs()).ok()?)?
+    } else {
+        usize::try_from(index).ok()?
+    };
+    (index < len).then_some(index)
+}
+
+/// Python's `str()` of a float: the shortest representation that round trips, with
+/// exponents outside `1e-4..1e16`.
+fn format_float(value: f64) -> String {
+    if value.is_nan() {
+        return "nan".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    let magnitude = value.abs();
+    if magnitude != 0.0 && !(1e-4..1e16).contains(&magnitude) {
+        let scientific = format!("{:e}", value);
+        let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+        let (sign, digits) = match exponent.strip_prefix('-') {
+            Some(digits) => ('-', digits),
+            None => ('+', exponent),
+        };
+        return format!("{}e{}{:0>2}", mantissa, sign, digits);
+    }
+    let text = value.to_string();
+    if text.contains('.') {
+        text
+    } else {
+        format!("{}.0", text)
+    }
+}
+
+impl fmt::Display for Value {
+    /// Python's `str()` of the value, which is how Django prints it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::None => write!(f, "None"),
+            Value::Bool(true) => write!(f, "True"),
+            Value::Bool(false) => write!(f, "False"),
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", format_float(*value)),
+            Value::Str(text) | Value::SafeString(text) => write!(f, "{}", text),
+            Value::Date(date) => write!(f, "{}", date),
+            Value::Time(time) => write!(f, "{}", time),
+            Value::DateTime(datetime) => write!(f, "{}", datetime),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item.repr())?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(entries) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in entries.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", Value::from(key.as_str()).repr(), value.repr())?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Python equality: numbers compare by value whatever their type, strings compare
+/// whether or not they are safe, and maps compare regardless of order.
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::None, Value::None) => true,
+            (Value::Date(left), Value::Date(right)) => left == right,
+            (Value::Time(left), Value::Time(right)) => left == right,
+            (Value::DateTime(left), Value::DateTime(right)) => left == right,
+            (Value::List(left), Value::List(right)) => left == right,
+            (Value::Map(left), Value::Map(right)) => {
+                left.len() == right.len()
+                    && left
+                        .iter()
+                        .all(|(key, value)| other.get(key) == Some(value))
+            }
+            _ => match (self.as_str(), other.as_str()) {
+                (Some(left), Some(right)) => left == right,
+                _ => {
+                    matches!((self.number(), other.number()), (Some(left), Some(right)) if left == right)
+                }
+            },
+        }
+    }
+}
+
+/// Python ordering, which only exists between numbers, between strings, between lists
+/// and between dates or times of the same kind.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Date(left), Value::Date(right)) => return Some(left.cmp(right)),
+            (Value::Time(left), Value::Time(right)) => return Some(left.cmp(right)),
+            (Value::DateTime(left), Value::DateTime(right)) => return Some(left.cmp(right)),
+            _ => {}
+        }
+        if let (Some(left), Some(right)) = (self.as_str(), other.as_str()) {
+            return Some(left.cmp(right));
+        }
+        if let (Value::List(left), Value::List(right)) = (self, other) {
+            for (left, right) in left.iter().zip(right) {
+                if left != right {
+                    return left.partial_cmp(right);
+                }
+            }
+            return Some(left.len().cmp(&right.len()));
+        }
+        self.number()?.partial_cmp(&other.number()?)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        i64::try_from(value).map_or(Value::Float(value as f64), Value::Int)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<char> for Value {
+    fn from(value: char) -> Self {
+        Value::Str(value.to_string())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Str(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Str(value)
+    }
+}
+
+impl From<Date> for Value {
+    fn from(value: Date) -> Self {
+        Value::Date(value)
+    }
+}
+
+impl From<Time> for Value {
+    fn from(value: Time) -> Self {
+        Value::Time(value)
+    }
+}
+
+impl From<DateTime> for Value {
+    fn from(value: DateTime) -> Self {
+        Value::DateTime(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::None, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Self {
+        Value::List(items.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<K: Into<String>, V: Into<Value>> From<BTreeMap<K, V>> for Value {
+    fn from(entries: BTreeMap<K, V>) -> Self {
+        Value::map(entries)
+    }
+}
+
+impl<T: Into<Value>> FromIterator<T> for Value {
+    fn from_iter<I: IntoIterator<Item = T>>(items: I) -> Self {
+        Value::List(items.into_iter().map(Into::into).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let test_cases = vec![
+            (Value::None, "None"),
+            (Value::Bool(true), "True"),
+            (Value::Int(-3), "-3"),
+            (Value::Float(1.0), "1.0"),
+            (Value::Float(0.1), "0.1"),
+            (Value::Float(1.5e-7), "1.5e-07"),
+            (Value::Float(1e16), "1e+16"),
+            (Value::Float(f64::NAN), "nan"),
+            (Value::from("a"), "a"),
+            (
+                Value::from(vec![Value::from("a'b"), Value::Int(1), Value::None]),
+                "[\"a'b\", 1, None]",
+            ),
+            (
+                Value::map([("a", Value::from("x\ny")), ("b", Value::Float(2.5))]),
+                "{'a': 'x\\ny', 'b': 2.5}",
+            ),
+            (Value::from(Date::new(2024, 1, 5).unwrap()), "2024-01-05"),
+            (
+                Value::from(vec![Value::from(DateTime::new(
+                    Date::new(2024, 1, 5).unwrap(),
+                    Time::new(9, 30, 0).unwrap(),
+                ))]),
+                "[datetime.datetime(2024, 1, 5, 9, 30)]",
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            println!("Testing input: {:?}", input);
+            assert_eq!(input.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let user = Value::map([
+            ("name", Value::from("Ada")),
+            ("roles", Value::from(vec!["admin", "staff"])),
+        ]);
+        let shadowed = Value::map([("items", "mine")]);
+        let roles = Value::from(vec!["admin", "staff"]);
+        let name = Value::from("Ada");
+
+        let test_cases = vec![
+            (&user, "name", Some(Value::from("Ada"))),
+            (&user, "email", None),
+            (&user, "keys", Some(Value::from(vec!["name", "roles"]))),
+            (&shadowed, "items", Some(Value::from("mine"))),
+            (&roles, "0", Some(Value::from("admin"))),
+            (&roles, "-1", Some(Value::from("staff"))),
+            (&roles, "2", None),
+            (&name, "1", Some(Value::from("d"))),
+            (&name, "upper", None),
+        ];
+
+        for (value, bit, expected) in test_cases {
+            println!("Testing input: {:?}", (value, bit));
+            assert_eq!(value.lookup(bit), expected);
+        }
+    }
+
+    #[test]
+    fn test_python_semantics() {
+        assert_eq!(Value::Int(1), Value::Float(1.0));
+        assert_eq!(Value::Bool(true), Value::Int(1));
+        assert_eq!(Value::from("a"), Value::safe("a"));
+        assert_ne!(Value::from("1"), Value::Int(1));
+        assert_eq!(
+            Value::map([("a", 1), ("b", 2)]),
+            Value::map([("b", 2), ("a", 1)])
+        );
+        assert!(Value::Int(2) > Value::Float(1.5));
+        assert!(Value::from("b") > Value::from("abc"));
+        assert_eq!(Value::from("a").partial_cmp(&Value::Int(1)), None);
+        assert!(!Value::from(Vec::<Value>::new()).is_truthy());
+        assert!(Value::from("0").is_truthy());
+        assert_eq!(Value::from("abc").contains(&Value::from("bc")), Some(true));
+        assert_eq!(Value::Int(1).contains(&Value::Int(1)), None);
+    }
+}
@@ -0,0 +1,72 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_django-template-ast"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_exit_status() {
+    let test_cases = vec![
+        (vec!["check"], "Hello, {{ name }}!", 0),
+        (vec!["check", "--color", "never"], "{% if a %}", 1),
+        (vec!["fmt", "--check"], "{{ name }}", 0),
+        (vec!["fmt", "--check"], "{{name}}", 1),
+        (vec!["lint"], "", 2),
+        (vec!["check", "missing.html"], "", 2),
+    ];
+
+    for (args, stdin, status) in test_cases {
+        println!("Testing args: {:?}", args);
+
+        let output = run(&args, stdin);
+        assert_eq!(output.status.code(), Some(status));
+    }
+}
+
+/// The number of templates the tool finds under `directory`, by the extensions it
+/// searches for.
+fn count_templates(directory: &Path) -> usize {
+    const TEMPLATE_EXTENSIONS: &[&str] = &["html", "htm", "txt", "xml", "djhtml"];
+
+    fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .map(
+            |path| match path.extension().and_then(|extension| extension.to_str()) {
+                _ if path.is_dir() => count_templates(&path),
+                Some(extension) if TEMPLATE_EXTENSIONS.contains(&extension) => 1,
+                _ => 0,
+            },
+        )
+        .sum()
+}
+
+#[test]
+fn test_check_fixtures() {
+    let output = run(&["check", "tests/fixtures"], "");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    let templates = count_templates(Path::new("tests/fixtures"));
+    assert!(output.status.success(), "{}", stderr);
+    assert!(
+        stderr.starts_with(&format!("checked {} templates: 0 errors", templates)),
+        "{}",
+        stderr
+    );
+}
@@ -1,7 +1,9 @@
-use django_template_ast::compile;
+use django_template_ast::{
+    compile, compile_with_registry, parse, parse_resilient, render, tokenize, ArgSpec, Context,
+    Error, Node, RenderError, Renderer, TagRegistry, TagSpec, TokenType, Value,
+};
 
 #[test]
-#[should_panic]
 fn test_empty_template() {
     let result = compile("");
     assert!(result.is_ok());
@@ -9,23 +11,39 @@ fn test_empty_template() {
 }
 
 #[test]
-#[should_panic]
 fn test_simple_template() {
-    let result = compile("Hello, {{ name }}!");
-    assert!(result.is_ok());
-    // You'll need to adjust this expected output based on your actual implementation
-    assert_eq!(result.unwrap(), "Hello, {{ name }}!");
+    let ast = parse("Hello, {{ name }}!").unwrap();
+
+    let mut context = Context::new();
+    context.insert("name", "World");
+    assert_eq!(render(&ast, &context).unwrap(), "Hello, World!");
+
+    context.insert("name", "<b>World</b>");
+    assert_eq!(
+        render(&ast, &context).unwrap(),
+        "Hello, &lt;b&gt;World&lt;/b&gt;!"
+    );
 }
 
 #[test]
-#[should_panic]
 fn test_invalid_template() {
-    let result = compile("{% invalid %}");
-    assert!(result.is_err());
+    let result = compile("{% if user %}Hello");
+    assert!(matches!(
+        result,
+        Err(Error::Diagnostic(ref diagnostic)) if diagnostic.code == "E102"
+    ));
+
+    // Unknown tags may come from a library the parser has no spec for, so they only
+    // fail once rendered
+    let ast = parse("{% invalid %}").unwrap();
+    let result = render(&ast, &Context::new());
+    assert!(matches!(
+        result,
+        Err(Error::Render(RenderError::UnsupportedTag { ref name, line: 1 })) if name == "invalid"
+    ));
 }
 
 #[test]
-#[should_panic]
 fn test_complex_template() {
     let template = r#"
         {% if user.is_authenticated %}
@@ -34,12 +52,159 @@ fn test_complex_template() {
             Please log in.
         {% endif %}
     "#;
-    let result = compile(template);
-    assert!(result.is_ok());
+    let ast = parse(template).unwrap();
+
+    let mut context = Context::new();
+    context.insert(
+        "user",
+        Value::map([
+            ("name", Value::from("Ada")),
+            ("is_authenticated", Value::from(true)),
+        ]),
+    );
+    assert_eq!(render(&ast, &context).unwrap().trim(), "Hello, Ada!");
+    assert_eq!(
+        render(&ast, &Context::new()).unwrap().trim(),
+        "Please log in."
+    );
+}
+
+#[test]
+fn test_unicode_templates() {
+    let fixtures = [
+        include_str!("fixtures/unicode/accents.html"),
+        include_str!("fixtures/unicode/cjk.html"),
+        include_str!("fixtures/unicode/emoji.html"),
+        include_str!("fixtures/unicode/mixed.html"),
+    ];
 
-    if let Ok(compiled) = result {
-        assert!(compiled.contains("Hello") && compiled.contains("Please log in"));
-    } else {
-        panic!("Compilation failed unexpectedly");
+    for fixture in fixtures {
+        let result = compile(fixture);
+        assert!(result.is_ok(), "Failed to compile: {}", fixture);
     }
 }
+
+#[test]
+fn test_custom_block_tags() {
+    let template = "{% cache 500 sidebar %}{{ items }}{% endcache %}";
+    let mut registry = TagRegistry::builtins();
+
+    // Without a spec both tags are standalone tags
+    let ast = parse(template).unwrap();
+    assert!(matches!(
+        ast.nodes[..],
+        [Node::Tag(_), Node::Variable(_), Node::Tag(_)]
+    ));
+
+    registry.register(TagSpec::block("cache").with_args(vec![
+        ArgSpec::Value("timeout".to_string()),
+        ArgSpec::VarArgs("fragment".to_string()),
+    ]));
+    let result = compile_with_registry(template, &registry);
+    assert_eq!(result.unwrap(), template);
+}
+
+#[test]
+fn test_parse_resilient() {
+    let template = "{% if user %}\n  Hello, {{ user.name\n{% endfor %}";
+    let (ast, diagnostics) = parse_resilient(template);
+
+    assert_eq!(ast.to_string(), template);
+    assert_eq!(diagnostics.len(), 3);
+}
+
+#[test]
+fn test_tokenize() {
+    let tokens = tokenize("Hi {{ name }}").unwrap();
+    let types: Vec<TokenType> = tokens.iter().map(|token| token.token_type).collect();
+
+    assert_eq!(
+        types,
+        vec![
+            TokenType::Text,
+            TokenType::DoubleLeftBrace,
+            TokenType::Identifier,
+            TokenType::DoubleRightBrace,
+            TokenType::Eof,
+        ]
+    );
+    assert_eq!(tokens[2].lexeme, "name");
+}
+
+#[test]
+fn test_parse() {
+    let template = "<p>{% if user %}\n  Hello, {{ user.name }}!\n{% endif %}</p>";
+    let ast = parse(template).unwrap();
+    assert_eq!(ast.to_string(), template);
+
+    let error = parse("{% if user %}").unwrap_err();
+    assert!(matches!(error, Error::Diagnostic(ref diagnostic) if diagnostic.code == "E102"));
+    assert_eq!(error.to_string(), "error[E102]: unclosed block tag 'if'");
+}
+
+#[test]
+fn test_render() {
+    let template = "\
+{% if user.is_authenticated %}Hello, {{ user.name }}!{% else %}Please log in.{% endif %}
+{% for item in order.items %}{{ forloop.counter }}. {{ item.title }} x{{ item.quantity }}
+{% empty %}Your basket is empty.
+{% endfor %}";
+    let ast = parse(template).unwrap();
+
+    let mut context = Context::new();
+    context.insert(
+        "user",
+        Value::map([
+            ("name", Value::from("Ada")),
+            ("is_authenticated", Value::from(true)),
+        ]),
+    );
+    context.insert(
+        "order",
+        Value::map([(
+            "items",
+            vec![
+                Value::map([("title", Value::from("Tea")), ("quantity", Value::from(2))]),
+                Value::map([("title", Value::from("Cake")), ("quantity", Value::from(1))]),
+            ],
+        )]),
+    );
+    assert_eq!(
+        render(&ast, &context).unwrap(),
+        "Hello, Ada!\n1. Tea x2\n2. Cake x1\n"
+    );
+    assert_eq!(
+        render(&ast, &Context::new()).unwrap(),
+        "Please log in.\nYour basket is empty.\n"
+    );
+
+    let ast = parse("{% include 'footer.html' %}").unwrap();
+    assert!(matches!(
+        render(&ast, &Context::new()),
+        Err(Error::Render(RenderError::UnsupportedTag { .. }))
+    ));
+}
+
+#[test]
+fn test_compile_error_diagnostic() {
+    let template = "<p>\n{% if user %}\n  Hello\n";
+    let Err(Error::Diagnostic(diagnostic)) = compile(template) else {
+        panic!("Expected a diagnostic for: {}", template);
+    };
+
+    assert_eq!(diagnostic.code, "E102");
+    assert_eq!(
+        Renderer::plain().render(&diagnostic, template, "page.html"),
+        "\
+error[E102]: unclosed block tag 'if'
+ --> page.html:3:8
+  |
+2 | {% if user %}
+  | ------------- 'if' opened here
+3 |   Hello
+  |        ^ expected the end tag here
+  |
+  = help: add the matching end tag
+"
+    );
+}
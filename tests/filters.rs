@@ -0,0 +1,53 @@
+use django_template_ast::{parse, render, Context, Date, DateTime, Time, Value};
+use serde_json::Value as Json;
+
+fn to_value(json: &Json) -> Value {
+    match json {
+        Json::Null => Value::None,
+        Json::Bool(value) => Value::from(*value),
+        Json::Number(number) => match number.as_i64() {
+            Some(number) => Value::from(number),
+            None => Value::from(number.as_f64().unwrap()),
+        },
+        Json::String(text) => Value::from(text.as_str()),
+        Json::Array(items) => items.iter().map(to_value).collect(),
+        Json::Object(entries) => Value::map(
+            entries
+                .iter()
+                .map(|(key, value)| (key.as_str(), to_value(value))),
+        ),
+    }
+}
+
+fn datetime(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime {
+    DateTime::new(
+        Date::new(year, month, day).unwrap(),
+        Time::new(hour, minute, 0).unwrap(),
+    )
+}
+
+/// Templates using Django's builtin filters, and what Django renders them as, taken
+/// from Django's documentation and test suite. Every context also has the dates `d`,
+/// `dt`, `t` and `earlier`.
+#[test]
+fn test_django_fixtures() {
+    let fixtures: Json = serde_json::from_str(include_str!("fixtures/filters.json")).unwrap();
+
+    for fixture in fixtures.as_array().unwrap() {
+        let template = fixture["template"].as_str().unwrap();
+        println!("Testing input: {:?}", template);
+        let mut context = Context::new();
+        context.insert("d", Date::new(2024, 1, 5).unwrap());
+        context.insert("dt", datetime(2024, 1, 5, 9, 30));
+        context.insert("t", Time::new(13, 5, 7).unwrap());
+        context.insert("earlier", datetime(2024, 1, 1, 3, 0));
+        for (name, value) in fixture["context"].as_object().unwrap() {
+            context.insert(name.as_str(), to_value(value));
+        }
+        let ast = parse(template).unwrap();
+        assert_eq!(
+            render(&ast, &context).unwrap(),
+            fixture["output"].as_str().unwrap()
+        );
+    }
+}
@@ -0,0 +1,337 @@
+use lsp_server::{Connection, Message, Notification, Request, RequestId};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidOpenTextDocument, Exit, Initialized, Notification as _,
+    PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, FoldingRangeRequest, GotoDefinition, HoverRequest,
+    Initialize, Request as _, Shutdown,
+};
+use lsp_types::{PublishDiagnosticsParams, Url};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread::{self, JoinHandle};
+
+/// An editor talking to a server running on another thread.
+struct Client {
+    connection: Connection,
+    server: Option<JoinHandle<()>>,
+    next_id: i32,
+}
+
+impl Client {
+    fn start(root: Option<&PathBuf>) -> Self {
+        let (server, connection) = Connection::memory();
+        let server = thread::spawn(move || django_template_ast::lsp::run(server).unwrap());
+        let mut client = Client {
+            connection,
+            server: Some(server),
+            next_id: 0,
+        };
+        let root_uri = root.map(|root| Url::from_directory_path(root).unwrap());
+        let result = client.request(
+            Initialize::METHOD,
+            json!({"capabilities": {}, "rootUri": root_uri}),
+        );
+        assert!(result["capabilities"]["hoverProvider"].as_bool().unwrap());
+        client.notify(Initialized::METHOD, json!({}));
+        client
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = RequestId::from(self.next_id);
+        let request = Request::new(id.clone(), method.to_string(), params);
+        self.connection.sender.send(request.into()).unwrap();
+        loop {
+            match self.connection.receiver.recv().unwrap() {
+                Message::Response(response) if response.id == id => {
+                    assert!(response.error.is_none(), "{:?}", response.error);
+                    return response.result.unwrap_or(Value::Null);
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    fn notify(&self, method: &str, params: Value) {
+        let notification = Notification::new(method.to_string(), params);
+        self.connection.sender.send(notification.into()).unwrap();
+    }
+
+    /// Open a document and return the diagnostics published for it.
+    fn open(&self, uri: &Url, text: &str) -> PublishDiagnosticsParams {
+        self.notify(
+            DidOpenTextDocument::METHOD,
+            json!({"textDocument": {
+                "uri": uri, "languageId": "django-html", "version": 1, "text": text,
+            }}),
+        );
+        self.diagnostics()
+    }
+
+    /// Change a document and return the diagnostics published for it.
+    fn change(&self, uri: &Url, changes: Value) -> PublishDiagnosticsParams {
+        self.notify(
+            DidChangeTextDocument::METHOD,
+            json!({"textDocument": {"uri": uri, "version": 2}, "contentChanges": changes}),
+        );
+        self.diagnostics()
+    }
+
+    fn diagnostics(&self) -> PublishDiagnosticsParams {
+        loop {
+            match self.connection.receiver.recv().unwrap() {
+                Message::Notification(notification)
+                    if notification.method == PublishDiagnostics::METHOD =>
+                {
+                    return serde_json::from_value(notification.params).unwrap();
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    fn at(&mut self, method: &str, uri: &Url, line: u32, character: u32) -> Value {
+        self.request(
+            method,
+            json!({
+                "textDocument": {"uri": uri},
+                "position": {"line": line, "character": character},
+            }),
+        )
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.request(Shutdown::METHOD, Value::Null);
+        self.notify(Exit::METHOD, Value::Null);
+        if let Some(server) = self.server.take() {
+            server.join().unwrap();
+        }
+    }
+}
+
+fn uri() -> Url {
+    Url::parse("file:///project/templates/page.html").unwrap()
+}
+
+fn labels(items: &Value) -> Vec<&str> {
+    items
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn test_diagnostics() {
+    let client = Client::start(None);
+
+    let published = client.open(&uri(), "<p>\n{% if a %}{{ b\n</p>");
+    let codes: Vec<_> = published
+        .diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.code.clone().unwrap())
+        .collect();
+    assert!(!codes.is_empty());
+    let first = &published.diagnostics[0];
+    assert_eq!(first.source.as_deref(), Some("django-template-ast"));
+    assert_eq!(first.range.start.line, 1);
+
+    let published = client.open(&uri(), "{% if a %}{{ b }}{% endif %}");
+    assert_eq!(published.diagnostics, vec![]);
+}
+
+#[test]
+fn test_incremental_changes() {
+    let mut client = Client::start(None);
+    client.open(&uri(), "<p>\n{% if a %}{{ b }}{% endif %}\n</p>");
+
+    let published = client.change(
+        &uri(),
+        json!([
+            {"range": {"start": {"line": 1, "character": 17},
+                       "end": {"line": 1, "character": 28}}, "text": ""},
+            {"range": {"start": {"line": 1, "character": 6},
+                       "end": {"line": 1, "character": 7}}, "text": "é|upper"},
+        ]),
+    );
+    assert_eq!(published.diagnostics.len(), 1);
+    // The unclosed block is reported at the end, and points back at the opening tag
+    let unclosed = &published.diagnostics[0];
+    assert_eq!(unclosed.range.start.line, 2);
+    let related = unclosed.related_information.as_ref().unwrap();
+    assert_eq!(related[0].message, "'if' opened here");
+    assert_eq!(related[0].location.range.start.line, 1);
+    let hover = client.at(HoverRequest::METHOD, &uri(), 1, 10);
+    assert!(hover["contents"]["value"]
+        .as_str()
+        .unwrap()
+        .starts_with("`|upper`"));
+
+    let published = client.change(&uri(), json!([{"text": "{{ c }}"}]));
+    assert_eq!(published.diagnostics, vec![]);
+}
+
+#[test]
+fn test_document_symbols() {
+    let mut client = Client::start(None);
+    let source = "{% block outer %}\n{% block inner %}{% endblock %}\n{% endblock %}\n\
+                  {% partialdef card %}{% endpartialdef %}";
+    client.open(&uri(), source);
+
+    let symbols = client.request(
+        DocumentSymbolRequest::METHOD,
+        json!({"textDocument": {"uri": uri()}}),
+    );
+    assert_eq!(symbols[0]["name"], "outer");
+    assert_eq!(symbols[0]["range"]["end"]["line"], 2);
+    assert_eq!(symbols[0]["children"][0]["name"], "inner");
+    assert_eq!(symbols[1]["name"], "card");
+    assert_eq!(symbols.as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn test_folding_ranges() {
+    let mut client = Client::start(None);
+    client.open(
+        &uri(),
+        "{% if a %}\nb\nc\n{% endif %}\n{% comment %}\nd\n{% endcomment %}",
+    );
+
+    let ranges = client.request(
+        FoldingRangeRequest::METHOD,
+        json!({"textDocument": {"uri": uri()}}),
+    );
+    assert_eq!(
+        ranges,
+        json!([
+            {"startLine": 0, "endLine": 2, "kind": "region"},
+            {"startLine": 4, "endLine": 5, "kind": "region"},
+        ])
+    );
+}
+
+#[test]
+fn test_hover() {
+    let mut client = Client::start(None);
+    client.open(&uri(), "{% for x in items %}{{ x|upper }}{% endfor %}");
+
+    let hover = client.at(HoverRequest::METHOD, &uri(), 0, 4);
+    assert!(hover["contents"]["value"]
+        .as_str()
+        .unwrap()
+        .starts_with("`{% for %}`"));
+    let hover = client.at(HoverRequest::METHOD, &uri(), 0, 27);
+    assert!(hover["contents"]["value"]
+        .as_str()
+        .unwrap()
+        .starts_with("`|upper`"));
+    assert_eq!(hover["range"]["start"]["character"], 25);
+    let hover = client.at(HoverRequest::METHOD, &uri(), 0, 38);
+    assert!(hover["contents"]["value"]
+        .as_str()
+        .unwrap()
+        .starts_with("`{% for %}`"));
+    assert_eq!(client.at(HoverRequest::METHOD, &uri(), 0, 12), Value::Null);
+}
+
+#[test]
+fn test_definition() {
+    let root = std::env::temp_dir().join(format!("django-template-lsp-{}", std::process::id()));
+    let templates = root.join("app").join("templates");
+    fs::create_dir_all(templates.join("partials")).unwrap();
+    fs::write(
+        templates.join("base.html"),
+        "{% block content %}{% endblock %}",
+    )
+    .unwrap();
+    fs::write(templates.join("partials").join("nav.html"), "<nav></nav>").unwrap();
+    let page = Url::from_file_path(root.join("page.html")).unwrap();
+
+    let mut client = Client::start(Some(&root));
+    client.open(
+        &page,
+        "{% extends \"base.html\" %}\n{% include 'partials/nav.html' %}\n{% include missing %}",
+    );
+    let base = client.at(GotoDefinition::METHOD, &page, 0, 14);
+    let nav = client.at(GotoDefinition::METHOD, &page, 1, 3);
+    let missing = client.at(GotoDefinition::METHOD, &page, 2, 3);
+    drop(client);
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(
+        base["uri"],
+        json!(Url::from_file_path(templates.join("base.html")).unwrap())
+    );
+    assert_eq!(
+        nav["uri"],
+        json!(Url::from_file_path(templates.join("partials").join("nav.html")).unwrap())
+    );
+    assert_eq!(missing, Value::Null);
+}
+
+#[test]
+fn test_completion() {
+    let mut client = Client::start(None);
+    client.open(
+        &uri(),
+        "{% load humanize static %}\n{% st\n{{ a|int }}\n{% load  %}",
+    );
+
+    let tags = client.at(Completion::METHOD, &uri(), 1, 5);
+    let tags = labels(&tags);
+    assert!(tags.contains(&"for"));
+    assert!(tags.contains(&"static"));
+    assert!(!tags.contains(&"trans"));
+
+    let filters = client.at(Completion::METHOD, &uri(), 2, 8);
+    let filters = labels(&filters);
+    assert!(filters.contains(&"upper"));
+    assert!(filters.contains(&"intcomma"));
+    assert!(!filters.contains(&"for"));
+
+    let libraries = client.at(Completion::METHOD, &uri(), 3, 8);
+    assert!(labels(&libraries).contains(&"i18n"));
+    assert_eq!(client.at(Completion::METHOD, &uri(), 0, 1), Value::Null);
+}
+
+#[test]
+fn test_binary() {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_django-template-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = server.stdin.take().unwrap();
+    let mut stdout = BufReader::new(server.stdout.take().unwrap());
+    let messages: Vec<Message> = vec![
+        Request::new(
+            1.into(),
+            Initialize::METHOD.to_string(),
+            json!({"capabilities": {}}),
+        )
+        .into(),
+        Notification::new(Initialized::METHOD.to_string(), json!({})).into(),
+        Request::new(2.into(), Shutdown::METHOD.to_string(), Value::Null).into(),
+        Notification::new(Exit::METHOD.to_string(), Value::Null).into(),
+    ];
+    for message in messages {
+        message.write(&mut stdin).unwrap();
+    }
+
+    let Some(Message::Response(response)) = Message::read(&mut stdout).unwrap() else {
+        panic!("Expected a response to initialize");
+    };
+    assert_eq!(response.id, 1.into());
+    assert!(response.result.unwrap()["capabilities"]["hoverProvider"]
+        .as_bool()
+        .unwrap());
+    assert!(server.wait().unwrap().success());
+}
@@ -0,0 +1,64 @@
+"""Smoke tests for the Python bindings. Build them with `maturin develop` first."""
+
+import pytest
+
+import django_template_ast as dta
+
+
+def test_tokenize():
+    tokens = dta.tokenize("{{ name }}")
+    assert [token.kind for token in tokens] == [
+        "DoubleLeftBrace",
+        "Identifier",
+        "DoubleRightBrace",
+        "Eof",
+    ]
+    assert (tokens[1].lexeme, tokens[1].start, tokens[1].end) == ("name", 3, 7)
+    assert len(dta.tokenize("{{ name }}", lossless=True)) == 6
+
+
+def test_offsets_are_code_points():
+    source = "café {{ 名前 }}\n🎉 %}"
+    tokens = dta.tokenize(source)
+    assert [source[token.start : token.end] for token in tokens] == [
+        token.lexeme for token in tokens
+    ]
+
+    [warning] = dta.check(source)
+    assert source[warning.start : warning.end] == "%}"
+    assert (warning.line, warning.column) == (2, 3)
+
+
+def test_parse():
+    source = "{% if user %}<p>{{ user.name|title }}</p>{% else %}Hi{% endif %}"
+    [node] = dta.parse(source)
+    assert (node.kind, node.name, node.bits) == ("block", "if", ["user"])
+    assert str(node) == source
+
+    [element] = node.children
+    assert (element.kind, element.name) == ("element", "p")
+    [variable] = element.children
+    assert (variable.name, variable.filters) == ("user.name", [("title", None)])
+
+    [branch] = node.branches
+    assert (branch.kind, branch.name, str(branch)) == ("branch", "else", "{% else %}Hi")
+
+
+def test_parse_errors():
+    with pytest.raises(dta.TemplateSyntaxError) as info:
+        dta.parse("{% if user %}\n  {{ name")
+    diagnostics = info.value.diagnostics
+    assert [diagnostic.code for diagnostic in diagnostics] == ["E101", "E102"]
+    assert (diagnostics[0].line, diagnostics[0].column) == (2, 3)
+    assert all(diagnostic.severity == "error" for diagnostic in diagnostics)
+
+
+def test_check():
+    assert dta.check("{% if user %}{% endif %}") == []
+
+    [warning] = dta.check("a\nb %}")
+    assert (warning.code, warning.severity) == ("E104", "warning")
+    assert (warning.start, warning.end, warning.line, warning.column) == (4, 6, 2, 3)
+    assert warning.render("page.html").startswith(
+        "warning[E104]: unexpected '%}'\n --> page.html:2:3\n"
+    )